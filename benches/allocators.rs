@@ -0,0 +1,206 @@
+// Compares the general and arena allocators when allocating lots of small objects. Alongside the
+// throughput, the peak number of bytes each allocator had in use is measured, under `allocators/peak_memory`.
+//
+//     cargo bench --features bench --bench allocators
+
+use std::{hint::black_box, ptr::NonNull};
+
+use azimuth_runtime::memory::allocators::{arena::ArenaAllocator, general::GeneralAllocator};
+use criterion::{
+    Criterion, Throughput, criterion_group, criterion_main,
+    measurement::{Measurement, ValueFormatter},
+};
+
+const OBJECT_COUNT: usize = 10_000;
+const CAPACITY: usize = 1 << 18;
+const DEPTH: usize = 15; // Makes the smallest block 16 bytes
+
+type SmallObject = [u64; 2]; // 16 bytes
+
+// Working out how much memory the general allocator is using is slow, so is only done when asked
+
+/// Allocate every object, then free them one by one
+fn general_alloc_free(allocator: &mut GeneralAllocator<DEPTH>, measure: bool) -> usize
+{
+    let live: Vec<NonNull<SmallObject>> = (0..OBJECT_COUNT as u64)
+        .map(|value| allocator.alloc(black_box([value; 2])).expect("Allocation failed"))
+        .collect();
+
+    let peak = if measure { allocator.used() } else { 0 };
+    for ptr in live
+    {
+        allocator.dealloc(ptr).expect("Deallocation failed");
+    }
+
+    peak
+}
+
+/// Allocate every object, then release them all at once
+fn arena_alloc_release(allocator: &mut ArenaAllocator) -> usize
+{
+    for value in 0..OBJECT_COUNT as u64
+    {
+        black_box(allocator.alloc([value; 2]).expect("Allocation failed"));
+    }
+
+    let peak = allocator.used();
+    allocator.release_all();
+
+    peak
+}
+
+/// Allocate every object, while freeing a pseudo-randomly chosen live object after every other allocation
+fn general_mixed(allocator: &mut GeneralAllocator<DEPTH>, measure: bool) -> usize
+{
+    let mut live: Vec<NonNull<SmallObject>> = Vec::with_capacity(OBJECT_COUNT);
+    let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+    let mut peak = 0;
+
+    for value in 0..OBJECT_COUNT as u64
+    {
+        live.push(allocator.alloc(black_box([value; 2])).expect("Allocation failed"));
+        if measure
+        {
+            peak = peak.max(allocator.used());
+        }
+
+        if value % 2 == 1
+        {
+            // Xorshift, so that the same pattern is used every run
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+
+            let index = usize::try_from(seed % live.len() as u64).expect("Index too large");
+            allocator.dealloc(live.swap_remove(index)).expect("Deallocation failed");
+        }
+    }
+
+    for ptr in live
+    {
+        allocator.dealloc(ptr).expect("Deallocation failed");
+    }
+
+    peak
+}
+
+/// Measures the number of bytes an allocator had in use at its peak, rather than how long it took.
+///
+/// Nothing is measured around the routine, so it has to be benchmarked with `iter_custom`, giving back the peak
+/// for each iteration.
+struct PeakMemory;
+
+impl Measurement for PeakMemory
+{
+    type Intermediate = ();
+    type Value = f64;
+
+    fn start(&self) -> Self::Intermediate {}
+
+    fn end(&self, (): Self::Intermediate) -> Self::Value
+    {
+        0.0
+    }
+
+    fn add(&self, v1: &Self::Value, v2: &Self::Value) -> Self::Value
+    {
+        v1 + v2
+    }
+
+    fn zero(&self) -> Self::Value
+    {
+        0.0
+    }
+
+    fn to_f64(&self, value: &Self::Value) -> f64
+    {
+        *value
+    }
+
+    fn formatter(&self) -> &dyn ValueFormatter
+    {
+        self
+    }
+}
+
+impl ValueFormatter for PeakMemory
+{
+    fn scale_values(&self, _typical_value: f64, _values: &mut [f64]) -> &'static str
+    {
+        "B"
+    }
+
+    fn scale_throughputs(&self, _typical_value: f64, _throughput: &Throughput, _values: &mut [f64]) -> &'static str
+    {
+        "B"
+    }
+
+    fn scale_for_machines(&self, _values: &mut [f64]) -> &'static str
+    {
+        "B"
+    }
+}
+
+/// The peak for `iters` iterations, which criterion then divides back down to the peak for one
+#[expect(
+    clippy::cast_precision_loss,
+    reason = "Peaks are far smaller than the point where f64 loses precision"
+)]
+fn total_peak(iters: u64, peak: usize) -> f64
+{
+    iters as f64 * peak as f64
+}
+
+fn peak_memory(criterion: &mut Criterion<PeakMemory>)
+{
+    let mut general = GeneralAllocator::<DEPTH>::with_capacity(CAPACITY).expect("Failed to create allocator");
+    let mut arena = ArenaAllocator::with_capacity(CAPACITY).expect("Failed to create allocator");
+
+    // Every run uses the same amount of memory, so there is nothing to be gained from more samples
+    let mut group = criterion.benchmark_group("allocators/peak_memory");
+    group.sample_size(10);
+
+    group.bench_function("general/alloc_free", |bencher| {
+        bencher.iter_custom(|iters| total_peak(iters, general_alloc_free(&mut general, true)));
+    });
+    group.bench_function("arena/alloc_release", |bencher| {
+        bencher.iter_custom(|iters| total_peak(iters, arena_alloc_release(&mut arena)));
+    });
+    group.bench_function("general/mixed", |bencher| {
+        bencher.iter_custom(|iters| total_peak(iters, general_mixed(&mut general, true)));
+    });
+
+    group.finish();
+}
+
+fn allocators(criterion: &mut Criterion)
+{
+    let mut general = GeneralAllocator::<DEPTH>::with_capacity(CAPACITY).expect("Failed to create allocator");
+    let mut arena = ArenaAllocator::with_capacity(CAPACITY).expect("Failed to create allocator");
+
+    let mut group = criterion.benchmark_group("allocators");
+
+    group.throughput(Throughput::Elements(OBJECT_COUNT as u64));
+    group.bench_function("general/alloc_free", |bencher| {
+        bencher.iter(|| general_alloc_free(&mut general, false));
+    });
+    group.bench_function("arena/alloc_release", |bencher| {
+        bencher.iter(|| arena_alloc_release(&mut arena));
+    });
+
+    // Every object is both allocated and freed
+    group.throughput(Throughput::Elements(2 * OBJECT_COUNT as u64));
+    group.bench_function("general/mixed", |bencher| {
+        bencher.iter(|| general_mixed(&mut general, false));
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, allocators);
+criterion_group! {
+    name = memory;
+    config = Criterion::default().with_measurement(PeakMemory).without_plots();
+    targets = peak_memory
+}
+criterion_main!(benches, memory);
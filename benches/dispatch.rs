@@ -0,0 +1,130 @@
+// Measures how quickly the runner can dispatch instructions, as a baseline for any changes to how
+// instructions are dispatched. Results are reported in instructions per second.
+//
+//     cargo bench --bench dispatch -- --save-baseline before
+//     cargo bench --bench dispatch -- --baseline before
+//
+// Enabling the threaded-dispatch feature also measures `Runner::run_threaded` alongside `Runner::run`.
+
+use std::{hint::black_box, iter};
+
+use azimuth_runtime::{
+    engine::{Runner, opcodes::Opcode, stack::Stack},
+    loader::{
+        Loader,
+        parser::{MAGIC_NUMBER, SUPPORTED_VERSION},
+    },
+};
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+
+const INSTRUCTION_COUNT: usize = 1_000_000;
+
+/// Build a file containing a single entry point, "main", with the given code
+fn program(code: &[u8], max_stack: u16) -> Vec<u8>
+{
+    let code_length = u32::try_from(code.len()).expect("Code too long");
+
+    let mut body = vec![];
+    body.extend_from_slice(&1_u32.to_le_bytes()); // 1 constant
+    body.push(4); // String "main"
+    body.extend_from_slice(&4_u32.to_le_bytes());
+    body.extend_from_slice(b"main");
+
+    body.extend_from_slice(&[Opcode::Directive as u8, 0]); // Symbol "main"
+    body.extend_from_slice(&0_u32.to_le_bytes());
+    body.extend_from_slice(&code_length.to_le_bytes());
+    body.extend_from_slice(&[Opcode::Directive as u8, 1]); // Start
+    body.extend_from_slice(&[Opcode::Directive as u8, 2]); // Max stack
+    body.extend_from_slice(&max_stack.to_le_bytes());
+    body.extend_from_slice(&[Opcode::Directive as u8, 3]); // Max locals
+    body.extend_from_slice(&0_u16.to_le_bytes());
+    body.extend_from_slice(code);
+
+    let mut checked = vec![0]; // No flags
+    checked.extend_from_slice(&body);
+
+    let mut data = MAGIC_NUMBER.to_le_bytes().to_vec();
+    data.push(SUPPORTED_VERSION);
+    data.extend_from_slice(&crc32fast::hash(&checked).to_le_bytes());
+    data.extend_from_slice(&checked);
+    data
+}
+
+fn bench_program(criterion: &mut Criterion, name: &str, code: &[u8], max_stack: u16)
+{
+    // The optimiser would otherwise rewrite the very instructions being measured
+    let loader = Loader::from_bytes_unoptimized(&program(code, max_stack)).expect("Failed to load program");
+    let mut stack = Stack::new(1024);
+    let mut runner = Runner::new(&mut stack, &loader);
+
+    let mut group = criterion.benchmark_group("dispatch");
+    group.throughput(Throughput::Elements(INSTRUCTION_COUNT as u64));
+    group.bench_function(name, |bencher| {
+        bencher.iter(|| black_box(runner.run()).expect("Program failed"));
+    });
+    #[cfg(feature = "threaded-dispatch")]
+    group.bench_function(format!("{name}/threaded"), |bencher| {
+        bencher.iter(|| black_box(runner.run_threaded()).expect("Program failed"));
+    });
+    group.finish();
+}
+
+fn nop(criterion: &mut Criterion)
+{
+    let code: Vec<u8> = iter::repeat_n(Opcode::Nop as u8, INSTRUCTION_COUNT - 1)
+        .chain([Opcode::Ret as u8])
+        .collect();
+
+    bench_program(criterion, "nop", &code, 0);
+}
+
+fn iadd(criterion: &mut Criterion)
+{
+    // Keep adding one onto a running total
+    let code: Vec<u8> = [Opcode::IConst0 as u8]
+        .into_iter()
+        .chain(iter::repeat_n([Opcode::IConst1 as u8, Opcode::IAdd as u8], (INSTRUCTION_COUNT - 2) / 2).flatten())
+        .chain([Opcode::Ret as u8])
+        .collect();
+
+    bench_program(criterion, "iadd", &code, 2);
+}
+
+fn peephole(criterion: &mut Criterion)
+{
+    // Keep adding a constant onto a running total, which the optimiser fuses into a single instruction
+    let code: Vec<u8> = [Opcode::IConst0 as u8]
+        .into_iter()
+        .chain(
+            iter::repeat_n(
+                [Opcode::IConst as u8, 5, Opcode::IAdd as u8],
+                (INSTRUCTION_COUNT - 2) / 2,
+            )
+            .flatten(),
+        )
+        .chain([Opcode::Ret as u8])
+        .collect();
+    let program = program(&code, 2);
+
+    let mut group = criterion.benchmark_group("peephole");
+    group.throughput(Throughput::Elements(INSTRUCTION_COUNT as u64)); // Measured against the unoptimised code
+
+    for (name, loader) in [
+        ("unoptimized", Loader::from_bytes_unoptimized(&program)),
+        ("optimized", Loader::from_bytes(&program)),
+    ]
+    {
+        let loader = loader.expect("Failed to load program");
+        let mut stack = Stack::new(1024);
+        let mut runner = Runner::new(&mut stack, &loader);
+
+        group.bench_function(name, |bencher| {
+            bencher.iter(|| black_box(runner.run()).expect("Program failed"));
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, nop, iadd, peephole);
+criterion_main!(benches);
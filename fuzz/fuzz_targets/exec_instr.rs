@@ -0,0 +1,65 @@
+// Executes arbitrary bytes as bytecode. Any instruction is allowed to fail, but none of them may
+// panic, no matter what the bytecode or the state of the stack is.
+//
+// Requires cargo-fuzz (`cargo install cargo-fuzz`) and a nightly toolchain. From the repository root:
+//
+//     cargo +nightly fuzz run exec_instr
+
+#![no_main]
+
+use azimuth_runtime::{
+    engine::{opcode_handler::exec_instruction, stack::Stack},
+    loader::{constant_table::ConstantTable, parser::Table},
+    memory::{heap::Heap, metaspace::Metaspace},
+};
+use libfuzzer_sys::fuzz_target;
+
+const MAX_LOCALS: usize = 4;
+const MAX_STACK: usize = 8;
+const HEAP_SIZE: usize = 1 << 24; // About the smallest heap that every generation can be split out of
+
+// One constant of each type
+#[rustfmt::skip]
+const CONSTANTS: [u8; 38] = [
+    0, 42, 0, 0, 0,                 // Integer 42
+    1, 0, 0, 0, 0, 1, 0, 0, 0,      // Long 1 << 32
+    2, 0, 0, 0xc0, 0x3f,            // Float 1.5
+    3, 0, 0, 0, 0, 0, 0, 0xf8, 0x3f, // Double 1.5
+    4, 2, 0, 0, 0, b'h', b'i',      // String "hi"
+    5, 1,                           // Boolean true
+    6,                              // Null
+];
+
+fuzz_target!(|data: &[u8]| {
+    let metaspace = Metaspace::new();
+    let Ok((table, _)) = Table::new(7, &CONSTANTS)
+    else
+    {
+        return;
+    };
+    let Some(constants) = ConstantTable::from_parsed_table(&table, &metaspace)
+    else
+    {
+        return;
+    };
+
+    let Ok(mut heap) = Heap::with_capacity(HEAP_SIZE)
+    else
+    {
+        return;
+    };
+
+    let mut stack = Stack::new(MAX_LOCALS + MAX_STACK);
+    let Some(mut frame) = stack.initial_frame(MAX_LOCALS, MAX_STACK)
+    else
+    {
+        return;
+    };
+
+    // Run an instruction from every offset in turn, carrying the frame over between them so that
+    // instructions get run against all sorts of different stack states
+    for pc in 0..data.len()
+    {
+        let _ = exec_instruction(data, pc, &mut frame, &constants, Some(&mut heap));
+    }
+});
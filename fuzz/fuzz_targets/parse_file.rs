@@ -0,0 +1,26 @@
+// Feeds arbitrary bytes into the file parser. Parsing must either succeed or fail with an error,
+// but never panic, as the runtime may well be given untrusted files.
+//
+// Requires cargo-fuzz (`cargo install cargo-fuzz`) and a nightly toolchain. From the repository root:
+//
+//     cargo +nightly fuzz run parse_file
+//
+// Passing the compiled test programs in as an extra corpus gets it past the header checks much sooner.
+// `cargo test --test runner` assembles them into cargo's `CARGO_TARGET_TMPDIR`, which is `target/tmp`
+// unless the target directory has been moved:
+//
+//     cargo +nightly fuzz run parse_file fuzz/corpus/parse_file target/tmp/compiled
+//
+// `fuzz_target!` generates the `#[no_mangle]` entry point that libFuzzer calls into.
+
+#![no_main]
+
+use azimuth_runtime::{loader::parser::FileLayout, memory::metaspace::Metaspace};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let metaspace = Metaspace::new();
+
+    // Either result is fine, as long as getting here didn't panic
+    let _ = FileLayout::from_bytes(data, &metaspace);
+});
@@ -0,0 +1,62 @@
+#[macro_export]
+macro_rules! guard {
+    ($check:expr) => {
+        if !($check)
+        {
+            return None;
+        }
+    };
+    ($check:expr, $ret:expr) => {
+        if !($check)
+        {
+            return Err($ret);
+        }
+    };
+}
+
+/// Check an internal invariant that should never fail, returning `Err(error)` if it does.
+///
+/// Debug builds panic instead, so that whatever broke the invariant is noticed straight away.
+#[macro_export]
+macro_rules! require {
+    ($check:expr, $ret:expr) => {{
+        let check: bool = $check;
+        debug_assert!(check, concat!("Requirement failed: ", stringify!($check)));
+
+        if !check
+        {
+            return Err($ret);
+        }
+    }};
+}
+
+#[cfg(test)]
+mod macro_tests
+{
+    fn checked(value: u8) -> Result<u8, ()>
+    {
+        require!(value < 10, ());
+        Ok(value)
+    }
+
+    #[test]
+    fn require_passes()
+    {
+        assert_eq!(checked(3), Ok(3));
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "Requirement failed: value < 10")]
+    fn require_panics_in_debug()
+    {
+        assert!(checked(20).is_err());
+    }
+
+    #[cfg(not(debug_assertions))]
+    #[test]
+    fn require_errors_in_release()
+    {
+        assert_eq!(checked(20), Err(()));
+    }
+}
@@ -0,0 +1,103 @@
+mod macros;
+
+/// Methods for working with a value part way through a chain of calls.
+///
+/// `scope` and `scope_mut` give back whatever `func` returns, so turn the value into something else. `also`
+/// and `also_mut` (and their aliases `tap` and `tap_mut`) give back the value itself, so are for side
+/// effects such as logging, without breaking the chain.
+pub trait ScopeMethods
+{
+    fn scope<F, R>(self, func: F) -> R
+    where
+        F: FnOnce(&Self) -> R;
+
+    fn scope_mut<F, R>(self, func: F) -> R
+    where
+        F: FnOnce(&mut Self) -> R;
+
+    #[must_use]
+    fn also<F>(self, func: F) -> Self
+    where
+        F: FnOnce(&Self);
+
+    #[must_use]
+    fn also_mut<F>(self, func: F) -> Self
+    where
+        F: FnOnce(&mut Self);
+
+    /// The same as `also`
+    #[must_use]
+    fn tap<F>(self, func: F) -> Self
+    where
+        F: FnOnce(&Self);
+
+    /// The same as `also_mut`
+    #[must_use]
+    fn tap_mut<F>(self, func: F) -> Self
+    where
+        F: FnOnce(&mut Self);
+}
+
+impl<T> ScopeMethods for T
+{
+    fn scope<F, R>(self, func: F) -> R
+    where
+        F: FnOnce(&Self) -> R,
+    {
+        func(&self)
+    }
+
+    fn scope_mut<F, R>(mut self, func: F) -> R
+    where
+        F: FnOnce(&mut Self) -> R,
+    {
+        func(&mut self)
+    }
+
+    fn also<F>(self, func: F) -> T
+    where
+        F: FnOnce(&Self),
+    {
+        func(&self);
+        self
+    }
+
+    fn also_mut<F>(mut self, func: F) -> T
+    where
+        F: FnOnce(&mut Self),
+    {
+        func(&mut self);
+        self
+    }
+
+    fn tap<F>(self, func: F) -> T
+    where
+        F: FnOnce(&Self),
+    {
+        self.also(func)
+    }
+
+    fn tap_mut<F>(self, func: F) -> T
+    where
+        F: FnOnce(&mut Self),
+    {
+        self.also_mut(func)
+    }
+}
+
+#[cfg(test)]
+mod scope_tests
+{
+    use super::*;
+
+    #[test]
+    fn tap()
+    {
+        let mut seen = 0;
+        let value = 3_u32.tap(|&x| seen = x).tap_mut(|x| *x += 1);
+
+        assert_eq!(seen, 3);
+        assert_eq!(value, 4);
+        assert_eq!(value.scope(|&x| x * 2), 8);
+    }
+}
@@ -1,8 +1,8 @@
-use std::env::args;
+use std::{env::args, process::ExitCode, time::Instant};
 
 use crate::{
-    engine::{Runner, RunnerError, stack::Stack},
-    loader::Loader,
+    engine::{ExecutionResult, Runner, RunnerError, stack::Stack, validator::ValidationError},
+    loader::{LoadOptions, Loader, constant_table::MAX_CONSTANT_POOL_SIZE},
     memory::heap::{Heap, HeapError},
 };
 
@@ -15,16 +15,23 @@ pub enum ConfigError
     MissingOperand(String),
     InvalidOperand(String),
     LoaderInitError,
+    ValidationFailed(Vec<ValidationError>),
     StackInitError,
     HeapInitError(HeapError),
     RunnerError(RunnerError),
 }
 
 // List of optional flags that can be passed in as arguments
+#[expect(clippy::struct_excessive_bools, reason = "Each flag is switched on separately")]
 struct Flags
 {
     stack_size: usize,
     heap_size: usize,
+    max_constants: usize,
+    validate: bool,
+    dry_run: bool,
+    verbose: bool,
+    quiet: bool,
 }
 
 impl Flags
@@ -41,6 +48,11 @@ impl Default for Flags
         Self {
             stack_size: Self::DEFAULT_STACK_SIZE,
             heap_size: Self::DEFAULT_HEAP_SIZE,
+            max_constants: MAX_CONSTANT_POOL_SIZE,
+            validate: true,
+            dry_run: false,
+            verbose: false,
+            quiet: false,
         }
     }
 }
@@ -53,9 +65,26 @@ pub struct Config
 
 impl Config
 {
+    /// Create a config from the arguments the program was run with
+    ///
+    /// ### Errors
+    /// If the arguments aren't valid, in the same way as `from_args`.
     pub fn new() -> Result<Self, ConfigError>
     {
-        let mut args = args().skip(1); // Skip the executable name itself
+        let args: Vec<String> = args().skip(1).collect(); // Skip the executable name itself
+        Self::from_args(&args)
+    }
+
+    /// Create a config from the given arguments, in the same form as they are given on the command line.
+    ///
+    /// The name of the executable shouldn't be included.
+    ///
+    /// ### Errors
+    /// If a flag isn't recognised, is missing its operand or has an operand that can't be parsed, or if no
+    /// file is given.
+    pub fn from_args(args: &[String]) -> Result<Self, ConfigError>
+    {
+        let mut args = args.iter().cloned();
         let mut flags = Flags::default();
         let mut filename: Option<String> = None;
 
@@ -68,6 +97,15 @@ impl Config
                     let operand = args.next().ok_or(ConfigError::MissingOperand(arg_.into()))?;
                     flags.stack_size = operand.parse().map_err(|_| ConfigError::InvalidOperand(operand))?;
                 }
+                arg_ @ "--max-constants" =>
+                {
+                    let operand = args.next().ok_or(ConfigError::MissingOperand(arg_.into()))?;
+                    flags.max_constants = operand.parse().map_err(|_| ConfigError::InvalidOperand(operand))?;
+                }
+                "--no-validate" => flags.validate = false, // The bytecode is trusted, so doesn't need checking
+                "--dry-run" => flags.dry_run = true,       // Only check the file, without running any of it
+                "--verbose" => flags.verbose = true, // Report on loading, and on the heap once the program has finished
+                "--quiet" => flags.quiet = true,     // Don't write out anything the program prints
                 _file =>
                 {
                     filename
@@ -83,24 +121,105 @@ impl Config
         })
     }
 
-    pub fn execute(&self) -> Result<(), ConfigError>
+    /// Run the program, giving back the exit code it finished with
+    ///
+    /// ### Errors
+    /// If the file can't be loaded or fails validation, the stack or heap can't be set up, or the program
+    /// fails while running.
+    pub fn execute(&self) -> Result<ExitCode, ConfigError>
     {
         // Load file
 
         // -- Init Required systems --
 
+        let start = Instant::now();
+
         // Init Loader (WIP)
-        let loader = Loader::from_file(&self.filename).map_err(|_| ConfigError::LoaderInitError)?;
+        let options = LoadOptions {
+            max_constants: self.flags.max_constants,
+            ..LoadOptions::default()
+        };
+        let loader =
+            Loader::from_file_with_options(&self.filename, options).map_err(|_| ConfigError::LoaderInitError)?;
+
+        if self.flags.dry_run
+        {
+            return Self::dry_run(&loader).map(|()| ExitCode::SUCCESS);
+        }
+
+        // Validate the bytecode up front, so that nothing is run if any of it is bad
+        if self.flags.validate
+        {
+            loader.validate_bytecode().map_err(ConfigError::ValidationFailed)?;
+        }
 
         // Init Stack
         let mut stack = Stack::new(self.flags.stack_size);
 
         // Init Heap
-        let mut heap = Heap::with_capacity(self.flags.heap_size).map_err(|x| ConfigError::HeapInitError(x));
+        let mut heap = Heap::with_capacity(self.flags.heap_size).map_err(ConfigError::HeapInitError);
+
+        if self.flags.verbose
+        {
+            println!("startup:    {:?}", start.elapsed());
+            println!("functions:  {}", loader.get_function_count());
+            println!("constants:  {}", loader.get_constant_table().len());
+        }
 
         // Pass information to runner
         let mut runner = Runner::new(&mut stack, &loader);
+        if let Ok(heap) = heap.as_mut()
+        {
+            runner = runner.with_heap(heap);
+        }
+        if self.flags.quiet
+        {
+            runner = runner.with_quiet();
+        }
+
+        // Nothing else is running, so a program that yields can be carried on with straight away
+        let mut result = runner.run();
+        while result == Ok(ExecutionResult::Yielded)
+        {
+            result = runner.run();
+        }
+        let result = result.map_err(ConfigError::RunnerError);
+
+        if self.flags.verbose
+        {
+            match heap
+            {
+                Ok(heap) => println!("{:#?}", heap.dump_stats()),
+                Err(error) => println!("No heap stats, as the heap couldn't be created: {error:?}"),
+            }
+        }
+
+        match result?
+        {
+            // Programs are run until they stop yielding, so never finish by yielding here
+            ExecutionResult::ReturnVoid | ExecutionResult::Yielded => Ok(ExitCode::SUCCESS),
+            ExecutionResult::ReturnValue(value) =>
+            {
+                if self.flags.verbose
+                {
+                    println!("Returned {value}");
+                }
+                Ok(ExitCode::SUCCESS)
+            }
+            // A halted program decides its own exit code
+            ExecutionResult::Halted(code) => Ok(ExitCode::from(code)),
+        }
+    }
+
+    /// Summarise a loaded file and check its bytecode, without running any of it
+    fn dry_run(loader: &Loader) -> Result<(), ConfigError>
+    {
+        let validation = loader.validate_bytecode();
+
+        println!("functions:  {}", loader.get_function_count());
+        println!("constants:  {}", loader.get_constant_table().len());
+        println!("validation: {}", if validation.is_ok() { "OK" } else { "FAIL" });
 
-        runner.run().map_err(ConfigError::RunnerError)
+        validation.map_err(ConfigError::ValidationFailed)
     }
 }
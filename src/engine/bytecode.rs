@@ -0,0 +1,86 @@
+use alloc::vec::Vec;
+
+use crate::engine::validator::{BytecodeValidator, ValidationError};
+
+/// The code of a single function, which has passed the bytecode validator unless it was made with `from_trusted`.
+///
+/// This is for building bytecode programmatically. Bytecode loaded from a file is only validated when asked
+/// for, so it is left as the raw bytes that were read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bytecode(Vec<u8>);
+
+impl Bytecode
+{
+    /// Wrap the given bytes, after checking them with the bytecode validator.
+    ///
+    /// Every problem found is reported at once.
+    ///
+    /// ### Errors
+    /// Every problem the validator finds with the bytes, if there are any.
+    pub fn new(bytes: Vec<u8>) -> Result<Self, Vec<ValidationError>>
+    {
+        let errors = BytecodeValidator.validate(&bytes);
+        errors.is_empty().then_some(Self(bytes)).ok_or(errors)
+    }
+
+    /// Wrap the given bytes without validating them, for code that is already known to be valid, or that is
+    /// deliberately invalid.
+    ///
+    /// The interpreter checks everything it runs regardless, so invalid bytes give errors rather than
+    /// undefined behaviour.
+    #[must_use]
+    pub const fn from_trusted(bytes: Vec<u8>) -> Self
+    {
+        Self(bytes)
+    }
+
+    #[must_use]
+    pub fn as_slice(&self) -> &[u8]
+    {
+        &self.0
+    }
+
+    /// The number of bytes of code
+    #[must_use]
+    pub fn len(&self) -> usize
+    {
+        self.0.len()
+    }
+
+    /// Whether there is no code at all. Valid bytecode always has to return, so this is never true for
+    /// anything made with `new`.
+    #[must_use]
+    pub fn is_empty(&self) -> bool
+    {
+        self.0.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod bytecode_tests
+{
+    use super::*;
+    use crate::engine::opcodes::Opcode;
+
+    #[test]
+    fn validated()
+    {
+        let code = vec![Opcode::IConst1 as u8, Opcode::RetVal as u8];
+        let bytecode = Bytecode::new(code.clone()).unwrap();
+        assert_eq!(bytecode.as_slice(), code);
+        assert_eq!(bytecode.len(), 2);
+
+        assert_eq!(
+            Bytecode::new(vec![Opcode::IConst1 as u8]),
+            Err(vec![ValidationError::MissingReturn { at: 1 }])
+        );
+        assert_eq!(
+            Bytecode::new(vec![]),
+            Err(vec![ValidationError::MissingReturn { at: 0 }])
+        );
+
+        // Trusted bytes are taken as they are
+        let trusted = Bytecode::from_trusted(vec![Opcode::IConst1 as u8]);
+        assert_eq!(trusted.as_slice(), [Opcode::IConst1 as u8]);
+    }
+}
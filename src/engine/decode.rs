@@ -0,0 +1,73 @@
+use crate::engine::{opcodes::Opcode, validator::ValidationError};
+
+/// A single decoded instruction within a bytecode stream
+pub(crate) struct Instruction<'a>
+{
+    pub at: usize,
+    pub opcode: Opcode,
+    pub params: &'a [u8],
+}
+
+impl Instruction<'_>
+{
+    /// Get the relative offset this instruction may jump by, if it is a branching instruction.
+    pub fn branch_offset(&self) -> Option<isize>
+    {
+        match self.opcode
+        {
+            Opcode::Jmp | Opcode::JmpIfTrue | Opcode::JmpIfFalse =>
+            {
+                self.params.first_chunk().map(|&x| isize::from(i16::from_le_bytes(x)))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Splits a stream of bytecode up into its instructions.
+///
+/// An unknown opcode is reported and skipped over, decoding carrying on from the next byte. A truncated
+/// instruction is reported and ends decoding, as there is nothing left after it.
+pub(crate) struct Decoder<'a>
+{
+    code: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Decoder<'a>
+{
+    pub const fn new(code: &'a [u8]) -> Self
+    {
+        Self { code, offset: 0 }
+    }
+}
+
+impl<'a> Iterator for Decoder<'a>
+{
+    type Item = Result<Instruction<'a>, ValidationError>;
+
+    fn next(&mut self) -> Option<Self::Item>
+    {
+        let at = self.offset;
+        let &byte = self.code.get(at)?;
+
+        // Directives are only valid in the function header, not within the code itself
+        let Some(opcode) = Opcode::try_from(byte).ok().filter(|x| *x != Opcode::Directive)
+        else
+        {
+            self.offset += 1;
+            return Some(Err(ValidationError::UnknownOpcode { at, byte }));
+        };
+
+        let end = at + 1 + usize::from(opcode.param_count());
+        let Some(params) = self.code.get(at + 1..end)
+        else
+        {
+            self.offset = self.code.len();
+            return Some(Err(ValidationError::MissingParams { at }));
+        };
+
+        self.offset = end;
+        Some(Ok(Instruction { at, opcode, params }))
+    }
+}
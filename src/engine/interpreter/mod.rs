@@ -0,0 +1,221 @@
+#[cfg(feature = "std")]
+use alloc::{format, string::String};
+
+#[cfg(feature = "profiling")]
+use crate::engine::profiler::{OpcodeProfiler, measure};
+#[cfg(feature = "std")]
+use crate::engine::{
+    opcode_handler::PrintFormat,
+    opcodes::Opcode,
+    stack::{StackEntry, stackable::Stackable as _},
+};
+use crate::{
+    engine::{
+        BreakpointHandler, ExecutionResult, RunnerError,
+        opcode_handler::{ExecutionError, HandlerResult, InstructionResult},
+        stack::StackFrame,
+    },
+    loader::{constant_table::ConstantTable, runnable::Runnable},
+    memory::heap::Heap,
+};
+
+/// Where the interpreter is up to within a function, along with everything it runs the function against.
+///
+/// This is all the interpreter needs to carry on, so it can be stopped and picked up again from the state
+/// alone, or pointed at a different function part way through.
+pub struct InterpreterState<'s>
+{
+    pub pc: usize, // The instruction about to be run
+    pub function: &'s Runnable<'s>,
+    pub frame: StackFrame<'s>,
+    pub heap: Option<&'s mut Heap>,
+    pub gas: Option<u64>, // The number of instructions left to run, if limited
+    pub trace: bool,      // Whether every instruction is printed as it is run
+    pub breakpoint: Option<&'s BreakpointHandler>,
+    pub quiet: bool, // Whether print instructions are stopped from writing anything
+    #[cfg(feature = "profiling")]
+    pub profiler: &'s mut OpcodeProfiler,
+}
+
+/// Interpret the state's function until it finishes, using `exec` to execute each instruction.
+///
+/// If the function fails, `state.pc` is left at the instruction that failed, and the error is given without
+/// anything about where it happened attached. If it yields, `state.pc` is moved on to the instruction to carry on from.
+///
+/// ### Errors
+/// If an instruction fails, the function runs out of gas, or it calls a function that fails.
+pub fn interpret<F>(
+    state: &mut InterpreterState,
+    constants: &ConstantTable,
+    exec: F,
+) -> Result<ExecutionResult, RunnerError>
+where
+    F: for<'c> Fn(&'c [u8], usize, &mut StackFrame, &ConstantTable<'c>, Option<&mut Heap>) -> HandlerResult,
+{
+    let code = state.function.code();
+
+    // Keep executing the program until a break condition is met: either a return statement or an
+    // error
+    loop
+    {
+        if let Some(gas) = state.gas.as_mut()
+        {
+            *gas = gas.checked_sub(1).ok_or(RunnerError::OutOfGas)?;
+        }
+
+        #[cfg(feature = "std")]
+        if state.trace
+        {
+            trace_instruction(state.function, state.pc, state.frame.depth());
+        }
+
+        #[cfg(not(feature = "profiling"))]
+        let exec_result = exec(code, state.pc, &mut state.frame, constants, state.heap.as_deref_mut());
+
+        #[cfg(feature = "profiling")]
+        let exec_result = {
+            let (result, cycles) =
+                measure(|| exec(code, state.pc, &mut state.frame, constants, state.heap.as_deref_mut()));
+            if let Some(&opcode) = code.get(state.pc)
+            {
+                state.profiler.record_opcode(opcode, cycles);
+            }
+            result
+        };
+
+        let exec_result = match exec_result
+        {
+            Ok(result) => result,
+            Err(error) =>
+            {
+                let handler = find_handler(state.function, error, state.pc).ok_or(RunnerError::execution(error))?;
+
+                // Whatever was being worked on when the error happened is abandoned
+                while state.frame.pop().is_some()
+                {}
+
+                InstructionResult::Jump(handler)
+            }
+        };
+
+        match exec_result
+        {
+            InstructionResult::Next(width) => state.pc = advance(code, state.pc, width)?,
+            InstructionResult::Jump(target) =>
+            {
+                // Jump to given target instruction after checking validity
+                (target < code.len())
+                    .then(|| state.pc = target)
+                    .ok_or(RunnerError::ProgramCounterOverflow)?;
+            }
+            InstructionResult::Return(has_value) =>
+            {
+                return finish(&mut state.frame, has_value).map_err(RunnerError::execution);
+            }
+            InstructionResult::Halt(code) => return Ok(ExecutionResult::Halted(code)),
+            InstructionResult::Breakpoint =>
+            {
+                if let Some(handler) = state.breakpoint
+                {
+                    handler(state.pc, &state.frame);
+                }
+
+                state.pc = advance(code, state.pc, 1)?;
+            }
+            #[cfg_attr(
+                not(feature = "std"),
+                expect(unused_variables, reason = "There is nowhere to print to without `std`")
+            )]
+            InstructionResult::Print(value, format) =>
+            {
+                #[cfg(feature = "std")]
+                if !state.quiet
+                {
+                    print_value(value, format);
+                }
+
+                state.pc = advance(code, state.pc, 2)?; // The opcode and its format
+            }
+            InstructionResult::Yield =>
+            {
+                // Carry on from the following instruction when the program is next run. Yielding takes no
+                // parameters, so that is the very next byte
+                state.pc = advance(code, state.pc, 1)?;
+                return Ok(ExecutionResult::Yielded);
+            }
+        }
+    }
+}
+
+/// Collect the value a function is returning, if it returns one
+pub(crate) fn finish(frame: &mut StackFrame, has_value: bool) -> Result<ExecutionResult, ExecutionError>
+{
+    // The returned value is whatever has been left on top of the stack
+    if has_value
+    {
+        frame
+            .pop()
+            .map(ExecutionResult::ReturnValue)
+            .ok_or(ExecutionError::EmptyStack)
+    }
+    else
+    {
+        Ok(ExecutionResult::ReturnVoid)
+    }
+}
+
+/// Move `width` bytes past the instruction at `pc`, after checking that there is something there
+fn advance(code: &[u8], pc: usize, width: usize) -> Result<usize, RunnerError>
+{
+    let next = pc + width;
+
+    (next < code.len())
+        .then_some(next)
+        .ok_or(RunnerError::ProgramCounterOverflow)
+}
+
+/// Find where to carry on from after an error, if the function handles it itself
+fn find_handler(function: &Runnable, error: ExecutionError, pc: usize) -> Option<usize>
+{
+    // Only errors caused by the program can be handled, not ones caused by broken bytecode
+    matches!(
+        error,
+        ExecutionError::DivideByZero | ExecutionError::AssertionFailed { .. }
+    )
+    .then(|| function.find_handler(pc))
+    .flatten()
+    .map(usize::from)
+}
+
+/// Write out a value for a print instruction, on its own line
+#[cfg(feature = "std")]
+fn print_value(value: StackEntry, format: PrintFormat)
+{
+    match format
+    {
+        PrintFormat::Integer => println!("{value}"),
+        PrintFormat::Float4 => println!("{}", <f32>::from_entry(value)),
+        PrintFormat::Float8 => println!("{}", <f64>::from_entry(value)),
+        PrintFormat::Hex => println!("{value:#x}"),
+    }
+}
+
+/// Print the instruction that is about to be run, along with the depth of the stack beforehand
+#[cfg(feature = "std")]
+fn trace_instruction(function: &Runnable, pc: usize, depth: usize)
+{
+    let code = function.code();
+    let instruction = code
+        .get(pc)
+        .and_then(|&x| Opcode::try_from(x).ok())
+        .map_or_else(String::new, |opcode| {
+            let params = code
+                .get(pc + 1..pc + 1 + usize::from(opcode.param_count()))
+                .unwrap_or_default();
+            params
+                .iter()
+                .fold(opcode.to_string(), |text, param| format!("{text} {param}"))
+        });
+
+    eprintln!("[trace] {}+{pc:<5} depth {depth:<3} {instruction}", function.name());
+}
@@ -0,0 +1,62 @@
+use crate::engine::{opcode_handler::HandlerResult, stack::StackFrame};
+
+/// A function's bytecode, compiled down to native code.
+///
+/// The compiled code is given the function's frame, with its arguments already in place, and runs the
+/// whole function before returning.
+#[derive(Debug, Clone, Copy)]
+pub struct CompiledFunction(fn(*mut StackFrame<'_>) -> HandlerResult);
+
+impl CompiledFunction
+{
+    pub const fn new(function: fn(*mut StackFrame<'_>) -> HandlerResult) -> Self
+    {
+        Self(function)
+    }
+
+    /// Run the compiled code on the given frame
+    ///
+    /// ### Errors
+    /// Whatever error the compiled code fails with.
+    pub fn call(&self, frame: &mut StackFrame) -> HandlerResult
+    {
+        (self.0)(frame)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JitError
+{
+    Unsupported,
+}
+
+/// Compiles functions to native code, so that they don't have to be interpreted.
+///
+/// Any function that can't be compiled is interpreted as normal.
+pub trait JitCompiler
+{
+    /// Whether `compile` is able to handle this bytecode
+    fn can_jit(&self, code: &[u8]) -> bool;
+
+    /// Compile the bytecode to native code
+    ///
+    /// ### Errors
+    /// `JitError::Unsupported` if the bytecode uses something the compiler can't handle.
+    fn compile(&mut self, code: &[u8]) -> Result<CompiledFunction, JitError>;
+}
+
+/// A compiler that never compiles anything, so everything is interpreted
+pub struct NoOpJit;
+
+impl JitCompiler for NoOpJit
+{
+    fn can_jit(&self, _code: &[u8]) -> bool
+    {
+        false
+    }
+
+    fn compile(&mut self, _code: &[u8]) -> Result<CompiledFunction, JitError>
+    {
+        Err(JitError::Unsupported)
+    }
+}
@@ -1,43 +1,437 @@
+pub mod bytecode;
+mod decode;
+pub mod interpreter;
+pub mod jit;
 pub mod opcode_handler;
 pub mod opcodes;
+pub mod optimizer;
+#[cfg(feature = "profiling")]
+pub mod profiler;
+pub mod snapshot;
 pub mod stack;
+pub mod validator;
 
+use alloc::{borrow::ToOwned as _, boxed::Box, collections::BTreeMap, string::String};
+use core::{
+    fmt::{self, Display, Formatter},
+    iter,
+};
+
+#[cfg(feature = "threaded-dispatch")]
+use crate::engine::opcode_handler::exec_instruction_threaded;
+#[cfg(feature = "profiling")]
+use crate::engine::profiler::OpcodeProfiler;
 use crate::{
     engine::{
-        opcode_handler::{ExecutionError, InstructionResult, exec_instruction},
-        stack::Stack,
+        interpreter::InterpreterState,
+        jit::{CompiledFunction, JitCompiler},
+        opcode_handler::{ExecutionError, HandlerResult, InstructionResult, exec_instruction},
+        opcodes::Opcode,
+        snapshot::RunnerSnapshot,
+        stack::{Stack, StackEntry, StackFrame},
+    },
+    guard,
+    loader::{
+        Loader,
+        constant_table::ConstantTable,
+        parser::DebugEntry,
+        runnable::{Runnable, RunnableError},
     },
-    loader::Loader,
+    memory::heap::Heap,
 };
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum RunnerError
 {
     MissingEntryPoint,
+    FunctionNotFound,
+    TooManyArguments,
     StackOverflow,
-    ExecutionError(ExecutionError),
+    ExecutionError
+    {
+        error: ExecutionError,
+        pc: Option<usize>,        // The offset of the failing instruction within its function, if known
+        function: Option<String>, // The name of the function that was running, if known
+        location: Option<DebugEntry>, // Where in the source the failing instruction came from, if known
+        source_path: Option<String>, // The source file the program was compiled from, if known
+    },
     ProgramCounterOverflow,
+    OutOfGas,
+    IncompatibleSnapshot, // A snapshot doesn't fit the function it names in the loaded program
+    InvalidSetup(RunnableError), // The function's max stack or max locals can't work for its code
+}
+
+impl From<RunnableError> for RunnerError
+{
+    fn from(value: RunnableError) -> Self
+    {
+        Self::InvalidSetup(value)
+    }
+}
+
+impl RunnerError
+{
+    /// An error from executing an instruction, before anything is known about where it happened
+    #[must_use]
+    pub const fn execution(error: ExecutionError) -> Self
+    {
+        Self::ExecutionError {
+            error,
+            pc: None,
+            function: None,
+            location: None,
+            source_path: None,
+        }
+    }
+}
+
+impl Display for RunnerError
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result
+    {
+        match *self
+        {
+            Self::MissingEntryPoint => write!(f, "no entry point"),
+            Self::FunctionNotFound => write!(f, "function not found"),
+            Self::TooManyArguments => write!(f, "too many arguments"),
+            Self::StackOverflow => write!(f, "stack overflow"),
+            Self::ExecutionError {
+                error,
+                pc,
+                ref function,
+                location,
+                ref source_path,
+            } =>
+            {
+                write!(f, "execution error")?;
+                if let Some(pc) = pc
+                {
+                    write!(f, " at pc={pc}")?;
+                }
+                if let Some(function) = function.as_deref()
+                {
+                    write!(f, " in function '{function}'")?;
+                }
+                match (source_path.as_deref(), location)
+                {
+                    (Some(path), Some(location)) => write!(f, " ({path}, line {})", location.line_number)?,
+                    (Some(path), None) => write!(f, " ({path})")?,
+                    (None, Some(location)) => write!(f, " (line {})", location.line_number)?,
+                    (None, None) => (),
+                }
+                write!(f, ": {error}")
+            }
+            Self::ProgramCounterOverflow => write!(f, "program counter overflow"),
+            Self::OutOfGas => write!(f, "out of gas"),
+            Self::IncompatibleSnapshot => write!(f, "snapshot doesn't match the loaded program"),
+            Self::InvalidSetup(error) => write!(f, "invalid function setup: {error}"),
+        }
+    }
+}
+
+/// How a program finished running
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecutionResult
+{
+    ReturnVoid,
+    ReturnValue(StackEntry),
+    Halted(u8), // The exit code given to the halt instruction
+    Yielded,    // The program paused itself, and carries on from there when it is next run
+}
+
+impl ExecutionResult
+{
+    /// The value that was returned, if there was one
+    #[must_use]
+    pub const fn value(self) -> Option<StackEntry>
+    {
+        match self
+        {
+            Self::ReturnValue(value) => Some(value),
+            Self::ReturnVoid | Self::Halted(_) | Self::Yielded => None,
+        }
+    }
+}
+
+/// Called whenever a breakpoint instruction is run, with its offset within the function and the function's
+/// frame as it is at that point
+pub type BreakpointHandler = dyn Fn(usize, &StackFrame);
+
+/// Where a function that yielded was up to, so that it can be carried on with
+struct Suspended<'a>
+{
+    function: Runnable<'a>,
+    pc: usize,
+    depth: usize, // How many values were on the operand stack
+}
+
+/// How to start running a function
+#[derive(Clone, Copy)]
+enum Start<'s>
+{
+    Call(&'s [StackEntry]), // From the beginning, with these arguments
+    Resume
+    {
+        pc: usize,
+        depth: usize,
+    },
 }
 
 pub struct Runner<'a>
 {
     stack: &'a mut Stack,
     loader: &'a Loader,
-    // heap
+    gas: Option<u64>, // The number of instructions left to run, if limited
+    max_call_depth: usize,
+    trace: bool, // Whether every instruction is printed as it is run
+    quiet: bool, // Whether print instructions are stopped from writing anything
+    #[cfg(feature = "profiling")]
+    profiler: OpcodeProfiler,
+
+    // Compiles functions to be run instead of interpreting them
+    jit: Option<Box<dyn JitCompiler>>,
+    // What each function has been compiled to, keyed by where its code starts, so that it is only compiled once.
+    // Functions the JIT couldn't compile are remembered as `None`.
+    compiled: BTreeMap<usize, Option<CompiledFunction>>,
+    // The function that last yielded, if it hasn't been carried on with yet. Its frame is left in place on the
+    // stack until then.
+    suspended: Option<Suspended<'a>>,
+    // Where heap instructions load from and store to. Running one without a heap is an error.
+    heap: Option<&'a mut Heap>,
+    // What breakpoint instructions hand over to. Without one, they do nothing.
+    breakpoint_handler: Option<Box<BreakpointHandler>>,
 }
 
 impl<'a> Runner<'a>
 {
+    pub const DEFAULT_MAX_CALL_DEPTH: usize = 256;
+
+    /// Create a runner with everything else left as the default, same as a `RunnerBuilder` given only the stack
+    /// and loader
     pub fn new(stack: &'a mut Stack, loader: &'a Loader) -> Self
     {
-        Self { stack, loader }
+        RunnerBuilder::new().build_with(stack, loader)
+    }
+
+    /// Compile functions with `jit` where possible, falling back to interpreting them
+    #[must_use]
+    pub fn with_jit(mut self, jit: Box<dyn JitCompiler>) -> Self
+    {
+        self.jit = Some(jit);
+        self.compiled.clear();
+        self
+    }
+
+    /// Give heap instructions a heap to load from and store to
+    #[must_use]
+    pub fn with_heap(mut self, heap: &'a mut Heap) -> Self
+    {
+        self.heap = Some(heap);
+        self
+    }
+
+    /// Stop print instructions from writing anything, for when the program's output isn't wanted
+    #[must_use]
+    pub const fn with_quiet(mut self) -> Self
+    {
+        self.quiet = true;
+        self
+    }
+
+    /// Call `handler` every time a breakpoint instruction is run, replacing any handler set before
+    pub fn set_breakpoint_handler(&mut self, handler: Box<BreakpointHandler>)
+    {
+        self.breakpoint_handler = Some(handler);
+    }
+
+    /// Limit the total number of instructions the runner will execute before giving up.
+    ///
+    /// Every instruction costs one unit of gas, shared between all the functions that are run. A call to a function
+    /// compiled by the JIT costs one unit, as the instructions within it can't be counted.
+    #[must_use]
+    pub const fn with_gas_limit(mut self, gas: u64) -> Self
+    {
+        self.gas = Some(gas);
+        self
     }
 
-    pub fn run(&mut self) -> Result<(), RunnerError>
+    /// The amount of gas left, if execution is being limited
+    #[must_use]
+    pub const fn remaining_gas(&self) -> Option<u64>
     {
+        self.gas
+    }
+
+    /// The deepest that calls between functions are allowed to nest.
+    ///
+    /// There is no call instruction yet, so this isn't enforced anywhere.
+    #[must_use]
+    pub const fn max_call_depth(&self) -> usize
+    {
+        self.max_call_depth
+    }
+
+    /// How long each opcode has spent running, across everything this runner has run
+    #[cfg(feature = "profiling")]
+    #[must_use]
+    pub const fn profiler(&self) -> &OpcodeProfiler
+    {
+        &self.profiler
+    }
+
+    /// Run the program from its entry point, giving back how it finished.
+    ///
+    /// If the program last finished by yielding, it carries on from where it was instead.
+    ///
+    /// ### Errors
+    /// If there is no entry point, or the program fails while running.
+    pub fn run(&mut self) -> Result<ExecutionResult, RunnerError>
+    {
+        if let Some(suspended) = self.suspended.take()
+        {
+            return self.resume(suspended, exec_instruction);
+        }
+
         // Get the entry point. This is the "main" function where execution will start
         let entry_point = self.loader.get_entry_point().ok_or(RunnerError::MissingEntryPoint)?;
-        let (maxstack, maxlocals) = entry_point.setup_info();
+
+        self.execute(entry_point, Start::Call(&[]), exec_instruction)
+    }
+
+    /// Run the entry point, in the same way as `run`, but using direct-threaded dispatch
+    ///
+    /// ### Errors
+    /// In the same way as `run`.
+    #[cfg(feature = "threaded-dispatch")]
+    pub fn run_threaded(&mut self) -> Result<ExecutionResult, RunnerError>
+    {
+        if let Some(suspended) = self.suspended.take()
+        {
+            return self.resume(suspended, exec_instruction_threaded);
+        }
+
+        let entry_point = self.loader.get_entry_point().ok_or(RunnerError::MissingEntryPoint)?;
+
+        self.execute(entry_point, Start::Call(&[]), exec_instruction_threaded)
+    }
+
+    /// Whether the program has yielded, so will carry on from there the next time it is run
+    #[must_use]
+    pub const fn is_suspended(&self) -> bool
+    {
+        self.suspended.is_some()
+    }
+
+    /// Capture where the program is up to, if it has yielded, so that it can be carried on with later using
+    /// `restore`.
+    ///
+    /// A program that isn't part way through has nothing to capture.
+    #[must_use]
+    pub fn snapshot(&self) -> Option<RunnerSnapshot>
+    {
+        let suspended = self.suspended.as_ref()?;
+        let (_, maxlocals) = suspended.function.setup_info();
+
+        // The function that yielded is always run in the first frame on the stack
+        let entries = self.stack.entries();
+        Some(RunnerSnapshot::new(
+            suspended.function.name().to_owned(),
+            suspended.pc,
+            entries.get(maxlocals..maxlocals + suspended.depth)?.to_vec(),
+            entries.get(..maxlocals)?.to_vec(),
+        ))
+    }
+
+    /// Pick a program back up from a snapshot, so that the next time it is run it carries on from there.
+    ///
+    /// Anything that had yielded is abandoned. The snapshot has to be of a function in the loaded program,
+    /// with the same number of locals and room for everything on its operand stack. Its pc has to be at the
+    /// instruction straight after a yield, as that is the only place a function can be carried on from.
+    ///
+    /// ### Errors
+    /// `RunnerError::FunctionNotFound` if the function isn't in the program, and
+    /// `RunnerError::IncompatibleSnapshot` if the snapshot doesn't fit it.
+    pub fn restore(&mut self, snapshot: &RunnerSnapshot) -> Result<(), RunnerError>
+    {
+        let function = self
+            .loader
+            .get_function_by_name(snapshot.function())
+            .ok_or(RunnerError::FunctionNotFound)?;
+        let (maxstack, maxlocals) = function.setup_info();
+        guard!(
+            snapshot.locals().len() == maxlocals
+                && snapshot.stack().len() <= maxstack
+                && snapshot.pc() < function.code().len()
+                && Self::follows_yield(function.code(), snapshot.pc()),
+            RunnerError::IncompatibleSnapshot
+        );
+
+        let mut frame = self
+            .stack
+            .initial_frame(maxlocals, maxstack)
+            .ok_or(RunnerError::StackOverflow)?;
+        for (index, &value) in snapshot.locals().iter().enumerate()
+        {
+            frame.set_local(index, value);
+        }
+        for &value in snapshot.stack()
+        {
+            frame.push(value);
+        }
+
+        self.suspended = Some(Suspended {
+            function,
+            pc: snapshot.pc(),
+            depth: snapshot.stack().len(),
+        });
+        Ok(())
+    }
+
+    /// Run the function with the given name, rather than the entry point.
+    ///
+    /// The arguments are placed into the function's first local variables, and the value it
+    /// returns, if any, is given back. A function that halts or yields gives back no value.
+    ///
+    /// This always starts the function from the beginning, so anything that had yielded is abandoned.
+    ///
+    /// ### Errors
+    /// `RunnerError::FunctionNotFound` if there is no function with that name, or any error it fails with
+    /// while running.
+    pub fn run_function(&mut self, name: &str, args: &[StackEntry]) -> Result<Option<StackEntry>, RunnerError>
+    {
+        let function = self
+            .loader
+            .get_function_by_name(name)
+            .ok_or(RunnerError::FunctionNotFound)?;
+
+        self.suspended = None;
+        self.execute(function, Start::Call(args), exec_instruction)
+            .map(ExecutionResult::value)
+    }
+
+    /// Carry on running a function that yielded, from where it was
+    fn resume<F>(&mut self, suspended: Suspended<'a>, exec: F) -> Result<ExecutionResult, RunnerError>
+    where
+        F: for<'c> Fn(&'c [u8], usize, &mut StackFrame, &ConstantTable<'c>, Option<&mut Heap>) -> HandlerResult,
+    {
+        let Suspended { function, pc, depth } = suspended;
+        self.execute(function, Start::Resume { pc, depth }, exec)
+    }
+
+    /// Run a function until it returns, using `exec` to execute each instruction
+    fn execute<F>(&mut self, function: Runnable<'a>, start: Start, exec: F) -> Result<ExecutionResult, RunnerError>
+    where
+        F: for<'c> Fn(&'c [u8], usize, &mut StackFrame, &ConstantTable<'c>, Option<&mut Heap>) -> HandlerResult,
+    {
+        function.verify_setup_info()?;
+        let (maxstack, maxlocals) = function.setup_info();
+
+        // Only functions that have been interpreted from the start can yield, so are never compiled
+        let compiled = match start
+        {
+            Start::Call(_) => self.compile(&function),
+            Start::Resume { .. } => None,
+        };
 
         // Initial Frame Creation and creating the constant table from
         // information provided in the loader
@@ -46,43 +440,536 @@ impl<'a> Runner<'a>
             .initial_frame(maxlocals, maxstack)
             .ok_or(RunnerError::StackOverflow)?;
 
-        // Convert the directly parsed constant table into a usable one
-        let constant_table = self.loader.get_constant_table();
-
-        let code = entry_point.code();
-        let mut pc: usize = 0;
+        let pc = Self::enter(&mut initial_frame, start, maxlocals)?;
 
-        // Keep executing the program until a break condition is met: either a return statement or an
-        // error
-        loop
+        // Compiled code runs the whole function in one go
+        if let Some(compiled) = compiled
         {
-            let exec_result = exec_instruction(&code[pc..], &mut initial_frame, &constant_table)
-                .map_err(RunnerError::ExecutionError)?;
+            if let Some(gas) = self.gas.as_mut()
+            {
+                *gas = gas.checked_sub(1).ok_or(RunnerError::OutOfGas)?;
+            }
 
-            match exec_result
+            // There is no program counter within compiled code, so only the function is known
+            let in_function = |x| Self::execution_error(self.loader, x, &function, None);
+
+            return match compiled.call(&mut initial_frame).map_err(in_function)?
             {
-                InstructionResult::Next =>
-                {
-                    // Move to next instruction after checking validity
-                    (pc + 1 < code.len())
-                        .then(|| pc += 1)
-                        .ok_or(RunnerError::ProgramCounterOverflow)?;
-                }
-                InstructionResult::Jump(target) =>
+                InstructionResult::Return(has_value) =>
                 {
-                    // Jump to given target instruction after checking validity
-                    (target < code.len())
-                        .then(|| pc = target)
-                        .ok_or(RunnerError::ProgramCounterOverflow)?;
+                    interpreter::finish(&mut initial_frame, has_value).map_err(in_function)
                 }
-                InstructionResult::Return(_) =>
+                InstructionResult::Halt(code) => Ok(ExecutionResult::Halted(code)),
+                // Compiled code has to finish by returning, rather than carrying on to another instruction
+                InstructionResult::Next(_)
+                | InstructionResult::Jump(_)
+                | InstructionResult::Yield
+                | InstructionResult::Breakpoint
+                | InstructionResult::Print(..) => Err(RunnerError::ProgramCounterOverflow),
+            };
+        }
+
+        let mut state = InterpreterState {
+            pc,
+            function: &function,
+            frame: initial_frame,
+            heap: self.heap.as_deref_mut(),
+            gas: self.gas,
+            trace: self.trace,
+            breakpoint: self.breakpoint_handler.as_deref(),
+            quiet: self.quiet,
+            #[cfg(feature = "profiling")]
+            profiler: &mut self.profiler,
+        };
+        let result = interpreter::interpret(&mut state, self.loader.get_constant_table(), exec);
+        let (pc, depth) = (state.pc, state.frame.depth());
+        self.gas = state.gas;
+
+        match result
+        {
+            Ok(ExecutionResult::Yielded) =>
+            {
+                self.suspended = Some(Suspended { function, pc, depth });
+
+                Ok(ExecutionResult::Yielded)
+            }
+            // The interpreter leaves the program counter at whatever failed, so that it can be found in the source
+            Err(RunnerError::ExecutionError { error, .. }) =>
+            {
+                Err(Self::execution_error(self.loader, error, &function, Some(pc)))
+            }
+            other => other,
+        }
+    }
+
+    /// Set up a function's frame to start running it, giving back where in its code to start from
+    fn enter(frame: &mut StackFrame, start: Start, maxlocals: usize) -> Result<usize, RunnerError>
+    {
+        match start
+        {
+            Start::Call(args) =>
+            {
+                // The arguments have to fit within the function's locals
+                guard!(args.len() <= maxlocals, RunnerError::TooManyArguments);
+
+                // Any locals not given an argument start as zero, rather than whatever was left in the stack
+                let locals = args.iter().copied().chain(iter::repeat(0));
+                for (index, value) in (0..maxlocals).zip(locals)
                 {
-                    // Return the required value here?
-                    break;
+                    frame.set_local(index, value);
                 }
+
+                Ok(0)
+            }
+            Start::Resume { pc, depth } =>
+            {
+                // The frame's contents have been left untouched on the stack since it yielded
+                guard!(frame.restore_depth(depth), RunnerError::StackOverflow);
+                Ok(pc)
             }
         }
+    }
 
-        Ok(())
+    /// Whether `pc` is the start of an instruction that comes straight after a yield.
+    ///
+    /// The code is decoded from the start, so that an offset part way through an instruction is never mistaken
+    /// for one.
+    fn follows_yield(code: &[u8], pc: usize) -> bool
+    {
+        let mut offset = 0;
+        let mut previous = None;
+        while offset < pc
+        {
+            let Some(opcode) = code.get(offset).and_then(|&x| Opcode::try_from(x).ok())
+            else
+            {
+                return false;
+            };
+
+            previous = Some(opcode);
+            offset += 1 + usize::from(opcode.param_count());
+        }
+
+        offset == pc && previous == Some(Opcode::Yield)
+    }
+
+    /// Compile a function's code with the JIT, if there is one and it is able to.
+    ///
+    /// Each function is only given to the JIT the first time it is called, after which the result is reused.
+    fn compile(&mut self, function: &Runnable) -> Option<CompiledFunction>
+    {
+        let jit = self.jit.as_mut()?;
+        *self.compiled.entry(function.code_offset()).or_insert_with(|| {
+            let code = function.code();
+            jit.can_jit(code).then(|| jit.compile(code).ok()).flatten()
+        })
+    }
+
+    /// Attach the function that was running to an error, along with the current instruction and where it came
+    /// from in the source, if they are known
+    fn execution_error(loader: &Loader, error: ExecutionError, function: &Runnable, pc: Option<usize>) -> RunnerError
+    {
+        RunnerError::ExecutionError {
+            error,
+            pc,
+            function: Some(function.name().to_owned()),
+            location: pc.and_then(|x| loader.source_location(function.code_offset() + x).copied()),
+            source_path: loader.source_path().map(str::to_owned),
+        }
+    }
+}
+
+/// Configures a `Runner` before it is created.
+///
+/// A stack and a loader must both be given before the runner can be built.
+#[derive(Default)]
+pub struct RunnerBuilder<'a>
+{
+    stack: Option<&'a mut Stack>,
+    loader: Option<&'a Loader>,
+    max_call_depth: Option<usize>,
+    gas: Option<u64>,
+    trace: bool,
+    quiet: bool,
+    jit: Option<Box<dyn JitCompiler>>,
+    heap: Option<&'a mut Heap>,
+}
+
+impl<'a> RunnerBuilder<'a>
+{
+    #[must_use]
+    pub fn new() -> Self
+    {
+        Self::default()
+    }
+
+    /// The stack the runner executes on
+    #[must_use]
+    pub fn stack(mut self, stack: &'a mut Stack) -> Self
+    {
+        self.stack = Some(stack);
+        self
+    }
+
+    /// The loaded file to run functions from
+    #[must_use]
+    pub const fn loader(mut self, loader: &'a Loader) -> Self
+    {
+        self.loader = Some(loader);
+        self
+    }
+
+    /// Limit how deeply calls between functions can nest
+    #[must_use]
+    pub const fn max_call_depth(mut self, depth: usize) -> Self
+    {
+        self.max_call_depth = Some(depth);
+        self
+    }
+
+    /// Limit the total number of instructions the runner will execute, same as `Runner::with_gas_limit`
+    #[must_use]
+    pub const fn gas(mut self, gas: u64) -> Self
+    {
+        self.gas = Some(gas);
+        self
+    }
+
+    /// Print every instruction to stderr as it is run.
+    ///
+    /// This does nothing without the `std` feature.
+    #[must_use]
+    pub const fn trace(mut self, trace: bool) -> Self
+    {
+        self.trace = trace;
+        self
+    }
+
+    /// Stop print instructions from writing anything, same as `Runner::with_quiet`
+    #[must_use]
+    pub const fn quiet(mut self, quiet: bool) -> Self
+    {
+        self.quiet = quiet;
+        self
+    }
+
+    /// Compile functions with `jit` where possible, same as `Runner::with_jit`
+    #[must_use]
+    pub fn jit(mut self, jit: Box<dyn JitCompiler>) -> Self
+    {
+        self.jit = Some(jit);
+        self
+    }
+
+    /// The heap for heap instructions to use, same as `Runner::with_heap`
+    #[must_use]
+    pub fn heap(mut self, heap: &'a mut Heap) -> Self
+    {
+        self.heap = Some(heap);
+        self
+    }
+
+    /// Create the runner, if both a stack and a loader have been given
+    #[must_use]
+    pub fn build(mut self) -> Option<Runner<'a>>
+    {
+        let stack = self.stack.take()?;
+        let loader = self.loader.take()?;
+        Some(self.build_with(stack, loader))
+    }
+
+    /// Create the runner from the given stack and loader, ignoring any given to the builder.
+    ///
+    /// This is the only place a runner is put together, so `Runner::new` goes through here as well.
+    fn build_with(self, stack: &'a mut Stack, loader: &'a Loader) -> Runner<'a>
+    {
+        Runner {
+            stack,
+            loader,
+            gas: self.gas,
+            max_call_depth: self.max_call_depth.unwrap_or(Runner::DEFAULT_MAX_CALL_DEPTH),
+            trace: self.trace,
+            quiet: self.quiet,
+            #[cfg(feature = "profiling")]
+            profiler: OpcodeProfiler::new(),
+            jit: self.jit,
+            compiled: BTreeMap::new(),
+            suspended: None,
+            heap: self.heap,
+            breakpoint_handler: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod runner_tests
+{
+    use alloc::rc::Rc;
+    use core::cell::Cell;
+
+    use super::*;
+    use crate::engine::jit::{JitError, NoOpJit};
+    use crate::engine::opcodes::Opcode;
+    use crate::loader::parser::MAGIC_NUMBER;
+
+    // Two functions: "main", the entry point which does nothing, and "add", which adds its two arguments
+    fn load() -> Loader
+    {
+        let body = [
+            2,
+            0,
+            0,
+            0, // 2 constants
+            4,
+            4,
+            0,
+            0,
+            0,
+            b'm',
+            b'a',
+            b'i',
+            b'n', // String "main"
+            4,
+            3,
+            0,
+            0,
+            0,
+            b'a',
+            b'd',
+            b'd', // String "add"
+            254,
+            0,
+            0,
+            0,
+            0,
+            0,
+            1,
+            0,
+            0,
+            0, // Symbol "main", 1 byte of code
+            254,
+            1, // Start
+            254,
+            2,
+            0,
+            0, // Max stack 0
+            254,
+            3,
+            0,
+            0, // Max locals 0
+            Opcode::Ret as u8,
+            254,
+            0,
+            1,
+            0,
+            0,
+            0,
+            4,
+            0,
+            0,
+            0, // Symbol "add", 4 bytes of code
+            254,
+            2,
+            2,
+            0, // Max stack 2
+            254,
+            3,
+            2,
+            0, // Max locals 2
+            Opcode::LdArg0 as u8,
+            Opcode::LdArg1 as u8,
+            Opcode::IAdd as u8,
+            Opcode::RetVal as u8,
+        ];
+
+        let mut checked = vec![0]; // No flags
+        checked.extend_from_slice(&body);
+
+        let mut data = MAGIC_NUMBER.to_le_bytes().to_vec();
+        data.push(1);
+        data.extend_from_slice(&crc32fast::hash(&checked).to_le_bytes());
+        data.extend_from_slice(&checked);
+
+        Loader::from_bytes(&data).expect("Failed to load file")
+    }
+
+    #[test]
+    fn run_entry_point()
+    {
+        let loader = load();
+        let mut stack = Stack::new(1024);
+        assert_eq!(Runner::new(&mut stack, &loader).run(), Ok(ExecutionResult::ReturnVoid));
+    }
+
+    #[test]
+    fn run_function()
+    {
+        let loader = load();
+        let mut stack = Stack::new(1024);
+        let mut runner = Runner::new(&mut stack, &loader);
+
+        assert!(matches!(runner.run_function("add", &[3, 4]), Ok(Some(7))));
+        assert!(matches!(runner.run_function("main", &[]), Ok(None)));
+    }
+
+    #[test]
+    fn interpret_without_runner()
+    {
+        let loader = load();
+        let function = loader.get_function_by_name("add").expect("Missing function");
+        let (maxstack, maxlocals) = function.setup_info();
+
+        let mut stack = Stack::new(1024);
+        let mut frame = stack.initial_frame(maxlocals, maxstack).expect("Stack overflow");
+        frame.set_local(0, 3);
+        frame.set_local(1, 4);
+
+        #[cfg(feature = "profiling")]
+        let mut profiler = OpcodeProfiler::default();
+        let mut state = InterpreterState {
+            pc: 0,
+            function: &function,
+            frame,
+            heap: None,
+            gas: Some(10),
+            trace: false,
+            breakpoint: None,
+            quiet: false,
+            #[cfg(feature = "profiling")]
+            profiler: &mut profiler,
+        };
+
+        let result = interpreter::interpret(&mut state, loader.get_constant_table(), exec_instruction);
+        assert_eq!(result, Ok(ExecutionResult::ReturnValue(7)));
+        assert_eq!(state.gas, Some(6));
+    }
+
+    #[test]
+    fn run_function_errors()
+    {
+        let loader = load();
+        let mut stack = Stack::new(1024);
+        let mut runner = Runner::new(&mut stack, &loader);
+
+        assert!(matches!(
+            runner.run_function("missing", &[]),
+            Err(RunnerError::FunctionNotFound)
+        ));
+        assert!(matches!(
+            runner.run_function("add", &[1, 2, 3]),
+            Err(RunnerError::TooManyArguments)
+        ));
+    }
+
+    #[test]
+    fn builder()
+    {
+        let loader = load();
+        let mut stack = Stack::new(1024);
+
+        // Both a stack and a loader are required
+        assert!(RunnerBuilder::new().loader(&loader).build().is_none());
+        assert!(RunnerBuilder::new().stack(&mut stack).build().is_none());
+
+        let mut runner = RunnerBuilder::new()
+            .stack(&mut stack)
+            .loader(&loader)
+            .gas(10)
+            .max_call_depth(8)
+            .trace(true)
+            .build()
+            .unwrap();
+
+        assert_eq!(runner.max_call_depth(), 8);
+        assert!(matches!(runner.run_function("add", &[3, 4]), Ok(Some(7))));
+        assert_eq!(runner.remaining_gas(), Some(6));
+    }
+
+    /// Compiles every function into one that returns 42
+    struct AnswerJit;
+
+    impl JitCompiler for AnswerJit
+    {
+        fn can_jit(&self, _code: &[u8]) -> bool
+        {
+            true
+        }
+
+        fn compile(&mut self, _code: &[u8]) -> Result<CompiledFunction, JitError>
+        {
+            Ok(CompiledFunction::new(|frame| {
+                let frame = unsafe { &mut *frame };
+                frame.push(42);
+                Ok(InstructionResult::Return(true))
+            }))
+        }
+    }
+
+    #[test]
+    fn jit()
+    {
+        let loader = load();
+        let mut stack = Stack::new(1024);
+
+        let mut runner = Runner::new(&mut stack, &loader).with_jit(Box::new(AnswerJit));
+        assert_eq!(runner.run_function("add", &[3, 4]), Ok(Some(42)));
+
+        // Anything that isn't compiled is still interpreted
+        let mut runner = RunnerBuilder::new()
+            .stack(&mut stack)
+            .loader(&loader)
+            .jit(Box::new(NoOpJit))
+            .build()
+            .unwrap();
+        assert_eq!(runner.run_function("add", &[3, 4]), Ok(Some(7)));
+    }
+
+    /// Compiles every function the same as `AnswerJit`, counting how many times it is asked to
+    struct CountingJit(Rc<Cell<usize>>);
+
+    impl JitCompiler for CountingJit
+    {
+        fn can_jit(&self, _code: &[u8]) -> bool
+        {
+            true
+        }
+
+        fn compile(&mut self, code: &[u8]) -> Result<CompiledFunction, JitError>
+        {
+            self.0.set(self.0.get() + 1);
+            AnswerJit.compile(code)
+        }
+    }
+
+    #[test]
+    fn jit_compiles_once()
+    {
+        let loader = load();
+        let mut stack = Stack::new(1024);
+        let count = Rc::new(Cell::new(0));
+
+        let mut runner = Runner::new(&mut stack, &loader).with_jit(Box::new(CountingJit(Rc::clone(&count))));
+        for _ in 0..3
+        {
+            assert_eq!(runner.run_function("add", &[3, 4]), Ok(Some(42)));
+        }
+
+        assert_eq!(count.get(), 1);
+    }
+
+    #[test]
+    fn jit_gas()
+    {
+        let loader = load();
+        let mut stack = Stack::new(1024);
+
+        // Each compiled call costs one unit of gas
+        let mut runner = Runner::new(&mut stack, &loader)
+            .with_jit(Box::new(AnswerJit))
+            .with_gas_limit(2);
+        assert_eq!(runner.run_function("add", &[3, 4]), Ok(Some(42)));
+        assert_eq!(runner.remaining_gas(), Some(1));
+        assert_eq!(runner.run_function("add", &[3, 4]), Ok(Some(42)));
+        assert_eq!(runner.run_function("add", &[3, 4]), Err(RunnerError::OutOfGas));
     }
 }
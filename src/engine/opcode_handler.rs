@@ -1,6 +1,7 @@
-use std::ops::{
-    Add as _, BitAnd as _, BitOr as _, BitXor as _, Div as _, Mul as _, Neg as _, Not as _, Rem as _, Shl as _,
-    Shr as _, Sub as _,
+use core::{
+    fmt::{self, Display, Formatter},
+    ops::{Add as _, BitAnd as _, BitOr as _, BitXor as _, Div as _, Mul as _, Neg as _, Not as _, Rem as _, Sub as _},
+    ptr::{self, NonNull},
 };
 
 use crate::{
@@ -9,7 +10,12 @@ use crate::{
         stack::stackable::Stackable,
         stack::{Stack, StackEntry, StackFrame, convert::StackableConvert},
     },
-    loader::constant_table::{ConstantTable, ConstantTableIndex},
+    guard,
+    loader::{
+        constant_table::{ConstantPushError, ConstantTable, ConstantTableIndex},
+        parser::LocalType,
+    },
+    memory::heap::Heap,
 };
 
 /// Contains information given to each instruction handler
@@ -17,25 +23,30 @@ use crate::{
 /// ### Fields
 /// `opcode` - The numerical value of the opcode
 ///
+/// `pc` - The offset of the opcode within the function's bytecode
+///
 /// `params` - A slice of the parameters passed into this opcode
 ///
 /// `frame` - A reference to the current stack frame
 ///
 /// `constants` - A reference to the constant table
 ///
+/// `heap` - The heap that values can be loaded from and stored to, if there is one
+///
 /// ### Note
 /// The lifetime parameters of this struct reflect the expected lifetimes of the references:
 /// the `params` slice will have the same lifetime as the contents of the constant table (`'a`),
 /// as they will both be stored within the loader's metaspace. The reference to the stack frame
 /// and the reference to the constant table will both be the same as they are both
 /// constructed in the loader
-#[derive(Debug)]
 struct HandlerInputInfo<'a, 'b, 'c>
 {
     opcode: u8,
+    pc: usize,
     params: &'a [u8],
     frame: &'b mut StackFrame<'c>,
     constants: &'b ConstantTable<'a>,
+    heap: Option<&'b mut Heap>,
 }
 
 // Bunch of helper functions to make things a bit cleaner
@@ -51,6 +62,17 @@ impl HandlerInputInfo<'_, '_, '_>
         self.frame.push(val).then_some(()).ok_or(ExecutionError::StackOverflow)
     }
 
+    /// Push a value, keeping track of its type
+    pub fn stack_push_typed<T>(&mut self, val: T) -> Result<(), ExecutionError>
+    where
+        T: Stackable,
+    {
+        self.frame
+            .push_typed(val)
+            .then_some(())
+            .ok_or(ExecutionError::StackOverflow)
+    }
+
     pub fn local_get(&mut self, index: u8) -> Result<StackEntry, ExecutionError>
     {
         self.frame
@@ -75,6 +97,34 @@ impl HandlerInputInfo<'_, '_, '_>
             .ok_or(ExecutionError::MissingParams)
     }
 
+    /// Get the target of a jump, given as a signed 2 byte offset from the start of the instruction
+    fn jump_target(&self) -> Result<usize, ExecutionError>
+    {
+        let bytes = self
+            .pull_params(size_of::<i16>())?
+            .first_chunk()
+            .ok_or(ExecutionError::MissingParams)?;
+
+        self.pc
+            .checked_add_signed(isize::from(<i16>::from_le_bytes(*bytes)))
+            .ok_or(ExecutionError::IllegalParam)
+    }
+
+    /// Move on to whatever follows this instruction and its parameters
+    fn next(&self) -> InstructionResult
+    {
+        let params = Opcode::INSTRUCTIONS
+            .get(usize::from(self.opcode))
+            .map_or(0, |x| usize::from(x.param_count()));
+
+        InstructionResult::Next(1 + params)
+    }
+
+    fn heap(&mut self) -> Result<&mut Heap, ExecutionError>
+    {
+        self.heap.as_deref_mut().ok_or(ExecutionError::MissingHeap)
+    }
+
     fn stack_pop_many<const N: usize>(&mut self) -> Result<[u64; N], ExecutionError>
     {
         let mut values = [0; N];
@@ -87,34 +137,29 @@ impl HandlerInputInfo<'_, '_, '_>
     }
 }
 
-/// Information about a handler for a given instruction
-///
-/// ## Fields
-/// `opcode` - The opcode this handler is responsible for. This is mainly used for validation
-///
-/// `param_count` - The number of bytes this handler takes as parameters
-///
-/// `handler` - The function that handles the given opcode
-///
-/// ## Note
-/// This type should remain a copy type
-#[derive(Clone, Copy)]
-struct HandlerInfo<'a>
-{
-    opcode: Opcode,
-    param_count: u8,
-    handler: &'a dyn Fn(&mut HandlerInputInfo) -> ExecutionResult,
-}
-
 #[derive(Clone, Copy)]
 pub enum InstructionResult
 {
-    Next,
+    Next(usize), // Move on by the given number of bytes: the instruction's opcode and its parameters
     Jump(usize),
     Return(bool),
+    Halt(u8),                       // Stop everything that is running, with an exit code
+    Yield,                          // Pause everything that is running, so that it can be carried on with later
+    Breakpoint,                     // Hand over to the debugger, if there is one, before moving on
+    Print(StackEntry, PrintFormat), // Write out a value, before moving on
 }
 
-#[derive(Debug, Clone, Copy)]
+/// How a print instruction writes out its value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrintFormat
+{
+    Integer,
+    Float4,
+    Float8,
+    Hex,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ExecutionError
 {
     OpcodeNotFound,
@@ -124,52 +169,302 @@ pub enum ExecutionError
     EmptyStack,
     StackOverflow,
     IndexOutOfBounds,
+    DivideByZero,
+    MissingHeap,     // A heap instruction was run without a heap to use
+    InvalidAddress,  // An address that doesn't lie within the heap
+    OutOfMemory,     // The heap had no room left for an allocation
+    NullDereference, // A null address was used to access the heap
+    AssertionFailed
+    {
+        pc: usize,
+    },
 }
 
-type ExecutionResult = Result<InstructionResult, ExecutionError>;
+impl Display for ExecutionError
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result
+    {
+        match *self
+        {
+            Self::OpcodeNotFound => write!(f, "no instruction to execute"),
+            Self::IllegalOpcode => write!(f, "illegal opcode"),
+            Self::MissingParams => write!(f, "missing parameters"),
+            Self::IllegalParam => write!(f, "illegal parameter"),
+            Self::EmptyStack => write!(f, "stack underflow"),
+            Self::StackOverflow => write!(f, "stack overflow"),
+            Self::IndexOutOfBounds => write!(f, "index out of bounds"),
+            Self::DivideByZero => write!(f, "divide by zero"),
+            Self::MissingHeap => write!(f, "no heap"),
+            Self::InvalidAddress => write!(f, "invalid address"),
+            Self::OutOfMemory => write!(f, "out of memory"),
+            Self::NullDereference => write!(f, "null dereference"),
+            Self::AssertionFailed { .. } => write!(f, "assertion failed"),
+        }
+    }
+}
+
+pub type HandlerResult = Result<InstructionResult, ExecutionError>;
 
 /// Executes the next instruction found from the sequence of bytes.
 ///
-/// Takes the current stream of bytcode, the current stack frame and the
-/// constant table associated with this bytecode stream.
-/// It is expected that the first byte in the `bytecode` slice will be
-/// the opcode, and then the remaining bytes can be whatever is next in the stream.
-#[expect(
-    clippy::panic_in_result_fn,
-    reason = "If this invariant check fails, the entire config is malformed"
-)]
+/// Takes the current function's bytecode, the offset of the instruction to execute
+/// within it, the current stack frame, the constant table associated with this
+/// bytecode stream, and the heap if there is one.
+/// It is expected that the byte at `pc` will be the opcode, and then the remaining
+/// bytes can be whatever is next in the stream.
+///
+/// ### Errors
+/// If there is no valid instruction at `pc`, its operands are missing, or the instruction itself fails.
 pub fn exec_instruction<'a>(
     bytecode: &'a [u8],
+    pc: usize,
     frame: &mut StackFrame,
     constants: &ConstantTable<'a>,
-) -> ExecutionResult
+    heap: Option<&mut Heap>,
+) -> HandlerResult
 {
     // Get the bytecode out of the stream. As this is "user input", it is critical
     // at all stages to check whether there are actually enough values in the stream
     // to meet expectations
-    let (&opcode, operands) = bytecode.split_first().ok_or(ExecutionError::OpcodeNotFound)?;
-    let handler_info = HANDLERS.get(opcode as usize).ok_or(ExecutionError::IllegalOpcode)?;
+    let (&byte, operands) = bytecode
+        .get(pc..)
+        .and_then(<[u8]>::split_first)
+        .ok_or(ExecutionError::OpcodeNotFound)?;
+    let opcode = Opcode::try_from(byte).map_err(|_| ExecutionError::IllegalOpcode)?;
 
-    if operands.len() < handler_info.param_count as usize
-    {
-        return Err(ExecutionError::MissingParams);
-    }
+    dispatch(
+        opcode,
+        &mut HandlerInputInfo {
+            opcode: byte,
+            pc,
+            params: operands,
+            frame,
+            constants,
+            heap,
+        },
+    )
+}
 
-    // If this assertion fails, this means that the HANDLERS table has been malformed
-    // and found handler doesn't match the opcode.
-    assert!(
-        opcode == handler_info.opcode as u8,
-        "HANDLERS Array invalid: misaligned opcode"
-    );
+/// Executes the next instruction, in the same way as `exec_instruction`.
+///
+/// Rather than matching on the opcode, this jumps straight to its handler through a table of
+/// function pointers indexed by the opcode's value.
+///
+/// ### Errors
+/// In the same way as `exec_instruction`.
+#[cfg(feature = "threaded-dispatch")]
+pub fn exec_instruction_threaded<'a>(
+    bytecode: &'a [u8],
+    pc: usize,
+    frame: &mut StackFrame,
+    constants: &ConstantTable<'a>,
+    heap: Option<&mut Heap>,
+) -> HandlerResult
+{
+    let (&byte, operands) = bytecode
+        .get(pc..)
+        .and_then(<[u8]>::split_first)
+        .ok_or(ExecutionError::OpcodeNotFound)?;
 
-    (handler_info.handler)(&mut HandlerInputInfo {
-        opcode,
+    // The table has an entry for every possible byte, so can't be indexed out of bounds
+    let handler = unsafe { THREADED_HANDLERS.get_unchecked(usize::from(byte)) };
+
+    handler(&mut HandlerInputInfo {
+        opcode: byte,
+        pc,
         params: operands,
         frame,
         constants,
+        heap,
     })
 }
 
+/// Runs the handler for the given opcode
+#[inline]
+#[expect(clippy::too_many_lines, reason = "Every opcode needs its own arm")]
+fn dispatch(opcode: Opcode, input: &mut HandlerInputInfo) -> HandlerResult
+{
+    // Any handler that takes parameters pulls exactly `param_count` of them out of the stream itself,
+    // failing if there aren't enough
+    match opcode
+    {
+        Opcode::Nop => Ok(input.next()),
+        // The padding is never read, but still has to be there
+        Opcode::NopW | Opcode::NopT | Opcode::NopD => input
+            .pull_params(usize::from(opcode.param_count()))
+            .map(|_| input.next()),
+        Opcode::IConst0 | Opcode::PushNull => push_numeric(input, 0_u64), // Null is address 0
+        Opcode::IConst1 => push_numeric(input, 1_u64),
+        Opcode::IConst2 => push_numeric(input, 2_u64),
+        Opcode::IConst3 => push_numeric(input, 3_u64),
+        Opcode::F4Const0 => push_numeric(input, 0.0_f32),
+        Opcode::F4Const1 => push_numeric(input, 1.0_f32),
+        Opcode::F8Const0 => push_numeric(input, 0.0_f64),
+        Opcode::F8Const1 => push_numeric(input, 1.0_f64),
+        Opcode::IConst => push_bytes(input, 1),
+        Opcode::IConstW => push_bytes(input, 2),
+        Opcode::Const => push_constant(input),
+        Opcode::LdArg0 => load_local(input, 0),
+        Opcode::LdArg1 => load_local(input, 1),
+        Opcode::LdArg2 => load_local(input, 2),
+        Opcode::LdArg3 => load_local(input, 3),
+        Opcode::LdArg => load_local(input, input.pull_params(1)?[0]),
+        Opcode::StArg0 => store_local(input, 0),
+        Opcode::StArg1 => store_local(input, 1),
+        Opcode::StArg2 => store_local(input, 2),
+        Opcode::StArg3 => store_local(input, 3),
+        Opcode::StArg => store_local(input, input.pull_params(1)?[0]),
+        Opcode::Pop => pop(input),
+        Opcode::Dup => dup(input),
+        Opcode::Swap => swap(input),
+        Opcode::Ret => Ok(InstructionResult::Return(false)),
+        Opcode::RetVal => Ok(InstructionResult::Return(true)),
+        Opcode::IAdd => binop(input, <u64>::wrapping_add),
+        Opcode::F4Add => binop(input, <f32>::add),
+        Opcode::F8Add => binop(input, <f64>::add),
+        Opcode::ISub => binop(input, <u64>::wrapping_sub),
+        Opcode::F4Sub => binop(input, <f32>::sub),
+        Opcode::F8Sub => binop(input, <f64>::sub),
+        Opcode::IMul => binop(input, <u64>::wrapping_mul),
+        Opcode::F4Mul => binop(input, <f32>::mul),
+        Opcode::F8Mul => binop(input, <f64>::mul),
+        Opcode::IDiv => checked_binop(input, <u64>::checked_div),
+        Opcode::F4Div => binop(input, <f32>::div),
+        Opcode::F8Div => binop(input, <f64>::div),
+        Opcode::IRem => checked_binop(input, <u64>::checked_rem),
+        Opcode::F4Rem => binop(input, <f32>::rem),
+        Opcode::F8Rem => binop(input, <f64>::rem),
+        Opcode::INeg => unaryop(input, <i64>::wrapping_neg),
+        Opcode::F4Neg => unaryop(input, <f32>::neg),
+        Opcode::F8Neg => unaryop(input, <f64>::neg),
+        Opcode::Shl => binop(input, |x: u64, y: u64| x << (y & 63)), // Only the bottom 6 bits of the shift are used
+        Opcode::Shr => binop(input, |x: u64, y: u64| x >> (y & 63)),
+        Opcode::AShr => binop(input, |x: i64, y: i64| x >> (y & 63)),
+        Opcode::And => binop(input, <u64>::bitand),
+        Opcode::Or => binop(input, <u64>::bitor),
+        Opcode::Xor => binop(input, <u64>::bitxor),
+        Opcode::Not => unaryop(input, <u64>::not),
+        Opcode::IConvertF4 => convert::<i64, f32>(input), // Using i64 to avoid sign loss
+        Opcode::IConvertF8 => convert::<i64, f64>(input),
+        Opcode::F4ConvertI => convert::<f32, i64>(input),
+        Opcode::F4ConvertF8 => convert::<f32, f64>(input),
+        Opcode::F8ConvertI => convert::<f64, i64>(input),
+        Opcode::F8ConvertF4 => convert::<f64, f32>(input),
+        Opcode::Assert => assert_nonzero(input),
+        Opcode::Jmp => jump(input),
+        Opcode::JmpIfTrue => jump_if(input, true),
+        Opcode::JmpIfFalse => jump_if(input, false),
+        Opcode::ICmpEq => cmpop(input, <u64>::eq),
+        Opcode::ICmpNe => cmpop(input, <u64>::ne),
+        Opcode::ICmpLt => cmpop(input, <u64>::lt),
+        Opcode::ICmpGt => cmpop(input, <u64>::gt),
+        Opcode::ICmpLe => cmpop(input, <u64>::le),
+        Opcode::ICmpGe => cmpop(input, <u64>::ge),
+        Opcode::IConstAdd => iconst_add(input),
+        Opcode::Halt => Ok(InstructionResult::Halt(input.pull_params(1)?[0])),
+        Opcode::Yield => Ok(InstructionResult::Yield),
+        Opcode::LoadHeap => load_heap(input),
+        Opcode::StoreHeap => store_heap(input),
+        Opcode::New => new(input),
+        Opcode::NewArray => new_array(input),
+        Opcode::ArrayLen => array_len(input),
+        Opcode::ArrayLoad => array_load(input),
+        Opcode::ArrayStore => array_store(input),
+        Opcode::NullCheck => null_check(input),
+        Opcode::ISCmpEq => cmpop(input, <i64>::eq),
+        Opcode::ISCmpNe => cmpop(input, <i64>::ne),
+        Opcode::ISCmpLt => cmpop(input, <i64>::lt),
+        Opcode::ISCmpGt => cmpop(input, <i64>::gt),
+        Opcode::ISCmpLe => cmpop(input, <i64>::le),
+        Opcode::ISCmpGe => cmpop(input, <i64>::ge),
+        Opcode::I4Extend => unaryop(input, |x: u64| x & 0xFFFF_FFFF),
+        Opcode::I4SignExtend => unaryop(input, |x: i64| (x << 32) >> 32), // Moves bit 31 into the sign bit, and back again
+        Opcode::Breakpoint => Ok(InstructionResult::Breakpoint),
+        Opcode::IConstD => push_bytes(input, 4),
+        Opcode::IConstL => push_bytes(input, 8),
+        Opcode::Print => print(input),
+        Opcode::TypeOf => type_of(input),
+        Opcode::CmpSwap => compare_swap(input),
+        Opcode::F4ToU32 => convert::<f32, u32>(input),
+        Opcode::F8ToU64 => convert::<f64, u64>(input),
+        Opcode::Swap2 => swap_pairs(input),
+        // Directives are metadata, so should never be reached while executing
+        Opcode::Directive | Opcode::Unimplemented => Err(ExecutionError::IllegalOpcode),
+    }
+}
+
+#[cfg(feature = "threaded-dispatch")]
+type Handler = fn(&mut HandlerInputInfo) -> HandlerResult;
+
+/// The handler for a single opcode. As the opcode is known at compile time, the match in `dispatch`
+/// is optimised away, leaving just the handler itself.
+#[cfg(feature = "threaded-dispatch")]
+fn threaded_handler<const OPCODE: u8>(input: &mut HandlerInputInfo) -> HandlerResult
+{
+    dispatch(const { Opcode::INSTRUCTIONS[OPCODE as usize] }, input)
+}
+
+/// Handles every byte that isn't an instruction
+#[cfg(feature = "threaded-dispatch")]
+fn illegal_opcode(_: &mut HandlerInputInfo) -> HandlerResult
+{
+    Err(ExecutionError::IllegalOpcode)
+}
+
+/// Whether a handler is given for every instruction, in the same order as `Opcode::INSTRUCTIONS`
+#[cfg(feature = "threaded-dispatch")]
+const fn verify_handlers(opcodes: &[usize]) -> bool
+{
+    if opcodes.len() != Opcode::INSTRUCTIONS.len()
+    {
+        return false;
+    }
+
+    let mut index = 0;
+    while index < opcodes.len()
+    {
+        if opcodes[index] != Opcode::INSTRUCTIONS[index] as usize
+        {
+            return false;
+        }
+        index += 1;
+    }
+
+    true
+}
+
+#[cfg(feature = "threaded-dispatch")]
+macro_rules! threaded_handlers {
+    ($($opcode:literal),+) => {{
+        // Every instruction needs to be given a handler, and a duplicate would leave another without one
+        assert!(
+            verify_handlers(&[$($opcode),+]),
+            "THREADED_HANDLERS invalid: missing or misaligned instructions"
+        );
+
+        let mut handlers = [illegal_opcode as Handler; u8::MAX as usize + 1];
+        $(
+            handlers[$opcode] = threaded_handler::<$opcode>;
+        )+
+        handlers
+    }};
+}
+
+#[cfg(feature = "threaded-dispatch")]
+#[rustfmt::skip]
+const THREADED_HANDLERS: [Handler; u8::MAX as usize + 1] = threaded_handlers!(
+    0,  1,  2,  3,  4,  5,  6,  7,  8,  9,
+    10, 11, 12, 13, 14, 15, 16, 17, 18, 19,
+    20, 21, 22, 23, 24, 25, 26, 27, 28, 29,
+    30, 31, 32, 33, 34, 35, 36, 37, 38, 39,
+    40, 41, 42, 43, 44, 45, 46, 47, 48, 49,
+    50, 51, 52, 53, 54, 55, 56, 57, 58, 59,
+    60, 61, 62, 63, 64, 65, 66, 67, 68, 69,
+    70, 71, 72, 73, 74, 75, 76, 77, 78, 79,
+    80, 81, 82, 83, 84, 85, 86, 87, 88, 89,
+    90, 91, 92, 93, 94, 95, 96, 97, 98, 99
+);
+
 /*
  * ******************************************************************************
  *                                  HANDLERS
@@ -182,34 +477,35 @@ pub fn exec_instruction<'a>(
 ///
 /// It is expected that any other numeric type (such as `f32` of `f64`) must be converted
 /// into a `u64` format. This behaviour is defined in the `Stackable` trait.
-fn push_numeric<T>(input: &mut HandlerInputInfo, value: T) -> ExecutionResult
+fn push_numeric<T>(input: &mut HandlerInputInfo, value: T) -> HandlerResult
 where
     T: Stackable,
 {
-    input.stack_push(value.into_entry()).map(|()| InstructionResult::Next)
+    input.stack_push_typed(value).map(|()| input.next())
 }
 
-/// Push bytes found from parameters onto the stack
+/// Push `count` bytes found from parameters onto the stack
 ///
-/// The number of bytes must be less than `Stack::ENTRY_SIZE`
-fn push_bytes(input: &mut HandlerInputInfo) -> ExecutionResult
+/// The number of bytes must be no more than `Stack::ENTRY_SIZE`
+fn push_bytes(input: &mut HandlerInputInfo, count: usize) -> HandlerResult
 {
     // Ensures that the number of bytes provided will actually fit
     // within a stack entry
-    if input.params.len() <= Stack::ENTRY_SIZE
+    if count > Stack::ENTRY_SIZE
     {
         return Err(ExecutionError::IllegalParam);
     }
 
+    let params = input.pull_params(count)?;
     let mut bytes = [0; Stack::ENTRY_SIZE]; // This is set to the stack entry size.
-    bytes[0..(input.params.len())].copy_from_slice(input.params);
+    bytes[0..count].copy_from_slice(params);
 
     // Defer to just pushing a normal numeric value
     push_numeric(input, <StackEntry>::from_le_bytes(bytes))
 }
 
 /// Gets a constant from the constant table and pushes it to the stack.
-fn push_constant(input: &mut HandlerInputInfo) -> ExecutionResult
+fn push_constant(input: &mut HandlerInputInfo) -> HandlerResult
 {
     // Construct the constant table index from the given parameters.
     let bytes = input
@@ -224,388 +520,705 @@ fn push_constant(input: &mut HandlerInputInfo) -> ExecutionResult
     input
         .constants
         .push_entry(input.frame, index)
-        .ok_or(ExecutionError::IndexOutOfBounds)?
-        .then_some(InstructionResult::Next)
-        .ok_or(ExecutionError::StackOverflow)
+        .map(|()| input.next())
+        .map_err(|x| match x
+        {
+            ConstantPushError::IndexOutOfBounds => ExecutionError::IndexOutOfBounds,
+            ConstantPushError::StackOverflow => ExecutionError::StackOverflow,
+        })
 }
 
 /// Pops a value off the stack, explicitly discarding it
 ///
 /// This should only be used to remove redundant values off the stack,
 /// as it throws away whatever the value it found was.
-fn pop(input: &mut HandlerInputInfo) -> ExecutionResult
+fn pop(input: &mut HandlerInputInfo) -> HandlerResult
 {
-    input.stack_pop().map(|_| InstructionResult::Next) // Discard value
+    input.stack_pop().map(|_| input.next()) // Discard value
 }
 
-/// Duplicates the value on top of the stack.
-fn dup(input: &mut HandlerInputInfo) -> ExecutionResult
+/// Duplicates the value on top of the stack, along with its type.
+fn dup(input: &mut HandlerInputInfo) -> HandlerResult
 {
-    let value = input.frame.peek().ok_or(ExecutionError::EmptyStack)?;
-    push_numeric(input, *value)
+    let value = *input.frame.peek().ok_or(ExecutionError::EmptyStack)?;
+    let value_type = input.frame.peek_type().ok_or(ExecutionError::EmptyStack)?;
+
+    input
+        .frame
+        .push_as(value, value_type)
+        .then(|| input.next())
+        .ok_or(ExecutionError::StackOverflow)
 }
 
-/// Swaps the top 2 stack values
-fn swap(input: &mut HandlerInputInfo) -> ExecutionResult
+/// Swaps the top 2 stack values, along with their types
+fn swap(input: &mut HandlerInputInfo) -> HandlerResult
 {
-    let value1 = input.stack_pop()?;
-    let value2 = input.stack_pop()?;
+    let (value1, type1) = input.frame.pop_typed().ok_or(ExecutionError::EmptyStack)?;
+    let (value2, type2) = input.frame.pop_typed().ok_or(ExecutionError::EmptyStack)?;
 
-    input
-        .stack_push(value1)
-        .and_then(|()| input.stack_push(value2))
-        .map(|()| InstructionResult::Next)
+    (input.frame.push_as(value1, type1) && input.frame.push_as(value2, type2))
+        .then(|| input.next())
+        .ok_or(ExecutionError::StackOverflow)
+}
+
+/// Swaps the top 2 pairs of stack values, along with their types, keeping the values within each pair in order
+fn swap_pairs(input: &mut HandlerInputInfo) -> HandlerResult
+{
+    let mut pop = || input.frame.pop_typed().ok_or(ExecutionError::EmptyStack);
+    let (top2, top1, below2, below1) = (pop()?, pop()?, pop()?, pop()?);
+
+    guard!(
+        [top1, top2, below1, below2]
+            .into_iter()
+            .all(|(value, value_type)| input.frame.push_as(value, value_type)),
+        ExecutionError::StackOverflow
+    );
+    Ok(input.next())
+}
+
+/// Pops an expected value, a replacement and a target. The replacement takes the target's place if the target
+/// is the expected value, and whether it did is pushed above it
+fn compare_swap(input: &mut HandlerInputInfo) -> HandlerResult
+{
+    let expected = input.stack_pop()?;
+    let replacement = input.frame.pop_typed().ok_or(ExecutionError::EmptyStack)?;
+    let target = input.frame.pop_typed().ok_or(ExecutionError::EmptyStack)?;
+
+    let swapped = target.0 == expected;
+    let (value, value_type) = if swapped { replacement } else { target };
+
+    guard!(input.frame.push_as(value, value_type), ExecutionError::StackOverflow);
+    push_numeric(input, StackEntry::from(swapped))
+}
+
+/// Pops a value, and pushes a tag for the type it was pushed as
+fn type_of(input: &mut HandlerInputInfo) -> HandlerResult
+{
+    let (_, value_type) = input.frame.pop_typed().ok_or(ExecutionError::EmptyStack)?;
+    push_numeric(input, StackEntry::from(value_type as u8))
 }
 
 // Basic Local Variable Handlers
 
 /// Loads a local variable at the provided index onto the stack
-fn load_local(input: &mut HandlerInputInfo, index: u8) -> ExecutionResult
+fn load_local(input: &mut HandlerInputInfo, index: u8) -> HandlerResult
 {
     let val = input.local_get(index)?;
-    input.stack_push(val).map(|()| InstructionResult::Next)
+    input.stack_push(val).map(|()| input.next())
 }
 
 /// Stores the value on top of the stack onto the stack
-fn store_local(input: &mut HandlerInputInfo, index: u8) -> ExecutionResult
+fn store_local(input: &mut HandlerInputInfo, index: u8) -> HandlerResult
 {
     let value = input.stack_pop()?;
-    input.local_set(index, value).map(|_| InstructionResult::Next)
+    input.local_set(index, value).map(|_| input.next())
+}
+
+// Heap Handlers
+
+/// Turn a value from the stack into the heap address it holds
+fn heap_address(value: StackEntry) -> Result<NonNull<u8>, ExecutionError>
+{
+    guard!(value != 0, ExecutionError::NullDereference);
+
+    let address = usize::try_from(value).map_err(|_| ExecutionError::InvalidAddress)?;
+    NonNull::new(ptr::without_provenance_mut(address)).ok_or(ExecutionError::InvalidAddress)
+}
+
+/// Fails if the address on top of the stack is null, without popping it
+fn null_check(input: &mut HandlerInputInfo) -> HandlerResult
+{
+    let &address = input.frame.peek().ok_or(ExecutionError::EmptyStack)?;
+    guard!(address != 0, ExecutionError::NullDereference);
+
+    Ok(input.next())
+}
+
+/// Allocates the number of bytes given by the parameters on the heap, and pushes their address.
+///
+/// The allocation is 8 byte aligned. Its address is only valid until the next GC, as
+/// the object may be moved or collected then.
+fn new(input: &mut HandlerInputInfo) -> HandlerResult
+{
+    let bytes = input
+        .pull_params(size_of::<u16>())?
+        .first_chunk()
+        .ok_or(ExecutionError::MissingParams)?;
+    let size = usize::from(<u16>::from_le_bytes(*bytes));
+
+    let ptr = input
+        .heap()?
+        .raw_alloc(size, align_of::<u64>())
+        .ok_or(ExecutionError::OutOfMemory)?;
+
+    push_numeric(input, ptr.as_ptr().cast_const())
+}
+
+/// Get the size of an array's elements from the parameters. Each element has to fit within a stack entry.
+fn element_size(input: &HandlerInputInfo) -> Result<usize, ExecutionError>
+{
+    let size = usize::from(input.pull_params(1)?[0]);
+    guard!((1..=Stack::ENTRY_SIZE).contains(&size), ExecutionError::IllegalParam);
+
+    Ok(size)
+}
+
+/// Get the address of one of an array's elements, checking that the index lies within the array
+fn array_element(
+    input: &mut HandlerInputInfo,
+    array: StackEntry,
+    index: StackEntry,
+    size: usize,
+) -> Result<NonNull<u8>, ExecutionError>
+{
+    let array = heap_address(array)?;
+    let length = input
+        .heap()?
+        .checked_array_len(array)
+        .ok_or(ExecutionError::InvalidAddress)?;
+
+    let index = usize::try_from(index).map_err(|_| ExecutionError::IndexOutOfBounds)?;
+    guard!(index < length, ExecutionError::IndexOutOfBounds);
+
+    index
+        .checked_mul(size)
+        .and_then(|x| array.addr().get().checked_add(x))
+        .and_then(|x| NonNull::new(ptr::without_provenance_mut(x)))
+        .ok_or(ExecutionError::InvalidAddress)
+}
+
+/// Pops a number of elements, and allocates an array of them on the heap, pushing its address.
+///
+/// The size of each element is given by the parameters. Like `new`, the address is only valid until the next GC.
+fn new_array(input: &mut HandlerInputInfo) -> HandlerResult
+{
+    let size = element_size(input)?;
+    let count = usize::try_from(input.stack_pop()?).map_err(|_| ExecutionError::OutOfMemory)?;
+
+    let ptr = input
+        .heap()?
+        .alloc_array(size, count, align_of::<u64>())
+        .ok_or(ExecutionError::OutOfMemory)?;
+
+    push_numeric(input, ptr.as_ptr().cast_const())
+}
+
+/// Pops an array, and pushes the number of elements in it
+fn array_len(input: &mut HandlerInputInfo) -> HandlerResult
+{
+    let array = heap_address(input.stack_pop()?)?;
+    let length = input
+        .heap()?
+        .checked_array_len(array)
+        .ok_or(ExecutionError::InvalidAddress)?;
+
+    push_numeric(input, length as StackEntry)
+}
+
+/// Pops an index and then an array, and pushes the element at that index.
+///
+/// Elements smaller than a stack entry are zero extended.
+fn array_load(input: &mut HandlerInputInfo) -> HandlerResult
+{
+    let size = element_size(input)?;
+    let [index, array] = input.stack_pop_many::<2>()?;
+    let element = array_element(input, array, index, size)?;
+
+    let mut bytes = [0; Stack::ENTRY_SIZE];
+    input
+        .heap()?
+        .read_bytes(element, &mut bytes[..size])
+        .ok_or(ExecutionError::InvalidAddress)?;
+
+    push_numeric(input, <StackEntry>::from_le_bytes(bytes))
+}
+
+/// Pops a value, an index and then an array, and writes the value to the element at that index.
+///
+/// Elements smaller than a stack entry only keep the value's lowest bytes.
+fn array_store(input: &mut HandlerInputInfo) -> HandlerResult
+{
+    let size = element_size(input)?;
+    let (value, value_type) = input.frame.pop_typed().ok_or(ExecutionError::EmptyStack)?;
+    let [index, array] = input.stack_pop_many::<2>()?;
+    let element = array_element(input, array, index, size)?;
+
+    let heap = input.heap()?;
+    heap.write_bytes(element, &value.to_le_bytes()[..size])
+        .ok_or(ExecutionError::InvalidAddress)?;
+    write_barrier(heap, heap_address(array)?, value, value_type);
+
+    Ok(input.next())
+}
+
+/// Pops a heap address, and pushes the 8 byte value stored there
+fn load_heap(input: &mut HandlerInputInfo) -> HandlerResult
+{
+    let address = heap_address(input.stack_pop()?)?;
+    let value = input.heap()?.read_u64(address).ok_or(ExecutionError::InvalidAddress)?;
+
+    push_numeric(input, value)
+}
+
+/// Pops a value and then a heap address, and writes the value to that address
+fn store_heap(input: &mut HandlerInputInfo) -> HandlerResult
+{
+    let (value, value_type) = input.frame.pop_typed().ok_or(ExecutionError::EmptyStack)?;
+    let address = heap_address(input.stack_pop()?)?;
+
+    let heap = input.heap()?;
+    heap.write_u64(address, value).ok_or(ExecutionError::InvalidAddress)?;
+    write_barrier(heap, address, value, value_type);
+
+    Ok(input.next())
+}
+
+/// Tell the heap about a value stored into `object`, if the value was pushed as a reference to something else
+fn write_barrier(heap: &mut Heap, object: NonNull<u8>, value: StackEntry, value_type: LocalType)
+{
+    if value_type == LocalType::Ptr
+        && let Ok(target) = heap_address(value)
+    {
+        heap.write_barrier(object, target);
+    }
 }
 
 // Arithmetic Handlers
 
-fn unaryop<T, F>(input: &mut HandlerInputInfo, op: F) -> ExecutionResult
+fn unaryop<T, F>(input: &mut HandlerInputInfo, op: F) -> HandlerResult
 where
     T: Stackable,
     F: Fn(T) -> T,
 {
     let value = input.stack_pop().map(T::from_entry)?;
-    input
-        .stack_push(op(value).into_entry())
-        .map(|()| InstructionResult::Next)
+    input.stack_push_typed(op(value)).map(|()| input.next())
 }
 
-fn binop<T, F>(input: &mut HandlerInputInfo, op: F) -> ExecutionResult
+/// Replaces the top two values with `op(value1, value2)`, where `value1` is the one that was pushed first, so that
+/// pushing `a` then `b` and subtracting gives `a - b`
+fn binop<T, F>(input: &mut HandlerInputInfo, op: F) -> HandlerResult
 where
     T: Stackable,
     F: Fn(T, T) -> T,
 {
-    let [value1, value2] = input.stack_pop_many::<2>()?.map(T::from_entry);
+    // The second value popped was pushed first, so is the left hand side
+    let [rhs, lhs] = input.stack_pop_many::<2>()?.map(T::from_entry);
+    input.stack_push_typed(op(lhs, rhs)).map(|()| input.next())
+}
+
+/// The same as `binop`, for operations that fail on a zero right hand side
+fn checked_binop<T, F>(input: &mut HandlerInputInfo, op: F) -> HandlerResult
+where
+    T: Stackable,
+    F: Fn(T, T) -> Option<T>,
+{
+    let [rhs, lhs] = input.stack_pop_many::<2>()?.map(T::from_entry);
+    let result = op(lhs, rhs).ok_or(ExecutionError::DivideByZero)?;
+    input.stack_push_typed(result).map(|()| input.next())
+}
+
+/// Adds the 1 byte parameter onto the integer on top of the stack, without having to push it first
+fn iconst_add(input: &mut HandlerInputInfo) -> HandlerResult
+{
+    let immediate = u64::from(input.pull_params(1)?[0]);
+    unaryop(input, |x: u64| x.wrapping_add(immediate))
+}
+
+/// Replaces the top two values with 1 if `op(value1, value2)` holds, otherwise 0, with the operands in the same
+/// order as `binop`
+fn cmpop<T, F>(input: &mut HandlerInputInfo, op: F) -> HandlerResult
+where
+    T: Stackable,
+    F: Fn(&T, &T) -> bool,
+{
+    let [rhs, lhs] = input.stack_pop_many::<2>()?.map(T::from_entry);
     input
-        .stack_push(op(value1, value2).into_entry())
-        .map(|()| InstructionResult::Next)
+        .stack_push(StackEntry::from(op(&lhs, &rhs)))
+        .map(|()| input.next())
+}
+
+// Control Flow
+
+/// Unconditionally jump by the offset given in the parameters
+fn jump(input: &mut HandlerInputInfo) -> HandlerResult
+{
+    input.jump_target().map(InstructionResult::Jump)
+}
+
+/// Jump by the offset given in the parameters if the condition on top of the stack
+/// matches what is expected, otherwise carry on to the next instruction
+fn jump_if(input: &mut HandlerInputInfo, expected: bool) -> HandlerResult
+{
+    let target = input.jump_target()?;
+    let condition = input.stack_pop()? != 0;
+
+    Ok(
+        if condition == expected
+        {
+            InstructionResult::Jump(target)
+        }
+        else
+        {
+            input.next()
+        },
+    )
 }
 
 // Conversion
 
-fn convert<I, O>(input: &mut HandlerInputInfo) -> ExecutionResult
+fn convert<I, O>(input: &mut HandlerInputInfo) -> HandlerResult
 where
     I: Stackable,
     O: Stackable + StackableConvert<I>,
 {
     let value = input.stack_pop().map(<I>::from_entry)?;
-    input
-        .stack_push(<O>::convert(value).into_entry())
-        .map(|()| InstructionResult::Next)
+    input.stack_push_typed(<O>::convert(value)).map(|()| input.next())
 }
 
-// Debugging Handlers. Not for actual use
+// Debugging Handlers
 
-#[expect(
-    clippy::panic_in_result_fn,
-    clippy::panic,
-    reason = "This is a debug handler that should never make it to a finished version"
-)]
-fn unimplemented_handler(_: &mut HandlerInputInfo) -> ExecutionResult
+/// Pops the value on top of the stack, for the runner to print in the format given by the parameter
+fn print(input: &mut HandlerInputInfo) -> HandlerResult
 {
-    panic!("Opcode not implemented")
+    let format = match input.pull_params(1)?[0]
+    {
+        0 => PrintFormat::Integer,
+        1 => PrintFormat::Float4,
+        2 => PrintFormat::Float8,
+        3 => PrintFormat::Hex,
+        _ => return Err(ExecutionError::IllegalParam),
+    };
+
+    input.stack_pop().map(|value| InstructionResult::Print(value, format))
 }
 
-/*
- * **************************************************************************
- *                               HANDLERS ARRAY
- * **************************************************************************
- */
+/// Pops the value on top of the stack, failing if it is zero.
+///
+/// Assertions are only checked in debug builds, otherwise the value is just discarded.
+fn assert_nonzero(input: &mut HandlerInputInfo) -> HandlerResult
+{
+    let value = input.stack_pop()?;
+    guard!(
+        !cfg!(debug_assertions) || value != 0,
+        ExecutionError::AssertionFailed { pc: input.pc }
+    );
 
-macro_rules! handlers {
-    ($($t:tt),+) => {
-        [
-            $(
-                handler!($t)
-            ),+
-        ]
-    };
+    Ok(input.next())
 }
 
-macro_rules! handler {
-    ({$i:expr, $p:expr, $h:ident}) => {
-        HandlerInfo { opcode: $i, param_count: $p, handler: &$h }
-    };
-    ({$i:expr, $p:expr, $h:ident, $($x:expr),+}) => {
-        HandlerInfo { opcode: $i, param_count: $p, handler: &(|x| $h(x, $($x),+)) }
-    };
-    ({$i:expr, $p:expr, $h:expr }) => {
-        HandlerInfo { opcode: $i, param_count: $p, handler: $h }
+#[cfg(test)]
+mod opcode_handler_tests
+{
+    use std::iter;
+
+    use super::*;
+    use crate::{
+        loader::parser::{LocalType, Table},
+        memory::metaspace::Metaspace,
     };
-}
 
-// Is it possible to add any sanity checks into this?
-const HANDLERS: [HandlerInfo; u8::MAX as usize + 1] = handlers!(
-    { Opcode::Nop,           0, &(|_| Ok(InstructionResult::Next)) },
-    { Opcode::IConst0,       0, push_numeric, 0_u64 },
-    { Opcode::IConst1,       0, push_numeric, 1_u64 },
-    { Opcode::IConst2,       0, push_numeric, 2_u64 },
-    { Opcode::IConst3,       0, push_numeric, 3_u64 },
-    { Opcode::F4Const0,      0, push_numeric, 0.0_f32 },
-    { Opcode::F4Const1,      0, push_numeric, 1.0_f32 },
-    { Opcode::F8Const0,      0, push_numeric, 0.0_f64 },
-    { Opcode::F8Const1,      0, push_numeric, 1.0_f64 },
-    { Opcode::IConst,        1, push_bytes },
-    { Opcode::IConstW,       2, push_bytes },
-    { Opcode::Const,         4, push_constant },
-    { Opcode::LdArg0,        0, load_local, 0 },
-    { Opcode::LdArg1,        0, load_local, 1 },
-    { Opcode::LdArg2,        0, load_local, 2 },
-    { Opcode::LdArg3,        0, load_local, 3 },
-    { Opcode::LdArg,         1, &(|x| load_local(x, x.pull_params(1)?[0])) },
-    { Opcode::StArg0,        0, store_local, 0 },
-    { Opcode::StArg1,        0, store_local, 1 },
-    { Opcode::StArg2,        0, store_local, 2 },
-    { Opcode::StArg3,        0, store_local, 3 },
-    { Opcode::StArg,         1, &(|x| store_local(x, x.pull_params(1)?[0])) },
-    { Opcode::Pop,           0, pop },
-    { Opcode::Dup,           0, dup },
-    { Opcode::Swap,          0, swap },
-    { Opcode::Ret,           0, &(|_| Ok(InstructionResult::Return(false))) },
-    { Opcode::RetVal,        0, &(|_| Ok(InstructionResult::Return(true))) },
-    { Opcode::IAdd,          0, binop, <u64>::wrapping_add },
-    { Opcode::F4Add,         0, binop, <f32>::add },
-    { Opcode::F8Add,         0, binop, <f64>::add },
-    { Opcode::ISub,          0, binop, <u64>::wrapping_sub },
-    { Opcode::F4Sub,         0, binop, <f32>::sub },
-    { Opcode::F8Sub,         0, binop, <f64>::sub },
-    { Opcode::IMul,          0, binop, <u64>::wrapping_mul },
-    { Opcode::F4Mul,         0, binop, <f32>::mul },
-    { Opcode::F8Mul,         0, binop, <f64>::mul },
-    { Opcode::IDiv,          0, binop, <u64>::div },
-    { Opcode::F4Div,         0, binop, <f32>::div },
-    { Opcode::F8Div,         0, binop, <f64>::div },
-    { Opcode::IRem,          0, binop, <u64>::rem },
-    { Opcode::F4Rem,         0, binop, <f32>::rem },
-    { Opcode::F8Rem,         0, binop, <f64>::rem },
-    { Opcode::INeg,          0, unaryop, <i64>::neg },
-    { Opcode::F4Neg,         0, unaryop, <f32>::neg },
-    { Opcode::F8Neg,         0, unaryop, <f64>::neg },
-    { Opcode::Shl,           0, binop, <u64>::shl },
-    { Opcode::Shr,           0, binop, <u64>::shr },
-    { Opcode::AShr,          0, binop, <i64>::shr },
-    { Opcode::And,           0, binop, <u64>::bitand },
-    { Opcode::Or,            0, binop, <u64>::bitor },
-    { Opcode::Xor,           0, binop, <u64>::bitxor },
-    { Opcode::Not,           0, unaryop, <u64>::not },
-    { Opcode::IConvertF4,    0, &(|x| convert::<i64, f32>(x)) }, // Using i64 to avoid sign loss
-    { Opcode::IConvertF8,    0, &(|x| convert::<i64, f64>(x)) },
-    { Opcode::F4ConvertI,    0, &(|x| convert::<f32, i64>(x)) },
-    { Opcode::F4ConvertF8,   0, &(|x| convert::<f32, f64>(x)) },
-    { Opcode::F8ConvertI,    0, &(|x| convert::<f64, i64>(x)) },
-    { Opcode::F8ConvertF4,   0, &(|x| convert::<f64, f32>(x)) },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler },
-    { Opcode::Directive,     0, unimplemented_handler },
-    { Opcode::Unimplemented, 0, unimplemented_handler }
-);
+    /// Run a test against a fresh stack frame, along with an empty constant table
+    fn with_frame(test: impl FnOnce(&mut StackFrame, &ConstantTable))
+    {
+        let metaspace = Metaspace::new();
+        let constants = ConstantTable::from_parsed_table(&Table::new(0, &[]).unwrap().0, &metaspace).unwrap();
+
+        let mut stack = Stack::new(8);
+        test(&mut stack.initial_frame(2, 4).unwrap(), &constants);
+    }
+
+    #[test]
+    fn instruction_widths()
+    {
+        let metaspace = Metaspace::new();
+        let (table, _) = Table::new(1, &[0, 42, 0, 0, 0]).unwrap();
+        let constants = ConstantTable::from_parsed_table(&table, &metaspace).unwrap();
+
+        let code = [
+            Opcode::IConst as u8,
+            5, // 1 byte operand
+            Opcode::Const as u8,
+            0,
+            0,
+            0,
+            0, // 4 byte operand
+            Opcode::IAdd as u8,
+            Opcode::RetVal as u8,
+        ];
+
+        let mut stack = Stack::new(8);
+        let mut frame = stack.initial_frame(0, 4).unwrap();
+
+        let mut pc = 0;
+        let mut visited = vec![];
+        while let InstructionResult::Next(width) = exec_instruction(&code, pc, &mut frame, &constants, None).unwrap()
+        {
+            visited.push(pc);
+            pc += width;
+        }
+
+        assert_eq!(visited, [0, 2, 7]);
+        assert_eq!(pc, 8);
+        assert_eq!(frame.pop(), Some(47));
+    }
+
+    #[test]
+    fn handlers_match_param_counts()
+    {
+        let metaspace = Metaspace::new();
+        let (table, _) = Table::new(1, &[0, 42, 0, 0, 0]).unwrap();
+        let constants = ConstantTable::from_parsed_table(&table, &metaspace).unwrap();
+
+        for opcode in Opcode::INSTRUCTIONS
+        {
+            // Zeroed parameters are valid for every instruction: constant 0, local 0, or a jump to itself
+            let count = usize::from(opcode.param_count());
+            let bytecode: Vec<u8> = iter::once(opcode as u8).chain(iter::repeat_n(0, count)).collect();
+
+            let mut stack = Stack::new(8);
+            let mut frame = stack.initial_frame(2, 4).unwrap();
+            frame.push(1);
+            frame.push(1);
+
+            let result = exec_instruction(&bytecode, 0, &mut frame, &constants, None);
+            assert!(
+                !matches!(result, Err(ExecutionError::MissingParams)),
+                "{opcode} ran out of params"
+            );
+
+            if count > 0
+            {
+                let result = exec_instruction(&bytecode[..count], 0, &mut frame, &constants, None);
+                assert!(
+                    matches!(result, Err(ExecutionError::MissingParams)),
+                    "{opcode} didn't need all of its params"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn null_check()
+    {
+        with_frame(|frame, constants| {
+            let code = [Opcode::NullCheck as u8];
+
+            frame.push(0);
+            assert!(matches!(
+                exec_instruction(&code, 0, frame, constants, None),
+                Err(ExecutionError::NullDereference)
+            ));
+
+            frame.push(8);
+            assert!(matches!(
+                exec_instruction(&code, 0, frame, constants, None),
+                Ok(InstructionResult::Next(1))
+            ));
+            assert_eq!(frame.pop(), Some(8)); // The address is left where it was
+
+            // Anything that dereferences an address checks it first
+            frame.push(0);
+            assert!(matches!(
+                exec_instruction(&[Opcode::LoadHeap as u8], 0, frame, constants, None),
+                Err(ExecutionError::NullDereference)
+            ));
+        });
+    }
+
+    #[test]
+    fn signed_comparisons()
+    {
+        with_frame(|frame, constants| {
+            let mut compare = |opcode: Opcode, lhs: i64, rhs: i64| {
+                frame.push(lhs.into_entry());
+                frame.push(rhs.into_entry());
+                exec_instruction(&[opcode as u8], 0, frame, constants, None).unwrap();
+                frame.pop().unwrap()
+            };
+
+            // -1 is u64::MAX when treated as unsigned
+            assert_eq!(compare(Opcode::ISCmpLt, -1, 0), 1);
+            assert_eq!(compare(Opcode::ICmpLt, -1, 0), 0);
+            assert_eq!(compare(Opcode::ISCmpGt, -1, 0), 0);
+            assert_eq!(compare(Opcode::ICmpGt, -1, 0), 1);
+            assert_eq!(compare(Opcode::ISCmpLe, -5, -5), 1);
+            assert_eq!(compare(Opcode::ISCmpGe, i64::MIN, i64::MAX), 0);
+            assert_eq!(compare(Opcode::ISCmpEq, -7, -7), 1);
+            assert_eq!(compare(Opcode::ISCmpNe, -7, -7), 0);
+        });
+    }
+
+    #[test]
+    fn operand_order()
+    {
+        with_frame(|frame, constants| {
+            let mut run = |opcode: Opcode, value1: u64, value2: u64| {
+                frame.push(value1);
+                frame.push(value2);
+                exec_instruction(&[opcode as u8], 0, frame, constants, None).map(|_| frame.pop().unwrap())
+            };
+
+            // The value pushed first is always the left hand side
+            assert_eq!(run(Opcode::ISub, 10, 3), Ok(7));
+            assert_eq!(run(Opcode::IDiv, 12, 4), Ok(3));
+            assert_eq!(run(Opcode::IRem, 7, 4), Ok(3));
+            assert_eq!(run(Opcode::ICmpLt, 1, 2), Ok(1));
+            assert_eq!(run(Opcode::ICmpGt, 1, 2), Ok(0));
+            assert_eq!(
+                run(Opcode::F8Sub, 10.0.into_entry(), 3.0.into_entry()),
+                Ok(7.0.into_entry())
+            );
+
+            // Only a zero divisor fails
+            assert_eq!(run(Opcode::IDiv, 0, 4), Ok(0));
+            assert!(matches!(run(Opcode::IDiv, 4, 0), Err(ExecutionError::DivideByZero)));
+        });
+    }
+
+    #[test]
+    fn i4_extend()
+    {
+        with_frame(|frame, constants| {
+            let mut extend = |opcode: Opcode, value: u64| {
+                frame.push(value);
+                exec_instruction(&[opcode as u8], 0, frame, constants, None).unwrap();
+                frame.pop().unwrap()
+            };
+
+            assert_eq!(extend(Opcode::I4Extend, 0xFFFF_FFFF), 0x0000_0000_FFFF_FFFF);
+            assert_eq!(extend(Opcode::I4SignExtend, 0xFFFF_FFFF), 0xFFFF_FFFF_FFFF_FFFF);
+
+            // Only the bottom 4 bytes are looked at
+            assert_eq!(extend(Opcode::I4Extend, 0x1234_5678_9ABC_DEF0), 0x9ABC_DEF0);
+            assert_eq!(
+                extend(Opcode::I4SignExtend, 0x1234_5678_9ABC_DEF0),
+                0xFFFF_FFFF_9ABC_DEF0
+            );
+            assert_eq!(extend(Opcode::I4SignExtend, 0xFFFF_FFFF_7FFF_FFFF), 0x7FFF_FFFF);
+        });
+    }
+
+    #[test]
+    fn float_to_unsigned()
+    {
+        with_frame(|frame, constants| {
+            let mut convert = |opcode: Opcode, value: u64| {
+                frame.push(value);
+                exec_instruction(&[opcode as u8], 0, frame, constants, None).unwrap();
+                frame.pop().unwrap()
+            };
+            let mut to_u32 = |value: f32| convert(Opcode::F4ToU32, value.into_entry());
+
+            assert_eq!(to_u32(3.9), 3);
+            assert_eq!(to_u32(0.0), 0);
+            assert_eq!(to_u32(-1.0), 0);
+            assert_eq!(to_u32(4.3e9), u64::from(u32::MAX));
+            assert_eq!(to_u32(f32::NAN), 0);
+
+            for (value, expected) in [(3.9, 3), (-1.0, 0), (1e20, u64::MAX), (f64::NAN, 0)]
+            {
+                assert_eq!(convert(Opcode::F8ToU64, value.into_entry()), expected);
+            }
+        });
+    }
+
+    #[test]
+    fn type_of()
+    {
+        with_frame(|frame, constants| {
+            let mut type_of = |code: &[u8]| {
+                for pc in 0..code.len()
+                {
+                    exec_instruction(code, pc, frame, constants, None).unwrap();
+                }
+                exec_instruction(&[Opcode::TypeOf as u8], 0, frame, constants, None).unwrap();
+                frame.pop().unwrap()
+            };
+
+            assert_eq!(type_of(&[Opcode::IConst1 as u8]), 0);
+            assert_eq!(type_of(&[Opcode::F4Const1 as u8]), 1);
+            assert_eq!(type_of(&[Opcode::F8Const0 as u8]), 2);
+            assert_eq!(type_of(&[Opcode::IConst0 as u8, Opcode::IConvertF8 as u8]), 2);
+
+            // The type is carried along with the value when it is moved around the stack
+            assert_eq!(
+                type_of(&[Opcode::F4Const0 as u8, Opcode::Dup as u8, Opcode::Pop as u8]),
+                1
+            );
+            assert_eq!(
+                type_of(&[Opcode::F8Const1 as u8, Opcode::IConst2 as u8, Opcode::Swap as u8]),
+                2
+            );
+            frame.pop();
+        });
+    }
+
+    #[test]
+    fn compare_swap()
+    {
+        with_frame(|frame, constants| {
+            let mut compare_swap = |target: u64, replacement: u64, expected: u64| {
+                for value in [target, replacement, expected]
+                {
+                    frame.push(value);
+                }
+                exec_instruction(&[Opcode::CmpSwap as u8], 0, frame, constants, None).unwrap();
+
+                let swapped = frame.pop().unwrap();
+                (frame.pop().unwrap(), swapped)
+            };
+
+            assert_eq!(compare_swap(5, 9, 5), (9, 1));
+            assert_eq!(compare_swap(5, 9, 4), (5, 0));
+
+            // The value left behind keeps its type
+            frame.push_as(2, LocalType::Float32);
+            frame.push(7);
+            frame.push(3);
+            exec_instruction(&[Opcode::CmpSwap as u8], 0, frame, constants, None).unwrap();
+            frame.pop();
+            assert_eq!(frame.pop_typed(), Some((2, LocalType::Float32)));
+        });
+    }
+
+    #[test]
+    fn swap_pairs()
+    {
+        with_frame(|frame, constants| {
+            frame.push(1);
+            frame.push_as(2, LocalType::Float32);
+            frame.push(3);
+            frame.push(4);
+            exec_instruction(&[Opcode::Swap2 as u8], 0, frame, constants, None).unwrap();
+
+            assert_eq!(frame.pop_typed(), Some((2, LocalType::Float32)));
+            assert_eq!(frame.pop(), Some(1));
+            assert_eq!(frame.pop(), Some(4));
+            assert_eq!(frame.pop(), Some(3));
+
+            // There have to be two whole pairs to swap
+            for value in [1, 2, 3]
+            {
+                frame.push(value);
+            }
+            assert!(matches!(
+                exec_instruction(&[Opcode::Swap2 as u8], 0, frame, constants, None),
+                Err(ExecutionError::EmptyStack)
+            ));
+        });
+    }
+
+    #[test]
+    fn directives_are_not_executable()
+    {
+        with_frame(|frame, constants| {
+            for byte in [Opcode::Directive as u8, Opcode::Unimplemented as u8, 200]
+            {
+                assert!(matches!(
+                    exec_instruction(&[byte], 0, frame, constants, None),
+                    Err(ExecutionError::IllegalOpcode)
+                ));
+            }
+        });
+    }
+}
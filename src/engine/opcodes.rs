@@ -1,4 +1,6 @@
-#[derive(Clone, Copy)]
+use core::fmt::{self, Display, Formatter};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Opcode
 {
     Nop,             // nop: Do nothing. [No Change]
@@ -59,6 +61,654 @@ pub enum Opcode
     F4ConvertF8, // f4.convert.f8: Convert from float32 to float32. [float32] -> [float64]
     F8ConvertI, // f8.convert.i: Convert from float64 to integer. [float64] -> [integer]
     F8ConvertF4, // f8.convert.f4: Convert from float64 to float32. [float64] -> [float64] (SHOULD THIS BE ALLOWED?)
+    Assert, // assert: Pop the top of the stack, and fail if it is zero (debug builds only). [value] ->
+    Jmp,  // jmp: Jump by the given signed 2 byte offset, relative to this instruction. -> !
+    JmpIfTrue, // jmp.if.true: Jump by the given offset if the top of the stack is non-zero. [condition] ->
+    JmpIfFalse, // jmp.if.false: Jump by the given offset if the top of the stack is zero. [condition] ->
+    ICmpEq, // i.cmp.eq: Push 1 if the top 2 integers are equal, otherwise 0. [value1], [value2] -> [result]
+    ICmpNe, // i.cmp.ne: Push 1 if the top 2 integers are not equal, otherwise 0. [value1], [value2] -> [result]
+    ICmpLt, // i.cmp.lt: Push 1 if value1 < value2 as unsigned integers, otherwise 0. [value1], [value2] -> [result]
+    ICmpGt, // i.cmp.gt: Push 1 if value1 > value2 as unsigned integers, otherwise 0. [value1], [value2] -> [result]
+    ICmpLe, // i.cmp.le: Push 1 if value1 <= value2 as unsigned integers, otherwise 0. [value1], [value2] -> [result]
+    ICmpGe, // i.cmp.ge: Push 1 if value1 >= value2 as unsigned integers, otherwise 0. [value1], [value2] -> [result]
+    IConstAdd, // i.const.add: Add the given 1 byte onto the integer on top of the stack. [value] -> [result]
+    Halt, // halt: Stop the whole program, with the given exit code. [] -> []
+    NopW, // nop.w: Do nothing, taking up 2 bytes for alignment. [] -> []
+    NopD, // nop.d: Do nothing, taking up 4 bytes for alignment. [] -> []
+    Yield, // yield: Pause the program, carrying on from the next instruction when it is next run. [] -> []
+    LoadHeap, // ld.heap: Read the 8 byte value at a heap address. [address] -> [value]
+    StoreHeap, // st.heap: Write an 8 byte value to a heap address. [address, value] -> []
+    New,  // new: Allocate bytes on the heap, pushing their address, which is valid until the next GC. [] -> [address]
+    NewArray, // new.array: Allocate an array of the popped number of elements, each the given number of bytes. [count] -> [array]
+    ArrayLen, // array.len: Get the number of elements in an array. [array] -> [count]
+    ArrayLoad, // ld.array: Load an element, of the given number of bytes, from an array. [array, index] -> [value]
+    ArrayStore, // st.array: Store an element, of the given number of bytes, into an array. [array, index, value] -> []
+    PushNull, // null: Push a null heap address. [] -> [address]
+    NullCheck, // null.check: Fail if the address on top of the stack is null, leaving it there. [address] -> [address]
+    ISCmpEq,  // is.cmp.eq: Push 1 if the top 2 integers are equal, as for i.cmp.eq. [value1], [value2] -> [result]
+    ISCmpNe,  // is.cmp.ne: Push 1 if the top 2 integers are not equal, as for i.cmp.ne. [value1], [value2] -> [result]
+    ISCmpLt,  // is.cmp.lt: Push 1 if value1 < value2 as signed integers, otherwise 0. [value1], [value2] -> [result]
+    ISCmpGt,  // is.cmp.gt: Push 1 if value1 > value2 as signed integers, otherwise 0. [value1], [value2] -> [result]
+    ISCmpLe,  // is.cmp.le: Push 1 if value1 <= value2 as signed integers, otherwise 0. [value1], [value2] -> [result]
+    ISCmpGe,  // is.cmp.ge: Push 1 if value1 >= value2 as signed integers, otherwise 0. [value1], [value2] -> [result]
+    I4Extend, // i4.extend: Zero extend the integer in the bottom 4 bytes of the value on top of the stack. [value] -> [result]
+    I4SignExtend, // i4.sign.extend: Sign extend the integer in the bottom 4 bytes of the value on top of the stack. [value] -> [result]
+    Breakpoint,   // breakpoint: Let an attached debugger look at the program, then carry on. [] -> []
+    IConstD,      // i.const.d: Push a given 4 bytes onto the stack. [] -> [little endian u32]
+    IConstL,      // i.const.l: Push a given 8 bytes onto the stack. [] -> [little endian u64]
+    Print, // print: Pop the value on top of the stack and print it, formatted as given by the parameter: 0 integer, 1 f4, 2 f8, 3 hex. [value] -> []
+    TypeOf, // type.of: Pop a value, and push the type it was pushed as: 0 integer, 1 f4, 2 f8, 3 pointer (best effort). [value] -> [type]
+    CmpSwap, // cmp.swap: Pop an expected value, a replacement and a target. If the target is the expected value, push the replacement in its place, otherwise push the target back. Then push 1 if it was replaced, or 0 if not. [target, replacement, expected] -> [result, swapped]
+    NopT,    // nop.t: Do nothing, taking up 3 bytes for alignment. [] -> []
+    F4ToU32, // f4.to.u32: Convert from float32 to an unsigned 32 bit integer, rounding towards zero and saturating. NaN becomes 0. [float32] -> [integer]
+    F8ToU64, // f8.to.u64: Convert from float64 to an unsigned 64 bit integer, rounding towards zero and saturating. NaN becomes 0. [float64] -> [integer]
+    Swap2,   // swap2: Swap the top 2 pairs of stack entries, keeping each pair in order. [a, b, c, d] -> [c, d, a, b]
     Directive = 254, // .X: Directives for supplying metadata
     Unimplemented = 255,
 }
+
+/// Error returned when a byte does not correspond to any known opcode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidOpcode(pub u8);
+
+impl Opcode
+{
+    /// Another name for `LdArg`. Arguments are just placed into a function's first locals, so any local can be
+    /// loaded with it, and this reads better where it isn't an argument.
+    pub const READ_LOCAL: Self = Self::LdArg;
+
+    /// Another name for `StArg`, in the same way as `READ_LOCAL`
+    pub const WRITE_LOCAL: Self = Self::StArg;
+
+    /// Every instruction opcode, ordered by its numerical value.
+    ///
+    /// The position of each opcode in this array must match its numerical value,
+    /// which is checked at compile time below. Directives are not instructions, so
+    /// they are not included.
+    pub const INSTRUCTIONS: [Self; 100] = [
+        Self::Nop,
+        Self::IConst0,
+        Self::IConst1,
+        Self::IConst2,
+        Self::IConst3,
+        Self::F4Const0,
+        Self::F4Const1,
+        Self::F8Const0,
+        Self::F8Const1,
+        Self::IConst,
+        Self::IConstW,
+        Self::Const,
+        Self::LdArg0,
+        Self::LdArg1,
+        Self::LdArg2,
+        Self::LdArg3,
+        Self::LdArg,
+        Self::StArg0,
+        Self::StArg1,
+        Self::StArg2,
+        Self::StArg3,
+        Self::StArg,
+        Self::Pop,
+        Self::Dup,
+        Self::Swap,
+        Self::Ret,
+        Self::RetVal,
+        Self::IAdd,
+        Self::F4Add,
+        Self::F8Add,
+        Self::ISub,
+        Self::F4Sub,
+        Self::F8Sub,
+        Self::IMul,
+        Self::F4Mul,
+        Self::F8Mul,
+        Self::IDiv,
+        Self::F4Div,
+        Self::F8Div,
+        Self::IRem,
+        Self::F4Rem,
+        Self::F8Rem,
+        Self::INeg,
+        Self::F4Neg,
+        Self::F8Neg,
+        Self::Shl,
+        Self::Shr,
+        Self::AShr,
+        Self::And,
+        Self::Or,
+        Self::Xor,
+        Self::Not,
+        Self::IConvertF4,
+        Self::IConvertF8,
+        Self::F4ConvertI,
+        Self::F4ConvertF8,
+        Self::F8ConvertI,
+        Self::F8ConvertF4,
+        Self::Assert,
+        Self::Jmp,
+        Self::JmpIfTrue,
+        Self::JmpIfFalse,
+        Self::ICmpEq,
+        Self::ICmpNe,
+        Self::ICmpLt,
+        Self::ICmpGt,
+        Self::ICmpLe,
+        Self::ICmpGe,
+        Self::IConstAdd,
+        Self::Halt,
+        Self::NopW,
+        Self::NopD,
+        Self::Yield,
+        Self::LoadHeap,
+        Self::StoreHeap,
+        Self::New,
+        Self::NewArray,
+        Self::ArrayLen,
+        Self::ArrayLoad,
+        Self::ArrayStore,
+        Self::PushNull,
+        Self::NullCheck,
+        Self::ISCmpEq,
+        Self::ISCmpNe,
+        Self::ISCmpLt,
+        Self::ISCmpGt,
+        Self::ISCmpLe,
+        Self::ISCmpGe,
+        Self::I4Extend,
+        Self::I4SignExtend,
+        Self::Breakpoint,
+        Self::IConstD,
+        Self::IConstL,
+        Self::Print,
+        Self::TypeOf,
+        Self::CmpSwap,
+        Self::NopT,
+        Self::F4ToU32,
+        Self::F8ToU64,
+        Self::Swap2,
+    ];
+
+    /// The mnemonic used for this opcode within the assembler.
+    #[expect(clippy::too_many_lines, reason = "Every opcode needs its own arm")]
+    #[must_use]
+    pub const fn mnemonic(self) -> &'static str
+    {
+        match self
+        {
+            Self::Nop => "nop",
+            Self::IConst0 => "i.const.0",
+            Self::IConst1 => "i.const.1",
+            Self::IConst2 => "i.const.2",
+            Self::IConst3 => "i.const.3",
+            Self::F4Const0 => "f4.const.0",
+            Self::F4Const1 => "f4.const.1",
+            Self::F8Const0 => "f8.const.0",
+            Self::F8Const1 => "f8.const.1",
+            Self::IConst => "i.const",
+            Self::IConstW => "i.const.w",
+            Self::Const => "const",
+            Self::LdArg0 => "ld.arg.0",
+            Self::LdArg1 => "ld.arg.1",
+            Self::LdArg2 => "ld.arg.2",
+            Self::LdArg3 => "ld.arg.3",
+            Self::LdArg => "ld.arg",
+            Self::StArg0 => "st.arg.0",
+            Self::StArg1 => "st.arg.1",
+            Self::StArg2 => "st.arg.2",
+            Self::StArg3 => "st.arg.3",
+            Self::StArg => "st.arg",
+            Self::Pop => "pop",
+            Self::Dup => "dup",
+            Self::Swap => "swap",
+            Self::Ret => "ret",
+            Self::RetVal => "ret.val",
+            Self::IAdd => "i.add",
+            Self::F4Add => "f4.add",
+            Self::F8Add => "f8.add",
+            Self::ISub => "i.sub",
+            Self::F4Sub => "f4.sub",
+            Self::F8Sub => "f8.sub",
+            Self::IMul => "i.mul",
+            Self::F4Mul => "f4.mul",
+            Self::F8Mul => "f8.mul",
+            Self::IDiv => "i.div",
+            Self::F4Div => "f4.div",
+            Self::F8Div => "f8.div",
+            Self::IRem => "i.rem",
+            Self::F4Rem => "f4.rem",
+            Self::F8Rem => "f8.rem",
+            Self::INeg => "i.neg",
+            Self::F4Neg => "f4.neg",
+            Self::F8Neg => "f8.neg",
+            Self::Shl => "shl",
+            Self::Shr => "shr",
+            Self::AShr => "ashr",
+            Self::And => "and",
+            Self::Or => "or",
+            Self::Xor => "xor",
+            Self::Not => "not",
+            Self::IConvertF4 => "i.convert.f4",
+            Self::IConvertF8 => "i.convert.f8",
+            Self::F4ConvertI => "f4.convert.i",
+            Self::F4ConvertF8 => "f4.convert.f8",
+            Self::F8ConvertI => "f8.convert.i",
+            Self::F8ConvertF4 => "f8.convert.f4",
+            Self::Assert => "assert",
+            Self::Jmp => "jmp",
+            Self::JmpIfTrue => "jmp.if.true",
+            Self::JmpIfFalse => "jmp.if.false",
+            Self::ICmpEq => "i.cmp.eq",
+            Self::ICmpNe => "i.cmp.ne",
+            Self::ICmpLt => "i.cmp.lt",
+            Self::ICmpGt => "i.cmp.gt",
+            Self::ICmpLe => "i.cmp.le",
+            Self::ICmpGe => "i.cmp.ge",
+            Self::IConstAdd => "i.const.add",
+            Self::Halt => "halt",
+            Self::NopW => "nop.w",
+            Self::NopD => "nop.d",
+            Self::Yield => "yield",
+            Self::LoadHeap => "ld.heap",
+            Self::StoreHeap => "st.heap",
+            Self::New => "new",
+            Self::NewArray => "new.array",
+            Self::ArrayLen => "array.len",
+            Self::ArrayLoad => "ld.array",
+            Self::ArrayStore => "st.array",
+            Self::PushNull => "null",
+            Self::NullCheck => "null.check",
+            Self::ISCmpEq => "is.cmp.eq",
+            Self::ISCmpNe => "is.cmp.ne",
+            Self::ISCmpLt => "is.cmp.lt",
+            Self::ISCmpGt => "is.cmp.gt",
+            Self::ISCmpLe => "is.cmp.le",
+            Self::ISCmpGe => "is.cmp.ge",
+            Self::I4Extend => "i4.extend",
+            Self::I4SignExtend => "i4.sign.extend",
+            Self::Breakpoint => "breakpoint",
+            Self::IConstD => "i.const.d",
+            Self::IConstL => "i.const.l",
+            Self::Print => "print",
+            Self::TypeOf => "type.of",
+            Self::CmpSwap => "cmp.swap",
+            Self::NopT => "nop.t",
+            Self::F4ToU32 => "f4.to.u32",
+            Self::F8ToU64 => "f8.to.u64",
+            Self::Swap2 => "swap2",
+            Self::Directive => ".directive",
+            Self::Unimplemented => "unimplemented",
+        }
+    }
+
+    /// The number of parameter bytes that follow this opcode in the bytecode stream.
+    ///
+    /// This allows bytecode to be stepped through without executing it.
+    /// Directives have a variable number of operands, so are reported as having none.
+    #[must_use]
+    pub const fn param_count(self) -> u8
+    {
+        match self
+        {
+            Self::IConst
+            | Self::LdArg
+            | Self::StArg
+            | Self::IConstAdd
+            | Self::Halt
+            | Self::NopW
+            | Self::NewArray
+            | Self::ArrayLoad
+            | Self::ArrayStore
+            | Self::Print => 1,
+            Self::IConstW | Self::Jmp | Self::JmpIfTrue | Self::JmpIfFalse | Self::New | Self::NopT => 2,
+            Self::NopD => 3,
+            Self::Const | Self::IConstD => 4,
+            Self::IConstL => 8,
+            _ => 0,
+        }
+    }
+
+    /// The effect this opcode has on the operand stack, as `(pops, pushes)`.
+    ///
+    /// This allows the depth of the stack to be tracked without executing any bytecode.
+    #[expect(clippy::too_many_lines, reason = "Opcodes are only grouped when their effects match")]
+    #[must_use]
+    pub const fn stack_effect(self) -> (u8, u8)
+    {
+        match self
+        {
+            Self::Nop
+            | Self::NopW
+            | Self::NopT
+            | Self::NopD
+            | Self::Ret
+            | Self::Jmp
+            | Self::Halt
+            | Self::Yield
+            | Self::Breakpoint
+            | Self::Directive
+            | Self::Unimplemented => (0, 0),
+            Self::IConst0
+            | Self::IConst1
+            | Self::IConst2
+            | Self::IConst3
+            | Self::F4Const0
+            | Self::F4Const1
+            | Self::F8Const0
+            | Self::F8Const1
+            | Self::IConst
+            | Self::IConstW
+            | Self::IConstD
+            | Self::IConstL
+            | Self::Const
+            | Self::LdArg0
+            | Self::LdArg1
+            | Self::LdArg2
+            | Self::LdArg3
+            | Self::LdArg
+            | Self::New
+            | Self::PushNull => (0, 1),
+            Self::StArg0
+            | Self::StArg1
+            | Self::StArg2
+            | Self::StArg3
+            | Self::StArg
+            | Self::Pop
+            | Self::Print
+            | Self::RetVal
+            | Self::Assert
+            | Self::JmpIfTrue
+            | Self::JmpIfFalse => (1, 0),
+            Self::StoreHeap => (2, 0),
+            Self::ArrayStore => (3, 0),
+            Self::Dup => (1, 2),
+            Self::Swap => (2, 2),
+            Self::CmpSwap => (3, 2),
+            Self::Swap2 => (4, 4),
+            Self::INeg
+            | Self::F4Neg
+            | Self::F8Neg
+            | Self::Not
+            | Self::IConvertF4
+            | Self::IConvertF8
+            | Self::F4ConvertI
+            | Self::F4ConvertF8
+            | Self::F8ConvertI
+            | Self::F8ConvertF4
+            | Self::IConstAdd
+            | Self::LoadHeap
+            | Self::NewArray
+            | Self::ArrayLen
+            | Self::NullCheck
+            | Self::I4Extend
+            | Self::I4SignExtend
+            | Self::TypeOf
+            | Self::F4ToU32
+            | Self::F8ToU64 => (1, 1),
+            Self::IAdd
+            | Self::F4Add
+            | Self::F8Add
+            | Self::ISub
+            | Self::F4Sub
+            | Self::F8Sub
+            | Self::IMul
+            | Self::F4Mul
+            | Self::F8Mul
+            | Self::IDiv
+            | Self::F4Div
+            | Self::F8Div
+            | Self::IRem
+            | Self::F4Rem
+            | Self::F8Rem
+            | Self::Shl
+            | Self::Shr
+            | Self::AShr
+            | Self::And
+            | Self::Or
+            | Self::Xor
+            | Self::ICmpEq
+            | Self::ICmpNe
+            | Self::ICmpLt
+            | Self::ICmpGt
+            | Self::ICmpLe
+            | Self::ICmpGe
+            | Self::ISCmpEq
+            | Self::ISCmpNe
+            | Self::ISCmpLt
+            | Self::ISCmpGt
+            | Self::ISCmpLe
+            | Self::ISCmpGe
+            | Self::ArrayLoad => (2, 1),
+        }
+    }
+}
+
+// Ensure that the instructions array is correctly ordered
+const _: () = {
+    let mut index = 0;
+    while index < Opcode::INSTRUCTIONS.len()
+    {
+        assert!(
+            Opcode::INSTRUCTIONS[index] as usize == index,
+            "INSTRUCTIONS Array invalid: misaligned opcode"
+        );
+        index += 1;
+    }
+};
+
+// The numerical value of each opcode is part of the bytecode format, so must never change
+const _: () = {
+    assert!(Opcode::Nop as u8 == 0, "Opcode encoding changed");
+    assert!(Opcode::IConst as u8 == 9, "Opcode encoding changed");
+    assert!(Opcode::Const as u8 == 11, "Opcode encoding changed");
+    assert!(Opcode::Ret as u8 == 25, "Opcode encoding changed");
+    assert!(Opcode::IAdd as u8 == 27, "Opcode encoding changed");
+    assert!(Opcode::Assert as u8 == 58, "Opcode encoding changed");
+    assert!(Opcode::Jmp as u8 == 59, "Opcode encoding changed");
+    assert!(Opcode::ICmpGe as u8 == 67, "Opcode encoding changed");
+    assert!(Opcode::Directive as u8 == 254, "Opcode encoding changed");
+    assert!(Opcode::Unimplemented as u8 == 255, "Opcode encoding changed");
+};
+
+impl Display for Opcode
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result
+    {
+        f.write_str(self.mnemonic())
+    }
+}
+
+impl TryFrom<u8> for Opcode
+{
+    type Error = InvalidOpcode;
+
+    /// Convert a raw byte from a bytecode stream into its opcode.
+    ///
+    /// This will fail for any byte that doesn't map onto an implemented opcode.
+    fn try_from(value: u8) -> Result<Self, Self::Error>
+    {
+        match value
+        {
+            x if x == Self::Directive as u8 => Ok(Self::Directive),
+            x => Self::INSTRUCTIONS.get(usize::from(x)).copied().ok_or(InvalidOpcode(x)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod opcode_tests
+{
+    use super::*;
+
+    const MNEMONICS: [&str; 100] = [
+        "nop",
+        "i.const.0",
+        "i.const.1",
+        "i.const.2",
+        "i.const.3",
+        "f4.const.0",
+        "f4.const.1",
+        "f8.const.0",
+        "f8.const.1",
+        "i.const",
+        "i.const.w",
+        "const",
+        "ld.arg.0",
+        "ld.arg.1",
+        "ld.arg.2",
+        "ld.arg.3",
+        "ld.arg",
+        "st.arg.0",
+        "st.arg.1",
+        "st.arg.2",
+        "st.arg.3",
+        "st.arg",
+        "pop",
+        "dup",
+        "swap",
+        "ret",
+        "ret.val",
+        "i.add",
+        "f4.add",
+        "f8.add",
+        "i.sub",
+        "f4.sub",
+        "f8.sub",
+        "i.mul",
+        "f4.mul",
+        "f8.mul",
+        "i.div",
+        "f4.div",
+        "f8.div",
+        "i.rem",
+        "f4.rem",
+        "f8.rem",
+        "i.neg",
+        "f4.neg",
+        "f8.neg",
+        "shl",
+        "shr",
+        "ashr",
+        "and",
+        "or",
+        "xor",
+        "not",
+        "i.convert.f4",
+        "i.convert.f8",
+        "f4.convert.i",
+        "f4.convert.f8",
+        "f8.convert.i",
+        "f8.convert.f4",
+        "assert",
+        "jmp",
+        "jmp.if.true",
+        "jmp.if.false",
+        "i.cmp.eq",
+        "i.cmp.ne",
+        "i.cmp.lt",
+        "i.cmp.gt",
+        "i.cmp.le",
+        "i.cmp.ge",
+        "i.const.add",
+        "halt",
+        "nop.w",
+        "nop.d",
+        "yield",
+        "ld.heap",
+        "st.heap",
+        "new",
+        "new.array",
+        "array.len",
+        "ld.array",
+        "st.array",
+        "null",
+        "null.check",
+        "is.cmp.eq",
+        "is.cmp.ne",
+        "is.cmp.lt",
+        "is.cmp.gt",
+        "is.cmp.le",
+        "is.cmp.ge",
+        "i4.extend",
+        "i4.sign.extend",
+        "breakpoint",
+        "i.const.d",
+        "i.const.l",
+        "print",
+        "type.of",
+        "cmp.swap",
+        "nop.t",
+        "f4.to.u32",
+        "f8.to.u64",
+        "swap2",
+    ];
+
+    #[test]
+    fn mnemonics_match_assembler()
+    {
+        for (opcode, mnemonic) in Opcode::INSTRUCTIONS.iter().zip(MNEMONICS)
+        {
+            assert_eq!(opcode.to_string(), mnemonic);
+        }
+
+        assert_eq!(Opcode::IAdd.to_string(), "i.add");
+        assert_eq!(Opcode::F4Mul.to_string(), "f4.mul");
+        assert_eq!(Opcode::LdArg0.to_string(), "ld.arg.0");
+    }
+
+    #[test]
+    fn aliases()
+    {
+        assert_eq!(Opcode::READ_LOCAL, Opcode::LdArg);
+        assert_eq!(Opcode::WRITE_LOCAL as u8, Opcode::StArg as u8);
+        assert_eq!(Opcode::try_from(Opcode::READ_LOCAL as u8), Ok(Opcode::LdArg));
+    }
+
+    #[test]
+    fn param_counts()
+    {
+        assert_eq!(Opcode::Nop.param_count(), 0);
+        assert_eq!(Opcode::IConst.param_count(), 1);
+        assert_eq!(Opcode::IConstW.param_count(), 2);
+        assert_eq!(Opcode::Const.param_count(), 4);
+        assert_eq!(Opcode::IConstD.param_count(), 4);
+        assert_eq!(Opcode::IConstL.param_count(), 8);
+        assert_eq!(Opcode::LdArg.param_count(), 1);
+        assert_eq!(Opcode::StArg0.param_count(), 0);
+        assert_eq!(Opcode::IAdd.param_count(), 0);
+    }
+
+    #[test]
+    fn stack_effects()
+    {
+        assert_eq!(Opcode::Nop.stack_effect(), (0, 0));
+        assert_eq!(Opcode::IConst.stack_effect(), (0, 1));
+        assert_eq!(Opcode::StArg0.stack_effect(), (1, 0));
+        assert_eq!(Opcode::Dup.stack_effect(), (1, 2));
+        assert_eq!(Opcode::IAdd.stack_effect(), (2, 1));
+        assert_eq!(Opcode::F8ConvertF4.stack_effect(), (1, 1));
+    }
+
+    #[test]
+    fn try_from_roundtrip()
+    {
+        for opcode in Opcode::INSTRUCTIONS
+        {
+            assert_eq!(Opcode::try_from(opcode as u8), Ok(opcode));
+        }
+
+        assert_eq!(Opcode::try_from(Opcode::Directive as u8), Ok(Opcode::Directive));
+    }
+
+    #[test]
+    fn try_from_rejects_unimplemented()
+    {
+        // The first byte after the last instruction
+        let unused = u8::try_from(Opcode::INSTRUCTIONS.len()).unwrap();
+        assert_eq!(Opcode::try_from(unused), Err(InvalidOpcode(unused)));
+        assert_eq!(Opcode::try_from(200), Err(InvalidOpcode(200)));
+        assert_eq!(
+            Opcode::try_from(Opcode::Unimplemented as u8),
+            Err(InvalidOpcode(Opcode::Unimplemented as u8))
+        );
+    }
+}
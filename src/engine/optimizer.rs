@@ -0,0 +1,289 @@
+use alloc::{vec, vec::Vec};
+
+use crate::engine::{
+    decode::{Decoder, Instruction},
+    opcodes::Opcode,
+};
+
+/// Rewrite common sequences of instructions into cheaper equivalents.
+///
+/// Currently this fuses an integer constant followed by `i.add` into a single `i.const.add`. Any jumps
+/// are adjusted to account for the instructions that have shrunk.
+///
+/// If the code can't be fully decoded, or any of its jumps don't land on an instruction, it is returned
+/// unchanged so that the validator can report the problem against the original code.
+#[must_use]
+pub fn peephole_optimize(code: &[u8]) -> Vec<u8>
+{
+    decode(code)
+        .and_then(|instructions| rewrite(&instructions))
+        .unwrap_or_else(|| code.to_vec())
+}
+
+/// Split the bytecode up into its instructions, failing if any of them are invalid
+fn decode(code: &[u8]) -> Option<Vec<Instruction<'_>>>
+{
+    Decoder::new(code).collect::<Result<_, _>>().ok()
+}
+
+/// Remove every `nop` from the code, moving any jumps to account for the instructions that have shifted.
+///
+/// A jump that landed on a `nop` lands on whatever followed it instead. Only the single byte `nop` is removed,
+/// so padding made up of wider no-ops is kept, but anything padded with `nop` loses its alignment. Exception
+/// tables aren't adjusted, so code covered by one shouldn't be given to this.
+///
+/// If the code can't be fully decoded, or any of its jumps don't land on an instruction, it is returned
+/// unchanged.
+#[must_use]
+pub fn remove_nops(code: &[u8]) -> Vec<u8>
+{
+    decode(code)
+        .and_then(|instructions| strip_nops(&instructions))
+        .unwrap_or_else(|| code.to_vec())
+}
+
+fn rewrite(instructions: &[Instruction]) -> Option<Vec<u8>>
+{
+    let jumps = jump_targets(instructions)?;
+
+    // Fusing an instruction that is jumped to would leave the jump landing part way through
+    let mut is_target = vec![false; instructions.len()];
+    for &(_, target) in &jumps
+    {
+        is_target[target] = true;
+    }
+
+    let mut code = vec![];
+    let mut positions = vec![0; instructions.len()]; // Where each instruction starts in the new code
+
+    let mut index = 0;
+    while let Some(instruction) = instructions.get(index)
+    {
+        positions[index] = code.len();
+
+        if let Some(value) = fusable_constant(instruction)
+            && instructions.get(index + 1).is_some_and(|x| x.opcode == Opcode::IAdd)
+            && !is_target[index + 1]
+        {
+            positions[index + 1] = code.len();
+            code.extend_from_slice(&[Opcode::IConstAdd as u8, value]);
+            index += 2;
+        }
+        else
+        {
+            code.push(instruction.opcode as u8);
+            code.extend_from_slice(instruction.params);
+            index += 1;
+        }
+    }
+
+    patch_jumps(&mut code, &jumps, &positions)?;
+    Some(code)
+}
+
+fn strip_nops(instructions: &[Instruction]) -> Option<Vec<u8>>
+{
+    let jumps = jump_targets(instructions)?;
+
+    // Work out where every instruction ends up first, with each `nop` taking the position of whatever follows it
+    let mut positions = Vec::with_capacity(instructions.len());
+    let mut length = 0;
+    for instruction in instructions
+    {
+        positions.push(length);
+        if instruction.opcode != Opcode::Nop
+        {
+            length += 1 + instruction.params.len();
+        }
+    }
+
+    // Then write out everything else, with the jumps moved to match
+    let mut code = Vec::with_capacity(length);
+    for instruction in instructions.iter().filter(|x| x.opcode != Opcode::Nop)
+    {
+        code.push(instruction.opcode as u8);
+        code.extend_from_slice(instruction.params);
+    }
+
+    patch_jumps(&mut code, &jumps, &positions)?;
+    Some(code)
+}
+
+/// Find the index of the instruction each jump lands on, alongside the index of the jump itself
+fn jump_targets(instructions: &[Instruction]) -> Option<Vec<(usize, usize)>>
+{
+    instructions
+        .iter()
+        .enumerate()
+        .filter_map(|(index, instruction)| {
+            let offset = instruction.branch_offset()?;
+            Some(
+                instruction
+                    .at
+                    .checked_add_signed(offset)
+                    .and_then(|target| instructions.binary_search_by_key(&target, |x| x.at).ok())
+                    .map(|target| (index, target)),
+            )
+        })
+        .collect()
+}
+
+/// Recalculate the offset of every jump, now that the instructions have moved to `positions`
+fn patch_jumps(code: &mut [u8], jumps: &[(usize, usize)], positions: &[usize]) -> Option<()>
+{
+    for &(index, target) in jumps
+    {
+        let at = positions[index];
+        let offset = isize::try_from(positions[target]).ok()? - isize::try_from(at).ok()?;
+        code.get_mut(at + 1..at + 3)?
+            .copy_from_slice(&i16::try_from(offset).ok()?.to_le_bytes());
+    }
+
+    Some(())
+}
+
+/// The constant pushed by an instruction, if it can be fused into a following `i.add`
+fn fusable_constant(instruction: &Instruction) -> Option<u8>
+{
+    match instruction.opcode
+    {
+        Opcode::IConst0 => Some(0),
+        Opcode::IConst1 => Some(1),
+        Opcode::IConst2 => Some(2),
+        Opcode::IConst3 => Some(3),
+        Opcode::IConst => instruction.params.first().copied(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod optimizer_tests
+{
+    use super::*;
+
+    const IADD: u8 = Opcode::IAdd as u8;
+    const ICONST: u8 = Opcode::IConst as u8;
+    const ICONST_ADD: u8 = Opcode::IConstAdd as u8;
+    const ICONST1: u8 = Opcode::IConst1 as u8;
+    const JMP: u8 = Opcode::Jmp as u8;
+    const JMP_IF_TRUE: u8 = Opcode::JmpIfTrue as u8;
+    const LD_ARG0: u8 = Opcode::LdArg0 as u8;
+    const NOP: u8 = Opcode::Nop as u8;
+    const RET: u8 = Opcode::Ret as u8;
+    const RET_VAL: u8 = Opcode::RetVal as u8;
+
+    #[test]
+    fn fuses_constant_add()
+    {
+        assert_eq!(
+            peephole_optimize(&[LD_ARG0, ICONST, 42, IADD, ICONST1, IADD, RET_VAL]),
+            [LD_ARG0, ICONST_ADD, 42, ICONST_ADD, 1, RET_VAL]
+        );
+    }
+
+    #[test]
+    fn leaves_other_code_alone()
+    {
+        let code = [
+            LD_ARG0,
+            ICONST,
+            42,
+            Opcode::ISub as u8,
+            ICONST,
+            1,
+            LD_ARG0,
+            IADD,
+            RET_VAL,
+        ];
+        assert_eq!(peephole_optimize(&code), code);
+    }
+
+    #[test]
+    fn adjusts_jumps()
+    {
+        #[rustfmt::skip]
+        let code = [
+            JMP, 11, 0,            // 0: Forwards to 11
+            LD_ARG0,               // 3
+            ICONST, 5, IADD,       // 4: Fused
+            JMP_IF_TRUE, 252, 255, // 7: Backwards to 3
+            RET_VAL,               // 10
+            LD_ARG0,               // 11
+            RET_VAL,               // 12
+        ];
+
+        #[rustfmt::skip]
+        let expected = [
+            JMP, 10, 0,            // 0: Forwards to 10
+            LD_ARG0,               // 3
+            ICONST_ADD, 5,         // 4
+            JMP_IF_TRUE, 253, 255, // 6: Backwards to 3
+            RET_VAL,               // 9
+            LD_ARG0,               // 10
+            RET_VAL,               // 11
+        ];
+
+        assert_eq!(peephole_optimize(&code), expected);
+    }
+
+    #[test]
+    fn jump_target_not_fused()
+    {
+        // The add is jumped to directly, so has to stay on its own
+        let code = [ICONST1, ICONST1, IADD, JMP, 255, 255];
+        assert_eq!(peephole_optimize(&code), code);
+    }
+
+    #[test]
+    fn removes_nops()
+    {
+        let mut code = vec![NOP; 100];
+        code.push(RET);
+        assert_eq!(remove_nops(&code), [RET]);
+
+        // Wider no-ops are kept, along with any operands that happen to be zero
+        let code = [Opcode::NopW as u8, 0, ICONST, 0, NOP, RET_VAL];
+        assert_eq!(remove_nops(&code), [Opcode::NopW as u8, 0, ICONST, 0, RET_VAL]);
+    }
+
+    #[test]
+    fn nop_jumps_adjusted()
+    {
+        #[rustfmt::skip]
+        let code = [
+            NOP,                   // 0
+            JMP, 6, 0,             // 1: Forwards to 7
+            NOP, NOP,              // 4
+            NOP,                   // 6
+            NOP,                   // 7: Removed, so the jump lands on what follows
+            LD_ARG0,               // 8
+            JMP_IF_TRUE, 248, 255, // 9: Backwards to 1
+            RET,                   // 12
+        ];
+
+        #[rustfmt::skip]
+        let expected = [
+            JMP, 3, 0,             // 0: Forwards to 3
+            LD_ARG0,               // 3
+            JMP_IF_TRUE, 252, 255, // 4: Backwards to 0
+            RET,                   // 7
+        ];
+
+        assert_eq!(remove_nops(&code), expected);
+    }
+
+    #[test]
+    fn invalid_code_unchanged()
+    {
+        // An unknown opcode, a truncated instruction, and a jump into the middle of an instruction
+        for code in [
+            vec![ICONST, 1, IADD, 200],
+            vec![ICONST, 1, IADD, ICONST],
+            vec![ICONST, 1, IADD, JMP, 254, 255],
+        ]
+        {
+            assert_eq!(peephole_optimize(&code), code);
+            assert_eq!(remove_nops(&code), code);
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use alloc::vec::Vec;
+
+/// Records how many times each opcode is run, and how long it spends running in total.
+///
+/// Times are measured in CPU cycles where a cycle counter is available (x86-64), and in nanoseconds
+/// everywhere else.
+pub struct OpcodeProfiler
+{
+    // The total time taken and number of runs for each opcode, indexed by the opcode's byte
+    opcodes: [(u64, u64); 256],
+}
+
+impl Default for OpcodeProfiler
+{
+    fn default() -> Self
+    {
+        Self::new()
+    }
+}
+
+impl OpcodeProfiler
+{
+    #[must_use]
+    pub const fn new() -> Self
+    {
+        Self { opcodes: [(0, 0); 256] }
+    }
+
+    /// Record a single run of an opcode that took `cycles` to complete
+    pub fn record_opcode(&mut self, opcode: u8, cycles: u64)
+    {
+        let entry = &mut self.opcodes[usize::from(opcode)];
+        entry.0 = entry.0.saturating_add(cycles);
+        entry.1 += 1;
+    }
+
+    /// Get every opcode that has been run, as (`opcode_byte`, `total_cycles`, `call_count`), with the
+    /// opcodes that took the longest in total first
+    #[must_use]
+    pub fn report(&self) -> Vec<(u8, u64, u64)>
+    {
+        let mut report: Vec<_> = (0..=u8::MAX)
+            .zip(self.opcodes)
+            .filter(|&(_, (_, count))| count > 0)
+            .map(|(opcode, (total, count))| (opcode, total, count))
+            .collect();
+
+        report.sort_by(|x, y| y.1.cmp(&x.1).then(x.0.cmp(&y.0)));
+        report
+    }
+}
+
+/// Run `function`, returning its result along with how long it took to run
+pub(crate) fn measure<T, F: FnOnce() -> T>(function: F) -> (T, u64)
+{
+    #[cfg(target_arch = "x86_64")]
+    {
+        use core::arch::x86_64::_rdtsc;
+
+        // Reading the timestamp counter has no preconditions, and every x86-64 processor has one
+        let start = unsafe { _rdtsc() };
+        let result = function();
+        let end = unsafe { _rdtsc() };
+
+        (result, end.saturating_sub(start))
+    }
+
+    #[cfg(not(target_arch = "x86_64"))]
+    {
+        use std::time::Instant;
+
+        let start = Instant::now();
+        let result = function();
+        (result, u64::try_from(start.elapsed().as_nanos()).unwrap_or(u64::MAX))
+    }
+}
+
+#[cfg(test)]
+mod profiler_tests
+{
+    use super::*;
+
+    #[test]
+    fn empty_report()
+    {
+        assert!(OpcodeProfiler::new().report().is_empty());
+    }
+
+    #[test]
+    fn sorted_by_total()
+    {
+        let mut profiler = OpcodeProfiler::new();
+        profiler.record_opcode(3, 10);
+        profiler.record_opcode(27, 5);
+        profiler.record_opcode(27, 7);
+        profiler.record_opcode(0, 1);
+        profiler.record_opcode(255, 12);
+
+        // Ties are broken by the opcode's byte
+        assert_eq!(profiler.report(), [(27, 12, 2), (255, 12, 1), (3, 10, 1), (0, 1, 1)]);
+    }
+}
@@ -0,0 +1,146 @@
+use alloc::{string::String, vec, vec::Vec};
+
+use crate::engine::stack::{Stack, StackEntry};
+
+/// Everything needed to carry on with a program that yielded, so that it can be stored away and carried on
+/// with later, or by a different runner.
+///
+/// Only the function that yielded is captured, not anything else about the runner, such as its gas or heap.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunnerSnapshot
+{
+    function: String, // The name of the function that yielded
+    pc: usize,
+    stack_bytes: Vec<StackEntry>,  // The operand stack, from bottom to top
+    locals_bytes: Vec<StackEntry>, // Every local of the function, in order
+}
+
+impl RunnerSnapshot
+{
+    pub(crate) const fn new(
+        function: String,
+        pc: usize,
+        stack_bytes: Vec<StackEntry>,
+        locals_bytes: Vec<StackEntry>,
+    ) -> Self
+    {
+        Self {
+            function,
+            pc,
+            stack_bytes,
+            locals_bytes,
+        }
+    }
+
+    #[must_use]
+    pub fn function(&self) -> &str
+    {
+        &self.function
+    }
+
+    /// Where in the function to carry on from
+    #[must_use]
+    pub const fn pc(&self) -> usize
+    {
+        self.pc
+    }
+
+    #[must_use]
+    pub fn stack(&self) -> &[StackEntry]
+    {
+        &self.stack_bytes
+    }
+
+    #[must_use]
+    pub fn locals(&self) -> &[StackEntry]
+    {
+        &self.locals_bytes
+    }
+
+    /// Write the snapshot out in the form read by `from_bytes`.
+    ///
+    /// The function name, operand stack and locals are each prefixed with their length as 4 bytes, and the
+    /// program counter takes up 8. Everything is little endian.
+    #[must_use]
+    pub fn to_bytes(&self) -> Vec<u8>
+    {
+        let mut output = Self::length(self.function.len()).to_le_bytes().to_vec();
+        output.extend_from_slice(self.function.as_bytes());
+        output.extend_from_slice(&(self.pc as u64).to_le_bytes());
+
+        for entries in [&self.stack_bytes, &self.locals_bytes]
+        {
+            output.extend_from_slice(&Self::length(entries.len()).to_le_bytes());
+            for entry in entries
+            {
+                output.extend_from_slice(&entry.to_le_bytes());
+            }
+        }
+
+        output
+    }
+
+    /// Read back a snapshot written by `to_bytes`, failing if anything is missing or left over
+    #[must_use]
+    pub fn from_bytes(input: &[u8]) -> Option<Self>
+    {
+        let (&length, rem) = input.split_first_chunk::<4>()?;
+        let (name, rem) = rem.split_at_checked(u32::from_le_bytes(length) as usize)?;
+        let function = String::from_utf8(name.to_vec()).ok()?;
+
+        let (&pc, mut rem) = rem.split_first_chunk::<8>()?;
+        let pc = usize::try_from(u64::from_le_bytes(pc)).ok()?;
+
+        let mut entries = [vec![], vec![]];
+        for list in &mut entries
+        {
+            let (&count, entry_bytes) = rem.split_first_chunk::<4>()?;
+            let (entry_bytes, after) =
+                entry_bytes.split_at_checked((u32::from_le_bytes(count) as usize).checked_mul(Stack::ENTRY_SIZE)?)?;
+
+            list.extend(
+                entry_bytes
+                    .chunks_exact(Stack::ENTRY_SIZE)
+                    .filter_map(|x| x.try_into().ok().map(StackEntry::from_le_bytes)),
+            );
+            rem = after;
+        }
+
+        let [stack_bytes, locals_bytes] = entries;
+        rem.is_empty().then_some(Self {
+            function,
+            pc,
+            stack_bytes,
+            locals_bytes,
+        })
+    }
+
+    /// Convert a length to the 4 byte form it is written as
+    #[expect(
+        clippy::cast_possible_truncation,
+        reason = "The name comes from a file, where lengths are 4 bytes, and the rest all fits on the stack"
+    )]
+    const fn length(length: usize) -> u32
+    {
+        length as u32
+    }
+}
+
+#[cfg(test)]
+mod snapshot_tests
+{
+    use super::*;
+
+    #[test]
+    fn bytes_round_trip()
+    {
+        let snapshot = RunnerSnapshot::new("main".to_owned(), 7, vec![1, u64::MAX], vec![3]);
+        let bytes = snapshot.to_bytes();
+        assert_eq!(RunnerSnapshot::from_bytes(&bytes), Some(snapshot));
+
+        // Anything cut short or left over is rejected
+        assert_eq!(RunnerSnapshot::from_bytes(&bytes[..bytes.len() - 1]), None);
+        assert_eq!(RunnerSnapshot::from_bytes(&[bytes.as_slice(), &[0]].concat()), None);
+        assert_eq!(RunnerSnapshot::from_bytes(&[]), None);
+    }
+}
@@ -36,5 +36,7 @@ impl_convert! {
     i64 => f32,
     f64 => f32,
     i64 => f64,
-    f32 => f64
+    f32 => f64,
+    f32 => u32, // Saturating, so that anything negative or NaN becomes 0
+    f64 => u64
 }
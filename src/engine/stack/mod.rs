@@ -1,6 +1,10 @@
 pub mod convert;
 pub mod stackable;
 
+use alloc::{format, string::String, vec, vec::Vec};
+
+use crate::{engine::stack::stackable::Stackable, loader::parser::LocalType};
+
 // Stack size is set at initiation and is hard coded somewhere.
 // Theoretically this could become a config value at some point in the future
 
@@ -34,6 +38,7 @@ impl Stack
     /// to stitch 64-bit values back together when stored on a 32-bit stack.
     pub const ENTRY_SIZE: usize = size_of::<StackEntry>();
 
+    #[must_use]
     pub fn new(capacity: usize) -> Self
     {
         Stack {
@@ -41,6 +46,14 @@ impl Stack
         }
     }
 
+    /// Use memory provided by the caller as a stack, instead of allocating it.
+    ///
+    /// This is for when the runtime is embedded in something that wants to decide where the stack lives.
+    pub fn from_raw_parts(data: &mut [StackEntry]) -> StackBorrowed<'_>
+    {
+        StackBorrowed { stack: data }
+    }
+
     /// Creates the initial base stack frame based on the given locals and stack size.
     ///
     /// ### Warning
@@ -48,8 +61,51 @@ impl Stack
     /// the operation will fail.
     pub fn initial_frame(&mut self, locals_size: usize, stack_size: usize) -> Option<StackFrame<'_>>
     {
-        (locals_size + stack_size <= self.stack.len())
-            .then(|| StackFrame::new(self, 0, locals_size, locals_size + stack_size))
+        StackFrame::initial(&mut self.stack, locals_size, stack_size)
+    }
+
+    /// The entire contents of the stack, including anything left behind by frames that have finished
+    #[must_use]
+    pub fn entries(&self) -> &[StackEntry]
+    {
+        &self.stack
+    }
+
+    /// Format the entire contents of the stack, one entry per line as its little endian bytes.
+    ///
+    /// The format is kept stable, so that it can be compared against in tests.
+    #[must_use]
+    pub fn dump(&self) -> String
+    {
+        let lines: Vec<String> = self
+            .stack
+            .iter()
+            .enumerate()
+            .map(|(index, entry)| {
+                let bytes: Vec<String> = entry.to_le_bytes().iter().map(|x| format!("{x:02x}")).collect();
+                format!("{index:04}: {}\n", bytes.join(" "))
+            })
+            .collect();
+
+        lines.concat()
+    }
+}
+
+/// A stack made from memory that has been borrowed, rather than owned like `Stack`.
+///
+/// The memory is left as it is when this is dropped, so anything written to it can still be read afterwards.
+#[derive(Debug)]
+pub struct StackBorrowed<'a>
+{
+    stack: &'a mut [StackEntry],
+}
+
+impl StackBorrowed<'_>
+{
+    /// Creates the initial base stack frame, in the same way as `Stack::initial_frame`
+    pub fn initial_frame(&mut self, locals_size: usize, stack_size: usize) -> Option<StackFrame<'_>>
+    {
+        StackFrame::initial(self.stack, locals_size, stack_size)
     }
 }
 
@@ -64,30 +120,38 @@ impl Stack
 ///
 /// ## Example
 /// ```
-///     entry.push(1); // Add 1 onto the stack
-///     assert_eq!(entry.pop(), Some(1)); // The variable on top of the stack is 1
+/// # use azimuth_runtime::engine::stack::Stack;
+/// let mut stack = Stack::new(16);
+/// let mut entry = stack.initial_frame(2, 4).unwrap();
 ///
-///     entry.set_local(0, 1); // Set local variable 0 to 1
-///     assert_eq!(entry.get_local(0), Some(1));
+/// entry.push(1); // Add 1 onto the stack
+/// assert_eq!(entry.pop(), Some(1)); // The variable on top of the stack is 1
 ///
-///     entry.with_next_frame(|x| {
-///         entry.push(1);
-///         assert_eq(entry.peek(), Some(1));
-///     })
+/// entry.set_local(0, 1); // Set local variable 0 to 1
+/// assert_eq!(entry.get_local(0), Some(1));
+///
+/// entry.with_next_frame(2, 4, |mut next| {
+///     next.push(1);
+///     assert_eq!(next.peek(), Some(&1));
+/// });
 /// ```
 #[derive(Debug)]
 pub struct StackFrame<'a>
 {
-    origin: &'a mut Stack, //
+    origin: &'a mut [StackEntry], // The whole of the stack, not just this frame
     locals_base: usize,
     stack_base: usize,
     stack_pointer: usize,
     size: usize,
+
+    // The type of each value on the operand stack, from bottom to top. The stack itself is untyped, so this
+    // is only as accurate as whatever pushed each value.
+    type_stack: Vec<LocalType>,
 }
 
 impl<'a> StackFrame<'a>
 {
-    pub fn new(origin: &'a mut Stack, locals_base: usize, stack_base: usize, size: usize) -> Self
+    pub fn new(origin: &'a mut [StackEntry], locals_base: usize, stack_base: usize, size: usize) -> Self
     {
         StackFrame {
             origin,
@@ -95,9 +159,16 @@ impl<'a> StackFrame<'a>
             stack_base,
             stack_pointer: 0,
             size,
+            type_stack: vec![],
         }
     }
 
+    /// The first frame on a stack, if it fits
+    fn initial(origin: &'a mut [StackEntry], locals_size: usize, stack_size: usize) -> Option<Self>
+    {
+        (locals_size + stack_size <= origin.len()).then(|| Self::new(origin, 0, locals_size, locals_size + stack_size))
+    }
+
     /// Runs the given function within the context of the "next" stack frame.
     ///
     /// This functions creates a new stack frame on top of the current one, and will then run
@@ -113,7 +184,7 @@ impl<'a> StackFrame<'a>
     where
         F: FnOnce(StackFrame<'a>),
     {
-        (self.size + locals_size + stack_size <= self.origin.stack.len()) // Check if the new frame fits
+        (self.size + locals_size + stack_size <= self.origin.len()) // Check if the new frame fits
             .then(|| {
                 // Create the new frame and run the action given it.
                 action(StackFrame::new(
@@ -137,20 +208,41 @@ impl<'a> StackFrame<'a>
      * call site, but in general these errors are rarely recoverable.
      */
 
-    /// Push value onto the stack.
+    /// Push value onto the stack. Its type isn't known, so it is treated as an integer.
     ///
     /// ### Possibles Errors
     /// Stack Overflow - returns `false`
     pub fn push(&mut self, value: StackEntry) -> bool
+    {
+        self.push_as(value, LocalType::Int)
+    }
+
+    /// Push a value onto the stack, keeping track of its type.
+    ///
+    /// ### Possibles Errors
+    /// Stack Overflow - returns `false`
+    pub fn push_typed<T>(&mut self, value: T) -> bool
+    where
+        T: Stackable,
+    {
+        self.push_as(value.into_entry(), T::TYPE)
+    }
+
+    /// Push a value onto the stack, recording it as the given type.
+    ///
+    /// ### Possibles Errors
+    /// Stack Overflow - returns `false`
+    pub fn push_as(&mut self, value: StackEntry, value_type: LocalType) -> bool
     {
         // Stack Overflow check
-        if self.stack_pointer > self.size
+        if self.stack_pointer >= self.stack_capacity()
         {
             return false;
         }
 
-        self.origin.stack[self.stack_base + self.stack_pointer] = value;
+        self.origin[self.stack_base + self.stack_pointer] = value;
         self.stack_pointer += 1;
+        self.type_stack.push(value_type);
         true
     }
 
@@ -161,10 +253,18 @@ impl<'a> StackFrame<'a>
     /// Empty Stack - return `None`
     pub fn pop(&mut self) -> Option<StackEntry>
     {
-        (self.stack_pointer > 0).then(|| {
-            self.stack_pointer -= 1;
-            self.origin.stack[self.stack_base + self.stack_pointer]
-        })
+        self.pop_typed().map(|(value, _)| value)
+    }
+
+    /// Pops a value off the stack, along with the type it was pushed as.
+    ///
+    /// ### Possible Errors
+    /// Empty Stack - return `None`
+    pub fn pop_typed(&mut self) -> Option<(StackEntry, LocalType)>
+    {
+        let value_type = self.type_stack.pop()?;
+        self.stack_pointer -= 1;
+        Some((self.origin[self.stack_base + self.stack_pointer], value_type))
     }
 
     /// Peeks at the element on the top of the stack without removing it,
@@ -172,19 +272,53 @@ impl<'a> StackFrame<'a>
     ///
     /// ### Possible Errors
     /// Empty Stack - return `None`
+    #[must_use]
     pub fn peek(&self) -> Option<&StackEntry>
     {
-        (self.stack_pointer > 0).then(|| &self.origin.stack[self.stack_base + self.stack_pointer])
+        (self.stack_pointer > 0).then(|| &self.origin[self.stack_base + self.stack_pointer - 1])
+    }
+
+    /// Get the type the value on top of the stack was pushed as, without removing it
+    #[must_use]
+    pub fn peek_type(&self) -> Option<LocalType>
+    {
+        self.type_stack.last().copied()
+    }
+
+    /// The number of values currently on the stack
+    #[must_use]
+    pub const fn depth(&self) -> usize
+    {
+        self.stack_pointer
+    }
+
+    /// Pick the operand stack back up at the given depth, keeping whatever values were left below it.
+    ///
+    /// ### Possible Errors
+    /// Stack Overflow - returns `false`
+    pub(crate) fn restore_depth(&mut self, depth: usize) -> bool
+    {
+        // The types of any values left below aren't known any more
+        (depth <= self.stack_capacity())
+            .then(|| {
+                self.stack_pointer = depth;
+                self.type_stack.resize(depth, LocalType::Int);
+            })
+            .is_some()
     }
 
     /// Get the value of a local variable at the given index.
     ///
     /// ### Possible Errors
     /// Index out of Bounds - return `None`
+    #[must_use]
     pub fn get_local(&self, index: usize) -> Option<StackEntry>
     {
-        let idx = self.locals_base + index;
-        (idx < self.stack_base + self.size).then(|| self.origin.stack[idx])
+        // The locals sit directly below the stack
+        self.locals_base
+            .checked_add(index)
+            .filter(|&idx| idx < self.stack_base)
+            .map(|idx| self.origin[idx])
     }
 
     /// Set the value of a local variable at the given index, returning the previous
@@ -194,19 +328,59 @@ impl<'a> StackFrame<'a>
     /// Index out of Bounds - return `None`
     pub fn set_local(&mut self, index: usize, value: StackEntry) -> Option<StackEntry>
     {
-        let idx = self.locals_base + index; // Calculate the index based on the offset from the local base
-        (idx < self.stack_base + self.size).then(|| {
-            let prev = self.origin.stack[idx]; // Store previous value to return
-            self.origin.stack[idx] = value;
+        // Calculate the index based on the offset from the local base
+        let idx = self
+            .locals_base
+            .checked_add(index)
+            .filter(|&idx| idx < self.stack_base)?;
+
+        let prev = self.origin[idx]; // Store previous value to return
+        self.origin[idx] = value;
+
+        Some(prev)
+    }
+
+    /// Format the frame's locals, the values on its operand stack from bottom to top, and its stack pointer.
+    ///
+    /// The format is kept stable, so that it can be compared against in tests.
+    #[must_use]
+    pub fn dump(&self) -> String
+    {
+        let locals = &self.origin[self.locals_base..self.stack_base];
+        let operands = &self.origin[self.stack_base..self.stack_base + self.stack_pointer];
+
+        let entries = |values: &[StackEntry]| -> String {
+            let lines: Vec<String> = values
+                .iter()
+                .enumerate()
+                .map(|(index, value)| format!("  [{index}] {value:#018x}\n"))
+                .collect();
+            lines.concat()
+        };
+
+        format!(
+            "locals:\n{}stack (depth {}):\n{}sp: {}\n",
+            entries(locals),
+            operands.len(),
+            entries(operands),
+            self.stack_pointer
+        )
+    }
 
-            prev
-        })
+    /// The maximum number of values the stack component of the frame can hold
+    const fn stack_capacity(&self) -> usize
+    {
+        self.size - (self.stack_base - self.locals_base)
     }
 }
 
 #[cfg(test)]
 mod stack_tests
 {
+    use std::mem;
+
+    use proptest::prelude::*;
+
     use super::*;
 
     #[test]
@@ -265,6 +439,70 @@ mod stack_tests
         assert!(frame.pop().is_none());
     }
 
+    #[test]
+    fn stack_frame_dump()
+    {
+        let mut stack = Stack::new(8);
+        let mut frame = stack.initial_frame(2, 4).unwrap();
+
+        frame.set_local(0, 10);
+        frame.set_local(1, u64::MAX);
+        frame.push(1);
+        frame.push(0xdead_beef);
+
+        assert_eq!(
+            frame.dump(),
+            "locals:
+  [0] 0x000000000000000a
+  [1] 0xffffffffffffffff
+stack (depth 2):
+  [0] 0x0000000000000001
+  [1] 0x00000000deadbeef
+sp: 2
+"
+        );
+    }
+
+    #[test]
+    fn stack_dump()
+    {
+        let mut stack = Stack::new(3);
+        let mut frame = stack.initial_frame(1, 2).unwrap();
+
+        frame.set_local(0, 0x0102);
+        frame.push(u64::MAX);
+
+        assert_eq!(
+            stack.dump(),
+            "0000: 02 01 00 00 00 00 00 00
+0001: ff ff ff ff ff ff ff ff
+0002: 00 00 00 00 00 00 00 00
+"
+        );
+    }
+
+    #[test]
+    fn borrowed_stack()
+    {
+        let mut data: [StackEntry; 1024] = [0; 1024];
+        let mut stack = Stack::from_raw_parts(&mut data);
+
+        assert!(stack.initial_frame(1000, 25).is_none());
+        let mut frame = stack.initial_frame(2, 4).unwrap();
+
+        frame.set_local(1, 7);
+        frame.push(10);
+        frame.push(1 << 33);
+
+        assert_eq!(frame.pop(), Some(1 << 33));
+        assert_eq!(frame.pop(), Some(10));
+        assert_eq!(frame.pop(), None);
+
+        // Everything was written straight into the borrowed memory
+        drop(stack);
+        assert_eq!(data[..4], [0, 7, 10, 1 << 33]);
+    }
+
     #[test]
     fn stack_frame_doubles()
     {
@@ -289,4 +527,108 @@ mod stack_tests
         assert_eq!(frame.get_local(0), Some(10));
         assert_eq!(frame.get_local(1), Some(1 << 33));
     }
+
+    #[test]
+    fn stack_frame_types()
+    {
+        let mut stack = Stack::new(1024);
+        let mut frame = stack.initial_frame(4, 4).unwrap();
+
+        assert_eq!(frame.peek_type(), None);
+        frame.push(10);
+        frame.push_typed(1.5_f32);
+        frame.push_as(8, LocalType::Ptr);
+
+        assert_eq!(frame.pop_typed(), Some((8, LocalType::Ptr)));
+        assert_eq!(frame.peek_type(), Some(LocalType::Float32));
+        assert_eq!(frame.pop_typed(), Some((1.5_f32.into_entry(), LocalType::Float32)));
+        assert_eq!(frame.pop_typed(), Some((10, LocalType::Int)));
+        assert_eq!(frame.pop_typed(), None);
+
+        // Nothing is known about values that are picked back up
+        assert!(frame.restore_depth(2));
+        assert_eq!(frame.pop_typed(), Some((1.5_f32.into_entry(), LocalType::Int)));
+    }
+
+    #[test]
+    fn stack_frame_peek()
+    {
+        let mut stack = Stack::new(1024);
+        let mut frame = stack.initial_frame(4, 4).unwrap();
+
+        assert_eq!(frame.peek(), None);
+        frame.push(10);
+        frame.push(20);
+
+        assert_eq!(frame.peek(), Some(&20));
+        assert_eq!(frame.depth(), 2);
+    }
+
+    const MAX_LOCALS: usize = 4;
+    const MAX_STACK: usize = 8;
+
+    #[derive(Debug, Clone)]
+    enum Operation
+    {
+        Push(StackEntry),
+        Pop,
+        Peek,
+        SetLocal(usize, StackEntry),
+        GetLocal(usize),
+    }
+
+    fn operation() -> impl Strategy<Value = Operation>
+    {
+        // Indices are allowed to go past the end of the locals, to check that those accesses are refused
+        prop_oneof![
+            any::<StackEntry>().prop_map(Operation::Push),
+            Just(Operation::Pop),
+            Just(Operation::Peek),
+            (0..MAX_LOCALS + 2, any::<StackEntry>()).prop_map(|(index, value)| Operation::SetLocal(index, value)),
+            (0..MAX_LOCALS + 2).prop_map(Operation::GetLocal),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn stack_frame_matches_model(operations in prop::collection::vec(operation(), 0..64))
+        {
+            // Values either side of the frame, which must never be touched
+            let mut stack = Stack::new(MAX_LOCALS + MAX_STACK + 2);
+            let mut frame = stack.initial_frame(MAX_LOCALS, MAX_STACK).unwrap();
+
+            let mut model_stack = vec![];
+            let mut model_locals = [0; MAX_LOCALS];
+
+            for operation in operations
+            {
+                match operation
+                {
+                    Operation::Push(value) =>
+                    {
+                        let expected = model_stack.len() < MAX_STACK;
+                        prop_assert_eq!(frame.push(value), expected);
+                        if expected
+                        {
+                            model_stack.push(value);
+                        }
+                    }
+                    Operation::Pop => prop_assert_eq!(frame.pop(), model_stack.pop()),
+                    Operation::Peek => prop_assert_eq!(frame.peek(), model_stack.last()),
+                    Operation::SetLocal(index, value) =>
+                    {
+                        let expected = model_locals.get_mut(index).map(|x| mem::replace(x, value));
+                        prop_assert_eq!(frame.set_local(index, value), expected);
+                    }
+                    Operation::GetLocal(index) => prop_assert_eq!(frame.get_local(index), model_locals.get(index).copied()),
+                }
+
+                prop_assert!(frame.depth() <= MAX_STACK);
+                prop_assert_eq!(frame.depth(), model_stack.len());
+            }
+
+            drop(frame);
+            prop_assert!(stack.stack[MAX_LOCALS + MAX_STACK..].iter().all(|&x| x == 0));
+        }
+    }
 }
@@ -1,13 +1,17 @@
-use crate::engine::stack::StackEntry;
+use crate::{engine::stack::StackEntry, loader::parser::LocalType};
 
 pub trait Stackable: Copy
 {
+    const TYPE: LocalType; // What the value is tracked as once it is on the stack
+
     fn into_entry(self) -> StackEntry;
     fn from_entry(entry: StackEntry) -> Self;
 }
 
 impl Stackable for StackEntry
 {
+    const TYPE: LocalType = LocalType::Int;
+
     fn into_entry(self) -> StackEntry
     {
         self
@@ -21,6 +25,8 @@ impl Stackable for StackEntry
 
 impl Stackable for i64
 {
+    const TYPE: LocalType = LocalType::Int;
+
     fn into_entry(self) -> StackEntry
     {
         // The compiler should be intelligent enough to realise this is a no-op
@@ -36,6 +42,8 @@ impl Stackable for i64
 
 impl Stackable for u32
 {
+    const TYPE: LocalType = LocalType::Int;
+
     fn into_entry(self) -> StackEntry
     {
         self.into()
@@ -50,6 +58,8 @@ impl Stackable for u32
 
 impl Stackable for f32
 {
+    const TYPE: LocalType = LocalType::Float32;
+
     fn into_entry(self) -> StackEntry
     {
         StackEntry::from(self.to_bits())
@@ -64,6 +74,8 @@ impl Stackable for f32
 
 impl Stackable for f64
 {
+    const TYPE: LocalType = LocalType::Float64;
+
     fn into_entry(self) -> StackEntry
     {
         self.to_bits()
@@ -77,6 +89,8 @@ impl Stackable for f64
 
 impl<T> Stackable for *const T
 {
+    const TYPE: LocalType = LocalType::Ptr;
+
     fn into_entry(self) -> StackEntry
     {
         self as StackEntry
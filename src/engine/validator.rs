@@ -0,0 +1,458 @@
+use alloc::{vec, vec::Vec};
+
+use crate::{
+    engine::{
+        decode::{Decoder, Instruction},
+        opcodes::Opcode,
+    },
+    loader::parser::ExceptionEntry,
+};
+
+/// An issue found within a stream of bytecode during validation.
+///
+/// Each variant carries the byte offset (`at`) of the instruction
+/// that caused the issue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValidationError
+{
+    UnknownOpcode
+    {
+        at: usize, byte: u8
+    },
+    MissingParams
+    {
+        at: usize
+    },
+    InvalidJumpTarget
+    {
+        at: usize, offset: isize
+    },
+    MissingReturn
+    {
+        at: usize
+    },
+    StackUnderflow
+    {
+        at: usize
+    },
+    StackImbalance
+    {
+        at: usize, expected: usize, found: usize
+    },
+    // An exception table entry whose try range is empty, or doesn't start and end on instruction boundaries.
+    // Rather than an instruction, this carries the range itself.
+    InvalidTryRange
+    {
+        start: usize, end: usize
+    },
+    // An exception table entry whose handler isn't the start of an instruction
+    InvalidHandler
+    {
+        at: usize
+    },
+    // A function of a lazily loaded file couldn't be parsed, so its code couldn't be checked. Rather than an
+    // offset, this carries the position of the function in the file.
+    MalformedFunction
+    {
+        index: usize
+    },
+}
+
+/// Statically verifies bytecode before it is executed.
+///
+/// Rather than stopping at the first problem, the validator scans the entire
+/// bytecode stream and reports every issue it finds at once.
+pub struct BytecodeValidator;
+
+impl BytecodeValidator
+{
+    #[must_use]
+    pub fn validate(&self, code: &[u8]) -> Vec<ValidationError>
+    {
+        self.validate_with_exceptions(code, &[])
+    }
+
+    /// Validate the code in the same way as `validate`, along with the exception table entries covering it
+    #[must_use]
+    pub fn validate_with_exceptions(&self, code: &[u8], exceptions: &[ExceptionEntry]) -> Vec<ValidationError>
+    {
+        let (instructions, mut errors) = Self::decode(code);
+        let is_boundary = |offset: usize| instructions.binary_search_by_key(&offset, |x| x.at).is_ok();
+
+        // Every jump must land on the start of an instruction
+        errors.extend(instructions.iter().filter_map(|instruction| {
+            let offset = instruction.branch_offset()?;
+            instruction
+                .at
+                .checked_add_signed(offset)
+                .and_then(|target| instructions.binary_search_by_key(&target, |x| x.at).ok())
+                .is_none()
+                .then_some(ValidationError::InvalidJumpTarget {
+                    at: instruction.at,
+                    offset,
+                })
+        }));
+
+        // Execution must never be able to fall off the end of the function
+        if instructions.last().is_none_or(|x| Self::falls_through(x.opcode))
+        {
+            errors.push(ValidationError::MissingReturn { at: code.len() });
+        }
+
+        errors.extend(Self::check_stack(&instructions, exceptions));
+
+        // Try ranges cover whole instructions, the end being just past the last one, and handlers are jumped to
+        for entry in exceptions
+        {
+            let (start, end) = (usize::from(entry.try_start), usize::from(entry.try_end));
+            if start >= end || !is_boundary(start) || !(is_boundary(end) || end == code.len())
+            {
+                errors.push(ValidationError::InvalidTryRange { start, end });
+            }
+
+            let handler = usize::from(entry.handler_pc);
+            if !is_boundary(handler)
+            {
+                errors.push(ValidationError::InvalidHandler { at: handler });
+            }
+        }
+
+        errors
+    }
+
+    /// Check that every path through the bytecode leaves the stack at a consistent depth.
+    ///
+    /// Each instruction records the depth of the stack the first time it is reached. Any
+    /// other path that reaches the same instruction must then arrive with the same depth.
+    /// Exception handlers are reached with an empty stack, as the runner clears it before
+    /// jumping to one.
+    fn check_stack(instructions: &[Instruction], exceptions: &[ExceptionEntry]) -> Vec<ValidationError>
+    {
+        let mut errors = vec![];
+        let mut depths: Vec<Option<usize>> = vec![None; instructions.len()];
+
+        // (instruction index, stack depth on arrival). Handlers that aren't on an instruction have already
+        // been reported, so can be ignored here
+        let handlers = exceptions.iter().filter_map(|entry| {
+            instructions
+                .binary_search_by_key(&usize::from(entry.handler_pc), |x| x.at)
+                .ok()
+                .map(|index| (index, 0))
+        });
+        let mut pending: Vec<(usize, usize)> = handlers.chain([(0, 0)]).collect();
+        while let Some((index, depth)) = pending.pop()
+        {
+            let (Some(instruction), Some(recorded)) = (instructions.get(index), depths.get_mut(index))
+            else
+            {
+                continue; // Falling off the end is reported separately
+            };
+
+            if let Some(expected) = *recorded
+            {
+                if expected != depth
+                {
+                    errors.push(ValidationError::StackImbalance {
+                        at: instruction.at,
+                        expected,
+                        found: depth,
+                    });
+                }
+                continue;
+            }
+            *recorded = Some(depth);
+
+            let (pops, pushes) = instruction.opcode.stack_effect();
+            let Some(remaining) = depth.checked_sub(pops.into())
+            else
+            {
+                errors.push(ValidationError::StackUnderflow { at: instruction.at });
+                continue;
+            };
+            let next_depth = remaining + usize::from(pushes);
+
+            if Self::falls_through(instruction.opcode)
+            {
+                pending.push((index + 1, next_depth));
+            }
+
+            // Invalid jump targets have already been reported, so can be ignored here
+            if let Some(target) = instruction
+                .branch_offset()
+                .and_then(|offset| instruction.at.checked_add_signed(offset))
+                .and_then(|target| instructions.binary_search_by_key(&target, |x| x.at).ok())
+            {
+                pending.push((target, next_depth));
+            }
+        }
+
+        errors
+    }
+
+    /// Split the bytecode up into its instructions, collecting any errors found in the process.
+    fn decode(code: &[u8]) -> (Vec<Instruction<'_>>, Vec<ValidationError>)
+    {
+        let mut instructions = vec![];
+        let mut errors = vec![];
+
+        for result in Decoder::new(code)
+        {
+            match result
+            {
+                Ok(instruction) => instructions.push(instruction),
+                Err(error) => errors.push(error),
+            }
+        }
+
+        (instructions, errors)
+    }
+
+    /// Whether execution can carry on to the next instruction after the given opcode
+    fn falls_through(opcode: Opcode) -> bool
+    {
+        !matches!(opcode, Opcode::Ret | Opcode::RetVal | Opcode::Jmp | Opcode::Halt)
+    }
+}
+
+#[cfg(test)]
+mod validator_tests
+{
+    use super::*;
+
+    #[test]
+    fn valid_code()
+    {
+        let code = [
+            Opcode::IConst1 as u8,
+            Opcode::IConst as u8,
+            5,
+            Opcode::IAdd as u8,
+            Opcode::RetVal as u8,
+        ];
+        assert!(BytecodeValidator.validate(&code).is_empty());
+    }
+
+    #[test]
+    fn unknown_opcode()
+    {
+        let code = [Opcode::Nop as u8, 200, Opcode::Ret as u8];
+        assert_eq!(
+            BytecodeValidator.validate(&code),
+            vec![ValidationError::UnknownOpcode { at: 1, byte: 200 }]
+        );
+    }
+
+    #[test]
+    fn directive_in_code()
+    {
+        let code = [Opcode::Directive as u8, Opcode::Ret as u8];
+        assert_eq!(
+            BytecodeValidator.validate(&code),
+            vec![ValidationError::UnknownOpcode {
+                at: 0,
+                byte: Opcode::Directive as u8
+            }]
+        );
+    }
+
+    #[test]
+    fn missing_params()
+    {
+        let code = [Opcode::Ret as u8, Opcode::Const as u8, 0, 0];
+        assert_eq!(
+            BytecodeValidator.validate(&code),
+            vec![ValidationError::MissingParams { at: 1 }]
+        );
+    }
+
+    #[test]
+    fn missing_return()
+    {
+        assert_eq!(
+            BytecodeValidator.validate(&[]),
+            vec![ValidationError::MissingReturn { at: 0 }]
+        );
+        assert_eq!(
+            BytecodeValidator.validate(&[Opcode::IConst0 as u8, Opcode::Pop as u8]),
+            vec![ValidationError::MissingReturn { at: 2 }]
+        );
+    }
+
+    #[test]
+    fn reports_all_errors()
+    {
+        let code = [255, Opcode::Nop as u8, 254, Opcode::IConstW as u8, 1];
+        assert_eq!(
+            BytecodeValidator.validate(&code),
+            vec![
+                ValidationError::UnknownOpcode { at: 0, byte: 255 },
+                ValidationError::UnknownOpcode { at: 2, byte: 254 },
+                ValidationError::MissingParams { at: 3 },
+                ValidationError::MissingReturn { at: 5 },
+            ]
+        );
+    }
+
+    #[test]
+    fn stack_underflow()
+    {
+        let code = [Opcode::IConst1 as u8, Opcode::IAdd as u8, Opcode::RetVal as u8];
+        assert_eq!(
+            BytecodeValidator.validate(&code),
+            vec![ValidationError::StackUnderflow { at: 1 }]
+        );
+    }
+
+    #[test]
+    fn balanced_stack()
+    {
+        let code = [
+            Opcode::IConst1 as u8,
+            Opcode::Dup as u8,
+            Opcode::Swap as u8,
+            Opcode::StArg0 as u8,
+            Opcode::Pop as u8,
+            Opcode::Ret as u8,
+        ];
+        assert!(BytecodeValidator.validate(&code).is_empty());
+    }
+
+    #[test]
+    fn jumps()
+    {
+        // Forwards past the return, and then back to the start. As the function ends with
+        // an unconditional jump, execution can't fall off the end of it.
+        let code = [
+            Opcode::IConst1 as u8,
+            Opcode::JmpIfTrue as u8,
+            4,
+            0,
+            Opcode::Ret as u8,
+            Opcode::Jmp as u8,
+            0xfb,
+            0xff,
+        ];
+        assert!(BytecodeValidator.validate(&code).is_empty());
+    }
+
+    #[test]
+    fn invalid_jump_target()
+    {
+        // Into the middle of an instruction, and out of the start of the function
+        let code = [
+            Opcode::Jmp as u8,
+            1,
+            0,
+            Opcode::Ret as u8,
+            Opcode::Jmp as u8,
+            0xf0,
+            0xff,
+        ];
+        assert_eq!(
+            BytecodeValidator.validate(&code),
+            vec![
+                ValidationError::InvalidJumpTarget { at: 0, offset: 1 },
+                ValidationError::InvalidJumpTarget { at: 4, offset: -16 },
+            ]
+        );
+    }
+
+    #[test]
+    fn exception_table()
+    {
+        let code = [
+            Opcode::IConst1 as u8,
+            Opcode::IConst as u8,
+            0,
+            Opcode::IDiv as u8,
+            Opcode::RetVal as u8,
+            Opcode::IConst0 as u8,
+            Opcode::RetVal as u8,
+        ];
+        let entry = |try_start, try_end, handler_pc| ExceptionEntry {
+            try_start,
+            try_end,
+            handler_pc,
+            catch_type: 0,
+        };
+
+        // Covering up to the end of the function is allowed
+        assert!(
+            BytecodeValidator
+                .validate_with_exceptions(&code, &[entry(0, 5, 5)])
+                .is_empty()
+        );
+        assert!(
+            BytecodeValidator
+                .validate_with_exceptions(&code, &[entry(3, 7, 5)])
+                .is_empty()
+        );
+
+        assert_eq!(
+            BytecodeValidator
+                .validate_with_exceptions(&code, &[entry(2, 4, 5), entry(0, 2, 5), entry(3, 3, 5), entry(0, 8, 5)]),
+            vec![
+                ValidationError::InvalidTryRange { start: 2, end: 4 },
+                ValidationError::InvalidTryRange { start: 0, end: 2 },
+                ValidationError::InvalidTryRange { start: 3, end: 3 },
+                ValidationError::InvalidTryRange { start: 0, end: 8 },
+            ]
+        );
+        assert_eq!(
+            BytecodeValidator.validate_with_exceptions(&code, &[entry(0, 5, 2), entry(0, 5, 7)]),
+            vec![
+                ValidationError::InvalidHandler { at: 2 },
+                ValidationError::InvalidHandler { at: 7 },
+            ]
+        );
+    }
+
+    #[test]
+    fn handler_stack()
+    {
+        // The handler is only reached through the exception table, and pops from the stack the runner empties
+        let code = [
+            Opcode::IConst1 as u8,
+            Opcode::IConst0 as u8,
+            Opcode::IDiv as u8,
+            Opcode::RetVal as u8,
+            Opcode::Pop as u8,
+            Opcode::IConst0 as u8,
+            Opcode::RetVal as u8,
+        ];
+        let entry = ExceptionEntry {
+            try_start: 0,
+            try_end: 3,
+            handler_pc: 4,
+            catch_type: 0,
+        };
+
+        assert!(BytecodeValidator.validate(&code).is_empty());
+        assert_eq!(
+            BytecodeValidator.validate_with_exceptions(&code, &[entry]),
+            vec![ValidationError::StackUnderflow { at: 4 }]
+        );
+    }
+
+    #[test]
+    fn stack_imbalance()
+    {
+        // The return can be reached both with and without the extra value on the stack
+        let code = [
+            Opcode::IConst1 as u8,
+            Opcode::JmpIfFalse as u8,
+            4,
+            0,
+            Opcode::IConst1 as u8,
+            Opcode::Ret as u8,
+        ];
+        assert_eq!(
+            BytecodeValidator.validate(&code),
+            vec![ValidationError::StackImbalance {
+                at: 5,
+                expected: 0,
+                found: 1
+            }]
+        );
+    }
+}
@@ -0,0 +1,10 @@
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+pub mod common;
+#[cfg(feature = "std")]
+pub mod config;
+pub mod engine;
+pub mod loader;
+pub mod memory;
@@ -1,13 +1,29 @@
 // This is a more formalised wrapper around the idea of the constant table.
-// In the future this can be more "referency" as things will instead be stored in metaspace
+// Anything that can't be stored directly, such as strings, is instead stored in metaspace
+
+use alloc::vec::Vec;
+use core::ptr;
 
 use crate::{
-    engine::{stack::StackFrame, stack::stackable::Stackable as _},
+    engine::stack::StackFrame,
     loader::parser::{Table, TableEntry},
+    memory::metaspace::Metaspace,
 };
 
 pub type ConstantTableIndex = u32;
 
+/// The most constants a file may have by default, so that a bad file can't make the loader use up huge
+/// amounts of memory
+pub const MAX_CONSTANT_POOL_SIZE: usize = 4096;
+
+/// Why a constant couldn't be pushed onto the stack
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConstantPushError
+{
+    IndexOutOfBounds, // There is no constant at the given index
+    StackOverflow,
+}
+
 #[derive(Debug)]
 pub struct ConstantTable<'a>
 {
@@ -21,7 +37,7 @@ pub struct ConstantTable<'a>
 /// of ease of use.
 ///
 /// ## Variants
-/// There are 5 main types of Constant:
+/// There are 7 main types of Constant:
 ///
 /// `Unsigned32` - Stores a `u32` (also called `int` in some languages).
 /// It is important to note that this exists
@@ -38,6 +54,10 @@ pub struct ConstantTable<'a>
 /// `Float64` - Stores a `f64` (also called `double` in some languages)
 ///
 /// `String` - Stores a string reference (the string data is stored in metaspace)
+///
+/// `Boolean` - Stores a `bool`, which is loaded onto the stack as either 0 or 1
+///
+/// `Null` - The null reference, which is loaded onto the stack as 0, but as a pointer rather than an integer
 #[derive(Debug, Clone, Copy)]
 pub enum Constant<'a>
 {
@@ -46,49 +66,216 @@ pub enum Constant<'a>
     Float32(f32),
     Float64(f64),
     String(&'a str),
+    Boolean(bool),
+    Null,
 }
 
 impl<'a> Constant<'a>
 {
-    pub fn from_parsed_entry(entry: &'a TableEntry) -> Self
+    /// Convert a parsed entry into a constant, interning any strings into metaspace.
+    ///
+    /// Returns `None` if the metaspace is unable to store a string.
+    pub fn from_parsed_entry(entry: &TableEntry, metaspace: &'a Metaspace) -> Option<Self>
     {
-        match *entry
+        Some(match *entry
         {
             TableEntry::Integer(x) => Self::Unsigned32(x),
             TableEntry::Long(x) => Self::Unsigned64(x),
             TableEntry::Float(x) => Self::Float32(x),
             TableEntry::Double(x) => Self::Float64(x),
-            TableEntry::String(ref string) => Self::String(string.as_str()),
-        }
+            TableEntry::String(ref string) => Self::String(metaspace.intern_str(string)?),
+            TableEntry::Boolean(x) => Self::Boolean(x != 0),
+            TableEntry::Null => Self::Null,
+        })
     }
 }
 
 impl<'a> ConstantTable<'a>
 {
-    pub fn from_parsed_table(table: &'a Table) -> Self
+    pub fn from_parsed_table(table: &Table, metaspace: &'a Metaspace) -> Option<Self>
     {
-        Self {
-            entries: table.entries().iter().map(Constant::from_parsed_entry).collect(),
-        }
+        Some(Self {
+            entries: table
+                .entries()
+                .iter()
+                .map(|x| Constant::from_parsed_entry(x, metaspace))
+                .collect::<Option<_>>()?,
+        })
     }
 
+    #[must_use]
     pub fn get_entry(&self, index: ConstantTableIndex) -> Option<&Constant<'a>>
     {
         self.entries.get(index as usize)
     }
 
+    /// Get a constant so that its value can be updated in place, such as to patch in the address of
+    /// something that has since been compiled.
+    ///
+    /// ### Safety
+    /// Only the constant's value may be changed, never its type. Bytecode that uses the constant expects
+    /// whatever it pushes onto the stack to be the type it was given in the file.
+    pub unsafe fn get_entry_mut(&mut self, index: ConstantTableIndex) -> Option<&mut Constant<'a>>
+    {
+        self.entries.get_mut(index as usize)
+    }
+
+    /// The number of constants in the table
+    #[must_use]
+    pub fn len(&self) -> usize
+    {
+        self.entries.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool
+    {
+        self.entries.is_empty()
+    }
+
     /// Pushes a constant onto the stack, converting each constant type into a stack
     /// value depending on its type.
-    pub fn push_entry(&self, stack: &mut StackFrame, index: ConstantTableIndex) -> Option<bool>
+    ///
+    /// ### Errors
+    /// If there is no constant at `index`, or there isn't room on the stack for it.
+    pub fn push_entry(&self, stack: &mut StackFrame, index: ConstantTableIndex) -> Result<(), ConstantPushError>
     {
-        self.get_entry(index).map(|x| match *x
+        let entry = self.get_entry(index).ok_or(ConstantPushError::IndexOutOfBounds)?;
+        let pushed = match *entry
         {
-            Constant::Unsigned32(x) => stack.push(x.into_entry()), // expanded into u64
+            Constant::Unsigned32(x) => stack.push_typed(x), // expanded into u64
             Constant::Unsigned64(x) => stack.push(x),
-            Constant::Float32(x) => stack.push(x.into_entry()), // expanded and tranmuted into u64
-            Constant::Float64(x) => stack.push(x.into_entry()), // transmuted into u64
+            Constant::Float32(x) => stack.push_typed(x), // expanded and tranmuted into u64
+            Constant::Float64(x) => stack.push_typed(x), // transmuted into u64
             // Strings a represented on the stack with their reference
-            Constant::String(string) => stack.push(string.as_ptr().into_entry()),
-        })
+            Constant::String(string) => stack.push_typed(string.as_ptr()),
+            Constant::Boolean(x) => stack.push(x.into()),
+            Constant::Null => stack.push_typed(ptr::null::<u8>()),
+        };
+
+        pushed.then_some(()).ok_or(ConstantPushError::StackOverflow)
+    }
+}
+
+#[cfg(test)]
+mod constant_table_tests
+{
+    use super::*;
+    use crate::{engine::stack::Stack, loader::parser::LocalType};
+
+    #[test]
+    fn strings_deduplicated()
+    {
+        let metaspace = Metaspace::new();
+        let table = Table::new(
+            3,
+            &[
+                4, 2, 0, 0, 0, b'h', b'i', // String "hi"
+                0, 5, 0, 0, 0, // Integer 5
+                4, 2, 0, 0, 0, b'h', b'i', // String "hi"
+            ],
+        )
+        .expect("Failed to parse table")
+        .0;
+
+        let constants = ConstantTable::from_parsed_table(&table, &metaspace).expect("Failed to create constant table");
+
+        let (Some(&Constant::String(first)), Some(&Constant::String(second))) =
+            (constants.get_entry(0), constants.get_entry(2))
+        else
+        {
+            panic!("Expected string constants");
+        };
+
+        assert_eq!(first, "hi");
+        assert_eq!(first.as_ptr(), second.as_ptr());
+    }
+
+    #[test]
+    fn push_entry()
+    {
+        let metaspace = Metaspace::new();
+        let mut table = Table::from_entries(vec![TableEntry::Integer(7), TableEntry::Long(u64::MAX)]);
+        table.push(TableEntry::Double(1.5));
+        table.push(TableEntry::Boolean(0));
+        table.push(TableEntry::Boolean(2));
+
+        let constants = ConstantTable::from_parsed_table(&table, &metaspace).expect("Failed to create constant table");
+
+        let mut stack = Stack::new(8);
+        let mut frame = stack.initial_frame(0, 5).expect("Failed to create frame");
+
+        for index in 0..5
+        {
+            assert_eq!(constants.push_entry(&mut frame, index), Ok(()));
+        }
+        assert_eq!(
+            constants.push_entry(&mut frame, 5),
+            Err(ConstantPushError::IndexOutOfBounds)
+        );
+        assert_eq!(
+            constants.push_entry(&mut frame, 0),
+            Err(ConstantPushError::StackOverflow)
+        );
+
+        // Any nonzero value is true
+        assert_eq!(frame.pop(), Some(1));
+        assert_eq!(frame.pop(), Some(0));
+        assert_eq!(frame.pop(), Some(1.5_f64.to_bits()));
+        assert_eq!(frame.pop(), Some(u64::MAX));
+        assert_eq!(frame.pop(), Some(7));
+    }
+
+    #[test]
+    fn push_null()
+    {
+        let metaspace = Metaspace::new();
+        let table = Table::new(2, &[6, 0, 0, 0, 0, 0]).expect("Failed to parse table").0;
+        assert_eq!(table.entries(), [TableEntry::Null, TableEntry::Integer(0)]);
+
+        let constants = ConstantTable::from_parsed_table(&table, &metaspace).expect("Failed to create constant table");
+
+        let mut stack = Stack::new(8);
+        let mut frame = stack.initial_frame(0, 2).expect("Failed to create frame");
+
+        // Null has the same value as integer zero, but is a pointer
+        assert_eq!(constants.push_entry(&mut frame, 0), Ok(()));
+        assert_eq!(constants.push_entry(&mut frame, 1), Ok(()));
+        assert_eq!(frame.pop_typed(), Some((0, LocalType::Int)));
+        assert_eq!(frame.pop_typed(), Some((0, LocalType::Ptr)));
+    }
+
+    #[test]
+    fn patch_entry()
+    {
+        let metaspace = Metaspace::new();
+        let mut table = Table::from_entries(vec![TableEntry::Integer(7)]);
+
+        // Patching the parsed table changes what a constant table is created with
+        let Some(&mut TableEntry::Integer(ref mut value)) = (unsafe { table.get_mut(0) })
+        else
+        {
+            panic!("Expected an integer entry");
+        };
+        *value = 8;
+
+        let mut constants =
+            ConstantTable::from_parsed_table(&table, &metaspace).expect("Failed to create constant table");
+
+        // Patching the constant table changes what is pushed straight away
+        let Some(&mut Constant::Unsigned32(ref mut value)) = (unsafe { constants.get_entry_mut(0) })
+        else
+        {
+            panic!("Expected an integer constant");
+        };
+        assert_eq!(*value, 8);
+        *value = 9;
+        assert!(unsafe { constants.get_entry_mut(1) }.is_none());
+
+        let mut stack = Stack::new(8);
+        let mut frame = stack.initial_frame(0, 4).expect("Failed to create frame");
+
+        assert_eq!(constants.push_entry(&mut frame, 0), Ok(()));
+        assert_eq!(frame.pop(), Some(9));
     }
 }
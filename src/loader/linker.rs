@@ -0,0 +1,237 @@
+use alloc::{borrow::ToOwned as _, collections::BTreeSet, string::String, vec, vec::Vec};
+
+use crate::{
+    engine::opcodes::Opcode,
+    guard,
+    loader::{
+        constant_table::ConstantTableIndex,
+        parser::{DebugEntry, Directive, FileLayout, FunctionInfo, ParseError, Table, TableEntry, WriteError},
+    },
+    memory::metaspace::Metaspace,
+};
+
+/// Combines modules that were compiled separately into a single file that can be loaded on its own.
+///
+/// Their constant pools are merged, with any identical constants only kept once, and their functions are
+/// laid out one after another in the order the modules were added. Every import has to be exported by the
+/// module it names, and exactly one function across all of them has to be the entry point.
+pub struct Linker<'m>
+{
+    metaspace: &'m Metaspace, // Where any code rewritten to use the merged constant pool is kept
+    modules: Vec<(String, FileLayout<'m>)>, // (module_name, layout)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum LinkerError
+{
+    DuplicateSymbol(String),          // More than one function has this name
+    UnresolvedImport(String, String), // (module_name, function_name) not exported by any module
+    NoEntryPoint,
+    DuplicateEntryPoint,                         // More than one function has a start directive
+    InvalidConstant(String, ConstantTableIndex), // (module_name, index) used by the module but not in its pool
+    TooManyConstants,                            // The merged constant pool has more entries than can be indexed
+    CodeTooLarge,                                // The linked functions are too long for debug info to refer to
+    ParseError(ParseError),                      // A function of a lazily parsed module couldn't be parsed
+    WriteError(WriteError),                      // The linked file is too large to be written out
+}
+
+impl From<ParseError> for LinkerError
+{
+    fn from(value: ParseError) -> Self
+    {
+        Self::ParseError(value)
+    }
+}
+
+impl From<WriteError> for LinkerError
+{
+    fn from(value: WriteError) -> Self
+    {
+        Self::WriteError(value)
+    }
+}
+
+impl<'m> Linker<'m>
+{
+    pub fn new(metaspace: &'m Metaspace) -> Self
+    {
+        Self {
+            metaspace,
+            modules: vec![],
+        }
+    }
+
+    /// Add a module to be linked, under the name that other modules import its functions from
+    pub fn add_module(&mut self, name: &str, layout: FileLayout<'m>) -> &mut Self
+    {
+        self.modules.push((name.to_owned(), layout));
+        self
+    }
+
+    /// Link every module added so far into a single file.
+    ///
+    /// Any debug info is kept, moved to wherever each module's functions end up. The linked file has no
+    /// imports left, as they have all been resolved.
+    ///
+    /// ### Errors
+    /// If the modules can't be put together, such as when an import can't be resolved or two modules define
+    /// the same name, or the linked file can't be written.
+    pub fn link(&self) -> Result<FileLayout<'m>, LinkerError>
+    {
+        let mut constants: Vec<TableEntry> = vec![];
+        let mut functions = vec![];
+        let mut debug_info: Option<Vec<DebugEntry>> = None;
+
+        let mut names = BTreeSet::new();
+        let mut exports = BTreeSet::new();
+        let mut entry_points = 0;
+
+        let mut section_start = 0; // Where the current module's functions start in the linked file
+        for entry in &self.modules
+        {
+            let (ref module_name, ref module) = *entry;
+
+            // Where each of the module's constants ends up in the merged constant pool
+            let indices = module
+                .constants()
+                .entries()
+                .iter()
+                .map(|entry| {
+                    let index = constants.iter().position(|x| x == entry).unwrap_or_else(|| {
+                        constants.push(entry.clone());
+                        constants.len() - 1
+                    });
+                    ConstantTableIndex::try_from(index).map_err(|_| LinkerError::TooManyConstants)
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let relink = |index: ConstantTableIndex| {
+                indices
+                    .get(index as usize)
+                    .copied()
+                    .ok_or_else(|| LinkerError::InvalidConstant(module_name.clone(), index))
+            };
+
+            let module_functions = Self::all_functions(module)?;
+            let section_length = module_functions.last().map_or(0, |x| x.code_offset() + x.code().len());
+
+            for mut function in module_functions
+            {
+                guard!(
+                    names.insert(function.name().to_owned()),
+                    LinkerError::DuplicateSymbol(function.name().to_owned())
+                );
+
+                for directive in function.directives_mut()
+                {
+                    match *directive
+                    {
+                        Directive::Start => entry_points += 1,
+                        Directive::Export(ref mut name_index) =>
+                        {
+                            let name = module
+                                .constants()
+                                .get_string(*name_index)
+                                .ok_or_else(|| LinkerError::InvalidConstant(module_name.clone(), *name_index))?;
+                            exports.insert((module_name.as_str(), name));
+                            *name_index = relink(*name_index)?;
+                        }
+                        _ => (),
+                    }
+                }
+
+                let code = relink_code(function.code(), relink)?;
+                if code != function.code()
+                {
+                    let code = self.metaspace.intern_bytes(&code).ok_or(ParseError::OutOfMetaspace)?;
+                    function.replace_code(code);
+                }
+
+                functions.push(function);
+            }
+
+            if module.has_debug_info()
+            {
+                let offset = u32::try_from(section_start).map_err(|_| LinkerError::CodeTooLarge)?;
+                for &entry in module.debug_info()
+                {
+                    let bytecode_offset = entry
+                        .bytecode_offset
+                        .checked_add(offset)
+                        .ok_or(LinkerError::CodeTooLarge)?;
+                    debug_info.get_or_insert_default().push(DebugEntry {
+                        bytecode_offset,
+                        ..entry
+                    });
+                }
+            }
+
+            section_start += section_length;
+        }
+
+        match entry_points
+        {
+            0 => return Err(LinkerError::NoEntryPoint),
+            1 => (),
+            _ => return Err(LinkerError::DuplicateEntryPoint),
+        }
+
+        for import in self.modules.iter().flat_map(|x| x.1.imports())
+        {
+            let (ref module_name, ref function_name) = *import;
+            guard!(
+                exports.contains(&(module_name.as_str(), function_name.as_str())),
+                LinkerError::UnresolvedImport(module_name.clone(), function_name.clone())
+            );
+        }
+
+        Ok(FileLayout::from_parts(
+            Table::from_entries(constants),
+            functions,
+            debug_info,
+        )?)
+    }
+
+    /// Get every function of the module, parsing any that were left unparsed by lazy parsing
+    fn all_functions(module: &FileLayout<'m>) -> Result<Vec<FunctionInfo<'m>>, ParseError>
+    {
+        let mut functions = module.functions().to_vec();
+        for function in (0..).map_while(|index| module.function_lazy(index))
+        {
+            functions.push(function?);
+        }
+
+        Ok(functions)
+    }
+}
+
+/// Point every constant used by the code at wherever it ends up in the merged constant pool.
+///
+/// Decoding stops at the first byte that isn't an instruction, leaving the rest unchanged for the validator
+/// to report.
+fn relink_code<F>(code: &[u8], relink: F) -> Result<Vec<u8>, LinkerError>
+where
+    F: Fn(ConstantTableIndex) -> Result<ConstantTableIndex, LinkerError>,
+{
+    let mut code = code.to_vec();
+
+    let mut offset = 0;
+    while let Some(opcode) = code
+        .get(offset)
+        .and_then(|&x| Opcode::try_from(x).ok())
+        .filter(|x| *x != Opcode::Directive)
+    {
+        let end = offset + 1 + usize::from(opcode.param_count());
+        if opcode == Opcode::Const
+            && let Some(params) = code
+                .get_mut(offset + 1..end)
+                .and_then(|x| <&mut [u8; size_of::<ConstantTableIndex>()]>::try_from(x).ok())
+        {
+            *params = relink(ConstantTableIndex::from_le_bytes(*params))?.to_le_bytes();
+        }
+
+        offset = end;
+    }
+
+    Ok(code)
+}
@@ -1,25 +1,80 @@
+use alloc::{string::String, vec, vec::Vec};
+use core::mem::transmute;
+#[cfg(all(unix, feature = "std"))]
+use std::fs::File;
+#[cfg(feature = "std")]
 use std::{fs::read, io};
 
-use crate::loader::{
-    constant_table::ConstantTable,
-    parser::{Directive, FileLayout, FunctionInfo},
-    runnable::Runnable,
+#[cfg(all(unix, feature = "std"))]
+use memmap2::Mmap;
+
+#[cfg(feature = "std")]
+use crate::guard;
+use crate::{
+    engine::{
+        optimizer::peephole_optimize,
+        validator::{BytecodeValidator, ValidationError},
+    },
+    loader::{
+        constant_table::{ConstantTable, MAX_CONSTANT_POOL_SIZE},
+        parser::{DebugEntry, Directive, FileLayout, FunctionInfo, ParseError, Table},
+        runnable::Runnable,
+    },
+    memory::metaspace::Metaspace,
 };
 
 pub mod constant_table;
-pub(super) mod parser;
+pub mod linker;
+pub mod parser;
 pub mod runnable;
 
 pub struct Loader
 {
-    layout: FileLayout,
+    // These refer to data stored in the metaspace, so must be declared (and therefore dropped)
+    // before it. The 'static lifetimes must never be exposed outside of the loader.
+    layout: FileLayout<'static>,
+    constants: ConstantTable<'static>,
+    metaspace: Metaspace,
+
+    // The file the loader was created from, if it was mapped into memory rather than read. Functions
+    // will eventually be loaded from it lazily, so it stays mapped for as long as the loader exists.
+    #[cfg(all(unix, feature = "std"))]
+    mapping: Option<Mmap>,
 }
 
 #[derive(Debug)]
 pub enum LoaderError
 {
+    #[cfg(feature = "std")]
     FileReadError(io::Error),
-    LayoutError,
+    ParseError(ParseError),
+    FunctionsRemoved, // A reloaded file has fewer functions than the one it replaces
+    ConstantPoolTooLarge
+    {
+        found: usize,
+        max: usize,
+    },
+}
+
+/// Settings for how a file is loaded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoadOptions
+{
+    pub optimize: bool,       // Whether the peephole optimiser is run over every function that isn't lazy
+    pub max_constants: usize, // The most constants the file may have
+    pub lazy: bool,           // Whether each function is only parsed once it is first needed
+}
+
+impl Default for LoadOptions
+{
+    fn default() -> Self
+    {
+        Self {
+            optimize: true,
+            max_constants: MAX_CONSTANT_POOL_SIZE,
+            lazy: false,
+        }
+    }
 }
 
 // This is a temporary solution that just statically loads the
@@ -27,12 +82,173 @@ pub enum LoaderError
 // In the future this will happen dynamically where required.
 impl Loader
 {
+    #[cfg(feature = "std")]
+    /// Load a file, mapping it into memory where the platform supports it
+    ///
+    /// ### Errors
+    /// If the file can't be read, or can't be loaded in the same way as `from_bytes`.
     pub fn from_file(filename: &str) -> Result<Self, LoaderError>
+    {
+        Self::from_file_mmap(filename)
+    }
+
+    #[cfg(feature = "std")]
+    /// Load a file in the same way as `from_file`, but with the given options
+    ///
+    /// ### Errors
+    /// In the same way as `from_file`.
+    pub fn from_file_with_options(filename: &str, options: LoadOptions) -> Result<Self, LoaderError>
+    {
+        Self::map_file(filename, options)
+    }
+
+    #[cfg(feature = "std")]
+    /// Load a file by mapping it into memory, rather than copying all of it up front.
+    ///
+    /// Falls back to reading the whole file on platforms without `mmap`.
+    ///
+    /// ### Errors
+    /// In the same way as `from_file`.
+    pub fn from_file_mmap(filename: &str) -> Result<Self, LoaderError>
+    {
+        Self::map_file(filename, LoadOptions::default())
+    }
+
+    #[cfg(feature = "std")]
+    /// Load a file by reading all of it into memory at once
+    ///
+    /// ### Errors
+    /// In the same way as `from_file`.
+    pub fn from_file_read(filename: &str) -> Result<Self, LoaderError>
+    {
+        Self::read_file(filename, LoadOptions::default())
+    }
+
+    #[cfg(feature = "std")]
+    fn map_file(filename: &str, options: LoadOptions) -> Result<Self, LoaderError>
+    {
+        #[cfg(unix)]
+        {
+            let file = File::open(filename).map_err(LoaderError::FileReadError)?;
+
+            // Mapping is only unsafe because another process could modify the file while it is mapped.
+            // Bytecode files aren't expected to change while they are being run.
+            let mapping = unsafe { Mmap::map(&file) }.map_err(LoaderError::FileReadError)?;
+
+            let mut loader = Self::load(&mapping, options)?;
+            loader.mapping = Some(mapping);
+            Ok(loader)
+        }
+
+        #[cfg(not(unix))]
+        Self::read_file(filename, options)
+    }
+
+    #[cfg(feature = "std")]
+    fn read_file(filename: &str, options: LoadOptions) -> Result<Self, LoaderError>
     {
         let file_contents = read(filename).map_err(LoaderError::FileReadError)?;
-        let layout = FileLayout::from_bytes(&file_contents).ok_or(LoaderError::LayoutError)?;
+        Self::load(&file_contents, options)
+    }
+
+    /// Replace the loaded file with a newer version of it, read from `filename`.
+    ///
+    /// The new file must have at least as many functions as the current one. If it doesn't, or it
+    /// can't be loaded, the current file is left in place.
+    ///
+    /// A `Runner` borrows the loader for as long as it exists, so this can only be called once any
+    /// runners using it have been dropped, and never part way through a `run()`.
+    ///
+    /// ### Errors
+    /// If the new file can't be loaded, or has fewer functions than the current one.
+    #[cfg(feature = "std")]
+    pub fn hot_reload(&mut self, filename: &str) -> Result<(), LoaderError>
+    {
+        let loader = Self::from_file(filename)?;
+        guard!(
+            loader.get_function_count() >= self.get_function_count(),
+            LoaderError::FunctionsRemoved
+        );
+
+        *self = loader;
+        Ok(())
+    }
+
+    /// Load a file from its bytes
+    ///
+    /// ### Errors
+    /// If the file can't be parsed, or fails any of the checks made while loading it.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, LoaderError>
+    {
+        Self::load(bytes, LoadOptions::default())
+    }
+
+    /// Load a file without running the peephole optimiser, so that its code is executed exactly as written
+    ///
+    /// ### Errors
+    /// In the same way as `from_bytes`.
+    pub fn from_bytes_unoptimized(bytes: &[u8]) -> Result<Self, LoaderError>
+    {
+        Self::load(
+            bytes,
+            LoadOptions {
+                optimize: false,
+                ..LoadOptions::default()
+            },
+        )
+    }
+
+    /// Load a file from its bytes in the same way as `from_bytes`, but with the given options
+    ///
+    /// ### Errors
+    /// In the same way as `from_bytes`.
+    pub fn from_bytes_with_options(bytes: &[u8], options: LoadOptions) -> Result<Self, LoaderError>
+    {
+        Self::load(bytes, options)
+    }
+
+    fn load(bytes: &[u8], options: LoadOptions) -> Result<Self, LoaderError>
+    {
+        let metaspace = Metaspace::new();
+        let mut layout = FileLayout::from_bytes_limited(bytes, &metaspace, options.lazy, options.max_constants)
+            .map_err(|error| match error
+            {
+                ParseError::TooManyConstants { found, max } => LoaderError::ConstantPoolTooLarge { found, max },
+                other => LoaderError::ParseError(other),
+            })?;
 
-        Ok(Self { layout })
+        // Optimising moves instructions around, which would leave the debug info pointing at the wrong ones
+        if options.optimize && !layout.has_debug_info()
+        {
+            // Handlers are found by their offsets too, so anything with an exception table is left alone
+            for function in layout.functions_mut().iter_mut().filter(|x| !x.has_exception_table())
+            {
+                let code = peephole_optimize(function.code());
+                if code != function.code()
+                {
+                    let code = metaspace
+                        .intern_bytes(&code)
+                        .ok_or(LoaderError::ParseError(ParseError::OutOfMetaspace))?;
+                    function.replace_code(code);
+                }
+            }
+        }
+
+        // The metaspace's data doesn't move along with it, so the layout stays valid for as long as
+        // the metaspace is kept alive inside the loader
+        let layout = unsafe { transmute::<FileLayout<'_>, FileLayout<'static>>(layout) };
+
+        let constants = ConstantTable::from_parsed_table(layout.constants(), &metaspace)
+            .ok_or(LoaderError::ParseError(ParseError::OutOfMetaspace))?;
+        let constants = unsafe { transmute::<ConstantTable<'_>, ConstantTable<'static>>(constants) };
+
+        Ok(Self {
+            layout,
+            constants,
+            metaspace,
+            #[cfg(all(unix, feature = "std"))]
+            mapping: None,
+        })
     }
 
     // Get the entry point (aka function marked with .start)
@@ -41,12 +257,335 @@ impl Loader
         self.layout
             .functions()
             .iter()
-            .find(|x| x.has_directive(Directive::Start))
+            .find(|x| x.has_directive(&Directive::Start))
             .and_then(FunctionInfo::into_runnable)
+            .or_else(|| {
+                self.lazy_functions()
+                    .find(|x| x.directives().contains(&Directive::Start))
+            })
+    }
+
+    /// Get the number of functions in the file
+    pub fn get_function_count(&self) -> usize
+    {
+        self.layout.function_count()
+    }
+
+    /// Whether the file doesn't contain any functions at all
+    pub fn is_empty(&self) -> bool
+    {
+        self.layout.function_count() == 0
+    }
+
+    /// Whether any function has been marked with .start.
+    ///
+    /// If the file was loaded lazily, this parses functions until one is found.
+    pub fn has_entry_point(&self) -> bool
+    {
+        self.layout
+            .functions()
+            .iter()
+            .any(|x| x.has_directive(&Directive::Start))
+            || self
+                .lazy_functions()
+                .any(|x| x.directives().contains(&Directive::Start))
+    }
+
+    /// Get the names of every function in the file, in the order they appear.
+    ///
+    /// If the file was loaded lazily, this parses every function, and any that can't be parsed are left out.
+    pub fn list_functions(&self) -> Vec<&str>
+    {
+        self.layout
+            .functions()
+            .iter()
+            .map(FunctionInfo::name)
+            .chain(self.lazy_functions().map(|x| x.name()))
+            .collect()
+    }
+
+    /// Get the names of every function that other modules can call
+    pub fn exports(&self) -> Vec<&str>
+    {
+        self.layout.exports().collect()
+    }
+
+    /// Get every function this file needs from other modules, as (`module_name`, `function_name`) pairs
+    pub fn imports(&self) -> Vec<(String, String)>
+    {
+        self.layout.imports().to_vec()
+    }
+
+    /// The path of the source file the program was compiled from, if the file records it
+    pub fn source_path(&self) -> Option<&str>
+    {
+        self.layout.source_path()
+    }
+
+    /// Find the source location of the instruction at the given offset, if the file has debug info
+    pub fn source_location(&self, offset: usize) -> Option<&DebugEntry>
+    {
+        self.layout.source_location(offset)
+    }
+
+    /// Statically check the bytecode of every function, without running any of it.
+    ///
+    /// Every problem found across all the functions is reported at once. If the file was loaded lazily, this
+    /// parses every function, and any that can't be parsed are reported as `MalformedFunction`.
+    ///
+    /// ### Errors
+    /// Every problem found, if there are any.
+    pub fn validate_bytecode(&self) -> Result<(), Vec<ValidationError>>
+    {
+        let lazy = (self.layout.functions().len()..self.layout.function_count()).map(|index| {
+            self.layout
+                .function_lazy(index)
+                .and_then(Result::ok)
+                .map_or(vec![ValidationError::MalformedFunction { index }], |x| {
+                    BytecodeValidator.validate_with_exceptions(x.code(), &x.exception_entries())
+                })
+        });
+
+        let errors: Vec<ValidationError> = self
+            .layout
+            .functions()
+            .iter()
+            .map(|x| BytecodeValidator.validate_with_exceptions(x.code(), &x.exception_entries()))
+            .chain(lazy)
+            .flatten()
+            .collect();
+
+        errors.is_empty().then_some(()).ok_or(errors)
+    }
+
+    /// Get the function with the given name
+    pub fn get_function_by_name(&self, name: &str) -> Option<Runnable<'_>>
+    {
+        self.layout
+            .functions()
+            .iter()
+            .find(|x| x.name() == name)
+            .and_then(FunctionInfo::into_runnable)
+            .or_else(|| self.lazy_functions().find(|x| x.name() == name))
+    }
+
+    /// Get the function at the given position in the file.
+    ///
+    /// If the file was loaded lazily, this parses the function if it hasn't been already.
+    pub fn get_function_by_index(&self, index: usize) -> Option<Runnable<'_>>
+    {
+        if let Some(function) = self.layout.functions().get(index)
+        {
+            return function.into_runnable();
+        }
+
+        let function = self.layout.function_lazy(index)?.ok()?;
+
+        // The parsed function is only a copy, so its name is kept in metaspace for the runnable to refer to
+        let name = self.metaspace.intern_str(function.name())?;
+        Runnable::from_parsed_data(name, function.directives(), function.code(), function.code_offset())
+    }
+
+    /// Go through the functions of a lazily loaded file in order, parsing each one as it is reached. Any that
+    /// can't be parsed are skipped.
+    fn lazy_functions(&self) -> impl Iterator<Item = Runnable<'_>>
+    {
+        // A file is either loaded lazily or not at all, so any functions that have been parsed up front come first
+        (self.layout.functions().len()..self.layout.function_count()).filter_map(|x| self.get_function_by_index(x))
+    }
+
+    pub fn get_constant_table(&self) -> &ConstantTable<'_>
+    {
+        &self.constants
+    }
+
+    /// Get the constant pool as it was parsed from the file, before anything was copied into metaspace
+    pub fn get_constant_table_ref(&self) -> &Table
+    {
+        self.layout.constants()
+    }
+}
+
+#[cfg(test)]
+mod loader_tests
+{
+    use super::*;
+    use crate::{
+        engine::{bytecode::Bytecode, opcodes::Opcode},
+        loader::parser::{LazyFunctionEntry, MAGIC_NUMBER, SUPPORTED_VERSION, TableEntry},
+    };
+
+    #[test]
+    fn no_functions()
+    {
+        let checked = [0, 0, 0, 0, 0]; // No flags, and no constants
+        let mut data = MAGIC_NUMBER.to_le_bytes().to_vec();
+        data.push(SUPPORTED_VERSION);
+        data.extend_from_slice(&crc32fast::hash(&checked).to_le_bytes());
+        data.extend_from_slice(&checked);
+
+        let loader = Loader::from_bytes(&data).unwrap();
+        assert_eq!(loader.get_function_count(), 0);
+        assert!(loader.is_empty());
+        assert!(!loader.has_entry_point());
+    }
+
+    /// A file with three functions, `main`, `first` and `second`, that is loaded lazily
+    fn load_lazy() -> Loader
+    {
+        let names = ["main", "first", "second"];
+        let code = [
+            vec![Opcode::Ret as u8],
+            vec![Opcode::IConst1 as u8, Opcode::RetVal as u8],
+            vec![Opcode::IConst2 as u8, Opcode::RetVal as u8],
+        ]
+        .map(|x| Bytecode::new(x).unwrap());
+
+        let functions = names
+            .iter()
+            .zip(&code)
+            .map(|(&name, code)| {
+                let mut directives = vec![Directive::MaxStack(1), Directive::MaxLocals(0)];
+                if name == "main"
+                {
+                    directives.insert(0, Directive::Start);
+                }
+                FunctionInfo::from_parts(name.to_owned(), directives, code)
+            })
+            .collect();
+        let table = Table::from_entries(names.map(|x| TableEntry::String(x.to_owned())).to_vec());
+
+        let options = LoadOptions {
+            lazy: true,
+            ..LoadOptions::default()
+        };
+        Loader::from_bytes_with_options(
+            &FileLayout::from_parts(table, functions, None)
+                .unwrap()
+                .to_bytes()
+                .unwrap(),
+            options,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn lazy_parsing()
+    {
+        let loader = load_lazy();
+        let parsed = |loader: &Loader| {
+            loader
+                .layout
+                .functions_lazy()
+                .iter()
+                .map(|x| matches!(*x, LazyFunctionEntry::Parsed(_)))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(loader.get_function_count(), 3);
+        assert_eq!(parsed(&loader), [false, false, false]);
+
+        // The entry point is the first function, so finding it doesn't need any of the others
+        assert_eq!(loader.get_entry_point().unwrap().name(), "main");
+        assert_eq!(parsed(&loader), [true, false, false]);
+
+        assert_eq!(loader.get_function_by_index(2).unwrap().name(), "second");
+        assert_eq!(parsed(&loader), [true, false, true]);
+        assert!(loader.get_function_by_index(3).is_none());
+    }
+
+    #[test]
+    fn lazy_inspection()
+    {
+        // Every function is looked at, rather than only those parsed up front, of which there are none
+        let loader = load_lazy();
+        assert!(loader.has_entry_point());
+        assert_eq!(loader.list_functions(), ["main", "first", "second"]);
+        assert_eq!(loader.validate_bytecode(), Ok(()));
+
+        // Code that fails validation is caught even though it is only parsed when asked for
+        let missing_return = Bytecode::from_trusted(vec![Opcode::Nop as u8]);
+        let function = FunctionInfo::from_parts(
+            "main".to_owned(),
+            vec![Directive::Start, Directive::MaxStack(0), Directive::MaxLocals(0)],
+            &missing_return,
+        );
+        let table = Table::from_entries(vec![TableEntry::String("main".to_owned())]);
+        let bytes = FileLayout::from_parts(table, vec![function], None)
+            .unwrap()
+            .to_bytes()
+            .unwrap();
+
+        let options = LoadOptions {
+            lazy: true,
+            ..LoadOptions::default()
+        };
+        let loader = Loader::from_bytes_with_options(&bytes, options).unwrap();
+        assert_eq!(
+            loader.validate_bytecode(),
+            Err(vec![ValidationError::MissingReturn { at: 1 }])
+        );
+    }
+
+    // A file with no functions, and `count` constants that are all `false`
+    fn with_constants(count: u32) -> Vec<u8>
+    {
+        with_declared_constants(count, count)
+    }
+
+    // The same as `with_constants`, but with the file saying it has `declared` constants
+    fn with_declared_constants(declared: u32, count: u32) -> Vec<u8>
+    {
+        let mut checked = vec![0]; // No flags
+        checked.extend_from_slice(&declared.to_le_bytes());
+        for _ in 0..count
+        {
+            checked.extend_from_slice(&[5, 0]);
+        }
+
+        let mut data = MAGIC_NUMBER.to_le_bytes().to_vec();
+        data.push(SUPPORTED_VERSION);
+        data.extend_from_slice(&crc32fast::hash(&checked).to_le_bytes());
+        data.extend_from_slice(&checked);
+        data
+    }
+
+    #[test]
+    fn constant_pool_limit()
+    {
+        let loader = Loader::from_bytes(&with_constants(4096)).unwrap();
+        assert_eq!(loader.get_constant_table().len(), MAX_CONSTANT_POOL_SIZE);
+        assert_eq!(loader.get_constant_table_ref().entries().len(), MAX_CONSTANT_POOL_SIZE);
+
+        assert!(matches!(
+            Loader::from_bytes(&with_constants(4097)),
+            Err(LoaderError::ConstantPoolTooLarge { found: 4097, max: 4096 })
+        ));
+
+        let options = LoadOptions {
+            max_constants: 10,
+            ..LoadOptions::default()
+        };
+        assert!(Loader::from_bytes_with_options(&with_constants(10), options).is_ok());
+        assert!(matches!(
+            Loader::from_bytes_with_options(&with_constants(11), options),
+            Err(LoaderError::ConstantPoolTooLarge { found: 11, max: 10 })
+        ));
+
+        // The count is rejected before the constants it claims to have are looked for
+        assert!(matches!(
+            Loader::from_bytes(&with_declared_constants(u32::MAX, 0)),
+            Err(LoaderError::ConstantPoolTooLarge { found, max: 4096 }) if found == u32::MAX as usize
+        ));
     }
 
-    pub fn get_constant_table(&self) -> ConstantTable<'_>
+    #[test]
+    #[cfg(feature = "std")]
+    fn missing_file()
     {
-        ConstantTable::from_parsed_table(self.layout.constants())
+        assert!(matches!(
+            Loader::from_file_mmap("does/not/exist.azc"),
+            Err(LoaderError::FileReadError(_))
+        ));
     }
 }
@@ -1,8 +1,29 @@
-use crate::{engine::opcodes::Opcode, guard, loader::runnable::Runnable};
+use alloc::{borrow::ToOwned as _, string::String, vec, vec::Vec};
+use core::cell::RefCell;
+
+use lz4_flex::block;
+
+use crate::{
+    engine::{bytecode::Bytecode, opcodes::Opcode},
+    guard,
+    loader::runnable::Runnable,
+    memory::metaspace::Metaspace,
+};
 
 const MAGIC_STRING: &[u8; 8] = b"azimuth\0";
 pub const MAGIC_NUMBER: u64 = u64::from_le_bytes(*MAGIC_STRING);
 
+// The newest version of the file format that this runtime understands
+pub const SUPPORTED_VERSION: u8 = 1;
+
+// Header flags
+pub const FLAG_COMPRESSED: u8 = 1 << 0; // The body of the file is LZ4 compressed
+
+// Optional section types, which can follow the functions in any order
+pub const SECTION_SOURCE_PATH: u8 = 0x01;
+pub const SECTION_DEBUG_INFO: u8 = 0x02;
+pub const SECTION_IMPORTS: u8 = 0x03;
+
 // Convert a set of bytes into a numeric type
 macro_rules! bytes_to_numeric {
     ($t:ty, $input:expr) => {
@@ -11,20 +32,56 @@ macro_rules! bytes_to_numeric {
 }
 
 // Macro to speed up splitting of a specific bit of the data into a specific
-// numeric type
+// numeric type. Given the offset the value starts at, running out of input is
+// reported as an error at that offset.
 macro_rules! split_off {
     ($t:ty, $input:ident) => {
         $input
             .split_at_checked(size_of::<$t>())
             .and_then(|(x, y)| Some((bytes_to_numeric!($t, x), y)))
     };
+    ($t:ty, $input:ident, $at:expr) => {
+        split_off!($t, $input).ok_or(ParseError::UnexpectedEof { at: $at })
+    };
+}
+
+/// Convert a length to the 4 byte form it is written in
+fn file_length(length: usize) -> Result<u32, WriteError>
+{
+    u32::try_from(length).map_err(|_| WriteError::TooLarge)
+}
+
+/// Convert a count to the 2 byte form it is written in, for directives with a variable length
+fn file_count(count: usize) -> Result<u16, WriteError>
+{
+    u16::try_from(count).map_err(|_| WriteError::TooLarge)
+}
+
+/// Write an optional section, prefixed with its type and length
+fn write_section(output: &mut Vec<u8>, section: u8, payload: &[u8]) -> Result<(), WriteError>
+{
+    output.push(section);
+    output.extend_from_slice(&file_length(payload.len())?.to_le_bytes());
+    output.extend_from_slice(payload);
+    Ok(())
+}
+
+/// Split the first byte off the input
+fn split_byte(input: &[u8]) -> Result<(u8, &[u8]), ParseError>
+{
+    input
+        .split_first()
+        .map(|(&x, rem)| (x, rem))
+        .ok_or(ParseError::UnexpectedEof { at: 0 })
 }
 
-type DirectiveHandler = &'static dyn Fn(&[u8]) -> Option<Directive>; // Creates a handler
-type TableTypeHandler = &'static dyn Fn(&[u8]) -> Option<(TableEntry, usize)>; // Creates a table
+type DirectiveHandler = fn(&[u8]) -> Option<Directive>; // Creates a handler
+type TableTypeHandler = fn(&[u8]) -> Result<(TableEntry, usize), ParseError>; // Creates a table
+type FoundFunctions<'a, 'i> = (Vec<LazyFunctionEntry<'a>>, &'i [u8]); // The functions, and the bytes they take up
 
 struct FileParser<'a>
 {
+    input: &'a [u8],
     remaining: &'a [u8],
 }
 
@@ -32,63 +89,621 @@ impl<'a> FileParser<'a>
 {
     pub fn new(input: &'a [u8]) -> Self
     {
-        Self { remaining: input }
+        Self {
+            input,
+            remaining: input,
+        }
+    }
+
+    /// How far through the input the parser has got
+    fn offset(&self) -> usize
+    {
+        self.input.len() - self.remaining.len()
     }
 
-    /// Create a type based on a given parser
-    pub fn parse_off<T, F>(&mut self, parser: F) -> Option<T>
+    /// Create a type based on a given parser.
+    ///
+    /// The parser reports the position of any error relative to the start of what it is given, which is
+    /// moved to be relative to the start of the whole input.
+    pub fn parse_off<T, F>(&mut self, parser: F) -> Result<T, ParseError>
     where
-        F: Fn(&'a [u8]) -> Option<(T, &'a [u8])>,
+        F: Fn(&'a [u8]) -> Result<(T, &'a [u8]), ParseError>,
     {
-        let (value, rem) = parser(self.remaining)?;
+        let (value, rem) = parser(self.remaining).map_err(|x| x.offset_by(self.offset()))?;
         self.remaining = rem;
-        Some(value)
+        Ok(value)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseError
+{
+    Malformed,
+    InvalidMagic
+    {
+        found: u64,
+    },
+    UnexpectedEof
+    {
+        at: usize, // Where the value that was cut short starts
+    },
+    UnknownTag
+    {
+        at: usize,
+        tag: u8,
+    },
+    UnsupportedVersion
+    {
+        found: u8,
+    },
+    ChecksumMismatch
+    {
+        expected: u32,
+        found: u32,
+    },
+    DecompressionFailed,
+    OutOfMetaspace,
+    DuplicateSection
+    {
+        section: u8,
+    },
+    BytecodeLengthMismatch
+    {
+        at: usize,       // Where the function's code starts
+        declared: usize, // The length given by the symbol directive
+        available: usize,
+    },
+    TooManyConstants
+    {
+        found: usize, // The number of constants the file says it has
+        max: usize,
+    },
+}
+
+/// Why a file couldn't be written out
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WriteError
+{
+    TooLarge,              // A length or count doesn't fit in the number of bytes the format gives it
+    MissingString(String), // A name that has to be written as a constant isn't in the constant pool
+}
+
+impl ParseError
+{
+    /// Move the position of an error found in part of the input, so that it is relative to an earlier
+    /// point in the input instead
+    const fn offset_by(self, offset: usize) -> Self
+    {
+        match self
+        {
+            Self::UnexpectedEof { at } => Self::UnexpectedEof { at: at + offset },
+            Self::UnknownTag { at, tag } => Self::UnknownTag { at: at + offset, tag },
+            Self::BytecodeLengthMismatch {
+                at,
+                declared,
+                available,
+            } => Self::BytecodeLengthMismatch {
+                at: at + offset,
+                declared,
+                available,
+            },
+            other => other,
+        }
+    }
+}
+
+/// Maps a single bytecode offset back to the line in the source that produced it.
+///
+/// Offsets are measured from the start of the first function in the file, so that they are
+/// unique across every function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DebugEntry
+{
+    pub bytecode_offset: u32,
+    pub line_number: u32,
+    pub source_file_idx: u16,
+}
+
+impl DebugEntry
+{
+    const SIZE: usize = 10; // bytecode_offset (4 bytes) + line_number (4 bytes) + source_file_idx (2 bytes)
+
+    /// Parse the contents of a debug info section.
+    ///
+    /// The entries must be sorted by their offset so that they can be searched.
+    fn parse_section(payload: &[u8]) -> Option<Vec<Self>>
+    {
+        let chunks = payload.chunks_exact(Self::SIZE);
+        guard!(chunks.remainder().is_empty());
+
+        let entries = chunks
+            .map(|mut x| {
+                let (bytecode_offset, rem) = split_off!(u32, x)?;
+                x = rem;
+                let (line_number, rem) = split_off!(u32, x)?;
+                x = rem;
+                let (source_file_idx, _) = split_off!(u16, x)?;
+
+                Some(Self {
+                    bytecode_offset,
+                    line_number,
+                    source_file_idx,
+                })
+            })
+            .collect::<Option<Vec<Self>>>()?;
+
+        guard!(entries.is_sorted_by_key(|x| x.bytecode_offset));
+        Some(entries)
     }
 }
 
-pub struct FileLayout
+#[derive(Debug, PartialEq)]
+pub struct FileLayout<'m>
 {
     magic: u64,
     version: u8,
+    checksum: u32,
+    flags: u8,
     constant_count: u32,
     constant_pool: Table,
-    functions: Vec<FunctionInfo>,
+    functions: Vec<FunctionInfo<'m>>,
+
+    // When parsed lazily, the functions are instead left as they are in the file until they are first
+    // needed, with the bytes of all of them kept in metaspace
+    function_bytes: &'m [u8],
+    lazy_functions: RefCell<Vec<LazyFunctionEntry<'m>>>,
+
+    source_path: Option<String>, // The source file the file was compiled from
+    debug_info: Option<Vec<DebugEntry>>,
+    imports: Option<Vec<(String, String)>>, // (module_name, function_name)
 }
 
-impl FileLayout
+impl<'m> FileLayout<'m>
 {
     /// Parse the direct information from a raw file, representing its format as closely as possible.
-    pub fn from_bytes(input: &[u8]) -> Option<Self>
+    ///
+    /// Anything that needs to outlive the raw file, such as the bytecode, is copied into `metaspace`.
+    ///
+    /// ### Errors
+    /// If the file is malformed or truncated, positioned at where the problem was found.
+    pub fn from_bytes(input: &[u8], metaspace: &'m Metaspace) -> Result<Self, ParseError>
+    {
+        Self::parse(input, metaspace, false, usize::MAX)
+    }
+
+    /// Parse a raw file in the same way as `from_bytes`, but without parsing any of its functions.
+    ///
+    /// Each function is only parsed the first time it is asked for with `function_lazy`, so none of them are
+    /// given by `functions`. Exports can't be checked against the functions in the file up front either.
+    ///
+    /// ### Errors
+    /// In the same way as `from_bytes`, except that problems within functions aren't found until they are
+    /// parsed.
+    pub fn from_bytes_lazy(input: &[u8], metaspace: &'m Metaspace) -> Result<Self, ParseError>
+    {
+        Self::parse(input, metaspace, true, usize::MAX)
+    }
+
+    /// Parse a raw file in the same way as `from_bytes`, or `from_bytes_lazy` if `lazy` is set, but reject it
+    /// if it has more than `max_constants` constants.
+    ///
+    /// The count is checked as soon as it is read, so a file claiming to have a huge number of constants is
+    /// rejected before any of them are parsed.
+    ///
+    /// ### Errors
+    /// `ParseError::TooManyConstants` if there are too many constants, otherwise in the same way as
+    /// `from_bytes`.
+    pub fn from_bytes_limited(
+        input: &[u8],
+        metaspace: &'m Metaspace,
+        lazy: bool,
+        max_constants: usize,
+    ) -> Result<Self, ParseError>
+    {
+        Self::parse(input, metaspace, lazy, max_constants)
+    }
+
+    fn parse(input: &[u8], metaspace: &'m Metaspace, lazy: bool, max_constants: usize) -> Result<Self, ParseError>
     {
         let mut parser = FileParser::new(input);
 
-        let magic = parser.parse_off(|x| split_off!(u64, x))?; // Magic Number
-        let &version = parser.parse_off(|x| x.split_first())?; // Version Number
-        let constant_count = parser.parse_off(|x| split_off!(u32, x))?; // Number of constants
-        let constant_pool = parser.parse_off(|x| Table::new(constant_count as usize, x))?; // Constant Table
-        let functions = parser.parse_off(|x| FunctionInfo::get_all_functions(x, &constant_pool))?; // Functions
+        let magic = parser.parse_off(|x| split_off!(u64, x, 0))?; // Magic Number
+        guard!(magic == MAGIC_NUMBER, ParseError::InvalidMagic { found: magic });
+
+        let version = parser.parse_off(split_byte)?; // Version Number
+
+        // Files produced for a newer version of the format cannot be safely understood
+        guard!(
+            version <= SUPPORTED_VERSION,
+            ParseError::UnsupportedVersion { found: version }
+        );
+
+        // The checksum covers everything in the file after the checksum itself
+        let checksum = parser.parse_off(|x| split_off!(u32, x, 0))?; // Checksum
+        let actual_checksum = crc32fast::hash(parser.remaining);
+        guard!(
+            checksum == actual_checksum,
+            ParseError::ChecksumMismatch {
+                expected: checksum,
+                found: actual_checksum
+            }
+        );
+
+        let flags = parser.parse_off(split_byte)?; // Flags
+
+        // If the body is compressed, it needs to be decompressed before it can be parsed.
+        // The decompressed size is stored up front so the buffer can be allocated in one go.
+        // Any errors after this point are positioned within the decompressed body, rather than the file.
+        let decompressed;
+        if flags & FLAG_COMPRESSED != 0
+        {
+            let size = parser.parse_off(|x| split_off!(u32, x, 0))? as usize; // Decompressed Size
+            decompressed = block::decompress(parser.remaining, size)
+                .ok()
+                .filter(|x| x.len() == size)
+                .ok_or(ParseError::DecompressionFailed)?;
+            parser = FileParser::new(&decompressed);
+        }
+
+        let (constant_count, constant_pool) = parser.parse_off(|x| Self::parse_constants(x, max_constants))?;
+        let (functions, function_bytes, lazy_functions) = if lazy
+        {
+            let (lazy_functions, function_bytes) = parser.parse_off(LazyFunctionEntry::find_all)?; // Functions
+            let function_bytes = metaspace
+                .intern_bytes(function_bytes)
+                .ok_or(ParseError::OutOfMetaspace)?;
+            (vec![], function_bytes, lazy_functions)
+        }
+        else
+        {
+            let functions = parser
+                .parse_off(|x| FunctionInfo::get_all_functions(x, &constant_pool))? // Functions
+                .into_iter()
+                .map(|x| x.intern(metaspace))
+                .collect::<Option<Vec<_>>>()
+                .ok_or(ParseError::OutOfMetaspace)?;
+            (functions, &[][..], vec![])
+        };
+
+        // Everything after the functions is made up of optional sections, each of which is
+        // prefixed with its type and length
+        let mut source_path = None;
+        let mut debug_info = None;
+        let mut imports = None;
+        while !parser.remaining.is_empty()
+        {
+            let (section, payload) = parser.parse_off(|x| {
+                let (section, rem) = split_byte(x)?;
+                let (length, rem) = split_off!(u32, rem, 1)?;
+                let (payload, rem) = rem.split_at_checked(length as usize).ok_or(ParseError::UnexpectedEof {
+                    at: 1 + size_of::<u32>(),
+                })?;
+                Ok(((section, payload), rem))
+            })?;
+
+            match section
+            {
+                SECTION_SOURCE_PATH =>
+                {
+                    guard!(source_path.is_none(), ParseError::DuplicateSection { section });
+                    source_path = Some(Self::parse_source_path(payload).ok_or(ParseError::Malformed)?);
+                }
+                SECTION_DEBUG_INFO =>
+                {
+                    guard!(debug_info.is_none(), ParseError::DuplicateSection { section });
+                    debug_info = Some(DebugEntry::parse_section(payload).ok_or(ParseError::Malformed)?);
+                }
+                SECTION_IMPORTS =>
+                {
+                    guard!(imports.is_none(), ParseError::DuplicateSection { section });
+                    imports = Some(Self::parse_imports(payload, &constant_pool).ok_or(ParseError::Malformed)?);
+                }
+                // Unknown sections are skipped, so that they can be added without breaking older runtimes
+                _ => (),
+            }
+        }
+
+        // Only functions that actually exist in this file can be exported
+        for export in functions.iter().flat_map(|x| x.exports(&constant_pool))
+        {
+            let name = export.ok_or(ParseError::Malformed)?;
+            guard!(functions.iter().any(|x| x.name() == name), ParseError::Malformed);
+        }
 
-        Some(Self {
+        Ok(Self {
             magic,
             version,
+            checksum,
+            flags,
             constant_count,
             constant_pool,
             functions,
+            function_bytes,
+            lazy_functions: RefCell::new(lazy_functions),
+            source_path,
+            debug_info,
+            imports,
+        })
+    }
+
+    /// Build an uncompressed file directly from its parts, rather than parsing it.
+    ///
+    /// The functions are laid out one after another in the order given, so their code offsets are
+    /// worked out again from where each one ends up. Every function name must be in the constant pool.
+    ///
+    /// ### Errors
+    /// If a function can't be written, such as when its name isn't in the constant pool.
+    pub fn from_parts(
+        constant_pool: Table,
+        functions: Vec<FunctionInfo<'m>>,
+        debug_info: Option<Vec<DebugEntry>>,
+    ) -> Result<Self, WriteError>
+    {
+        let mut written = vec![];
+        let functions = functions
+            .into_iter()
+            .map(|mut function| {
+                function.write(&mut written, &constant_pool)?;
+                function.code_offset = written.len() - function.code.len();
+                Ok(function)
+            })
+            .collect::<Result<_, WriteError>>()?;
+
+        Ok(Self {
+            magic: MAGIC_NUMBER,
+            version: SUPPORTED_VERSION,
+            checksum: 0, // Only worked out when the file is written
+            flags: 0,
+            constant_count: file_length(constant_pool.entries().len())?,
+            constant_pool,
+            functions,
+            function_bytes: &[],
+            lazy_functions: RefCell::new(vec![]),
+            source_path: None,
+            debug_info,
+            imports: None,
         })
     }
 
-    pub fn functions(&self) -> &[FunctionInfo]
+    /// Write the file back out in the format read by `from_bytes`, compressing the body if the file was
+    /// compressed.
+    ///
+    /// Optional sections are always written in the same order, and any that weren't understood when the
+    /// file was parsed aren't kept, so are not written.
+    ///
+    /// A file that was put together with `from_parts` can be too large for the format, or refer to names that
+    /// aren't in its constant pool, in which case it can't be written.
+    ///
+    /// ### Errors
+    /// If part of the file is too large for the format, or a name isn't in the constant pool.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, WriteError>
+    {
+        let mut body = vec![];
+
+        body.extend_from_slice(&self.constant_count.to_le_bytes());
+        for entry in self.constant_pool.entries()
+        {
+            entry.write(&mut body)?;
+        }
+
+        for function in &self.functions
+        {
+            function.write(&mut body, &self.constant_pool)?;
+        }
+        body.extend_from_slice(self.function_bytes);
+
+        if let Some(ref path) = self.source_path
+        {
+            write_section(&mut body, SECTION_SOURCE_PATH, &[path.as_bytes(), &[0]].concat())?;
+        }
+
+        if let Some(ref entries) = self.debug_info
+        {
+            let mut payload = vec![];
+            for entry in entries
+            {
+                payload.extend_from_slice(&entry.bytecode_offset.to_le_bytes());
+                payload.extend_from_slice(&entry.line_number.to_le_bytes());
+                payload.extend_from_slice(&entry.source_file_idx.to_le_bytes());
+            }
+            write_section(&mut body, SECTION_DEBUG_INFO, &payload)?;
+        }
+
+        if let Some(ref imports) = self.imports
+        {
+            let mut payload = vec![];
+            for import in imports
+            {
+                let (ref module_name, ref function_name) = *import;
+                payload.extend_from_slice(&self.constant_pool.string_index(module_name)?.to_le_bytes());
+                payload.extend_from_slice(&self.constant_pool.string_index(function_name)?.to_le_bytes());
+            }
+            write_section(&mut body, SECTION_IMPORTS, &payload)?;
+        }
+
+        // Everything after the checksum is covered by it, including the flags
+        let mut checked = vec![self.flags];
+        if self.is_compressed()
+        {
+            checked.extend_from_slice(&file_length(body.len())?.to_le_bytes());
+            checked.extend_from_slice(&block::compress(&body));
+        }
+        else
+        {
+            checked.extend_from_slice(&body);
+        }
+
+        let mut data = self.magic.to_le_bytes().to_vec();
+        data.push(self.version);
+        data.extend_from_slice(&crc32fast::hash(&checked).to_le_bytes());
+        data.extend_from_slice(&checked);
+        Ok(data)
+    }
+
+    /// Parse the number of constants followed by the constant table, checking the count against the limit before
+    /// anything is allocated for the table
+    fn parse_constants(input: &[u8], max_constants: usize) -> Result<((u32, Table), &[u8]), ParseError>
+    {
+        let (count, rem) = split_off!(u32, input, 0)?; // Number of constants
+        guard!(
+            count as usize <= max_constants,
+            ParseError::TooManyConstants {
+                found: count as usize,
+                max: max_constants
+            }
+        );
+
+        let (table, rem) = Table::new(count as usize, rem).map_err(|x| x.offset_by(size_of::<u32>()))?; // Constant Table
+        Ok(((count, table), rem))
+    }
+
+    /// Parse the contents of a source path section, which is a UTF-8 path ended by a null byte
+    fn parse_source_path(payload: &[u8]) -> Option<String>
+    {
+        let path = payload.strip_suffix(&[0])?;
+        guard!(!path.contains(&0));
+        str::from_utf8(path).ok().map(str::to_owned)
+    }
+
+    /// Parse the contents of an imports section.
+    ///
+    /// Each import is a pair of indices into the constant pool, which must both refer to strings.
+    fn parse_imports(payload: &[u8], table: &Table) -> Option<Vec<(String, String)>>
+    {
+        const IMPORT_SIZE: usize = 8; // module_name index (4 bytes) + function_name index (4 bytes)
+
+        let chunks = payload.chunks_exact(IMPORT_SIZE);
+        guard!(chunks.remainder().is_empty());
+
+        chunks
+            .map(|x| {
+                let (module_index, rem) = split_off!(u32, x)?;
+                let (function_index, _) = split_off!(u32, rem)?;
+
+                Some((
+                    table.get_string(module_index)?.to_owned(),
+                    table.get_string(function_index)?.to_owned(),
+                ))
+            })
+            .collect()
+    }
+
+    pub fn version(&self) -> u8
+    {
+        self.version
+    }
+
+    pub fn is_compressed(&self) -> bool
+    {
+        self.flags & FLAG_COMPRESSED != 0
+    }
+
+    pub fn functions(&self) -> &[FunctionInfo<'m>]
     {
         self.functions.as_slice()
     }
 
+    /// The number of functions defined in the file, whether or not they have been parsed
+    pub fn function_count(&self) -> usize
+    {
+        self.functions.len() + self.lazy_functions.borrow().len()
+    }
+
+    /// Get every function of a lazily parsed file, as far as each of them has been parsed so far
+    pub fn functions_lazy(&self) -> Vec<LazyFunctionEntry<'m>>
+    {
+        self.lazy_functions.borrow().clone()
+    }
+
+    /// Get the function at `index` of a lazily parsed file, parsing it if this is the first time it has been
+    /// asked for.
+    ///
+    /// Errors are positioned relative to the start of the first function.
+    pub fn function_lazy(&self, index: usize) -> Option<Result<FunctionInfo<'m>, ParseError>>
+    {
+        let mut lazy_functions = self.lazy_functions.borrow_mut();
+        let entry = lazy_functions.get_mut(index)?;
+
+        let function = match *entry
+        {
+            LazyFunctionEntry::Parsed(ref function) => return Some(Ok(function.clone())),
+            LazyFunctionEntry::Unparsed { byte_offset, byte_len } => FunctionInfo::new(
+                self.function_bytes.get(byte_offset..byte_offset + byte_len)?,
+                &self.constant_pool,
+            )
+            .map(|(mut function, _)| {
+                function.code_offset += byte_offset;
+                function
+            })
+            .map_err(|x| x.offset_by(byte_offset)),
+        };
+
+        // Failing to parse is reported every time, rather than only the first
+        if let Ok(ref function) = function
+        {
+            *entry = LazyFunctionEntry::Parsed(function.clone());
+        }
+        Some(function)
+    }
+
+    pub fn functions_mut(&mut self) -> &mut [FunctionInfo<'m>]
+    {
+        self.functions.as_mut_slice()
+    }
+
+    pub fn has_debug_info(&self) -> bool
+    {
+        self.debug_info.is_some()
+    }
+
     pub fn constants(&self) -> &Table
     {
         &self.constant_pool
     }
+
+    /// Get the names of every function that has been marked for export
+    pub fn exports(&self) -> impl Iterator<Item = &str>
+    {
+        self.functions
+            .iter()
+            .flat_map(|x| x.exports(&self.constant_pool))
+            .flatten()
+    }
+
+    /// Get every function imported from other modules as (`module_name`, `function_name`) pairs
+    pub fn imports(&self) -> &[(String, String)]
+    {
+        self.imports.as_deref().unwrap_or_default()
+    }
+
+    /// The path of the source file the file was compiled from, if it was recorded
+    pub fn source_path(&self) -> Option<&str>
+    {
+        self.source_path.as_deref()
+    }
+
+    /// Get every debug entry in the file, in order of the offsets they start at
+    pub fn debug_info(&self) -> &[DebugEntry]
+    {
+        self.debug_info.as_deref().unwrap_or_default()
+    }
+
+    /// Find the source location that produced the instruction at the given bytecode offset.
+    ///
+    /// Each entry covers every offset up until the next entry, so this finds the last entry
+    /// at or before `pc`.
+    pub fn source_location(&self, pc: usize) -> Option<&DebugEntry>
+    {
+        let entries = self.debug_info.as_deref()?;
+        let index = entries
+            .partition_point(|x| x.bytecode_offset as usize <= pc)
+            .checked_sub(1)?;
+
+        entries.get(index)
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum TableEntry
 {
     Integer(u32),
@@ -96,25 +711,69 @@ pub enum TableEntry
     Float(f32),
     Double(f64),
     String(String), // This can eventually be a reference to a metaspace string
+    Boolean(u8),    // 0 is false, anything else is true
+    Null,           // The null reference, which has no payload
 }
 
 impl TableEntry
 {
-    pub const HANDLERS: [TableTypeHandler; 5] = [
-        &|x| Some((TableEntry::Integer(bytes_to_numeric!(u32, x)), 4)),
-        &|x| Some((TableEntry::Long(bytes_to_numeric!(u64, x)), 8)),
-        &|x| Some((TableEntry::Float(f32::from_bits(bytes_to_numeric!(u32, x))), 4)),
-        &|x| Some((TableEntry::Double(f64::from_bits(bytes_to_numeric!(u64, x))), 8)),
-        &|x| {
-            let str_len = bytes_to_numeric!(u32, x) as usize;
-            let str_bytes = x.get(size_of::<u32>()..(size_of::<u32>() + str_len))?;
-            let string = String::from_utf8(str_bytes.to_vec()).ok()?;
-            Some((TableEntry::String(string), size_of::<u32>() + str_len))
+    /// Write the entry in the same form it is parsed from, tag first
+    fn write(&self, output: &mut Vec<u8>) -> Result<(), WriteError>
+    {
+        match *self
+        {
+            Self::Integer(value) =>
+            {
+                output.push(0);
+                output.extend_from_slice(&value.to_le_bytes());
+            }
+            Self::Long(value) =>
+            {
+                output.push(1);
+                output.extend_from_slice(&value.to_le_bytes());
+            }
+            Self::Float(value) =>
+            {
+                output.push(2);
+                output.extend_from_slice(&value.to_bits().to_le_bytes());
+            }
+            Self::Double(value) =>
+            {
+                output.push(3);
+                output.extend_from_slice(&value.to_bits().to_le_bytes());
+            }
+            Self::String(ref value) =>
+            {
+                output.push(4);
+                output.extend_from_slice(&file_length(value.len())?.to_le_bytes());
+                output.extend_from_slice(value.as_bytes());
+            }
+            Self::Boolean(value) => output.extend_from_slice(&[5, value]),
+            Self::Null => output.push(6),
+        }
+
+        Ok(())
+    }
+
+    pub const HANDLERS: [TableTypeHandler; 7] = [
+        |x| Ok((TableEntry::Integer(split_off!(u32, x, 0)?.0), 4)),
+        |x| Ok((TableEntry::Long(split_off!(u64, x, 0)?.0), 8)),
+        |x| Ok((TableEntry::Float(f32::from_bits(split_off!(u32, x, 0)?.0)), 4)),
+        |x| Ok((TableEntry::Double(f64::from_bits(split_off!(u64, x, 0)?.0)), 8)),
+        |x| {
+            let (str_len, rem) = split_off!(u32, x, 0)?;
+            let str_bytes = rem
+                .get(..str_len as usize)
+                .ok_or(ParseError::UnexpectedEof { at: size_of::<u32>() })?;
+            let string = String::from_utf8(str_bytes.to_vec()).map_err(|_| ParseError::Malformed)?;
+            Ok((TableEntry::String(string), size_of::<u32>() + str_bytes.len()))
         },
+        |x| Ok((TableEntry::Boolean(split_off!(u8, x, 0)?.0), 1)),
+        |_| Ok((TableEntry::Null, 0)),
     ];
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct Table
 {
     entries: Vec<TableEntry>,
@@ -122,23 +781,36 @@ pub struct Table
 
 impl Table
 {
-    pub fn new(count: usize, from: &[u8]) -> Option<(Self, &[u8])>
+    /// Parse `count` entries from the start of the input, returning the table along with the rest of the
+    /// input.
+    ///
+    /// ### Errors
+    /// If there are fewer than `count` entries, or one of them is malformed.
+    pub fn new(count: usize, from: &[u8]) -> Result<(Self, &[u8]), ParseError>
     {
-        let mut entries: Vec<TableEntry> = Vec::with_capacity(count);
+        // The count comes straight from the file, so can't be trusted to size the allocation. However, every
+        // entry takes up at least one byte, so there can't be more of them than that.
+        let mut entries: Vec<TableEntry> = Vec::with_capacity(count.min(from.len()));
 
         let mut remaining: &[u8] = from;
         for _ in 0..count
         // Parse entries based on the count previously given
         {
+            let at = from.len() - remaining.len();
             match *remaining
             {
-                [] => return None, // There were not enough entries, therefore the file is malformed
+                [] => return Err(ParseError::UnexpectedEof { at }), // There were not enough entries
                 [tag, ref res @ ..] =>
                 // Parse the entry
                 {
-                    let (result, operands) = TableEntry::HANDLERS.get(<usize>::from(tag))?(res)?;
+                    let handler = TableEntry::HANDLERS
+                        .get(<usize>::from(tag))
+                        .ok_or(ParseError::UnknownTag { at, tag })?;
+                    let (result, operands) = handler(res).map_err(|x| x.offset_by(at + 1))?;
 
-                    let (_, rem) = res.split_at_checked(operands)?;
+                    let (_, rem) = res
+                        .split_at_checked(operands)
+                        .ok_or(ParseError::UnexpectedEof { at: at + 1 })?;
                     entries.push(result);
 
                     remaining = rem;
@@ -146,97 +818,370 @@ impl Table
             }
         }
 
-        Some((Self { entries }, remaining))
+        Ok((Self { entries }, remaining))
+    }
+
+    /// Parse `count` entries starting `start` bytes into the data, in the same way as `new`, returning the
+    /// table along with the offset just past its last entry.
+    ///
+    /// Errors are positioned relative to the start of the data, rather than `start`.
+    ///
+    /// ### Errors
+    /// In the same way as `new`, or if `start` is past the end of the data.
+    pub fn from_bytes_with_offset(data: &[u8], start: usize, count: usize) -> Result<(Self, usize), ParseError>
+    {
+        let input = data.get(start..).ok_or(ParseError::UnexpectedEof { at: data.len() })?;
+        let (table, rem) = Self::new(count, input).map_err(|x| x.offset_by(start))?;
+        Ok((table, data.len() - rem.len()))
+    }
+
+    /// Create a table directly from its entries, rather than parsing it
+    #[must_use]
+    pub fn from_entries(entries: Vec<TableEntry>) -> Self
+    {
+        Self { entries }
+    }
+
+    /// Add an entry to the end of the table
+    pub fn push(&mut self, entry: TableEntry)
+    {
+        self.entries.push(entry);
     }
 
+    #[must_use]
     pub fn get(&self, idx: u32) -> Option<&TableEntry>
     {
         self.entries.get(idx as usize)
     }
 
+    /// Get an entry so that its value can be updated in place.
+    ///
+    /// ### Safety
+    /// Only the entry's value may be changed, never its type. Anything that has already looked the entry up,
+    /// such as a function's name or an import, relies on it staying the same type.
+    pub unsafe fn get_mut(&mut self, idx: u32) -> Option<&mut TableEntry>
+    {
+        self.entries.get_mut(idx as usize)
+    }
+
+    /// Get an entry, only if it is a string
+    #[must_use]
+    pub fn get_string(&self, idx: u32) -> Option<&str>
+    {
+        match *self.get(idx)?
+        {
+            TableEntry::String(ref x) => Some(x.as_str()),
+            _ => None,
+        }
+    }
+
+    #[must_use]
     pub fn entries(&self) -> &[TableEntry]
     {
         &self.entries
     }
+
+    /// Find the index of a string within the table, so that it can be written out
+    fn string_index(&self, string: &str) -> Result<u32, WriteError>
+    {
+        let index = self
+            .entries
+            .iter()
+            .position(|x| matches!(*x, TableEntry::String(ref y) if y == string))
+            .ok_or_else(|| WriteError::MissingString(string.to_owned()))?;
+
+        u32::try_from(index).map_err(|_| WriteError::TooLarge)
+    }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub enum Directive
 {
     Symbol(u32, u32), // (name_index, descriptor_index)
     Start,
-    MaxStack(u16),  // max_stack
-    MaxLocals(u16), // max_locals
+    MaxStack(u16),              // max_stack
+    MaxLocals(u16),             // max_locals
+    Export(u32),                // name_index
+    LocalTypes(Vec<LocalType>), // The type of each local, in order
+    ExceptionTable(Vec<ExceptionEntry>),
+    Inline,   // The function is a good candidate for inlining
+    NoInline, // The function should never be inlined
+}
+
+/// Maps a range of a function's code onto the code that handles any errors raised within it
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct ExceptionEntry
+{
+    pub try_start: u16, // Offset of the first instruction covered
+    pub try_end: u16,   // Offset just past the last instruction covered
+    pub handler_pc: u16,
+    pub catch_type: u16, // Reserved for when errors have types. Every entry currently catches any error.
+}
+
+impl ExceptionEntry
+{
+    const SIZE: usize = 8;
+
+    /// Whether the instruction at `pc` is covered by this entry
+    #[must_use]
+    pub fn covers(&self, pc: usize) -> bool
+    {
+        (usize::from(self.try_start)..usize::from(self.try_end)).contains(&pc)
+    }
+}
+
+/// The type of value a local variable is declared to hold
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum LocalType
+{
+    Int,
+    Float32,
+    Float64,
+    Ptr,
+}
+
+impl LocalType
+{
+    #[must_use]
+    pub const fn from_tag(tag: u8) -> Option<Self>
+    {
+        match tag
+        {
+            0 => Some(Self::Int),
+            1 => Some(Self::Float32),
+            2 => Some(Self::Float64),
+            3 => Some(Self::Ptr),
+            _ => None,
+        }
+    }
 }
 
 impl Directive
 {
     const OPCODE: u8 = Opcode::Directive as u8; // Opcode for a directive
     const SYMBOL: u8 = 0; // The symbol directive is important and should always be 0
+    const LOCAL_TYPES: u8 = 5;
+    const EXCEPTION_TABLE: u8 = 6;
 
     const HEADER_SIZE: usize = 2; // Opcode (1 byte) + Directive Type (1 byte)
 
-    const HANDLERS: [(usize, DirectiveHandler); 4] = [
-        (8, &|x| {
+    const HANDLERS: [(usize, DirectiveHandler); 9] = [
+        (8, |x| {
             Some(Directive::Symbol(
                 u32::from_le_bytes(x[0..4].try_into().ok()?),
                 u32::from_le_bytes(x[4..8].try_into().ok()?),
             ))
         }),
-        (0, &|_| Some(Directive::Start)),
-        (2, &|x| Some(Directive::MaxStack(bytes_to_numeric!(u16, x)))),
-        (2, &|x| Some(Directive::MaxLocals(bytes_to_numeric!(u16, x)))),
+        (0, |_| Some(Directive::Start)),
+        (2, |x| Some(Directive::MaxStack(bytes_to_numeric!(u16, x)))),
+        (2, |x| Some(Directive::MaxLocals(bytes_to_numeric!(u16, x)))),
+        (4, |x| Some(Directive::Export(bytes_to_numeric!(u32, x)))),
+        (0, |_| None), // Local types and exception tables don't have a fixed number of operands, so are parsed
+        (0, |_| None), // separately
+        (0, |_| Some(Directive::Inline)),
+        (0, |_| Some(Directive::NoInline)),
     ];
-}
-
-#[derive(Debug)]
-pub struct FunctionInfo
-{
-    directives: Vec<Directive>,
-
-    // In the future this code section will be able to be a byte slice
-    // (&[u8]) rather than an owned vector as the actual data will be stored in
-    // metaspace somewhere.
-    // However, as metaspace doesnt exist yet, right now it has to be
-    // owned.
-    code: Vec<u8>,
-}
 
-impl FunctionInfo
-{
-    pub fn new<'b>(input: &'b [u8], table: &Table) -> Option<(Self, &'b [u8])>
+    /// Write the directive in the same form it is parsed from, including its opcode and type
+    fn write(&self, output: &mut Vec<u8>) -> Result<(), WriteError>
     {
-        // Get symbol directive. The symbol directive
-        // should be Directive 0, so get its entry in the handler array
-        let &(symbol_operand_byte_count, symbol_handler) = Directive::HANDLERS.get(<usize>::from(Directive::SYMBOL))?;
-        let (symbol_directive, rem_dirs) =
-            input.split_at_checked(symbol_operand_byte_count + Directive::HEADER_SIZE)?;
-
-        let symbol_operands = symbol_directive.get(Directive::HEADER_SIZE..)?;
-
-        let (name, descriptor): (&str, u32) = symbol_handler(symbol_operands).and_then(|x| {
-            match x
+        output.push(Self::OPCODE);
+        match *self
+        {
+            Self::Symbol(name_index, code_count) =>
+            {
+                output.push(Self::SYMBOL);
+                output.extend_from_slice(&name_index.to_le_bytes());
+                output.extend_from_slice(&code_count.to_le_bytes());
+            }
+            Self::Start => output.push(1),
+            Self::MaxStack(max_stack) =>
+            {
+                output.push(2);
+                output.extend_from_slice(&max_stack.to_le_bytes());
+            }
+            Self::MaxLocals(max_locals) =>
+            {
+                output.push(3);
+                output.extend_from_slice(&max_locals.to_le_bytes());
+            }
+            Self::Export(name_index) =>
+            {
+                output.push(4);
+                output.extend_from_slice(&name_index.to_le_bytes());
+            }
+            Self::LocalTypes(ref types) =>
+            {
+                output.push(Self::LOCAL_TYPES);
+                output.extend_from_slice(&file_count(types.len())?.to_le_bytes());
+                output.extend(types.iter().map(|&x| x as u8));
+            }
+            Self::ExceptionTable(ref entries) =>
             {
-                Directive::Symbol(name_index, code_count) =>
+                output.push(Self::EXCEPTION_TABLE);
+                output.extend_from_slice(&file_count(entries.len())?.to_le_bytes());
+                for entry in entries
                 {
-                    // Even thought the name is not needed here, it is
-                    // important still to verify that it is a valid constant pool entry,
-                    // and does in fact refer to a string entry
+                    for field in [entry.try_start, entry.try_end, entry.handler_pc, entry.catch_type]
+                    {
+                        output.extend_from_slice(&field.to_le_bytes());
+                    }
+                }
+            }
+            Self::Inline => output.push(7),
+            Self::NoInline => output.push(8),
+        }
+
+        Ok(())
+    }
+
+    /// Parse the operands of a local types directive, which are a 2 byte count followed by a type tag for
+    /// each local. Returns the directive along with the rest of the input.
+    fn local_types(input: &[u8]) -> Result<(Self, &[u8]), ParseError>
+    {
+        const TAGS_START: usize = size_of::<u16>();
+
+        let (count, rem) = split_off!(u16, input, 0)?;
+        let (tags, rem) = rem
+            .split_at_checked(usize::from(count))
+            .ok_or(ParseError::UnexpectedEof { at: TAGS_START })?;
+
+        let types = (TAGS_START..)
+            .zip(tags)
+            .map(|(at, &tag)| LocalType::from_tag(tag).ok_or(ParseError::UnknownTag { at, tag }))
+            .collect::<Result<_, _>>()?;
+        Ok((Self::LocalTypes(types), rem))
+    }
+
+    /// Parse the operands of an exception table directive, which are a 2 byte count followed by that many
+    /// entries. Returns the directive along with the rest of the input.
+    fn exception_table(input: &[u8]) -> Result<(Self, &[u8]), ParseError>
+    {
+        let (count, rem) = split_off!(u16, input, 0)?;
+        let (entries, rem) = rem
+            .split_at_checked(usize::from(count) * ExceptionEntry::SIZE)
+            .ok_or(ParseError::UnexpectedEof { at: size_of::<u16>() })?;
+
+        let entries = entries
+            .chunks_exact(ExceptionEntry::SIZE)
+            .map(|x| {
+                let (try_start, x) = split_off!(u16, x)?;
+                let (try_end, x) = split_off!(u16, x)?;
+                let (handler_pc, x) = split_off!(u16, x)?;
+                let (catch_type, _) = split_off!(u16, x)?;
+
+                Some(ExceptionEntry {
+                    try_start,
+                    try_end,
+                    handler_pc,
+                    catch_type,
+                })
+            })
+            .collect::<Option<_>>()
+            .ok_or(ParseError::Malformed)?;
+
+        Ok((Self::ExceptionTable(entries), rem))
+    }
+}
+
+/// A function in a lazily parsed file, which is only parsed once it is first needed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LazyFunctionEntry<'a>
+{
+    Parsed(FunctionInfo<'a>),
+    Unparsed
+    {
+        byte_offset: usize, // Where the function starts, relative to the start of the first function
+        byte_len: usize,
+    },
+}
+
+impl<'a> LazyFunctionEntry<'a>
+{
+    /// Find where every function at the start of the input is, without parsing any of them. Returns them
+    /// along with the bytes they take up, and the rest of the input.
+    fn find_all<'i>(input: &'i [u8]) -> Result<(FoundFunctions<'a, 'i>, &'i [u8]), ParseError>
+    {
+        let mut entries = vec![];
+        let mut remaining = input;
+        while let &[Directive::OPCODE, Directive::SYMBOL, ..] = remaining
+        // There is another function to find
+        {
+            let byte_offset = input.len() - remaining.len();
+            let byte_len = FunctionInfo::measure(remaining).map_err(|x| x.offset_by(byte_offset))?;
+
+            entries.push(Self::Unparsed { byte_offset, byte_len });
+            remaining = remaining.get(byte_len..).ok_or(ParseError::Malformed)?;
+        }
+
+        let (function_bytes, remaining) = input.split_at(input.len() - remaining.len());
+        Ok(((entries, function_bytes), remaining))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionInfo<'a>
+{
+    name: String,
+    directives: Vec<Directive>,
+
+    // Whilst parsing this refers to the raw file, but once loaded it refers
+    // to a copy stored in metaspace
+    code: &'a [u8],
+
+    // Where the code starts, relative to the start of the first function
+    code_offset: usize,
+}
+
+impl<'a> FunctionInfo<'a>
+{
+    /// Parse a single function from the start of the input, returning it along with the rest of the input.
+    ///
+    /// ### Panics
+    /// If running on an architecture where `usize` is smaller than 32 bits.
+    ///
+    /// ### Errors
+    /// If the function's directives are missing or malformed, or its name isn't in `table`.
+    pub fn new(input: &'a [u8], table: &Table) -> Result<(Self, &'a [u8]), ParseError>
+    {
+        // Get symbol directive. The symbol directive
+        // should be Directive 0, so get its entry in the handler array
+        let &(symbol_operand_byte_count, symbol_handler) = Directive::HANDLERS
+            .get(<usize>::from(Directive::SYMBOL))
+            .ok_or(ParseError::Malformed)?;
+        let (symbol_directive, rem_dirs) = input
+            .split_at_checked(symbol_operand_byte_count + Directive::HEADER_SIZE)
+            .ok_or(ParseError::UnexpectedEof { at: 0 })?;
 
-                    // Get the name and descriptor from the constant pool.
-                    // This will also check whether the given indices are in fact valid.
-                    let name = table.get(name_index)?;
+        let symbol_operands = symbol_directive
+            .get(Directive::HEADER_SIZE..)
+            .ok_or(ParseError::Malformed)?;
 
-                    match *name
+        let (name, descriptor): (&str, u32) = symbol_handler(symbol_operands)
+            .and_then(|x| {
+                match x
+                {
+                    Directive::Symbol(name_index, code_count) =>
                     {
-                        // The name should refer to a String, and the descriptor should refer to an Integer
-                        TableEntry::String(ref name_str) => Some((name_str.as_str(), code_count)),
-                        _ => None,
+                        // Even thought the name is not needed here, it is
+                        // important still to verify that it is a valid constant pool entry,
+                        // and does in fact refer to a string entry
+
+                        // Get the name and descriptor from the constant pool.
+                        // This will also check whether the given indices are in fact valid.
+                        let name = table.get(name_index)?;
+
+                        match *name
+                        {
+                            // The name should refer to a String, and the descriptor should refer to an Integer
+                            TableEntry::String(ref name_str) => Some((name_str.as_str(), code_count)),
+                            _ => None,
+                        }
                     }
+                    _ => None, // Something has gone really wrong if this triggers
                 }
-                _ => None, // Something has gone really wrong if this triggers
-            }
-        })?;
+            })
+            .ok_or(ParseError::Malformed)?;
 
         let mut directives: Vec<Directive> = vec![];
         let mut remaining = rem_dirs;
@@ -244,62 +1189,287 @@ impl FunctionInfo
         // Loop through the bytes until it doesn't represent a directive anymore
         while let &[Directive::OPCODE, x, ref res @ ..] = remaining
         {
+            let at = input.len() - remaining.len();
+            let operands_at = at + Directive::HEADER_SIZE;
+
             // This means that there has been a second symbol directive which isnt
             // legal
-            guard!(x != Directive::SYMBOL);
+            guard!(x != Directive::SYMBOL, ParseError::Malformed);
 
-            // Parse the found directive
-            let &(operand_count, handler) = Directive::HANDLERS.get(<usize>::from(x))?;
-            let (operands, rem) = res.split_at_checked(operand_count)?;
+            // Parse the found directive, some of which don't have a fixed number of operands
+            let (directive, rem) = match x
+            {
+                Directive::LOCAL_TYPES => Directive::local_types(res).map_err(|x| x.offset_by(operands_at))?,
+                Directive::EXCEPTION_TABLE => Directive::exception_table(res).map_err(|x| x.offset_by(operands_at))?,
+                _ =>
+                {
+                    let &(operand_count, handler) = Directive::HANDLERS
+                        .get(<usize>::from(x))
+                        .ok_or(ParseError::UnknownTag { at: at + 1, tag: x })?;
+                    let (operands, rem) = res
+                        .split_at_checked(operand_count)
+                        .ok_or(ParseError::UnexpectedEof { at: operands_at })?;
+                    (handler(operands).ok_or(ParseError::Malformed)?, rem)
+                }
+            };
 
-            directives.push(handler(operands)?);
+            directives.push(directive);
 
             remaining = rem;
         }
 
+        let code_offset = input.len() - remaining.len();
+
         #[expect(
             clippy::expect_used,
             reason = "Running this program on a less than 32-bit architecture isn't supported"
         )]
-        let (code_slice, remaining) = remaining.split_at_checked(
-            descriptor
-                .try_into()
-                .expect("Running on a none 32-bit or 64-bit architecture. How? Why?"),
-        )?;
+        let declared: usize = descriptor
+            .try_into()
+            .expect("Running on a none 32-bit or 64-bit architecture. How? Why?");
+        Self::check_code_length(code_offset, declared, remaining.len())?;
+        let (code_slice, remaining) = remaining.split_at(declared);
 
-        Some((
+        Ok((
             Self {
+                name: name.to_owned(),
                 directives,
-                code: code_slice.to_vec(),
+                code: code_slice,
+                code_offset,
             },
             remaining,
         ))
     }
 
-    pub fn get_all_functions<'a>(input: &'a [u8], table: &Table) -> Option<(Vec<Self>, &'a [u8])>
+    /// Find how many bytes the function at the start of the input takes up.
+    ///
+    /// Only the size of each directive is read, so nothing else about the function is checked.
+    fn measure(input: &[u8]) -> Result<usize, ParseError>
+    {
+        let mut code_count = None;
+        let mut remaining = input;
+        while let &[Directive::OPCODE, x, ref res @ ..] = remaining
+        {
+            let at = input.len() - remaining.len();
+            let operands_at = at + Directive::HEADER_SIZE;
+
+            // The symbol directive has to come first, and can't be given again
+            guard!((x == Directive::SYMBOL) == code_count.is_none(), ParseError::Malformed);
+
+            // Some directives start with the number of entries they have, rather than having a fixed size
+            let operand_count = match x
+            {
+                Directive::LOCAL_TYPES | Directive::EXCEPTION_TABLE =>
+                {
+                    let entry_size = if x == Directive::LOCAL_TYPES
+                    {
+                        1
+                    }
+                    else
+                    {
+                        ExceptionEntry::SIZE
+                    };
+                    let (count, _) = split_off!(u16, res, operands_at)?;
+                    size_of::<u16>() + usize::from(count) * entry_size
+                }
+                _ =>
+                {
+                    Directive::HANDLERS
+                        .get(<usize>::from(x))
+                        .ok_or(ParseError::UnknownTag { at: at + 1, tag: x })?
+                        .0
+                }
+            };
+            let (operands, rem) = res
+                .split_at_checked(operand_count)
+                .ok_or(ParseError::UnexpectedEof { at: operands_at })?;
+
+            if x == Directive::SYMBOL
+            {
+                // The symbol directive is the name's index, followed by the length of the code
+                let code_operand = operands.get(size_of::<u32>()..).ok_or(ParseError::Malformed)?;
+                let (count, _) = split_off!(u32, code_operand, 0)?;
+                code_count = Some(usize::try_from(count).map_err(|_| ParseError::Malformed)?);
+            }
+
+            remaining = rem;
+        }
+
+        let code_offset = input.len() - remaining.len();
+        let code_count = code_count.ok_or(ParseError::Malformed)?;
+        Self::check_code_length(code_offset, code_count, remaining.len())?;
+
+        Ok(code_offset + code_count)
+    }
+
+    /// Check that the length of code given by a function's symbol directive is actually there.
+    ///
+    /// Every function has to at least return, so a function without any code at all is malformed.
+    fn check_code_length(at: usize, declared: usize, available: usize) -> Result<(), ParseError>
+    {
+        guard!(declared != 0, ParseError::Malformed);
+        guard!(
+            declared <= available,
+            ParseError::BytecodeLengthMismatch {
+                at,
+                declared,
+                available
+            }
+        );
+
+        Ok(())
+    }
+
+    /// Build a function directly from its parts, rather than parsing it.
+    ///
+    /// The symbol directive isn't needed, as it only gives the name and the length of the code. The code is
+    /// treated as though it starts at the very beginning of the file.
+    #[must_use]
+    pub fn from_parts(name: String, directives: Vec<Directive>, code: &'a Bytecode) -> Self
+    {
+        Self {
+            name,
+            directives,
+            code: code.as_slice(),
+            code_offset: 0,
+        }
+    }
+
+    /// Parse functions from the start of the input until there are none left, returning them along with
+    /// the rest of the input.
+    ///
+    /// ### Errors
+    /// If any of the functions can't be parsed, positioned relative to the start of the input.
+    pub fn get_all_functions(input: &'a [u8], table: &Table) -> Result<(Vec<Self>, &'a [u8]), ParseError>
     {
         let mut functions = vec![];
         let mut remaining = input;
         while let &[Directive::OPCODE, Directive::SYMBOL, ..] = remaining
         // There is another function to read
         {
-            let (function, rem) = Self::new(remaining, table)?;
+            // Offsets are only relative to the start of this function so far
+            let offset = input.len() - remaining.len();
+            let (mut function, rem) = Self::new(remaining, table).map_err(|x| x.offset_by(offset))?;
+            function.code_offset += offset;
+
             functions.push(function);
             remaining = rem;
         }
 
-        Some((functions, remaining))
+        Ok((functions, remaining))
+    }
+
+    /// Write the function in the same form it is parsed from, starting with its symbol directive
+    fn write(&self, output: &mut Vec<u8>, table: &Table) -> Result<(), WriteError>
+    {
+        Directive::Symbol(table.string_index(&self.name)?, file_length(self.code.len())?).write(output)?;
+
+        for directive in &self.directives
+        {
+            directive.write(output)?;
+        }
+        output.extend_from_slice(self.code);
+        Ok(())
+    }
+
+    /// Copy the function's code into metaspace, so that it no longer depends on the raw file
+    pub fn intern(self, metaspace: &Metaspace) -> Option<FunctionInfo<'_>>
+    {
+        Some(FunctionInfo {
+            name: self.name,
+            directives: self.directives,
+            code: metaspace.intern_bytes(self.code)?,
+            code_offset: self.code_offset,
+        })
     }
 
     /// Turn a raw parsed `FunctionInfo` into a usable `Runnable`, with safety checks
+    #[must_use]
     pub fn into_runnable(&self) -> Option<Runnable<'_>>
     {
-        Runnable::from_parsed_data(&self.directives, &self.code)
+        Runnable::from_parsed_data(&self.name, &self.directives, self.code, self.code_offset)
+    }
+
+    #[must_use]
+    pub fn has_directive(&self, directive: &Directive) -> bool
+    {
+        self.directives.contains(directive)
+    }
+
+    #[must_use]
+    pub fn name(&self) -> &str
+    {
+        &self.name
+    }
+
+    /// Every directive given for the function, other than its symbol directive
+    #[must_use]
+    pub fn directives(&self) -> &[Directive]
+    {
+        &self.directives
+    }
+
+    pub fn directives_mut(&mut self) -> &mut [Directive]
+    {
+        self.directives.as_mut_slice()
+    }
+
+    /// Whether any of the function's code is covered by an exception table
+    #[must_use]
+    pub fn has_exception_table(&self) -> bool
+    {
+        self.directives
+            .iter()
+            .any(|x| matches!(*x, Directive::ExceptionTable(_)))
+    }
+
+    /// Every entry of the function's exception tables, in the order they are checked
+    #[must_use]
+    pub fn exception_entries(&self) -> Vec<ExceptionEntry>
+    {
+        self.directives
+            .iter()
+            .filter_map(|x| match *x
+            {
+                Directive::ExceptionTable(ref entries) => Some(entries),
+                _ => None,
+            })
+            .flatten()
+            .copied()
+            .collect()
+    }
+
+    #[must_use]
+    pub fn code(&self) -> &'a [u8]
+    {
+        self.code
+    }
+
+    /// Where the code starts, relative to the start of the first function
+    #[must_use]
+    pub fn code_offset(&self) -> usize
+    {
+        self.code_offset
     }
 
-    pub fn has_directive(&self, directive: Directive) -> bool
+    /// Swap the function's code for a rewritten version of it.
+    ///
+    /// The offset of the code within the file is kept, as it is what any debug info refers to.
+    pub fn replace_code(&mut self, code: &'a [u8])
     {
-        self.directives.contains(&directive)
+        self.code = code;
+    }
+
+    /// Get the names given by this function's export directives.
+    ///
+    /// A name is `None` if its directive doesn't refer to a string in the constant pool.
+    fn exports<'t>(&self, table: &'t Table) -> impl Iterator<Item = Option<&'t str>>
+    {
+        self.directives.iter().filter_map(|x| match *x
+        {
+            Directive::Export(name_index) => Some(table.get_string(name_index)),
+            _ => None,
+        })
     }
 }
 
@@ -313,7 +1483,7 @@ mod table_tests
     {
         let data: [u8; 0] = [];
         let (table, rem) = Table::new(0, &data).expect("Failed to parse empty table");
-        assert!(table.entries.is_empty());
+        assert!(table.entries().is_empty());
         assert!(rem.is_empty());
     }
 
@@ -326,10 +1496,10 @@ mod table_tests
             0, 30, 0, 0, 0, // Integer 30
         ];
         let (table, rem) = Table::new(3, &data).expect("Failed to parse homogeneous table");
-        assert_eq!(table.entries.len(), 3);
-        assert!(matches!(table.get(0), Some(TableEntry::Integer(10))));
-        assert!(matches!(table.get(1), Some(TableEntry::Integer(20))));
-        assert!(matches!(table.get(2), Some(TableEntry::Integer(30))));
+        assert_eq!(table.entries().len(), 3);
+        assert_eq!(table.get(0), Some(&TableEntry::Integer(10)));
+        assert_eq!(table.get(1), Some(&TableEntry::Integer(20)));
+        assert_eq!(table.get(2), Some(&TableEntry::Integer(30)));
         assert!(rem.is_empty());
     }
 
@@ -343,19 +1513,45 @@ mod table_tests
             3, 0, 0, 0, 0, 0, 0, 240, 63, // Double 1.0
         ];
         let (table, rem) = Table::new(4, &data).expect("Failed to parse heterogeneous table");
-        assert_eq!(table.entries.len(), 4);
-        assert!(matches!(table.get(0), Some(TableEntry::Integer(10))));
-        assert!(matches!(table.get(1), Some(TableEntry::Long(100))));
-        assert!(matches!(table.get(2), Some(TableEntry::Float(f)) if (f - 1.0).abs() < f32::EPSILON));
-        assert!(matches!(table.get(3), Some(TableEntry::Double(d)) if (d - 1.0).abs() < f64::EPSILON));
+        assert_eq!(table.entries().len(), 4);
+        assert_eq!(table.get(0), Some(&TableEntry::Integer(10)));
+        assert_eq!(table.get(1), Some(&TableEntry::Long(100)));
+        assert_eq!(table.get(2), Some(&TableEntry::Float(1.0)));
+        assert_eq!(table.get(3), Some(&TableEntry::Double(1.0)));
         assert!(rem.is_empty());
     }
+
+    #[test]
+    fn table_at_offset()
+    {
+        let data: [u8; 14] = [
+            9, 9, // Not part of the table
+            0, 10, 0, 0, 0, // Integer 10
+            5, 1, // Boolean true
+            0, 20, 0, 0, 0, // Integer 20, after the end of the table
+        ];
+        let (table, end) = Table::from_bytes_with_offset(&data, 2, 2).expect("Failed to parse table");
+        assert_eq!(table.entries(), [TableEntry::Integer(10), TableEntry::Boolean(1)]);
+        assert_eq!(end, 9);
+        assert_eq!(Table::new(2, &data[2..]).expect("Failed to parse table").0, table);
+
+        // Errors are positioned within the whole of the data
+        assert_eq!(
+            Table::from_bytes_with_offset(&data, 2, 4),
+            Err(ParseError::UnexpectedEof { at: 14 })
+        );
+        assert_eq!(
+            Table::from_bytes_with_offset(&data, 15, 0),
+            Err(ParseError::UnexpectedEof { at: 14 })
+        );
+    }
 }
 
 #[cfg(test)]
 mod function_info_tests
 {
     use super::*;
+    use crate::loader::runnable::RunnableError;
 
     #[test]
     fn basic_function()
@@ -390,8 +1586,743 @@ mod function_info_tests
         assert_eq!(function.code, vec![0x01, 0x02, 0x03, 0x04]);
         assert!(rem.is_empty());
     }
+
+    #[test]
+    fn bytecode_length_mismatch()
+    {
+        let table = Table {
+            entries: vec![TableEntry::String("main".into())],
+        };
+        let function = |declared: u32, available: usize| {
+            let mut data = vec![Directive::OPCODE, Directive::SYMBOL, 0, 0, 0, 0];
+            data.extend_from_slice(&declared.to_le_bytes());
+            data.resize(data.len() + available, Opcode::Ret as u8);
+            FunctionInfo::new(&data, &table).map(|(function, _)| function.code.len())
+        };
+
+        assert_eq!(function(50, 50), Ok(50));
+        assert_eq!(
+            function(100, 50),
+            Err(ParseError::BytecodeLengthMismatch {
+                at: 10,
+                declared: 100,
+                available: 50
+            })
+        );
+
+        // There has to be some code for the function to return with
+        assert_eq!(function(0, 50), Err(ParseError::Malformed));
+    }
+
+    #[test]
+    fn into_runnable()
+    {
+        #[rustfmt::skip]
+        let data = [
+            Directive::OPCODE, Directive::SYMBOL, 0, 0, 0, 0, 1, 0, 0, 0, // Symbol "main", 1 byte of code
+            Directive::OPCODE, 1,                                         // Start
+            Directive::OPCODE, 2, 2, 0,                                   // Max stack 2
+            Directive::OPCODE, 3, 1, 0,                                   // Max locals 1
+            Opcode::Ret as u8,
+        ];
+        let table = Table {
+            entries: vec![TableEntry::String("main".into())],
+        };
+
+        let (function, _) = FunctionInfo::new(&data, &table).expect("Failed to parse function");
+        assert!(function.has_directive(&Directive::Start));
+        assert!(!function.has_directive(&Directive::Export(0)));
+
+        let runnable = function.into_runnable().expect("Function should be runnable");
+        assert_eq!(runnable.name(), "main");
+        assert_eq!(runnable.setup_info(), (2, 1));
+        assert_eq!(runnable.code(), [Opcode::Ret as u8]);
+
+        // Without a max stack directive, there is no way to set the function up
+        let mut data = data.to_vec();
+        data.drain(12..16);
+        let (function, _) = FunctionInfo::new(&data, &table).expect("Failed to parse function");
+        assert!(function.into_runnable().is_none());
+    }
+
+    #[test]
+    fn from_parts()
+    {
+        let code = [Opcode::Ret as u8];
+        let bytecode = Bytecode::new(code.to_vec()).expect("Code should be valid");
+        let directives = vec![Directive::Start, Directive::MaxStack(2), Directive::MaxLocals(1)];
+
+        // The same function that `into_runnable` parses from bytes
+        let function = FunctionInfo::from_parts("main".into(), directives.clone(), &bytecode);
+        assert_eq!(function.directives(), directives);
+        assert_eq!(function.code(), code);
+
+        let runnable = function.into_runnable().expect("Function should be runnable");
+        assert_eq!(runnable.name(), "main");
+        assert_eq!(runnable.setup_info(), (2, 1));
+        assert_eq!(runnable.code(), code);
+    }
+
+    #[test]
+    fn verify_setup_info()
+    {
+        let setup = |maxstack, maxlocals| vec![Directive::MaxStack(maxstack), Directive::MaxLocals(maxlocals)];
+        let code = [Opcode::Nop as u8, Opcode::LdArg0 as u8, Opcode::RetVal as u8];
+
+        let runnable = Runnable::from_parsed_data("main", &setup(1, 1), &code, 0).expect("Function should be runnable");
+        assert_eq!(runnable.verify_setup_info(), Ok(()));
+
+        let runnable = Runnable::from_parsed_data("main", &setup(0, 1), &code, 0).expect("Function should be runnable");
+        assert_eq!(
+            runnable.verify_setup_info(),
+            Err(RunnableError::InsufficientStack { maxstack: 0, at: 1 })
+        );
+
+        let runnable = Runnable::from_parsed_data("main", &setup(1, 0), &code, 0).expect("Function should be runnable");
+        assert_eq!(
+            runnable.verify_setup_info(),
+            Err(RunnableError::InsufficientLocals { maxlocals: 0, at: 1 })
+        );
+
+        // Code that never pushes or touches a local doesn't need any room for them
+        let code = [Opcode::Nop as u8, Opcode::Ret as u8];
+        let runnable = Runnable::from_parsed_data("main", &setup(0, 0), &code, 0).expect("Function should be runnable");
+        assert_eq!(runnable.verify_setup_info(), Ok(()));
+    }
+
+    #[test]
+    fn local_types()
+    {
+        #[rustfmt::skip]
+        let data = [
+            Directive::OPCODE, Directive::SYMBOL, 0, 0, 0, 0, 1, 0, 0, 0, // Symbol "main", 1 byte of code
+            Directive::OPCODE, 2, 0, 0,                                   // Max stack 0
+            Directive::OPCODE, 3, 3, 0,                                   // Max locals 3
+            Directive::OPCODE, 5, 3, 0, 0, 2, 3,                          // Local types int, f8, ptr
+            Opcode::Ret as u8,
+        ];
+        let table = Table {
+            entries: vec![TableEntry::String("main".into())],
+        };
+
+        let (function, rem) = FunctionInfo::new(&data, &table).expect("Failed to parse function");
+        assert!(rem.is_empty());
+        assert!(function.has_directive(&Directive::LocalTypes(vec![
+            LocalType::Int,
+            LocalType::Float64,
+            LocalType::Ptr
+        ])));
+
+        let runnable = function.into_runnable().expect("Function should be runnable");
+        assert_eq!(runnable.local_type(0), Some(LocalType::Int));
+        assert_eq!(runnable.local_type(1), Some(LocalType::Float64));
+        assert_eq!(runnable.local_type(2), Some(LocalType::Ptr));
+        assert_eq!(runnable.local_type(3), None);
+
+        // Every local has to be given a type
+        let mut fewer = data.to_vec();
+        fewer[20] = 2;
+        fewer.remove(24);
+        let (function, _) = FunctionInfo::new(&fewer, &table).expect("Failed to parse function");
+        assert!(function.into_runnable().is_none());
+
+        // Unknown types are rejected
+        let mut unknown = data.to_vec();
+        unknown[24] = 4;
+        assert_eq!(
+            FunctionInfo::new(&unknown, &table),
+            Err(ParseError::UnknownTag { at: 24, tag: 4 })
+        );
+    }
+
+    #[test]
+    fn inline_hints()
+    {
+        #[rustfmt::skip]
+        let data = [
+            Directive::OPCODE, Directive::SYMBOL, 0, 0, 0, 0, 1, 0, 0, 0, // Symbol "main", 1 byte of code
+            Directive::OPCODE, 2, 0, 0,                                   // Max stack 0
+            Directive::OPCODE, 3, 0, 0,                                   // Max locals 0
+            Directive::OPCODE, 7,                                         // Inline
+            Opcode::Ret as u8,
+        ];
+        let table = Table {
+            entries: vec![TableEntry::String("main".into())],
+        };
+
+        let (function, rem) = FunctionInfo::new(&data, &table).expect("Failed to parse function");
+        assert!(rem.is_empty());
+        assert!(function.has_directive(&Directive::Inline));
+        assert!(
+            function
+                .into_runnable()
+                .expect("Function should be runnable")
+                .is_inline_hint()
+        );
+
+        let mut output = vec![];
+        function.write(&mut output, &table).expect("Failed to write function");
+        assert_eq!(output, data);
+
+        // Being told never to inline the function wins out over the hint
+        let mut never = data.to_vec();
+        never.splice(20..20, [Directive::OPCODE, 8]);
+        let (function, _) = FunctionInfo::new(&never, &table).expect("Failed to parse function");
+        assert!(function.has_directive(&Directive::NoInline));
+        assert!(
+            !function
+                .into_runnable()
+                .expect("Function should be runnable")
+                .is_inline_hint()
+        );
+    }
+
+    #[test]
+    fn exception_table()
+    {
+        #[rustfmt::skip]
+        let data = [
+            Directive::OPCODE, Directive::SYMBOL, 0, 0, 0, 0, 1, 0, 0, 0, // Symbol "main", 1 byte of code
+            Directive::OPCODE, 2, 0, 0,                                   // Max stack 0
+            Directive::OPCODE, 3, 0, 0,                                   // Max locals 0
+            Directive::OPCODE, 6, 2, 0,                                   // Exception table with 2 entries
+            2, 0, 4, 0, 20, 0, 0, 0,                                      // 2..4 handled at 20
+            0, 0, 10, 0, 30, 0, 0, 0,                                     // 0..10 handled at 30
+            Opcode::Ret as u8,
+        ];
+        let table = Table {
+            entries: vec![TableEntry::String("main".into())],
+        };
+
+        let (function, rem) = FunctionInfo::new(&data, &table).expect("Failed to parse function");
+        assert!(rem.is_empty());
+        assert!(function.has_exception_table());
+
+        // The first entry covering an instruction is the one used
+        let runnable = function.into_runnable().expect("Function should be runnable");
+        assert_eq!(runnable.find_handler(0), Some(30));
+        assert_eq!(runnable.find_handler(2), Some(20));
+        assert_eq!(runnable.find_handler(3), Some(20));
+        assert_eq!(runnable.find_handler(4), Some(30));
+        assert_eq!(runnable.find_handler(10), None);
+
+        // An entry is cut short, so none of the entries are all there
+        assert_eq!(
+            FunctionInfo::new(&data[..30], &table),
+            Err(ParseError::UnexpectedEof { at: 22 })
+        );
+    }
+
+    #[test]
+    fn untyped_locals()
+    {
+        #[rustfmt::skip]
+        let data = [
+            Directive::OPCODE, Directive::SYMBOL, 0, 0, 0, 0, 1, 0, 0, 0, // Symbol "main", 1 byte of code
+            Directive::OPCODE, 2, 0, 0,                                   // Max stack 0
+            Directive::OPCODE, 3, 1, 0,                                   // Max locals 1
+            Opcode::Ret as u8,
+        ];
+        let table = Table {
+            entries: vec![TableEntry::String("main".into())],
+        };
+
+        let (function, _) = FunctionInfo::new(&data, &table).expect("Failed to parse function");
+        let runnable = function.into_runnable().expect("Function should be runnable");
+        assert_eq!(runnable.local_type(0), None);
+    }
 }
 
 #[cfg(test)]
 mod parser_tests
-{}
+{
+    use super::*;
+
+    // The metaspace is leaked so that the layout isn't tied to it
+    fn parse(data: &[u8]) -> Result<FileLayout<'static>, ParseError>
+    {
+        FileLayout::from_bytes(data, Box::leak(Box::new(Metaspace::new())))
+    }
+
+    fn file_from_body(version: u8, body: &[u8]) -> Vec<u8>
+    {
+        let mut data = MAGIC_NUMBER.to_le_bytes().to_vec();
+        data.push(version);
+
+        let mut checked = vec![0]; // No flags
+        checked.extend_from_slice(body);
+
+        data.extend_from_slice(&crc32fast::hash(&checked).to_le_bytes());
+        data.extend_from_slice(&checked);
+        data
+    }
+
+    fn compressed_file_from_body(body: &[u8]) -> Vec<u8>
+    {
+        let mut data = MAGIC_NUMBER.to_le_bytes().to_vec();
+        data.push(SUPPORTED_VERSION);
+
+        let mut checked = vec![FLAG_COMPRESSED];
+        checked.extend_from_slice(&u32::try_from(body.len()).unwrap().to_le_bytes());
+        checked.extend_from_slice(&block::compress(body));
+
+        data.extend_from_slice(&crc32fast::hash(&checked).to_le_bytes());
+        data.extend_from_slice(&checked);
+        data
+    }
+
+    // A file body containing a single function that returns 1
+    const FUNCTION_BODY: [u8; 27] = [
+        1,
+        0,
+        0,
+        0, // 1 constant
+        4,
+        4,
+        0,
+        0,
+        0,
+        b'm',
+        b'a',
+        b'i',
+        b'n', // String "main"
+        Directive::OPCODE,
+        Directive::SYMBOL,
+        0,
+        0,
+        0,
+        0,
+        2,
+        0,
+        0,
+        0, // Symbol "main", 2 bytes of code
+        Directive::OPCODE,
+        1, // Start
+        Opcode::IConst1 as u8,
+        Opcode::RetVal as u8,
+    ];
+
+    fn empty_file(version: u8) -> Vec<u8>
+    {
+        file_from_body(version, &0_u32.to_le_bytes()) // No constants
+    }
+
+    #[test]
+    fn supported_version()
+    {
+        let layout = parse(&empty_file(1)).expect("Failed to parse version 1 file");
+        assert_eq!(layout.version(), 1);
+        assert!(layout.functions().is_empty());
+    }
+
+    #[test]
+    fn unsupported_version()
+    {
+        assert!(matches!(
+            parse(&empty_file(2)),
+            Err(ParseError::UnsupportedVersion { found: 2 })
+        ));
+    }
+
+    #[test]
+    fn invalid_magic()
+    {
+        let mut data = empty_file(SUPPORTED_VERSION);
+        data[..8].copy_from_slice(b"notazim\0");
+        assert_eq!(
+            parse(&data),
+            Err(ParseError::InvalidMagic {
+                found: u64::from_le_bytes(*b"notazim\0")
+            })
+        );
+    }
+
+    #[test]
+    fn error_positions()
+    {
+        // The file ends straight after the magic number
+        assert_eq!(
+            parse(&MAGIC_NUMBER.to_le_bytes()),
+            Err(ParseError::UnexpectedEof { at: 8 })
+        );
+
+        // The body starts after the 14 byte header, so the second constant is at 23
+        let body = [
+            2, 0, 0, 0, // 2 constants
+            0, 10, 0, 0, 0, // Integer 10
+            9, // Unknown type
+        ];
+        assert_eq!(
+            parse(&file_from_body(SUPPORTED_VERSION, &body)),
+            Err(ParseError::UnknownTag { at: 23, tag: 9 })
+        );
+
+        // The first constant's value is cut short
+        assert_eq!(
+            parse(&file_from_body(SUPPORTED_VERSION, &body[..7])),
+            Err(ParseError::UnexpectedEof { at: 19 })
+        );
+
+        // The start directive is replaced with one that doesn't exist
+        let mut body = FUNCTION_BODY.to_vec();
+        body[24] = 100;
+        assert_eq!(
+            parse(&file_from_body(SUPPORTED_VERSION, &body)),
+            Err(ParseError::UnknownTag { at: 38, tag: 100 })
+        );
+
+        // The function has less code than its symbol says, whether or not it is parsed lazily
+        let body = &FUNCTION_BODY[..FUNCTION_BODY.len() - 1];
+        let error = ParseError::BytecodeLengthMismatch {
+            at: 39,
+            declared: 2,
+            available: 1,
+        };
+        assert_eq!(parse(&file_from_body(SUPPORTED_VERSION, body)), Err(error));
+        assert_eq!(
+            FileLayout::from_bytes_lazy(&file_from_body(SUPPORTED_VERSION, body), &Metaspace::new()).err(),
+            Some(error)
+        );
+    }
+
+    #[test]
+    fn checksum_mismatch()
+    {
+        let body = [
+            1, 0, 0, 0, // 1 constant
+            0, 10, 0, 0, 0, // Integer 10
+        ];
+        let mut data = file_from_body(1, &body);
+        assert!(parse(&data).is_ok());
+
+        // Corrupt a single byte of the constant
+        if let Some(byte) = data.last_mut()
+        {
+            *byte ^= 1;
+        }
+
+        assert!(matches!(
+            parse(&data),
+            Err(ParseError::ChecksumMismatch { expected, found }) if expected != found
+        ));
+    }
+
+    #[test]
+    fn compressed_matches_uncompressed()
+    {
+        let plain =
+            parse(&file_from_body(SUPPORTED_VERSION, &FUNCTION_BODY)).expect("Failed to parse uncompressed file");
+        let compressed = parse(&compressed_file_from_body(&FUNCTION_BODY)).expect("Failed to parse compressed file");
+
+        assert_eq!(
+            plain.constants().entries().len(),
+            compressed.constants().entries().len()
+        );
+        assert_eq!(plain.functions().len(), 1);
+        assert_eq!(compressed.functions().len(), 1);
+        assert_eq!(plain.functions()[0].code, compressed.functions()[0].code);
+        assert_eq!(plain.functions()[0].directives, compressed.functions()[0].directives);
+    }
+
+    fn debug_section(entries: &[(u32, u32, u16)]) -> Vec<u8>
+    {
+        let mut section = vec![SECTION_DEBUG_INFO];
+        section.extend_from_slice(&u32::try_from(entries.len() * DebugEntry::SIZE).unwrap().to_le_bytes());
+        for &(offset, line, file) in entries
+        {
+            section.extend_from_slice(&offset.to_le_bytes());
+            section.extend_from_slice(&line.to_le_bytes());
+            section.extend_from_slice(&file.to_le_bytes());
+        }
+        section
+    }
+
+    #[test]
+    fn debug_info()
+    {
+        let mut body = FUNCTION_BODY.to_vec();
+        body.extend(debug_section(&[(12, 5, 0), (13, 6, 0)]));
+
+        let layout = parse(&file_from_body(SUPPORTED_VERSION, &body)).expect("Failed to parse file");
+
+        // The code comes after the symbol and start directives
+        assert_eq!(layout.functions()[0].code_offset, 12);
+
+        assert_eq!(layout.source_location(11), None);
+        assert_eq!(layout.source_location(12).map(|x| x.line_number), Some(5));
+        assert_eq!(layout.source_location(13).map(|x| x.line_number), Some(6));
+        assert_eq!(layout.source_location(100).map(|x| x.line_number), Some(6));
+    }
+
+    #[test]
+    fn source_path()
+    {
+        let section = |path: &[u8]| {
+            let mut section = vec![SECTION_SOURCE_PATH];
+            section.extend_from_slice(&u32::try_from(path.len()).unwrap().to_le_bytes());
+            section.extend_from_slice(path);
+            section
+        };
+
+        let layout = parse(&file_from_body(SUPPORTED_VERSION, &FUNCTION_BODY)).expect("Failed to parse file");
+        assert_eq!(layout.source_path(), None);
+
+        let body = [FUNCTION_BODY.as_slice(), &section(b"src/main.az\0")].concat();
+        let layout = parse(&file_from_body(SUPPORTED_VERSION, &body)).expect("Failed to parse file");
+        assert_eq!(layout.source_path(), Some("src/main.az"));
+        assert_eq!(
+            parse(&layout.to_bytes().unwrap())
+                .expect("Failed to parse written file")
+                .source_path(),
+            Some("src/main.az")
+        );
+
+        // The path has to be valid UTF-8, ended by a single null byte
+        for path in [&b"src/main.az"[..], b"src\0main.az\0", b"\xff\0"]
+        {
+            let body = [FUNCTION_BODY.as_slice(), &section(path)].concat();
+            assert_eq!(
+                parse(&file_from_body(SUPPORTED_VERSION, &body)),
+                Err(ParseError::Malformed)
+            );
+        }
+
+        let body = [FUNCTION_BODY.as_slice(), &section(b"a\0"), &section(b"b\0")].concat();
+        assert_eq!(
+            parse(&file_from_body(SUPPORTED_VERSION, &body)),
+            Err(ParseError::DuplicateSection {
+                section: SECTION_SOURCE_PATH
+            })
+        );
+    }
+
+    #[test]
+    fn no_debug_info()
+    {
+        let layout = parse(&file_from_body(SUPPORTED_VERSION, &FUNCTION_BODY)).expect("Failed to parse file");
+        assert_eq!(layout.source_location(12), None);
+    }
+
+    #[test]
+    fn bad_debug_info()
+    {
+        // Entries out of order
+        let mut body = FUNCTION_BODY.to_vec();
+        body.extend(debug_section(&[(13, 6, 0), (12, 5, 0)]));
+        assert!(matches!(
+            parse(&file_from_body(SUPPORTED_VERSION, &body)),
+            Err(ParseError::Malformed)
+        ));
+
+        // More than one debug section
+        let mut body = FUNCTION_BODY.to_vec();
+        body.extend(debug_section(&[(12, 5, 0)]));
+        body.extend(debug_section(&[(12, 5, 0)]));
+        assert!(matches!(
+            parse(&file_from_body(SUPPORTED_VERSION, &body)),
+            Err(ParseError::DuplicateSection {
+                section: SECTION_DEBUG_INFO
+            })
+        ));
+
+        // Section cut short
+        let mut body = FUNCTION_BODY.to_vec();
+        body.extend(debug_section(&[(12, 5, 0)]));
+        body.pop();
+        assert_eq!(
+            parse(&file_from_body(SUPPORTED_VERSION, &body)),
+            Err(ParseError::UnexpectedEof { at: 46 }) // The start of the section's payload
+        );
+    }
+
+    #[test]
+    fn unknown_section_skipped()
+    {
+        let mut body = FUNCTION_BODY.to_vec();
+        body.extend([0xFF, 2, 0, 0, 0, 1, 2]);
+        assert!(parse(&file_from_body(SUPPORTED_VERSION, &body)).is_ok());
+    }
+
+    // Two functions, "main" and "helper", where only "helper" is exported
+    const EXPORT_BODY: [u8; 62] = [
+        3,
+        0,
+        0,
+        0, // 3 constants
+        4,
+        4,
+        0,
+        0,
+        0,
+        b'm',
+        b'a',
+        b'i',
+        b'n', // String "main"
+        4,
+        6,
+        0,
+        0,
+        0,
+        b'h',
+        b'e',
+        b'l',
+        b'p',
+        b'e',
+        b'r', // String "helper"
+        4,
+        5,
+        0,
+        0,
+        0,
+        b'm',
+        b'a',
+        b't',
+        b'h',
+        b's', // String "maths"
+        Directive::OPCODE,
+        Directive::SYMBOL,
+        0,
+        0,
+        0,
+        0,
+        1,
+        0,
+        0,
+        0, // Symbol "main", 1 byte of code
+        Opcode::Ret as u8,
+        Directive::OPCODE,
+        Directive::SYMBOL,
+        1,
+        0,
+        0,
+        0,
+        1,
+        0,
+        0,
+        0, // Symbol "helper", 1 byte of code
+        Directive::OPCODE,
+        4,
+        1,
+        0,
+        0,
+        0, // Export "helper"
+        Opcode::Ret as u8,
+    ];
+
+    #[test]
+    fn exports()
+    {
+        let layout = parse(&file_from_body(SUPPORTED_VERSION, &EXPORT_BODY)).expect("Failed to parse file");
+
+        let names: Vec<&str> = layout.functions().iter().map(FunctionInfo::name).collect();
+        assert_eq!(names, vec!["main", "helper"]);
+        assert_eq!(layout.function_count(), 2);
+        assert_eq!(layout.exports().collect::<Vec<_>>(), vec!["helper"]);
+        assert!(layout.imports().is_empty());
+    }
+
+    #[test]
+    fn export_unknown_function()
+    {
+        // Export "maths" instead, which isn't a function
+        let mut body = EXPORT_BODY;
+        body[58] = 2;
+        assert!(matches!(
+            parse(&file_from_body(SUPPORTED_VERSION, &body)),
+            Err(ParseError::Malformed)
+        ));
+    }
+
+    #[test]
+    fn imports()
+    {
+        let mut body = EXPORT_BODY.to_vec();
+        body.extend([SECTION_IMPORTS, 8, 0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0]); // "helper" from "maths"
+
+        let layout = parse(&file_from_body(SUPPORTED_VERSION, &body)).expect("Failed to parse file");
+        assert_eq!(layout.imports(), [("maths".to_owned(), "helper".to_owned())]);
+
+        // Imports must refer to strings
+        let mut body = EXPORT_BODY.to_vec();
+        body.extend([SECTION_IMPORTS, 8, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0]);
+        assert!(matches!(
+            parse(&file_from_body(SUPPORTED_VERSION, &body)),
+            Err(ParseError::Malformed)
+        ));
+    }
+
+    #[test]
+    fn round_trip()
+    {
+        // Sections are always written in the same order, so only files using that order come out the same
+        let mut with_sections = EXPORT_BODY.to_vec();
+        with_sections.extend(debug_section(&[(12, 5, 0), (13, 6, 0)]));
+        with_sections.extend([SECTION_IMPORTS, 8, 0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0]);
+
+        #[rustfmt::skip]
+        let with_directives = [
+            1, 0, 0, 0, 4, 4, 0, 0, 0, b'm', b'a', b'i', b'n',
+            Directive::OPCODE, Directive::SYMBOL, 0, 0, 0, 0, 2, 0, 0, 0,
+            Directive::OPCODE, 1,
+            Directive::OPCODE, 2, 1, 0,
+            Directive::OPCODE, 3, 1, 0,
+            Directive::OPCODE, Directive::LOCAL_TYPES, 1, 0, 3,
+            Directive::OPCODE, Directive::EXCEPTION_TABLE, 1, 0, 0, 0, 1, 0, 1, 0, 0, 0,
+            Opcode::IConst1 as u8,
+            Opcode::RetVal as u8,
+        ];
+
+        for data in [
+            file_from_body(SUPPORTED_VERSION, &FUNCTION_BODY),
+            file_from_body(SUPPORTED_VERSION, &with_sections),
+            file_from_body(SUPPORTED_VERSION, &with_directives),
+            compressed_file_from_body(&FUNCTION_BODY),
+        ]
+        {
+            let layout = parse(&data).expect("Failed to parse file");
+            let bytes = layout.to_bytes().expect("Failed to write file");
+
+            assert_eq!(parse(&bytes), Ok(layout));
+            assert_eq!(bytes, data);
+        }
+    }
+
+    #[test]
+    fn write_errors()
+    {
+        let bytecode = Bytecode::new(vec![Opcode::Ret as u8]).expect("Code should be valid");
+        let table = || Table::from_entries(vec![TableEntry::String("main".into())]);
+
+        // Function names are written as constants, so have to be in the pool
+        let function = FunctionInfo::from_parts("other".into(), vec![Directive::MaxStack(0)], &bytecode);
+        assert_eq!(
+            FileLayout::from_parts(table(), vec![function], None),
+            Err(WriteError::MissingString("other".into()))
+        );
+
+        // More locals than a 2 byte count can give the types of
+        let types = Directive::LocalTypes(vec![LocalType::Int; usize::from(u16::MAX) + 1]);
+        let function = FunctionInfo::from_parts("main".into(), vec![types], &bytecode);
+        assert_eq!(
+            FileLayout::from_parts(table(), vec![function], None),
+            Err(WriteError::TooLarge)
+        );
+
+        let function = FunctionInfo::from_parts("main".into(), vec![Directive::MaxStack(0)], &bytecode);
+        let layout = FileLayout::from_parts(table(), vec![function], None).expect("Layout should be valid");
+        assert!(layout.to_bytes().is_ok());
+    }
+
+    #[test]
+    fn bad_compressed_data()
+    {
+        let mut data = compressed_file_from_body(&FUNCTION_BODY);
+        data.truncate(data.len() - 4);
+
+        // Fix up the checksum so that the decompression is actually attempted
+        let checksum = crc32fast::hash(&data[13..]);
+        data[9..13].copy_from_slice(&checksum.to_le_bytes());
+
+        assert!(matches!(parse(&data), Err(ParseError::DecompressionFailed)));
+    }
+}
@@ -1,11 +1,68 @@
-use crate::loader::parser::Directive;
+use alloc::{vec, vec::Vec};
+use core::fmt::{self, Display, Formatter};
+
+use crate::{
+    engine::opcodes::Opcode,
+    guard,
+    loader::parser::{Directive, LocalType},
+};
 
 pub struct Runnable<'a>
 {
+    name: &'a str,
     maxstack: usize,
     maxlocals: usize,
     directives: Vec<Directive>,
     bytecode: &'a [u8],
+    code_offset: usize,
+}
+
+/// Error returned when a function's max stack or max locals can't work for its code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunnableError
+{
+    InsufficientStack
+    {
+        maxstack: usize,
+        at: usize, // The offset of an instruction that pushes onto the stack
+    },
+    InsufficientLocals
+    {
+        maxlocals: usize,
+        at: usize, // The offset of an instruction that uses a local
+    },
+    FrameTooLarge
+    {
+        maxstack: usize, maxlocals: usize
+    },
+}
+
+impl Display for RunnableError
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result
+    {
+        match *self
+        {
+            Self::InsufficientStack { maxstack, at } =>
+            {
+                write!(f, "max stack of {maxstack} is too small for the push at pc={at}")
+            }
+            Self::InsufficientLocals { maxlocals, at } =>
+            {
+                write!(
+                    f,
+                    "max locals of {maxlocals} is too small for the local used at pc={at}"
+                )
+            }
+            Self::FrameTooLarge { maxstack, maxlocals } =>
+            {
+                write!(
+                    f,
+                    "max stack of {maxstack} and max locals of {maxlocals} are too large together"
+                )
+            }
+        }
+    }
 }
 
 impl<'a> Runnable<'a>
@@ -15,51 +72,178 @@ impl<'a> Runnable<'a>
     /// This also checks the validity of that data. For example, if there
     /// isnt a maxstack or maxlocal directive specifying such data, then
     /// the runnable cannot be constructed.
-    pub fn from_parsed_data(directives: &[Directive], bytecode: &'a [u8]) -> Option<Self>
+    #[must_use]
+    pub fn from_parsed_data(
+        name: &'a str,
+        directives: &[Directive],
+        bytecode: &'a [u8],
+        code_offset: usize,
+    ) -> Option<Self>
     {
         directives
             .iter()
             .try_fold(
                 // Collect the required data, checking for invalid states
                 (None, None, vec![]),
-                |(max_stack, max_locals, mut optionals), directive| match (max_stack, max_locals, *directive)
+                |(max_stack, max_locals, mut optionals), directive| match (max_stack, max_locals, directive)
                 {
-                    (Some(_), _, Directive::MaxStack(_)) | (_, Some(_), Directive::MaxLocals(_)) => None,
-                    (None, ml, Directive::MaxStack(x)) => Some((Some(x.into()), ml, optionals)),
-                    (ms, None, Directive::MaxLocals(x)) => Some((ms, Some(x.into()), optionals)),
+                    (Some(_), _, &Directive::MaxStack(_)) | (_, Some(_), &Directive::MaxLocals(_)) => None,
+                    (None, ml, &Directive::MaxStack(x)) => Some((Some(x.into()), ml, optionals)),
+                    (ms, None, &Directive::MaxLocals(x)) => Some((ms, Some(x.into()), optionals)),
                     (ms, ml, x) =>
                     {
-                        optionals.push(x);
+                        optionals.push(x.clone());
                         Some((ms, ml, optionals))
                     }
                 },
             )
             .and_then(|(max_stack, max_locals, optionals)| {
+                let maxlocals: usize = max_locals?;
+
+                // If the locals are given types, every one of them needs one
+                guard!(optionals.iter().all(|x| match *x
+                {
+                    Directive::LocalTypes(ref types) => types.len() == maxlocals,
+                    _ => true,
+                }));
+
                 // Construct the runnable based on this data
                 Some(Self {
+                    name,
                     maxstack: max_stack?,
-                    maxlocals: max_locals?,
+                    maxlocals,
                     directives: optionals,
                     bytecode,
+                    code_offset,
                 })
             })
     }
 
+    /// The name given to the function by its symbol directive
+    #[must_use]
+    pub fn name(&self) -> &'a str
+    {
+        self.name
+    }
+
+    #[must_use]
     pub fn directives(&self) -> &[Directive]
     {
         &self.directives
     }
 
+    /// Find the code that handles an error raised by the instruction at `pc`, if there is any.
+    ///
+    /// Where more than one entry covers the instruction, the first one is used.
+    #[must_use]
+    pub fn find_handler(&self, pc: usize) -> Option<u16>
+    {
+        self.directives
+            .iter()
+            .filter_map(|x| match *x
+            {
+                Directive::ExceptionTable(ref entries) => Some(entries),
+                _ => None,
+            })
+            .flatten()
+            .find(|x| x.covers(pc))
+            .map(|x| x.handler_pc)
+    }
+
+    /// Whether the function has been marked as a good candidate for inlining.
+    ///
+    /// This is only a hint, so is false if the function has also been marked to never be inlined.
+    #[must_use]
+    pub fn is_inline_hint(&self) -> bool
+    {
+        self.directives.contains(&Directive::Inline) && !self.directives.contains(&Directive::NoInline)
+    }
+
+    /// The type a local has been declared as, if the function declares them
+    #[must_use]
+    pub fn local_type(&self, index: usize) -> Option<LocalType>
+    {
+        self.directives.iter().find_map(|x| match *x
+        {
+            Directive::LocalTypes(ref types) => types.get(index).copied(),
+            _ => None,
+        })
+    }
+
     /// Returns information critical to the setup of an executing process.
     ///
     /// This is mainly the max stack and the max locals space.
+    #[must_use]
     pub fn setup_info(&self) -> (usize, usize)
     {
         (self.maxstack, self.maxlocals)
     }
 
+    /// Check that the max stack and max locals make sense for the code.
+    ///
+    /// Code that pushes anything needs some stack, and code that uses a local needs some locals. This only
+    /// catches values that can't possibly work, with the validator checking the code in full. Decoding stops
+    /// at the first byte that isn't an instruction.
+    ///
+    /// ### Errors
+    /// The first problem found with the max stack or max locals.
+    pub fn verify_setup_info(&self) -> Result<(), RunnableError>
+    {
+        let (maxstack, maxlocals) = self.setup_info();
+        guard!(
+            maxstack.checked_add(maxlocals).is_some(),
+            RunnableError::FrameTooLarge { maxstack, maxlocals }
+        );
+
+        let mut offset = 0;
+        while let Some(opcode) = self
+            .bytecode
+            .get(offset)
+            .and_then(|&x| Opcode::try_from(x).ok())
+            .filter(|x| *x != Opcode::Directive)
+        {
+            let (_, pushes) = opcode.stack_effect();
+            guard!(
+                maxstack > 0 || pushes == 0,
+                RunnableError::InsufficientStack { maxstack, at: offset }
+            );
+
+            let uses_local = matches!(
+                opcode,
+                Opcode::LdArg0
+                    | Opcode::LdArg1
+                    | Opcode::LdArg2
+                    | Opcode::LdArg3
+                    | Opcode::LdArg
+                    | Opcode::StArg0
+                    | Opcode::StArg1
+                    | Opcode::StArg2
+                    | Opcode::StArg3
+                    | Opcode::StArg
+            );
+            guard!(
+                maxlocals > 0 || !uses_local,
+                RunnableError::InsufficientLocals { maxlocals, at: offset }
+            );
+
+            offset += 1 + usize::from(opcode.param_count());
+        }
+
+        Ok(())
+    }
+
+    #[must_use]
     pub fn code(&self) -> &[u8]
     {
         self.bytecode
     }
+
+    /// Where the code starts in the file, relative to the start of the first function.
+    ///
+    /// This is what offsets in the debug info are measured against.
+    #[must_use]
+    pub fn code_offset(&self) -> usize
+    {
+        self.code_offset
+    }
 }
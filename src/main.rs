@@ -1,12 +1,8 @@
-use crate::config::{Config, ConfigError};
+use std::process::ExitCode;
 
-mod common;
-mod config;
-mod engine;
-mod loader;
-mod memory;
+use azimuth_runtime::config::{Config, ConfigError};
 
-fn main() -> Result<(), ConfigError>
+fn main() -> Result<ExitCode, ConfigError>
 {
     Config::new()?.execute()
 }
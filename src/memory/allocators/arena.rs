@@ -1,7 +1,5 @@
-use std::{
-    alloc::{Layout, alloc, dealloc},
-    ptr::NonNull,
-};
+use alloc::alloc::{alloc, dealloc};
+use core::{alloc::Layout, ptr::NonNull, slice, str};
 
 use crate::memory::allocators::{AllocatorError, MIN_PAGE_ALIGNMENT};
 
@@ -26,11 +24,19 @@ impl Drop for ArenaAllocator
     }
 }
 
+// The arena only points into the block of memory it hands out allocations from, which it owns unless it was
+// given an existing allocation to use, so moving it to another thread moves nothing that is shared
+unsafe impl Send for ArenaAllocator {}
+
 impl ArenaAllocator
 {
+    /// Create an arena that owns a new block of `capacity` bytes
+    ///
+    /// ### Errors
+    /// If the memory for the arena can't be allocated.
     pub fn with_capacity(capacity: usize) -> Result<Self, AllocatorError>
     {
-        let layout = Layout::from_size_align(capacity, MIN_PAGE_ALIGNMENT).map_err(|x| AllocatorError::BadLayout(x))?;
+        let layout = Layout::from_size_align(capacity, MIN_PAGE_ALIGNMENT).map_err(AllocatorError::BadLayout)?;
         let data = unsafe { alloc(layout) };
 
         Ok(Self {
@@ -41,6 +47,7 @@ impl ArenaAllocator
         })
     }
 
+    #[must_use]
     pub fn from_existing_allocation(base: NonNull<u8>, capacity: usize) -> Self
     {
         Self {
@@ -62,6 +69,7 @@ impl ArenaAllocator
         })
     }
 
+    #[cfg(any(test, feature = "bench"))]
     pub fn alloc<T>(&mut self, value: T) -> Option<NonNull<T>>
     {
         let adjusted_size = size_of_val(&value).next_multiple_of(align_of_val(&value));
@@ -74,20 +82,83 @@ impl ArenaAllocator
         })
     }
 
+    /// Copy a set of bytes into the arena, returning a reference to the copy.
+    ///
+    /// The copy is valid for as long as the arena is alive and `release_all` hasn't been called, both of which
+    /// are ensured by it borrowing the arena.
+    pub fn alloc_bytes<'a>(&'a mut self, bytes: &[u8]) -> Option<&'a [u8]>
+    {
+        let ptr = self.raw_alloc(bytes.len(), 1)?;
+        unsafe { ptr.copy_from_nonoverlapping(NonNull::from(bytes).cast(), bytes.len()) };
+
+        Some(unsafe { slice::from_raw_parts(ptr.as_ptr(), bytes.len()) })
+    }
+
+    /// Copy a string into the arena, returning a reference to the copy.
+    ///
+    /// Like `alloc_bytes`, the copy is valid for as long as the arena is alive and `release_all` hasn't been
+    /// called.
+    pub fn alloc_str<'a>(&'a mut self, string: &str) -> Option<&'a str>
+    {
+        // The bytes were copied directly from a valid string
+        self.alloc_bytes(string.as_bytes())
+            .map(|x| unsafe { str::from_utf8_unchecked(x) })
+    }
+
+    /// Resize an allocation, giving back where it now lives.
+    ///
+    /// Nothing is ever freed from an arena, so this always makes a new allocation and copies the data into it.
+    /// Gives `None` if `ptr` isn't in this arena, if `old_size` bytes from it would run past what has been
+    /// allocated, or if there isn't room for the new allocation.
+    pub fn realloc(&mut self, ptr: NonNull<u8>, old_size: usize, new_size: usize, align: usize) -> Option<NonNull<u8>>
+    {
+        if !self.contains(ptr)
+        {
+            return None;
+        }
+        let offset = unsafe { ptr.byte_offset_from_unsigned(self.base) };
+        if offset.checked_add(old_size)? > self.head_offset
+        {
+            return None;
+        }
+
+        let new = self.raw_alloc(new_size, align)?;
+        unsafe { ptr.copy_to_nonoverlapping(new, old_size.min(new_size)) };
+
+        Some(new)
+    }
+
+    #[cfg(any(test, feature = "bench"))]
     pub fn release_all(&mut self)
     {
         self.head_offset = 0;
     }
 
+    #[must_use]
     pub fn contains(&self, ptr: NonNull<u8>) -> bool
     {
         (self.base..(unsafe { self.base.byte_add(self.capacity) })).contains(&ptr)
     }
+
+    /// The number of bytes currently allocated
+    #[must_use]
+    pub const fn used(&self) -> usize
+    {
+        self.head_offset
+    }
+
+    #[must_use]
+    pub const fn capacity(&self) -> usize
+    {
+        self.capacity
+    }
 }
 
 #[cfg(test)]
 mod arena_tests
 {
+    use std::{array::from_fn, thread};
+
     use super::*;
 
     struct TestingData
@@ -168,6 +239,47 @@ mod arena_tests
         }
     }
 
+    #[test]
+    fn realloc()
+    {
+        let mut arena = ArenaAllocator::with_capacity(1024).unwrap();
+
+        let ptr = arena.raw_alloc(64, 8).unwrap();
+        unsafe { ptr.cast::<[u8; 64]>().write(from_fn(|x| x as u8)) };
+
+        let moved = arena.realloc(ptr, 64, 128, 8).unwrap();
+        assert_ne!(moved, ptr);
+        assert_eq!(arena.used(), 64 + 128);
+        assert_eq!(unsafe { moved.cast::<[u8; 64]>().read() }, from_fn(|x| x as u8));
+
+        assert_eq!(arena.realloc(moved, 128, 1024, 8), None);
+
+        // Copying from outside what has been allocated is refused
+        assert_eq!(arena.realloc(moved, 256, 16, 8), None);
+        assert_eq!(arena.realloc(unsafe { ptr.byte_add(arena.used()) }, 8, 16, 8), None);
+        let outside = NonNull::from(&mut [0_u8; 8]).cast::<u8>();
+        assert_eq!(arena.realloc(outside, 8, 16, 8), None);
+        assert_eq!(arena.used(), 64 + 128);
+    }
+
+    #[test]
+    fn alloc_str()
+    {
+        let mut arena = ArenaAllocator::with_capacity(16).unwrap();
+
+        let first = arena.alloc_str("Hello!").unwrap();
+        assert_eq!(first, "Hello!");
+        let first = first.as_ptr();
+
+        let second = arena.alloc_bytes(b"World!").unwrap();
+        assert_eq!(second, b"World!");
+        assert_ne!(second.as_ptr(), first);
+
+        // Strings aren't aligned, so are packed in one after the other
+        assert_eq!(arena.used(), 12);
+        assert_eq!(arena.alloc_str("Too long!"), None);
+    }
+
     #[test]
     fn deallocation()
     {
@@ -199,4 +311,22 @@ mod arena_tests
         let ptr2 = arena.alloc(12).unwrap();
         assert_eq!(unsafe { ptr2.read() }, 12);
     }
+
+    #[test]
+    fn send_to_thread()
+    {
+        let mut arena = ArenaAllocator::with_capacity(1024).unwrap();
+        let ptr = arena.alloc(42_u64).unwrap();
+
+        let arena = thread::spawn(move || {
+            let other = arena.alloc(7_u64).unwrap();
+            assert_eq!(unsafe { other.read() }, 7);
+            arena
+        })
+        .join()
+        .unwrap();
+
+        assert_eq!(unsafe { ptr.read() }, 42);
+        assert_eq!(arena.head_offset, 16);
+    }
 }
@@ -1,9 +1,9 @@
 // A memory manager manages a block of memory as a heap
 
-use std::{
-    alloc::{Layout, alloc, dealloc},
-    ptr::NonNull,
-};
+#[cfg(any(test, feature = "bench"))]
+use alloc::alloc::alloc;
+use alloc::alloc::dealloc;
+use core::{alloc::Layout, iter, ptr::NonNull};
 
 use crate::{
     common::ScopeMethods as _,
@@ -33,6 +33,12 @@ impl<const N: usize> Drop for GeneralAllocator<N>
     }
 }
 
+// Every pointer the allocator holds points into the block of memory it manages, which nothing else has access
+// to, so it can be moved to another thread along with that memory. It isn't Sync, as every change to it goes
+// through `&mut self`. If it was given an existing allocation, that already has to outlive the allocator,
+// whichever thread it ends up on.
+unsafe impl<const DEPTH: usize> Send for GeneralAllocator<DEPTH> {}
+
 impl<const DEPTH: usize> GeneralAllocator<DEPTH>
 {
     fn new(base: NonNull<u8>, capacity: usize, layout: Option<Layout>) -> Result<Self, AllocatorError>
@@ -61,80 +67,182 @@ impl<const DEPTH: usize> GeneralAllocator<DEPTH>
         })
     }
 
+    /// Create an allocator that owns a new block of `capacity` bytes
+    ///
+    /// ### Errors
+    /// If the memory can't be allocated, or `capacity` can't be split into `DEPTH` orders of block.
+    #[cfg(any(test, feature = "bench"))]
     pub fn with_capacity(capacity: usize) -> Result<Self, AllocatorError>
     {
-        let layout = Layout::from_size_align(capacity, MIN_PAGE_ALIGNMENT).map_err(|x| AllocatorError::BadLayout(x))?;
+        let layout = Layout::from_size_align(capacity, MIN_PAGE_ALIGNMENT).map_err(AllocatorError::BadLayout)?;
 
         let base = NonNull::new(unsafe { alloc(layout) }).ok_or(AllocatorError::FailedInitialAllocation)?;
 
         Self::new(base, capacity, Some(layout))
     }
 
+    /// Manage an existing block of memory, which must be aligned to a page
+    ///
+    /// ### Errors
+    /// `AllocatorError::BadConstraints` if the block isn't aligned, or `capacity` can't be split into `DEPTH`
+    /// orders of block.
     pub fn from_existing_allocation(base: NonNull<u8>, capacity: usize) -> Result<Self, AllocatorError>
     {
         Self::new(base, capacity, None)
     }
 
-    pub fn raw_alloc(&mut self, size: usize, align: usize) -> Option<NonNull<u8>>
-    {
-        self.get_allocation_order(size, align)
-            .map(|target| {
-                (target..DEPTH)
-                    .map(|order| {
-                        self.block_pop(order).inspect(|block| {
-                            if order > target
-                            {
-                                unsafe {
-                                    self.split_block(*block, order, target);
-                                }
-                            }
-                        })
-                    })
-                    .find(Option::is_some)
-                    .flatten()
+    /// Allocate a block of at least `size` bytes, aligned to `align`
+    ///
+    /// ### Errors
+    /// `AllocatorError::BadRequest` if the request could never be met, or `AllocatorError::OutOfMemory` if
+    /// there isn't a large enough block free.
+    pub fn raw_alloc(&mut self, size: usize, align: usize) -> Result<NonNull<u8>, AllocatorError>
+    {
+        let target = self.get_allocation_order(size, align)?;
+
+        (target..DEPTH)
+            .find_map(|order| {
+                self.block_pop(order).inspect(|block| {
+                    if order > target
+                    {
+                        unsafe {
+                            self.split_block(*block, order, target);
+                        }
+                    }
+                })
             })
-            .unwrap_or(None)
+            .ok_or(AllocatorError::OutOfMemory)
     }
 
-    pub fn alloc<T>(&mut self, value: T) -> Option<NonNull<T>>
+    /// Allocate space for `value` and move it there
+    ///
+    /// ### Errors
+    /// In the same way as `raw_alloc`.
+    #[cfg(any(test, feature = "bench"))]
+    pub fn alloc<T>(&mut self, value: T) -> Result<NonNull<T>, AllocatorError>
     {
         self.raw_alloc(size_of_val(&value), align_of_val(&value))
             .map(NonNull::cast)
             .inspect(|x| unsafe { x.write(value) })
     }
 
-    #[expect(clippy::expect_used, reason = "If somehow the align and size, it doesn't make sense")]
-    pub fn raw_dealloc(&mut self, ptr: NonNull<u8>, size: usize, align: usize)
+    /// Return a block of memory to the allocator.
+    ///
+    /// In debug builds, the freelists are checked to make sure that the block isn't already free.
+    ///
+    /// ### Errors
+    /// `AllocatorError::BadRequest` if the size or alignment could never have been allocated, or
+    /// `AllocatorError::DoubleFree` if the block is already free.
+    pub fn raw_dealloc(&mut self, ptr: NonNull<u8>, size: usize, align: usize) -> Result<(), AllocatorError>
     {
-        let initial = self
-            .get_allocation_order(size, align)
-            .expect("Invalid Block Deallocation Request");
+        let initial = self.get_allocation_order(size, align)?;
+
+        guard!(
+            !cfg!(debug_assertions) || !self.is_free(ptr),
+            AllocatorError::DoubleFree {
+                ptr: ptr.as_ptr() as usize
+            }
+        );
 
         let mut block = ptr;
         for order in initial..DEPTH
         {
+            // If the block's buddy is also free, the two can be merged back together
             if let Some(buddy) = self.find_buddy(order, block)
-                && self.block_remove(order, block)
+                && self.block_remove(order, buddy)
             {
                 block = block.min(buddy);
                 continue;
             }
 
             self.block_insert(order, block);
-            return;
+            break;
+        }
+
+        Ok(())
+    }
+
+    /// Resize an allocation, giving back where it now lives.
+    ///
+    /// Blocks are always shrunk in place, and are grown in place if the blocks directly after them are free.
+    /// Otherwise the data is copied into a new block, and the old one is freed. If that fails, the original
+    /// allocation is left as it was.
+    ///
+    /// `ptr` must have been allocated by this allocator, with the same `old_size` and `align`.
+    ///
+    /// ### Errors
+    /// `AllocatorError::BadRequest` if either size or the alignment could never have been allocated, or
+    /// `AllocatorError::OutOfMemory` if the allocation has to move and there isn't a large enough block free.
+    pub fn realloc(
+        &mut self,
+        ptr: NonNull<u8>,
+        old_size: usize,
+        new_size: usize,
+        align: usize,
+    ) -> Result<NonNull<u8>, AllocatorError>
+    {
+        let old_order = self.get_allocation_order(old_size, align)?;
+        let new_order = self.get_allocation_order(new_size, align)?;
+
+        if new_order <= old_order
+        {
+            // Free the top half of the block, until it is small enough
+            for order in new_order..old_order
+            {
+                let half = unsafe { ptr.byte_add(self.get_required_block_size(order)) };
+                self.block_insert(order, half);
+            }
+
+            return Ok(ptr);
+        }
+
+        if self.can_grow_in_place(ptr, old_order, new_order)
+        {
+            for order in old_order..new_order
+            {
+                let buddy = unsafe { ptr.byte_add(self.get_required_block_size(order)) };
+                self.block_remove(order, buddy);
+            }
+
+            return Ok(ptr);
         }
+
+        let new = self.raw_alloc(new_size, align)?;
+        unsafe { ptr.copy_to_nonoverlapping(new, old_size) };
+        self.raw_dealloc(ptr, old_size, align)?;
+
+        Ok(new)
     }
 
-    pub fn dealloc<T>(&mut self, ptr: NonNull<T>)
+    /// Free an object allocated with `alloc`
+    ///
+    /// ### Errors
+    /// In the same way as `raw_dealloc`.
+    #[cfg(any(test, feature = "bench"))]
+    pub fn dealloc<T>(&mut self, ptr: NonNull<T>) -> Result<(), AllocatorError>
     {
-        self.raw_dealloc(ptr.cast(), size_of::<T>(), align_of::<T>());
+        self.raw_dealloc(ptr.cast(), size_of::<T>(), align_of::<T>())
     }
 
+    #[must_use]
     pub fn contains(&self, ptr: NonNull<u8>) -> bool
     {
         (self.base..(unsafe { self.base.byte_add(self.capacity) })).contains(&ptr)
     }
 
+    /// The number of bytes currently allocated, including any lost to rounding up to the block size
+    #[must_use]
+    pub fn used(&self) -> usize
+    {
+        self.capacity - self.free_blocks().map(|(_, size)| size).sum::<usize>()
+    }
+
+    #[must_use]
+    pub const fn capacity(&self) -> usize
+    {
+        self.capacity
+    }
+
     fn get_allocation_size(&self, in_size: usize, alignment: usize) -> Result<usize, AllocatorError>
     {
         guard!(alignment.is_power_of_two(), AllocatorError::BadRequest);
@@ -216,7 +324,7 @@ impl<const DEPTH: usize> GeneralAllocator<DEPTH>
         let block_size = self.get_required_block_size(order);
 
         let mut index = 0;
-        while (order >> index) > target
+        while order - index > target
         {
             index += 1;
 
@@ -225,6 +333,48 @@ impl<const DEPTH: usize> GeneralAllocator<DEPTH>
         }
     }
 
+    /// Whether the given pointer lies within any of the blocks currently on a freelist
+    fn is_free(&self, ptr: NonNull<u8>) -> bool
+    {
+        self.free_blocks()
+            .any(|(start, size)| (start..(unsafe { start.byte_add(size) })).contains(&ptr))
+    }
+
+    /// Every block currently on a freelist, along with its size
+    fn free_blocks(&self) -> impl Iterator<Item = (NonNull<u8>, usize)>
+    {
+        self.freelists.iter().enumerate().flat_map(move |(order, &head)| {
+            let size = self.get_required_block_size(order);
+
+            iter::successors(head, move |block| {
+                // The header of the top level block is never initialised, same as in `block_pop`
+                if order == DEPTH - 1
+                {
+                    None
+                }
+                else
+                {
+                    unsafe { block.read().next }
+                }
+            })
+            .map(move |block| (block.cast(), size))
+        })
+    }
+
+    /// Whether a block can grow from `old_order` to `new_order` by taking over the free blocks directly after it
+    fn can_grow_in_place(&self, block: NonNull<u8>, old_order: usize, new_order: usize) -> bool
+    {
+        let relative = unsafe { block.byte_offset_from_unsigned(self.base) };
+
+        // The block has to be the lower buddy at every order, so that the ones it takes over come after it
+        relative.is_multiple_of(self.get_required_block_size(new_order))
+            && (old_order..new_order).all(|order| {
+                let size = self.get_required_block_size(order);
+                let buddy = unsafe { block.byte_add(size) };
+                self.free_blocks().any(|free| free == (buddy, size))
+            })
+    }
+
     fn find_buddy(&self, order: usize, block: NonNull<u8>) -> Option<NonNull<u8>>
     {
         let relative = unsafe { block.byte_offset_from_unsigned(self.base) };
@@ -245,7 +395,7 @@ struct BlockHeader
 #[cfg(test)]
 mod general_allocator_tests
 {
-    use std::array::from_fn;
+    use std::{array::from_fn, thread};
 
     use super::*;
 
@@ -346,9 +496,9 @@ mod general_allocator_tests
         let ptr = allocator.alloc([0_u8; 256]).unwrap();
 
         let ptr2 = allocator.alloc(42);
-        assert_eq!(ptr2, None);
+        assert_eq!(ptr2, Err(AllocatorError::OutOfMemory));
 
-        allocator.dealloc(ptr);
+        allocator.dealloc(ptr).unwrap();
 
         let ptr2 = allocator.alloc(42).unwrap();
         let data = unsafe { ptr2.read() };
@@ -374,7 +524,7 @@ mod general_allocator_tests
             assert_eq!(correct, unsafe { test.as_ref() });
             if i % 2 == 1
             {
-                allocator.dealloc(test);
+                allocator.dealloc(test).unwrap();
             }
         }
 
@@ -383,7 +533,7 @@ mod general_allocator_tests
             assert_eq!(i + 100, unsafe { integer.read() });
             if i % 2 == 1
             {
-                allocator.dealloc(*integer);
+                allocator.dealloc(*integer).unwrap();
             }
         }
 
@@ -419,4 +569,193 @@ mod general_allocator_tests
             }
         }
     }
+
+    #[test]
+    fn out_of_memory()
+    {
+        let mut allocator = GeneralAllocator::<4>::with_capacity(256).unwrap();
+
+        // Every block is of the minimum size, so each allocation uses one of them up
+        for _ in 0..8
+        {
+            allocator.alloc(0_u64).unwrap();
+        }
+
+        assert_eq!(allocator.alloc(0_u64), Err(AllocatorError::OutOfMemory));
+        assert_eq!(allocator.raw_alloc(256, 8), Err(AllocatorError::OutOfMemory));
+    }
+
+    #[test]
+    fn bad_request()
+    {
+        let mut allocator = GeneralAllocator::<DEPTH>::with_capacity(CAPACITY).unwrap();
+
+        assert_eq!(allocator.raw_alloc(16, 3), Err(AllocatorError::BadRequest));
+        assert_eq!(allocator.raw_alloc(16, 0), Err(AllocatorError::BadRequest));
+        assert_eq!(
+            allocator.raw_alloc(16, MIN_PAGE_ALIGNMENT * 2),
+            Err(AllocatorError::BadRequest)
+        );
+        assert_eq!(allocator.raw_alloc(CAPACITY + 1, 8), Err(AllocatorError::BadRequest));
+
+        // None of these should have used up any memory
+        assert!(allocator.raw_alloc(CAPACITY, 8).is_ok());
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn double_free()
+    {
+        let mut allocator = GeneralAllocator::<DEPTH>::with_capacity(CAPACITY).unwrap();
+
+        let ptr = allocator.alloc(42_u64).unwrap();
+        let _ = allocator.alloc(42_u64).unwrap();
+
+        allocator.dealloc(ptr).unwrap();
+        assert_eq!(
+            allocator.dealloc(ptr),
+            Err(AllocatorError::DoubleFree {
+                ptr: ptr.as_ptr() as usize
+            })
+        );
+
+        // A block that was never allocated at all is also still free
+        let never_allocated = unsafe { ptr.byte_add(CAPACITY / 2) };
+        assert_eq!(
+            allocator.dealloc(never_allocated),
+            Err(AllocatorError::DoubleFree {
+                ptr: never_allocated.as_ptr() as usize
+            })
+        );
+    }
+
+    #[test]
+    fn used()
+    {
+        let mut allocator = GeneralAllocator::<4>::with_capacity(256).unwrap();
+        assert_eq!(allocator.used(), 0);
+
+        // Allocations are rounded up to the block size
+        let ptr1 = allocator.alloc(0_u64).unwrap();
+        let ptr2 = allocator.alloc([0_u8; 40]).unwrap();
+        assert_eq!(allocator.used(), 32 + 64);
+
+        allocator.dealloc(ptr1).unwrap();
+        assert_eq!(allocator.used(), 64);
+        allocator.dealloc(ptr2).unwrap();
+        assert_eq!(allocator.used(), 0);
+    }
+
+    #[test]
+    fn split_to_smallest()
+    {
+        let mut allocator = GeneralAllocator::<DEPTH>::with_capacity(CAPACITY).unwrap();
+
+        // Splitting the only block down to the smallest size leaves one free buddy at every order in between
+        let _ = allocator.alloc(0_u64).unwrap();
+        assert_eq!(allocator.used(), CAPACITY >> (DEPTH - 1));
+        assert_eq!(allocator.free_blocks().count(), DEPTH - 1);
+
+        // So the rest of the space can still be handed out
+        assert!(allocator.raw_alloc(CAPACITY / 2, 8).is_ok());
+        assert!(allocator.raw_alloc(CAPACITY / 4, 8).is_ok());
+    }
+
+    #[test]
+    fn realloc_in_place()
+    {
+        let mut allocator = GeneralAllocator::<4>::with_capacity(256).unwrap();
+
+        let ptr = allocator.raw_alloc(64, 8).unwrap();
+        unsafe { ptr.cast::<[u8; 64]>().write(from_fn(|x| x as u8)) };
+
+        // The block after it is free, so it can just take that over
+        assert_eq!(allocator.realloc(ptr, 64, 128, 8), Ok(ptr));
+        assert_eq!(allocator.used(), 128);
+        assert_eq!(unsafe { ptr.cast::<[u8; 64]>().read() }, from_fn(|x| x as u8));
+
+        // Shrinking frees the space that is no longer needed
+        assert_eq!(allocator.realloc(ptr, 128, 32, 8), Ok(ptr));
+        assert_eq!(allocator.used(), 32);
+        assert_eq!(unsafe { ptr.cast::<[u8; 32]>().read() }, from_fn(|x| x as u8));
+
+        allocator.raw_dealloc(ptr, 32, 8).unwrap();
+        assert_eq!(allocator.used(), 0);
+    }
+
+    #[test]
+    fn realloc_moves()
+    {
+        let mut allocator = GeneralAllocator::<4>::with_capacity(256).unwrap();
+
+        let ptr = allocator.raw_alloc(64, 8).unwrap();
+        let blocker = allocator.raw_alloc(64, 8).unwrap();
+        unsafe { ptr.cast::<[u8; 64]>().write(from_fn(|x| x as u8)) };
+
+        // The block after it is in use, so it has to move, freeing where it was
+        let moved = allocator.realloc(ptr, 64, 128, 8).unwrap();
+        assert_ne!(moved, ptr);
+        assert_eq!(allocator.used(), 64 + 128);
+        assert_eq!(unsafe { moved.cast::<[u8; 64]>().read() }, from_fn(|x| x as u8));
+
+        // Nothing is lost if there isn't room anywhere
+        assert_eq!(allocator.realloc(blocker, 64, 256, 8), Err(AllocatorError::OutOfMemory));
+        assert_eq!(allocator.used(), 64 + 128);
+    }
+
+    #[test]
+    fn buddies_merge()
+    {
+        let mut allocator = GeneralAllocator::<4>::with_capacity(256).unwrap();
+
+        let ptrs: Vec<_> = (0..8).map(|_| allocator.alloc(0_u64).unwrap()).collect();
+        for ptr in ptrs
+        {
+            allocator.dealloc(ptr).unwrap();
+        }
+
+        // Only possible if every block was merged back into one
+        assert!(allocator.alloc([0_u8; 256]).is_ok());
+    }
+
+    #[test]
+    fn buddy_pair_merges()
+    {
+        let mut allocator = GeneralAllocator::<4>::with_capacity(256).unwrap();
+        let first = allocator.alloc(0_u64).unwrap();
+        let second = allocator.alloc(0_u64).unwrap();
+
+        // Freeing the first leaves it on its own, as its buddy is still in use
+        allocator.dealloc(first).unwrap();
+        assert!(
+            allocator
+                .free_blocks()
+                .any(|(block, size)| block == first.cast() && size == 32)
+        );
+
+        // Freeing its buddy takes the first back off the freelist, and the pair carries on merging up to the
+        // whole heap, rather than the buddy being left behind as a free block
+        allocator.dealloc(second).unwrap();
+        assert_eq!(allocator.free_blocks().collect::<Vec<_>>(), [(first.cast(), 256)]);
+    }
+
+    #[test]
+    fn send_to_thread()
+    {
+        let mut allocator = GeneralAllocator::<DEPTH>::with_capacity(CAPACITY).unwrap();
+        let ptr = allocator.alloc(42_u64).unwrap();
+
+        let mut allocator = thread::spawn(move || {
+            let other = allocator.alloc(7_u64).unwrap();
+            assert_eq!(unsafe { other.read() }, 7);
+            allocator.dealloc(other).unwrap();
+            allocator
+        })
+        .join()
+        .unwrap();
+
+        assert_eq!(unsafe { ptr.read() }, 42);
+        allocator.dealloc(ptr).unwrap();
+        assert_eq!(allocator.used(), 0);
+    }
 }
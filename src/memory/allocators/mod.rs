@@ -1,15 +1,23 @@
-use std::alloc::LayoutError;
+use core::alloc::LayoutError;
 
 pub mod arena;
 pub mod general;
+// Nothing allocates from a slab yet, so it is only built for its tests and the benchmarks
+#[cfg(any(test, feature = "bench"))]
+pub mod slab;
 
 const MIN_PAGE_ALIGNMENT: usize = 4096; // Page size
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum AllocatorError
 {
     BadLayout(LayoutError),
     FailedInitialAllocation,
     BadConstraints,
     BadRequest,
+    OutOfMemory,
+    DoubleFree
+    {
+        ptr: usize,
+    },
 }
@@ -0,0 +1,209 @@
+// A slab allocator manages a block of memory as a pool of identically sized objects
+
+use alloc::alloc::{alloc, dealloc};
+use core::{alloc::Layout, ptr::NonNull};
+
+use crate::{
+    guard,
+    memory::allocators::{AllocatorError, MIN_PAGE_ALIGNMENT},
+};
+
+pub struct SlabAllocator<const OBJECT_SIZE: usize, const SLAB_CAPACITY: usize>
+{
+    base: NonNull<u8>,
+    freelist: Option<NonNull<FreeBlock>>,
+    layout: Option<Layout>,
+}
+
+impl<const OBJECT_SIZE: usize, const SLAB_CAPACITY: usize> Drop for SlabAllocator<OBJECT_SIZE, SLAB_CAPACITY>
+{
+    fn drop(&mut self)
+    {
+        if let Some(layout) = self.layout
+        {
+            unsafe {
+                dealloc(self.base.as_ptr(), layout);
+            }
+        }
+    }
+}
+
+impl<const OBJECT_SIZE: usize, const SLAB_CAPACITY: usize> SlabAllocator<OBJECT_SIZE, SLAB_CAPACITY>
+{
+    const CAPACITY: usize = OBJECT_SIZE * SLAB_CAPACITY;
+
+    fn new(base: NonNull<u8>, layout: Option<Layout>) -> Result<Self, AllocatorError>
+    {
+        // Every free block has to be able to hold a pointer to the next one
+        const {
+            assert!(
+                OBJECT_SIZE >= size_of::<*mut u8>() && OBJECT_SIZE.is_multiple_of(align_of::<*mut u8>()),
+                "Objects must be large enough and aligned enough to hold a pointer"
+            );
+            assert!(SLAB_CAPACITY > 0, "A slab must be able to hold at least one object");
+        };
+
+        guard!(
+            base.as_ptr() as usize & (MIN_PAGE_ALIGNMENT - 1) == 0,
+            AllocatorError::BadConstraints
+        );
+
+        // Thread every block onto the freelist, so that they are handed out in address order
+        let mut freelist = None;
+        for index in (0..SLAB_CAPACITY).rev()
+        {
+            let block: NonNull<FreeBlock> = unsafe { base.byte_add(index * OBJECT_SIZE) }.cast();
+            unsafe { block.write(FreeBlock { next: freelist }) };
+            freelist = Some(block);
+        }
+
+        Ok(Self { base, freelist, layout })
+    }
+
+    /// Create a slab that owns a new block of memory, large enough for every object
+    ///
+    /// ### Errors
+    /// If the memory for the slab can't be allocated.
+    pub fn with_capacity() -> Result<Self, AllocatorError>
+    {
+        let layout = Layout::from_size_align(Self::CAPACITY, MIN_PAGE_ALIGNMENT).map_err(AllocatorError::BadLayout)?;
+
+        let base = NonNull::new(unsafe { alloc(layout) }).ok_or(AllocatorError::FailedInitialAllocation)?;
+
+        Self::new(base, Some(layout))
+    }
+
+    /// Manage an existing block of memory, which must be at least `OBJECT_SIZE * SLAB_CAPACITY` bytes long
+    ///
+    /// ### Errors
+    /// `AllocatorError::BadConstraints` if the block isn't aligned to a page.
+    pub fn from_existing_allocation(base: NonNull<u8>) -> Result<Self, AllocatorError>
+    {
+        Self::new(base, None)
+    }
+
+    pub fn alloc(&mut self) -> Option<NonNull<u8>>
+    {
+        let block = self.freelist?;
+        self.freelist = unsafe { block.read() }.next;
+
+        Some(block.cast())
+    }
+
+    /// Return an object to the slab.
+    ///
+    /// ### Panics
+    /// If the pointer isn't the start of an object within this slab.
+    pub fn dealloc(&mut self, ptr: NonNull<u8>)
+    {
+        assert!(
+            self.contains(ptr) && (ptr.as_ptr() as usize - self.base.as_ptr() as usize).is_multiple_of(OBJECT_SIZE),
+            "Invalid Slab Deallocation Request"
+        );
+
+        let block: NonNull<FreeBlock> = ptr.cast();
+        unsafe { block.write(FreeBlock { next: self.freelist }) };
+        self.freelist = Some(block);
+    }
+
+    #[must_use]
+    pub fn contains(&self, ptr: NonNull<u8>) -> bool
+    {
+        (self.base..(unsafe { self.base.byte_add(Self::CAPACITY) })).contains(&ptr)
+    }
+}
+
+struct FreeBlock
+{
+    next: Option<NonNull<Self>>,
+}
+
+#[cfg(test)]
+mod slab_allocator_tests
+{
+    use std::iter;
+
+    use super::*;
+
+    const OBJECT_SIZE: usize = 16;
+    const SLAB_CAPACITY: usize = 32;
+
+    type Slab = SlabAllocator<OBJECT_SIZE, SLAB_CAPACITY>;
+
+    #[test]
+    fn create_allocator()
+    {
+        let _ = Slab::with_capacity().unwrap();
+    }
+
+    #[test]
+    fn exhaustion()
+    {
+        let mut slab = Slab::with_capacity().unwrap();
+
+        let ptrs: Vec<_> = iter::repeat_with(|| slab.alloc().unwrap())
+            .take(SLAB_CAPACITY)
+            .collect();
+        assert_eq!(slab.alloc(), None);
+
+        // Every allocation is unique and aligned
+        for (i, ptr) in ptrs.iter().enumerate()
+        {
+            assert!(slab.contains(*ptr));
+            assert_eq!(ptr.as_ptr() as usize % OBJECT_SIZE, 0);
+            assert!(!ptrs[i + 1..].contains(ptr));
+        }
+    }
+
+    #[test]
+    fn reuse()
+    {
+        let mut slab = Slab::with_capacity().unwrap();
+
+        let _ = slab.alloc().unwrap();
+        let ptr = slab.alloc().unwrap();
+        unsafe { ptr.cast::<u64>().write(1234) };
+
+        slab.dealloc(ptr);
+        assert_eq!(slab.alloc(), Some(ptr));
+    }
+
+    #[test]
+    fn dealloc_when_full()
+    {
+        let mut slab = Slab::with_capacity().unwrap();
+
+        let ptrs: Vec<_> = iter::repeat_with(|| slab.alloc().unwrap())
+            .take(SLAB_CAPACITY)
+            .collect();
+        assert_eq!(slab.alloc(), None);
+
+        slab.dealloc(ptrs[5]);
+        assert_eq!(slab.alloc(), Some(ptrs[5]));
+        assert_eq!(slab.alloc(), None);
+    }
+
+    #[test]
+    fn existing_allocation()
+    {
+        let layout = Layout::from_size_align(Slab::CAPACITY, MIN_PAGE_ALIGNMENT).unwrap();
+        let base = NonNull::new(unsafe { alloc(layout) }).unwrap();
+
+        // The memory has to start on a page boundary
+        let second = unsafe { base.byte_add(OBJECT_SIZE) };
+        assert!(matches!(
+            Slab::from_existing_allocation(second),
+            Err(AllocatorError::BadConstraints)
+        ));
+
+        // Objects are handed out in address order, starting from the beginning of the memory
+        {
+            let mut slab = Slab::from_existing_allocation(base).unwrap();
+            assert_eq!(slab.alloc(), Some(base));
+            assert_eq!(slab.alloc(), Some(second));
+        }
+
+        // The slab didn't own the memory, so it is still there to be freed once the slab has gone
+        unsafe { dealloc(base.as_ptr(), layout) };
+    }
+}
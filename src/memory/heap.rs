@@ -1,10 +1,14 @@
-use std::{
-    alloc::{Layout, LayoutError, alloc},
+use alloc::{alloc::alloc_zeroed, boxed::Box, collections::BTreeMap, vec, vec::Vec};
+use core::{
+    alloc::{Layout, LayoutError},
     array::from_fn,
-    ptr::NonNull,
+    ptr::{self, NonNull},
 };
 
-use crate::memory::allocators::{AllocatorError, arena::ArenaAllocator, general::GeneralAllocator};
+use crate::{
+    guard,
+    memory::allocators::{AllocatorError, arena::ArenaAllocator, general::GeneralAllocator},
+};
 
 const HEAP_ALIGN: usize = 4096;
 
@@ -13,23 +17,22 @@ const TEEN_ALLOCATOR_DEPTH: usize = 16;
 
 const ADULT_ALLOCATOR_DEPTH: usize = 16;
 
+// Every array is preceded by its number of elements
+const ARRAY_HEADER_SIZE: usize = size_of::<u32>();
+
 struct Ratio(u32, u32);
 const YOUNG_OLD_RATIO: Ratio = Ratio(1, 2);
 const INFANT_TEEN_RATIO: Ratio = Ratio(15, 1);
 
 impl Ratio
 {
-    #[expect(
-        clippy::cast_sign_loss,
-        clippy::cast_possible_truncation,
-        clippy::cast_precision_loss,
-        reason = "who cares lol"
-    )]
+    #[expect(clippy::integer_division, reason = "Rounding is done by hand")]
     pub const fn split(&self, value: usize) -> (usize, usize)
     {
-        let total = self.0 + self.1;
+        let total = (self.0 + self.1) as u128;
 
-        let first = ((self.0 as f64 / total as f64) * value as f64).round() as usize;
+        // Rounded to the nearest whole number, without needing floating point maths
+        let first = ((self.0 as u128 * value as u128 * 2 + total) / (total * 2)) as usize;
         let second = value - first;
 
         (first, second)
@@ -51,6 +54,18 @@ pub enum HeapError
     CannotProvision(AllocatorError),
 }
 
+/// How much of each generation of the heap is in use, in bytes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeapStats
+{
+    pub infant_used: usize,
+    pub infant_capacity: usize,
+    pub teen_used: [usize; TEEN_COUNT],
+    pub teen_capacity: [usize; TEEN_COUNT],
+    pub adult_used: usize,
+    pub adult_capacity: usize,
+}
+
 pub struct Heap
 {
     base: NonNull<u8>,
@@ -58,10 +73,21 @@ pub struct Heap
     infant: ArenaAllocator,
     teen: [GeneralAllocator<TEEN_ALLOCATOR_DEPTH>; TEEN_COUNT],
     adult: GeneralAllocator<ADULT_ALLOCATOR_DEPTH>,
+
+    // Older objects that have had a pointer to an infant written into them. A minor GC has to treat
+    // these as roots, as it won't be scanning the older generations.
+    remembered_set: Vec<NonNull<u8>>,
+
+    // Cleanup to run when an object is freed, for objects that wrap something outside of the heap
+    finalizers: BTreeMap<NonNull<u8>, Box<dyn FnOnce()>>,
 }
 
 impl Heap
 {
+    /// Create a heap with room for about `capacity` bytes, split between its generations
+    ///
+    /// ### Errors
+    /// If the memory for the heap can't be allocated, or split up between the generations.
     pub fn with_capacity(capacity: usize) -> Result<Self, HeapError>
     {
         let (young_init, old_init) = YOUNG_OLD_RATIO.split(capacity);
@@ -73,21 +99,24 @@ impl Heap
 
         let total_capacity = infant_capacity + teen_capacity + adult_capacity;
 
-        let layout = Layout::from_size_align(total_capacity, HEAP_ALIGN).map_err(|x| HeapError::InvalidLayout(x))?;
+        let layout = Layout::from_size_align(total_capacity, HEAP_ALIGN).map_err(HeapError::InvalidLayout)?;
 
-        let base = NonNull::new(unsafe { alloc(layout) })
+        // Zeroed, so that reading memory that hasn't been written to yet still gives a value
+        let base = NonNull::new(unsafe { alloc_zeroed(layout) })
             .ok_or(HeapError::CannotProvision(AllocatorError::FailedInitialAllocation))?;
         let infant_base = base;
         let teen_base = unsafe { infant_base.byte_add(infant_capacity) };
         let adult_base = unsafe { teen_base.byte_add(teen_capacity) };
 
+        #[expect(
+            clippy::integer_division,
+            reason = "The teen capacity is a power of two, so it always splits evenly"
+        )]
+        let teen_size = teen_capacity / TEEN_COUNT;
+
         let infant = ArenaAllocator::from_existing_allocation(infant_base, infant_capacity);
         let teen = from_fn::<Option<GeneralAllocator<_>>, TEEN_COUNT, _>(|x| {
-            GeneralAllocator::from_existing_allocation(
-                unsafe { teen_base.byte_add((teen_capacity * x) / TEEN_COUNT) },
-                teen_capacity / TEEN_COUNT,
-            )
-            .ok()
+            GeneralAllocator::from_existing_allocation(unsafe { teen_base.byte_add(teen_size * x) }, teen_size).ok()
         })
         .into_iter()
         .collect::<Option<Vec<_>>>()
@@ -103,6 +132,8 @@ impl Heap
             infant,
             teen,
             adult,
+            remembered_set: vec![],
+            finalizers: BTreeMap::new(),
         })
     }
 
@@ -136,17 +167,294 @@ impl Heap
         })
     }
 
-    pub fn dealloc<T>(&mut self, ptr: NonNull<T>)
+    /// Allocate an array of `count` elements, each `element_size` bytes long, returning a pointer to the
+    /// first element.
+    ///
+    /// The number of elements is stored just before the first one, so can be read back with `array_len`.
+    /// Returns `None` if the array is too large or can't be allocated, or `align` isn't a power of two.
+    pub fn alloc_array(&mut self, element_size: usize, count: usize, align: usize) -> Option<NonNull<u8>>
+    {
+        let length = u32::try_from(count).ok()?;
+        let (header_size, total, align) = Self::array_layout(element_size, count, align)?;
+
+        let base = self.raw_alloc(total, align)?;
+        let array = unsafe { base.byte_add(header_size) };
+        let header = unsafe { array.byte_sub(ARRAY_HEADER_SIZE) };
+        unsafe { header.cast::<u32>().write(length) };
+
+        Some(array)
+    }
+
+    /// Free an array allocated by `alloc_array`, given the same element size and alignment it was allocated
+    /// with.
+    ///
+    /// The array starts before the pointer to its first element, so it can't be freed with `dealloc`.
+    ///
+    /// ### Errors
+    /// `AllocatorError::BadRequest` if `array` isn't an array allocated by this heap.
+    pub fn dealloc_array(&mut self, array: NonNull<u8>, element_size: usize, align: usize)
+    -> Result<(), AllocatorError>
+    {
+        let count = self.checked_array_len(array).ok_or(AllocatorError::BadRequest)?;
+        let (header_size, total, align) =
+            Self::array_layout(element_size, count, align).ok_or(AllocatorError::BadRequest)?;
+
+        let base = array
+            .addr()
+            .get()
+            .checked_sub(header_size)
+            .and_then(|x| NonNull::new(ptr::without_provenance_mut(x)))
+            .and_then(|x| self.checked_ptr(x, header_size))
+            .ok_or(AllocatorError::BadRequest)?;
+
+        // Anything keyed by the array is keyed by the pointer it was handed out as
+        self.remembered_set.retain(|&x| x != array);
+        if let Some(finalizer) = self.finalizers.remove(&array)
+        {
+            finalizer();
+        }
+
+        self.free(base, total, align)
+    }
+
+    /// The size of the header before an array's elements, the number of bytes to allocate for the whole array,
+    /// and the alignment to allocate it with.
+    ///
+    /// The header is padded out so that the elements after it are still aligned. Returns `None` if the array
+    /// is too large, or `align` isn't a power of two.
+    fn array_layout(element_size: usize, count: usize, align: usize) -> Option<(usize, usize, usize)>
+    {
+        guard!(align.is_power_of_two());
+
+        let header_size = ARRAY_HEADER_SIZE.next_multiple_of(align);
+        let total = element_size.checked_mul(count)?.checked_add(header_size)?;
+        Some((header_size, total, align.max(align_of::<u32>())))
+    }
+
+    /// The number of elements in an array.
+    ///
+    /// ### Safety
+    /// `ptr` must have been returned by `alloc_array`, and the array not yet deallocated.
+    #[must_use]
+    pub unsafe fn array_len(ptr: NonNull<u8>) -> usize
+    {
+        let header = unsafe { ptr.byte_sub(ARRAY_HEADER_SIZE) };
+        unsafe { header.cast::<u32>().read() as usize }
+    }
+
+    /// Record that a pointer to `target` has been written into `object`.
+    ///
+    /// This must be called for every pointer stored into a heap object, so that references from the
+    /// older generations into the infant one aren't missed.
+    pub fn write_barrier(&mut self, object: NonNull<u8>, target: NonNull<u8>)
+    {
+        let is_old = matches!(self.get_pool(object), Some(PoolType::Teen(_) | PoolType::Adult));
+
+        if is_old && self.infant.contains(target) && !self.remembered_set.contains(&object)
+        {
+            self.remembered_set.push(object);
+        }
+    }
+
+    /// Every older object that may point into the infant generation
+    #[must_use]
+    pub fn remembered_set(&self) -> &[NonNull<u8>]
+    {
+        &self.remembered_set
+    }
+
+    /// Run `finalizer` when the object at `ptr` is freed, replacing any finalizer it already had.
+    ///
+    /// This is for objects that wrap resources outside of the heap, such as file handles, which need to be
+    /// cleaned up along with the object.
+    ///
+    /// ### Errors
+    /// `AllocatorError::BadRequest` if `ptr` wasn't allocated by this heap.
+    pub fn register_finalizer(&mut self, ptr: NonNull<u8>, finalizer: Box<dyn FnOnce()>) -> Result<(), AllocatorError>
+    {
+        // Not allocated by this heap, so would never be freed by it
+        guard!(self.contains(ptr), AllocatorError::BadRequest);
+
+        self.finalizers.insert(ptr, finalizer);
+        Ok(())
+    }
+
+    /// Free an object allocated by this heap, running its finalizer first if it has one
+    ///
+    /// ### Errors
+    /// `AllocatorError::BadRequest` if `ptr` wasn't allocated by this heap.
+    pub fn dealloc<T>(&mut self, ptr: NonNull<T>) -> Result<(), AllocatorError>
+    {
+        // A freed object can't be keeping anything alive
+        self.remembered_set.retain(|&x| x != ptr.cast());
+
+        guard!(self.contains(ptr.cast()), AllocatorError::BadRequest); // Not allocated by this heap
+
+        // Whatever the object wraps is cleaned up before the object itself goes
+        if let Some(finalizer) = self.finalizers.remove(&ptr.cast())
+        {
+            finalizer();
+        }
+
+        self.free(ptr.cast(), size_of::<T>(), align_of::<T>())
+    }
+
+    /// Resize an allocation, giving back where it now lives.
+    ///
+    /// The allocation stays in the generation it was made in, and is resized in place where that generation
+    /// is able to. If it has to move, anything keyed by the old pointer, such as its finalizer, moves along
+    /// with it. `ptr` must have been allocated by this heap, with the same `old_size` and `align`.
+    ///
+    /// ### Errors
+    /// `AllocatorError::BadRequest` if `ptr` wasn't allocated by this heap, or `AllocatorError::OutOfMemory` if
+    /// there isn't room for the new size.
+    pub fn realloc(
+        &mut self,
+        ptr: NonNull<u8>,
+        old_size: usize,
+        new_size: usize,
+        align: usize,
+    ) -> Result<NonNull<u8>, AllocatorError>
+    {
+        let new = match self.get_pool(ptr).ok_or(AllocatorError::BadRequest)?
+        {
+            PoolType::Infant => self
+                .infant
+                .realloc(ptr, old_size, new_size, align)
+                .ok_or(AllocatorError::OutOfMemory)?,
+            PoolType::Teen(index) => self.teen[index].realloc(ptr, old_size, new_size, align)?,
+            PoolType::Adult => self.adult.realloc(ptr, old_size, new_size, align)?,
+        };
+
+        if new != ptr
+        {
+            for object in &mut self.remembered_set
+            {
+                if *object == ptr
+                {
+                    *object = new;
+                }
+            }
+            if let Some(finalizer) = self.finalizers.remove(&ptr)
+            {
+                self.finalizers.insert(new, finalizer);
+            }
+        }
+
+        Ok(new)
+    }
+
+    /// Give `size` bytes at `ptr` back to whichever generation they were allocated from
+    fn free(&mut self, ptr: NonNull<u8>, size: usize, align: usize) -> Result<(), AllocatorError>
     {
-        match self.get_pool(ptr.cast())
+        match self.get_pool(ptr).ok_or(AllocatorError::BadRequest)?
         {
-            None | Some(PoolType::Infant) =>
-            { /* Do nothing */ }
-            Some(PoolType::Teen(index)) => self.teen[index].dealloc(ptr),
-            Some(PoolType::Adult) => self.adult.dealloc(ptr),
+            PoolType::Infant => Ok(()), // Do nothing
+            PoolType::Teen(index) => self.teen[index].raw_dealloc(ptr, size, align),
+            PoolType::Adult => self.adult.raw_dealloc(ptr, size, align),
+        }
+    }
+
+    /// Whether a pointer lies within any of the heap's generations
+    #[must_use]
+    pub fn contains(&self, ptr: NonNull<u8>) -> bool
+    {
+        self.get_pool(ptr).is_some()
+    }
+
+    /// The number of elements in an array, if its header lies within the heap.
+    ///
+    /// Unlike `array_len`, this is safe to call with any pointer, but if `ptr` isn't actually an array then
+    /// whatever happens to be before it is read instead.
+    #[must_use]
+    pub fn checked_array_len(&self, ptr: NonNull<u8>) -> Option<usize>
+    {
+        let header = NonNull::new(ptr::without_provenance_mut(
+            ptr.addr().get().checked_sub(ARRAY_HEADER_SIZE)?,
+        ))?;
+        // Arrays can be in any generation, so the header is only checked against the heap as a whole
+        let header = self.checked_ptr(header, ARRAY_HEADER_SIZE)?;
+        let length = unsafe { header.cast::<u32>().read_unaligned() };
+
+        usize::try_from(length).ok()
+    }
+
+    /// Read the 8 byte value at `ptr`, if all of it has been allocated, in the same way as `read_bytes`.
+    ///
+    /// `ptr` doesn't need to be aligned.
+    #[must_use]
+    pub fn read_u64(&self, ptr: NonNull<u8>) -> Option<u64>
+    {
+        let ptr = self.allocated_ptr(ptr, size_of::<u64>())?;
+        Some(unsafe { ptr.cast::<u64>().read_unaligned() })
+    }
+
+    /// Fill `output` with the bytes starting at `ptr`, if all of them have been allocated.
+    ///
+    /// This is how bytecode reaches into the heap, so only memory the infant generation has handed out can be
+    /// read, as it is the only generation that bytecode allocates from.
+    pub fn read_bytes(&self, ptr: NonNull<u8>, output: &mut [u8]) -> Option<()>
+    {
+        let ptr = self.allocated_ptr(ptr, output.len())?;
+        unsafe { ptr.as_ptr().copy_to_nonoverlapping(output.as_mut_ptr(), output.len()) };
+        Some(())
+    }
+
+    /// Copy `input` into the heap starting at `ptr`, if all of it has been allocated, in the same way as
+    /// `read_bytes`.
+    ///
+    /// The older generations keep their freelists inside their free blocks, so they are never written to.
+    pub fn write_bytes(&mut self, ptr: NonNull<u8>, input: &[u8]) -> Option<()>
+    {
+        let ptr = self.allocated_ptr(ptr, input.len())?;
+        unsafe { ptr.as_ptr().copy_from_nonoverlapping(input.as_ptr(), input.len()) };
+        Some(())
+    }
+
+    /// Write an 8 byte value to `ptr`, if all of it has been allocated, in the same way as `write_bytes`.
+    ///
+    /// `ptr` doesn't need to be aligned.
+    pub fn write_u64(&mut self, ptr: NonNull<u8>, value: u64) -> Option<()>
+    {
+        let ptr = self.allocated_ptr(ptr, size_of::<u64>())?;
+        unsafe { ptr.cast::<u64>().write_unaligned(value) };
+        Some(())
+    }
+
+    /// How many bytes each generation has in use, along with how many it has room for
+    #[must_use]
+    pub fn dump_stats(&self) -> HeapStats
+    {
+        HeapStats {
+            infant_used: self.infant.used(),
+            infant_capacity: self.infant.capacity(),
+            teen_used: self.teen.each_ref().map(GeneralAllocator::used),
+            teen_capacity: self.teen.each_ref().map(GeneralAllocator::capacity),
+            adult_used: self.adult.used(),
+            adult_capacity: self.adult.capacity(),
         }
     }
 
+    /// Get a pointer to the `size` bytes at `ptr`, if they all lie within the heap.
+    ///
+    /// The pointer is rebuilt from the heap's own base, so addresses that have been through the stack as
+    /// plain numbers can still be used.
+    fn checked_ptr(&self, ptr: NonNull<u8>, size: usize) -> Option<NonNull<u8>>
+    {
+        let offset = ptr.addr().get().checked_sub(self.base.addr().get())?;
+        (offset.checked_add(size)? <= self.layout.size()).then(|| unsafe { self.base.byte_add(offset) })
+    }
+
+    /// Get a pointer to the `size` bytes at `ptr`, in the same way as `checked_ptr`, but only if they have all
+    /// been handed out by the infant generation.
+    ///
+    /// The infant generation starts at the heap's base, so this is everything up to how much of it is used.
+    fn allocated_ptr(&self, ptr: NonNull<u8>, size: usize) -> Option<NonNull<u8>>
+    {
+        let offset = ptr.addr().get().checked_sub(self.base.addr().get())?;
+        (offset.checked_add(size)? <= self.infant.used()).then(|| unsafe { self.base.byte_add(offset) })
+    }
+
     fn get_pool(&self, ptr: NonNull<u8>) -> Option<PoolType>
     {
         // This isnt a great implementation but will do for now
@@ -168,3 +476,264 @@ impl Heap
         }
     }
 }
+
+#[cfg(test)]
+mod heap_tests
+{
+    use std::sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    };
+
+    use super::*;
+
+    // Much smaller, and the teen pools' smallest blocks are too small to hold a block header
+    const CAPACITY: usize = 1 << 24;
+
+    #[test]
+    fn array()
+    {
+        let mut heap = Heap::with_capacity(CAPACITY).unwrap();
+
+        let array = heap
+            .alloc_array(size_of::<u64>(), 10, align_of::<u64>())
+            .unwrap()
+            .cast::<u64>();
+        assert_eq!(array.as_ptr() as usize % align_of::<u64>(), 0);
+        assert_eq!(unsafe { Heap::array_len(array.cast()) }, 10);
+
+        for index in 0..10
+        {
+            unsafe { array.add(index).write(index as u64 * 3) };
+        }
+        for index in 0..10
+        {
+            assert_eq!(unsafe { array.add(index).read() }, index as u64 * 3);
+        }
+
+        // Writing the elements mustn't have touched the length
+        assert_eq!(unsafe { Heap::array_len(array.cast()) }, 10);
+    }
+
+    #[test]
+    fn array_round_trip()
+    {
+        let mut heap = Heap::with_capacity(CAPACITY).unwrap();
+
+        let array = heap.alloc_array(8, 10, 8).unwrap();
+        assert_eq!(heap.dealloc_array(array, 8, 8), Ok(()));
+
+        // Arrays only end up in the older generations once they are moved there, which is done by hand here
+        let (header_size, total, align) = Heap::array_layout(8, 10, 8).unwrap();
+        let base = heap.adult.raw_alloc(total, align).unwrap();
+        let array = unsafe { base.byte_add(header_size) };
+        unsafe { array.byte_sub(ARRAY_HEADER_SIZE).cast::<u32>().write(10) };
+
+        assert_ne!(heap.dump_stats().adult_used, 0);
+        assert_eq!(heap.dealloc_array(array, 8, 8), Ok(()));
+        assert_eq!(heap.dump_stats().adult_used, 0);
+
+        // The whole block is free again, so can be handed straight back out
+        assert_eq!(heap.adult.raw_alloc(total, align), Ok(base));
+
+        // Only the start of an array can be freed as one
+        let mut value = 0_u64;
+        let foreign = NonNull::from(&mut value).cast();
+        assert_eq!(heap.dealloc_array(foreign, 8, 8), Err(AllocatorError::BadRequest));
+        assert_eq!(heap.dealloc_array(array, 8, 3), Err(AllocatorError::BadRequest));
+    }
+
+    #[test]
+    fn realloc()
+    {
+        let mut heap = Heap::with_capacity(CAPACITY).unwrap();
+        let data: [u8; 64] = from_fn(|x| x as u8);
+
+        // Nothing is freed from the infant generation, so growing there always moves the data
+        let infant = heap.raw_alloc(64, 8).unwrap();
+        unsafe { infant.cast::<[u8; 64]>().write(data) };
+        let finalized = Arc::new(AtomicBool::new(false));
+        let flag = Arc::clone(&finalized);
+        heap.register_finalizer(infant, Box::new(move || flag.store(true, Ordering::Relaxed)))
+            .unwrap();
+
+        let moved = heap.realloc(infant, 64, 128, 8).unwrap();
+        assert_ne!(moved, infant);
+        assert_eq!(unsafe { moved.cast::<[u8; 64]>().read() }, data);
+
+        // The finalizer follows the object to where it now lives
+        assert_eq!(heap.dealloc(moved.cast::<[u8; 128]>()), Ok(()));
+        assert!(finalized.load(Ordering::Relaxed));
+
+        // The adult generation can grow the allocation into the free space after it
+        let adult = heap.adult.raw_alloc(1024, 8).unwrap();
+        unsafe { adult.cast::<[u8; 64]>().write(data) };
+        assert_eq!(heap.realloc(adult, 1024, 2048, 8), Ok(adult));
+        assert_eq!(heap.dump_stats().adult_used, 2048);
+        assert_eq!(unsafe { adult.cast::<[u8; 64]>().read() }, data);
+
+        let mut value = 0_u64;
+        let foreign = NonNull::from(&mut value).cast();
+        assert_eq!(heap.realloc(foreign, 8, 16, 8), Err(AllocatorError::BadRequest));
+    }
+
+    #[test]
+    fn invalid_array()
+    {
+        let mut heap = Heap::with_capacity(CAPACITY).unwrap();
+
+        assert_eq!(heap.alloc_array(usize::MAX, 2, 8), None);
+        assert_eq!(heap.alloc_array(8, 10, 3), None);
+        assert_eq!(heap.alloc_array(1, CAPACITY, 1), None); // Larger than the heap
+    }
+
+    #[test]
+    fn write_barrier()
+    {
+        let mut heap = Heap::with_capacity(CAPACITY).unwrap();
+
+        let infant = heap.alloc(0_u64).unwrap().cast();
+        let other_infant = heap.alloc(0_u64).unwrap().cast();
+        let adult = heap.adult.raw_alloc(16, 8).unwrap();
+        let teen = heap.teen[0].raw_alloc(16, 8).unwrap();
+
+        // Only older objects pointing at infants need remembering
+        heap.write_barrier(other_infant, infant);
+        heap.write_barrier(adult, teen);
+        assert!(heap.remembered_set().is_empty());
+
+        heap.write_barrier(adult, infant);
+        heap.write_barrier(adult, other_infant);
+        heap.write_barrier(teen, infant);
+        assert_eq!(heap.remembered_set(), [adult, teen]);
+
+        heap.dealloc(adult).unwrap();
+        assert_eq!(heap.remembered_set(), [teen]);
+    }
+
+    #[test]
+    fn stats()
+    {
+        let mut heap = Heap::with_capacity(CAPACITY).unwrap();
+        let empty = heap.dump_stats();
+        assert_eq!(
+            empty.infant_used + empty.teen_used.iter().sum::<usize>() + empty.adult_used,
+            0
+        );
+        assert_eq!(
+            empty.infant_capacity + empty.teen_capacity.iter().sum::<usize>() + empty.adult_capacity,
+            heap.layout.size()
+        );
+
+        for value in 0..100_u64
+        {
+            heap.alloc(value).unwrap();
+        }
+        heap.adult.raw_alloc(3000, 8).unwrap();
+        heap.teen[1].raw_alloc(3000, 8).unwrap();
+
+        // The general allocators round up to a power of two
+        let stats = heap.dump_stats();
+        assert_eq!(stats.infant_used, 800);
+        assert_eq!(stats.teen_used[0], 0);
+        assert!((3000..6000).contains(&stats.teen_used[1]));
+        assert!((3000..6000).contains(&stats.adult_used));
+    }
+
+    #[test]
+    fn read_write()
+    {
+        let mut heap = Heap::with_capacity(CAPACITY).unwrap();
+        let ptr = heap.alloc([0_u64; 2]).unwrap().cast::<u8>();
+
+        assert_eq!(heap.read_u64(ptr), Some(0));
+        assert_eq!(heap.write_u64(ptr, 7), Some(()));
+        assert_eq!(heap.write_u64(unsafe { ptr.byte_add(8) }, u64::MAX), Some(()));
+        assert_eq!(heap.read_u64(ptr), Some(7));
+        assert_eq!(heap.read_u64(unsafe { ptr.byte_add(3) }), Some(0xffff_ff00_0000_0000));
+
+        // Anything that doesn't lie entirely within what has been allocated is refused
+        let mut value = 0_u64;
+        let foreign = NonNull::from(&mut value).cast();
+        let end = unsafe { heap.base.byte_add(heap.layout.size()) };
+        assert_eq!(heap.read_u64(foreign), None);
+        assert_eq!(heap.write_u64(foreign, 1), None);
+        assert_eq!(heap.read_u64(unsafe { end.byte_sub(8) }), None);
+        assert_eq!(heap.read_u64(unsafe { ptr.byte_add(12) }), None);
+        assert_eq!(heap.write_u64(unsafe { ptr.byte_add(16) }, 1), None);
+        assert_eq!(value, 0);
+
+        // The older generations keep their freelists in their free blocks, so can't be written to at all
+        let teen = heap.teen[0].raw_alloc(64, 8).unwrap();
+        let adult = heap.adult.raw_alloc(1024, 8).unwrap();
+        let teen_base = unsafe { heap.base.byte_add(heap.infant.capacity()) };
+        assert_eq!(heap.write_u64(teen_base, 1), None);
+        assert_eq!(heap.write_u64(teen, 1), None);
+        assert_eq!(heap.write_u64(adult, 1), None);
+        assert_eq!(heap.read_u64(adult), None);
+    }
+
+    #[test]
+    fn checked_array()
+    {
+        let mut heap = Heap::with_capacity(CAPACITY).unwrap();
+        let array = heap.alloc_array(2, 5, 2).unwrap();
+
+        assert_eq!(heap.checked_array_len(array), Some(5));
+        assert_eq!(heap.write_bytes(unsafe { array.byte_add(8) }, &[1, 2]), Some(()));
+
+        let mut element = [0; 2];
+        assert_eq!(heap.read_bytes(unsafe { array.byte_add(8) }, &mut element), Some(()));
+        assert_eq!(element, [1, 2]);
+        assert_eq!(heap.checked_array_len(array), Some(5));
+
+        // The header of something at the very start of the heap would lie outside it
+        assert_eq!(heap.checked_array_len(heap.base), None);
+        assert_eq!(heap.write_bytes(heap.base, &[]), Some(()));
+    }
+
+    #[test]
+    fn foreign_pointer()
+    {
+        let mut heap = Heap::with_capacity(CAPACITY).unwrap();
+        let mut value = 0_u64;
+        let foreign = NonNull::from(&mut value);
+
+        let infant = heap.alloc(0_u64).unwrap();
+        let adult = heap.adult.raw_alloc(16, 8).unwrap();
+        assert!(heap.contains(infant.cast()));
+        assert!(heap.contains(adult));
+        assert!(!heap.contains(foreign.cast()));
+
+        assert_eq!(heap.dealloc(foreign), Err(AllocatorError::BadRequest));
+        assert_eq!(heap.dealloc(infant), Ok(()));
+    }
+
+    #[test]
+    fn finalizers()
+    {
+        let mut heap = Heap::with_capacity(CAPACITY).unwrap();
+        let finalized = Arc::new(AtomicBool::new(false));
+
+        let object = heap.alloc(0_u64).unwrap();
+        let flag = Arc::clone(&finalized);
+        heap.register_finalizer(object.cast(), Box::new(move || flag.store(true, Ordering::Relaxed)))
+            .unwrap();
+        assert!(!finalized.load(Ordering::Relaxed));
+
+        assert_eq!(heap.dealloc(object), Ok(()));
+        assert!(finalized.load(Ordering::Relaxed));
+
+        // Finalizers only run once, and only for objects in the heap
+        finalized.store(false, Ordering::Relaxed);
+        assert_eq!(heap.dealloc(object), Ok(()));
+        assert!(!finalized.load(Ordering::Relaxed));
+
+        let mut value = 0_u64;
+        let foreign = NonNull::from(&mut value).cast();
+        assert_eq!(
+            heap.register_finalizer(foreign, Box::new(|| {})),
+            Err(AllocatorError::BadRequest)
+        );
+    }
+}
@@ -0,0 +1,181 @@
+use alloc::{collections::BTreeSet, vec, vec::Vec};
+use core::{cell::RefCell, mem::transmute, ptr::NonNull, str};
+
+use crate::memory::allocators::arena::ArenaAllocator;
+
+/// Storage for immutable data that lives for as long as the program is loaded, such as bytecode.
+///
+/// Data is copied into a series of arenas. As these arenas are never released or moved until the
+/// metaspace itself is dropped, references into them remain valid for the metaspace's whole
+/// lifetime, even while more data is being added.
+pub struct Metaspace
+{
+    chunks: RefCell<Vec<ArenaAllocator>>,
+    chunk_size: usize,
+
+    // Every string interned so far. These actually only live as long as the metaspace, so
+    // must never be handed out with a 'static lifetime.
+    strings: RefCell<BTreeSet<&'static str>>,
+}
+
+impl Default for Metaspace
+{
+    fn default() -> Self
+    {
+        Self::new()
+    }
+}
+
+impl Metaspace
+{
+    const DEFAULT_CHUNK_SIZE: usize = 1 << 16;
+
+    #[must_use]
+    pub fn new() -> Self
+    {
+        Self::with_chunk_size(Self::DEFAULT_CHUNK_SIZE)
+    }
+
+    #[must_use]
+    pub fn with_chunk_size(chunk_size: usize) -> Self
+    {
+        Self {
+            chunks: RefCell::new(vec![]),
+            chunk_size,
+            strings: RefCell::new(BTreeSet::new()),
+        }
+    }
+
+    /// Copy a set of bytes into the metaspace, returning a reference to the copy.
+    ///
+    /// Returns `None` if a new arena was needed, but couldn't be allocated.
+    pub fn intern_bytes(&self, data: &[u8]) -> Option<&[u8]>
+    {
+        // Zero sized allocations aren't allowed, and there is nothing to copy anyway
+        if data.is_empty()
+        {
+            return Some(&[]);
+        }
+
+        let copy = self.copy_in(data.len(), |chunk| chunk.alloc_bytes(data))?;
+
+        // The arena containing the data is only freed once the metaspace is dropped, and is never written to
+        // again, so the data is valid for as long as `self` is
+        Some(unsafe { copy.as_ref() })
+    }
+
+    /// Copy a string into the metaspace, returning a reference to the copy.
+    ///
+    /// If an identical string has already been interned, that copy is returned instead.
+    pub fn intern_str(&self, string: &str) -> Option<&str>
+    {
+        if let Some(&interned) = self.strings.borrow().get(string)
+        {
+            return Some(interned);
+        }
+
+        let interned: &str = if string.is_empty()
+        {
+            ""
+        }
+        else
+        {
+            // Same as with `intern_bytes`, the copy is valid for as long as `self` is
+            unsafe { self.copy_in(string.len(), |chunk| chunk.alloc_str(string))?.as_ref() }
+        };
+
+        // Same as with the bytes themselves, the string lives for as long as the metaspace does
+        let extended = unsafe { transmute::<&str, &'static str>(interned) };
+        self.strings.borrow_mut().insert(extended);
+
+        Some(interned)
+    }
+
+    /// Copy something `size` bytes long into the last chunk with `copy`, or a new chunk if it doesn't fit there,
+    /// giving back where the copy was made
+    fn copy_in<T, F>(&self, size: usize, copy: F) -> Option<NonNull<T>>
+    where
+        T: ?Sized,
+        F: Fn(&mut ArenaAllocator) -> Option<&T>,
+    {
+        let mut chunks = self.chunks.borrow_mut();
+
+        if let Some(copied) = chunks.last_mut().and_then(|x| copy(x).map(NonNull::from))
+        {
+            return Some(copied);
+        }
+
+        // Data too large for a regular chunk is given a chunk of its own
+        let mut chunk = ArenaAllocator::with_capacity(self.chunk_size.max(size)).ok()?;
+        let copied = copy(&mut chunk).map(NonNull::from)?;
+        chunks.push(chunk);
+
+        Some(copied)
+    }
+}
+
+#[cfg(test)]
+mod metaspace_tests
+{
+    use super::*;
+
+    #[test]
+    fn intern_bytes()
+    {
+        let metaspace = Metaspace::new();
+
+        let data = vec![1, 2, 3, 4];
+        let interned = metaspace.intern_bytes(&data).unwrap();
+        drop(data);
+
+        assert_eq!(interned, [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn references_stay_valid()
+    {
+        // Small chunks, so that multiple have to be created
+        let metaspace = Metaspace::with_chunk_size(8);
+
+        let interned: Vec<&[u8]> = (0..32_u8).map(|x| metaspace.intern_bytes(&[x; 3]).unwrap()).collect();
+
+        for (i, bytes) in (0..32_u8).zip(interned)
+        {
+            assert_eq!(bytes, [i; 3]);
+        }
+    }
+
+    #[test]
+    fn large_data()
+    {
+        let metaspace = Metaspace::with_chunk_size(8);
+
+        let small = metaspace.intern_bytes(&[1; 4]).unwrap();
+        let large = metaspace.intern_bytes(&[2; 100]).unwrap();
+
+        assert_eq!(small, [1; 4]);
+        assert_eq!(large, [2; 100]);
+    }
+
+    #[test]
+    fn intern_str()
+    {
+        let metaspace = Metaspace::new();
+
+        let first = metaspace.intern_str("Hello").unwrap();
+        let second = metaspace.intern_str(&String::from("Hello")).unwrap();
+        let other = metaspace.intern_str("World").unwrap();
+
+        assert_eq!(first, "Hello");
+        assert_eq!(other, "World");
+        assert_eq!(first.as_ptr(), second.as_ptr());
+        assert_ne!(first.as_ptr(), other.as_ptr());
+    }
+
+    #[test]
+    fn empty_data()
+    {
+        let metaspace = Metaspace::new();
+        assert!(metaspace.intern_bytes(&[]).unwrap().is_empty());
+    }
+}
@@ -1,2 +1,7 @@
+// The benchmarks need direct access to the allocators
+#[cfg(feature = "bench")]
+pub mod allocators;
+#[cfg(not(feature = "bench"))]
 mod allocators;
 pub mod heap;
+pub mod metaspace;
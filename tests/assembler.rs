@@ -1,7 +1,33 @@
-use std::{collections::HashMap, error::Error, fmt::Display, io::Write, str::FromStr, sync::LazyLock};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt::Display,
+    fs,
+    io::{self, Write},
+    iter::{self, Peekable},
+    mem,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::LazyLock,
+};
 
 const MAGIC_STRING: &[u8; 8] = b"azimuth\0";
 const MAGIC_NUMBER: u64 = u64::from_le_bytes(*MAGIC_STRING);
+const VERSION: u8 = 1;
+
+const FLAG_COMPRESSED: u8 = 1 << 0;
+
+const SECTION_SOURCE_PATH: u8 = 0x01;
+const SECTION_DEBUG_INFO: u8 = 0x02;
+const SECTION_IMPORTS: u8 = 0x03;
+
+/// Options controlling the layout of the assembled file
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AssemblerOptions
+{
+    pub compress: bool,
+    pub debug_info: bool, // Equivalent to passing --debug-info
+}
 
 #[derive(Debug, Clone, Copy)]
 pub enum OperandType
@@ -10,6 +36,7 @@ pub enum OperandType
     Unsigned16,
     Unsigned32,
     Unsigned64,
+    Label, // A signed 2 byte offset to a label, relative to the start of the instruction
 }
 
 impl OperandType
@@ -19,7 +46,7 @@ impl OperandType
         match self
         {
             Self::Unsigned8 => 1,
-            Self::Unsigned16 => 2,
+            Self::Unsigned16 | Self::Label => 2,
             Self::Unsigned32 => 4,
             Self::Unsigned64 => 8,
         }
@@ -86,9 +113,63 @@ static OPCODES: LazyLock<HashMap<&'static str, (u8, &'static [OperandType])>> =
         ("f4.convert.f8", &[]),
         ("f8.convert.i", &[]),
         ("f8.convert.f4", &[]),
+        ("assert", &[]),
+        ("jmp", &[OperandType::Label]),
+        ("jmp.if.true", &[OperandType::Label]),
+        ("jmp.if.false", &[OperandType::Label]),
+        ("i.cmp.eq", &[]),
+        ("i.cmp.ne", &[]),
+        ("i.cmp.lt", &[]),
+        ("i.cmp.gt", &[]),
+        ("i.cmp.le", &[]),
+        ("i.cmp.ge", &[]),
+        ("i.const.add", &[OperandType::Unsigned8]),
+        ("halt", &[OperandType::Unsigned8]),
+        ("nop.w", &[OperandType::Unsigned8]),
+        ("nop.d", &[OperandType::Unsigned8, OperandType::Unsigned16]),
+        ("yield", &[]),
+        ("ld.heap", &[]),
+        ("st.heap", &[]),
+        ("new", &[OperandType::Unsigned16]),
+        ("new.array", &[OperandType::Unsigned8]),
+        ("array.len", &[]),
+        ("ld.array", &[OperandType::Unsigned8]),
+        ("st.array", &[OperandType::Unsigned8]),
+        ("null", &[]),
+        ("null.check", &[]),
+        ("is.cmp.eq", &[]),
+        ("is.cmp.ne", &[]),
+        ("is.cmp.lt", &[]),
+        ("is.cmp.gt", &[]),
+        ("is.cmp.le", &[]),
+        ("is.cmp.ge", &[]),
+        ("i4.extend", &[]),
+        ("i4.sign.extend", &[]),
+        ("breakpoint", &[]),
+        ("i.const.d", &[OperandType::Unsigned32]),
+        ("i.const.l", &[OperandType::Unsigned64]),
+        ("print", &[OperandType::Unsigned8]),
+        ("type.of", &[]),
+        ("cmp.swap", &[]),
+        ("nop.t", &[OperandType::Unsigned16]),
+        ("f4.to.u32", &[]),
+        ("f8.to.u64", &[]),
+        ("swap2", &[]),
     ];
 
-    HashMap::from_iter(data.into_iter().zip(0..).map(|((code, ops), num)| (code, (num, ops))))
+    let mut opcodes: HashMap<_, _> = data
+        .into_iter()
+        .zip(0..)
+        .map(|((code, ops), num)| (code, (num, ops)))
+        .collect();
+
+    // Other names for opcodes, which assemble to exactly the same thing
+    for (alias, mnemonic) in [("ld.local", "ld.arg"), ("st.local", "st.arg")]
+    {
+        opcodes.insert(alias, opcodes[mnemonic]);
+    }
+
+    opcodes
 });
 
 static DIRECTIVES: LazyLock<HashMap<&'static str, (u8, &'static [OperandType])>> = LazyLock::new(|| {
@@ -100,10 +181,13 @@ static DIRECTIVES: LazyLock<HashMap<&'static str, (u8, &'static [OperandType])>>
         (".start", (1, [].as_slice())),
         (".maxstack", (2, [OperandType::Unsigned16].as_slice())),
         (".maxlocal", (3, [OperandType::Unsigned16].as_slice())),
+        (".export", (4, [OperandType::Unsigned32].as_slice())),
+        (".inline", (7, [].as_slice())),
+        (".noinline", (8, [].as_slice())),
     ])
 });
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug)]
 pub enum AssemblerError
 {
     BadFormat,
@@ -113,13 +197,25 @@ pub enum AssemblerError
     IncorrectOperandCount,
     OperandParseError(OperandType),
     MalformedConstantTable,
+    UnknownLabel,
+    DuplicateLabel,
+    JumpOutOfRange,
+    UnknownLocalType,
+    IncludeError(io::Error), // An included file couldn't be read
+    CircularInclude(String), // A file ended up including itself, either directly or through others
 }
 
 impl Display for AssemblerError
 {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
     {
-        write!(f, "{self:?}")
+        match *self
+        {
+            Self::OperandParseError(operand_type) => write!(f, "operand couldn't be parsed as {operand_type:?}"),
+            Self::IncludeError(ref error) => write!(f, "included file couldn't be read: {error}"),
+            Self::CircularInclude(ref name) => write!(f, "{name} ends up including itself"),
+            ref other => write!(f, "{other:?}"),
+        }
     }
 }
 
@@ -127,39 +223,289 @@ impl Error for AssemblerError {}
 
 type AssemblerResult<T> = Result<T, AssemblerError>;
 
+/// The positions of the labels within a function, along with every jump that refers to one.
+///
+/// Jumps can refer to labels that are only defined after them, so they are written out with
+/// a placeholder offset, which is patched once the whole function has been assembled.
+#[derive(Debug, Default)]
+struct Labels<'a>
+{
+    positions: HashMap<&'a str, usize>,
+    references: Vec<LabelReference<'a>>,
+}
+
+#[derive(Debug)]
+struct LabelReference<'a>
+{
+    label: &'a str,
+    instruction: usize, // Where the jump instruction starts, which the offset is relative to
+    operand: usize,     // Where the placeholder offset was written
+}
+
+impl<'a> Labels<'a>
+{
+    fn define(&mut self, label: &'a str, position: usize) -> AssemblerResult<()>
+    {
+        match self.positions.insert(label, position)
+        {
+            Some(_) => Err(AssemblerError::DuplicateLabel),
+            None => Ok(()),
+        }
+    }
+
+    fn refer(&mut self, operand: &'a str, instruction: usize, position: usize) -> AssemblerResult<()>
+    {
+        let label = operand.strip_prefix('@').ok_or(AssemblerError::UnknownLabel)?;
+        self.references.push(LabelReference {
+            label,
+            instruction,
+            operand: position,
+        });
+
+        Ok(())
+    }
+
+    fn resolve(self, code: &mut [u8]) -> AssemblerResult<()>
+    {
+        for reference in self.references
+        {
+            let target = *self
+                .positions
+                .get(reference.label)
+                .ok_or(AssemblerError::UnknownLabel)?;
+
+            let offset = i16::try_from(target.cast_signed() - reference.instruction.cast_signed())
+                .map_err(|_| AssemblerError::JumpOutOfRange)?;
+            code[reference.operand..reference.operand + size_of::<i16>()].copy_from_slice(&offset.to_le_bytes());
+        }
+
+        Ok(())
+    }
+}
+
 pub fn assemble(input: &str, target: &mut dyn Write) -> AssemblerResult<()>
 {
-    target
-        .write(&MAGIC_NUMBER.to_le_bytes())
-        .map_err(|_| AssemblerError::WriteError)?;
-    target.write(&[0]).map_err(|_| AssemblerError::WriteError)?;
+    assemble_with_options(input, target, AssemblerOptions::default())
+}
+
+/// Assemble a program from a file, in the same way as `assemble_with_options`.
+///
+/// Included files are found relative to the file that includes them. The path is recorded in the assembled
+/// file as the source it was compiled from.
+pub fn assemble_file(path: &Path, target: &mut dyn Write, options: AssemblerOptions) -> AssemblerResult<()>
+{
+    let input = fs::read_to_string(path).map_err(AssemblerError::IncludeError)?;
+    let canonical = path.canonicalize().map_err(AssemblerError::IncludeError)?;
+    let dir = path.parent().unwrap_or(Path::new("."));
+
+    let input = expand_includes(&input, dir, &mut vec![canonical])?;
+    assemble_source(&input, path.to_str(), target, options)
+}
+
+/// Assemble a program, with any files it includes being found relative to the working directory
+pub fn assemble_with_options(input: &str, target: &mut dyn Write, options: AssemblerOptions) -> AssemblerResult<()>
+{
+    let input = expand_includes(input, Path::new("."), &mut vec![])?;
+    assemble_source(&input, None, target, options)
+}
+
+/// Replace every `.include "path"` line with the contents of the file it names, relative to `dir`.
+///
+/// `including` holds every file that is part way through being included, so that a file which ends up
+/// including itself is caught rather than being expanded forever.
+fn expand_includes(input: &str, dir: &Path, including: &mut Vec<PathBuf>) -> AssemblerResult<String>
+{
+    let mut output = String::new();
+    for line in input.split('\n')
+    {
+        let Some(operand) = line.trim().strip_prefix(".include ")
+        else
+        {
+            output.push_str(line);
+            output.push('\n');
+            continue;
+        };
+
+        let name = operand
+            .trim()
+            .strip_prefix('"')
+            .and_then(|x| x.strip_suffix('"'))
+            .ok_or(AssemblerError::BadFormat)?;
+        let path = dir.join(name);
+        let canonical = path.canonicalize().map_err(AssemblerError::IncludeError)?;
+        if including.contains(&canonical)
+        {
+            return Err(AssemblerError::CircularInclude(name.to_owned()));
+        }
+
+        let contents = fs::read_to_string(&path).map_err(AssemblerError::IncludeError)?;
+        including.push(canonical);
+        output.push_str(&expand_includes(&contents, path.parent().unwrap_or(dir), including)?);
+        including.pop();
+    }
+
+    Ok(output)
+}
+
+fn assemble_source(
+    input: &str,
+    source_path: Option<&str>,
+    target: &mut dyn Write,
+    options: AssemblerOptions,
+) -> AssemblerResult<()>
+{
+    // The body has to be assembled up front so that its checksum can be written
+    // into the header before it
+    let mut body: Vec<u8> = vec![];
+
+    // Line numbers are kept so that instructions can be mapped back to them
+    let mut lines = input.split('\n').zip(1..).filter(|(x, _)| !x.is_empty()).peekable();
+    assemble_constant_table(&mut lines, &mut body)?;
+
+    // Debug info offsets are relative to the start of the first function
+    let functions_start = body.len();
+    let mut debug_info: Vec<u8> = vec![];
+    let mut imports: Vec<u8> = vec![];
+    let mut labels = Labels::default();
+    let mut code_start = body.len(); // Where the code of the current function starts, after its directives
+
+    for (line, line_number) in lines
+    {
+        // Labels only exist within a single function
+        if line.starts_with(".symbol")
+        {
+            mem::take(&mut labels).resolve(&mut body)?;
+        }
+
+        // A label marks the position of whatever follows it, which may be on the same line
+        let line = match line.strip_prefix('@').and_then(|x| x.split_once(':'))
+        {
+            Some((label, rest)) =>
+            {
+                labels.define(label, body.len())?;
+                rest.trim()
+            }
+            None => line,
+        };
+
+        if line.is_empty()
+        {
+            continue;
+        }
+
+        // Imports aren't part of any function, so get their own section
+        if let Some(operands) = line.strip_prefix(".import ")
+        {
+            assemble_import(&mut operands.split_whitespace(), &mut imports)?;
+            continue;
+        }
+
+        if assemble_list_directive(line, &mut body)?
+        {
+            code_start = body.len();
+            continue;
+        }
+
+        if let Some(operand) = line.strip_prefix(".align ")
+        {
+            assemble_alignment(operand.trim(), body.len() - code_start, &mut body)?;
+            continue;
+        }
+
+        // Directives are not executed, so don't need mapping
+        if !line.starts_with('.')
+        {
+            let offset = u32::try_from(body.len() - functions_start).map_err(|_| AssemblerError::WriteError)?;
+            debug_info.extend_from_slice(&offset.to_le_bytes());
+            debug_info.extend_from_slice(&<u32>::to_le_bytes(line_number));
+            debug_info.extend_from_slice(&0_u16.to_le_bytes()); // There is only ever a single source file
+        }
+
+        assemble_instruction(&mut line.split_whitespace(), &mut body, &mut labels)?;
+        if line.starts_with('.')
+        {
+            code_start = body.len();
+        }
+    }
+    labels.resolve(&mut body)?;
+
+    if let Some(path) = source_path
+    {
+        let length = u32::try_from(path.len() + 1).map_err(|_| AssemblerError::WriteError)?;
+        body.push(SECTION_SOURCE_PATH);
+        body.extend_from_slice(&length.to_le_bytes());
+        body.extend_from_slice(path.as_bytes());
+        body.push(0);
+    }
+
+    if !imports.is_empty()
+    {
+        let length = u32::try_from(imports.len()).map_err(|_| AssemblerError::WriteError)?;
+        body.push(SECTION_IMPORTS);
+        body.extend_from_slice(&length.to_le_bytes());
+        body.append(&mut imports);
+    }
 
-    let mut lines = input.split('\n').filter(|x| !x.is_empty());
-    assemble_constant_table(&mut lines, target)?;
+    if options.debug_info
+    {
+        let length = u32::try_from(debug_info.len()).map_err(|_| AssemblerError::WriteError)?;
+        body.push(SECTION_DEBUG_INFO);
+        body.extend_from_slice(&length.to_le_bytes());
+        body.append(&mut debug_info);
+    }
 
-    for line in lines
+    // Everything after the checksum is covered by it, including the flags
+    let mut checked: Vec<u8> = vec![];
+    if options.compress
+    {
+        let size = u32::try_from(body.len()).map_err(|_| AssemblerError::WriteError)?;
+        checked.push(FLAG_COMPRESSED);
+        checked.extend_from_slice(&size.to_le_bytes());
+        checked.extend_from_slice(&lz4_flex::block::compress(&body));
+    }
+    else
     {
-        assemble_instruction(&mut line.split_whitespace(), target)?;
+        checked.push(0);
+        checked.extend_from_slice(&body);
     }
+
+    target
+        .write(&MAGIC_NUMBER.to_le_bytes())
+        .map_err(|_| AssemblerError::WriteError)?;
+    target.write(&[VERSION]).map_err(|_| AssemblerError::WriteError)?;
+    target
+        .write(&crc32fast::hash(&checked).to_le_bytes())
+        .map_err(|_| AssemblerError::WriteError)?;
+    target.write_all(&checked).map_err(|_| AssemblerError::WriteError)?;
+
     Ok(())
 }
 
 fn assemble_constant_table<'a>(
-    entries: &mut impl Iterator<Item = &'a str>,
+    entries: &mut Peekable<impl Iterator<Item = (&'a str, u32)>>,
     target: &mut dyn Write,
 ) -> AssemblerResult<()>
 {
     let mut bytes: Vec<u8> = vec![];
     let mut counter: u32 = 0;
 
-    for (i, entry) in entries.take_while(|x| x.starts_with('#')).enumerate()
+    // Only consume the lines that are part of the table, leaving the first
+    // line after it for the instructions
+    for (i, (entry, _)) in iter::from_fn(|| entries.next_if(|(x, _)| x.starts_with('#'))).enumerate()
     {
-        let &[raw_number, raw_ty, raw_data] = entry
-            .split_whitespace()
-            .collect::<Vec<&str>>()
-            .first_chunk()
+        let parts = entry.split_whitespace().collect::<Vec<&str>>();
+        let (&[raw_number, raw_ty], rest) = parts
+            .split_first_chunk()
             .ok_or(AssemblerError::MalformedConstantTable)?;
 
+        // Only null constants have no data
+        let raw_data = match (raw_ty, rest.first())
+        {
+            ("null", None) => "",
+            (_, Some(&x)) => x,
+            (_, None) => return Err(AssemblerError::MalformedConstantTable),
+        };
+
         // Get the index
         let number: u16 = match raw_number
             .split_at_checked(1)
@@ -219,6 +565,13 @@ fn assemble_constant_table<'a>(
                 length_bytes.extend_from_slice(string_bytes);
                 (4, length_bytes)
             }
+            "bool" => match raw_data
+            {
+                "true" => (5, vec![1]),
+                "false" => (5, vec![0]),
+                _ => return Err(AssemblerError::MalformedConstantTable),
+            },
+            "null" if raw_data.is_empty() => (6, vec![]),
             _ => return Err(AssemblerError::MalformedConstantTable),
         };
 
@@ -238,7 +591,8 @@ fn assemble_constant_table<'a>(
 
 fn assemble_instruction<'a>(
     operation: &mut impl Iterator<Item = &'a str>,
-    target: &mut dyn Write,
+    target: &mut Vec<u8>,
+    labels: &mut Labels<'a>,
 ) -> AssemblerResult<()>
 {
     const MAX_BYTES: usize = 10;
@@ -246,10 +600,15 @@ fn assemble_instruction<'a>(
     let mut bytes: [u8; MAX_BYTES] = [0; MAX_BYTES];
     let (operand_types, written) = get_opcode_data(operation, &mut bytes)?;
 
+    let start = target.len();
     let mut byte_pointer: usize = written;
     for (operand, operand_type) in operation.zip(operand_types)
     {
         assert!(byte_pointer < MAX_BYTES);
+        if matches!(operand_type, OperandType::Label)
+        {
+            labels.refer(operand, start, start + byte_pointer)?;
+        }
         byte_pointer += parse_operand(operand, *operand_type, &mut bytes[byte_pointer..])?;
     }
 
@@ -259,6 +618,122 @@ fn assemble_instruction<'a>(
     Ok(())
 }
 
+fn assemble_import<'a>(operands: &mut impl Iterator<Item = &'a str>, target: &mut Vec<u8>) -> AssemblerResult<()>
+{
+    // module_name index, function_name index
+    for _ in 0..2
+    {
+        let operand = operands.next().ok_or(AssemblerError::IncorrectOperandCount)?;
+        target.extend_from_slice(&numeric_from_str::<u32>(OperandType::Unsigned32, operand)?.to_le_bytes());
+    }
+
+    Ok(())
+}
+
+/// Exception tables can have any number of entries, each of which is a try start, try end, handler and
+/// catch type
+fn assemble_exception_table<'a>(
+    operands: &mut impl Iterator<Item = &'a str>,
+    target: &mut Vec<u8>,
+) -> AssemblerResult<()>
+{
+    const EXCEPTION_TABLE: [u8; 2] = [254, 6];
+
+    let values = operands
+        .map(|x| numeric_from_str::<u16>(OperandType::Unsigned16, x))
+        .collect::<AssemblerResult<Vec<_>>>()?;
+    // Each entry is made up of four values
+    let entries = values.chunks_exact(4);
+    if !entries.remainder().is_empty()
+    {
+        return Err(AssemblerError::IncorrectOperandCount);
+    }
+    let count = u16::try_from(entries.len()).map_err(|_| AssemblerError::IncorrectOperandCount)?;
+
+    target.extend_from_slice(&EXCEPTION_TABLE);
+    target.extend_from_slice(&count.to_le_bytes());
+    for value in entries.flatten()
+    {
+        target.extend_from_slice(&value.to_le_bytes());
+    }
+
+    Ok(())
+}
+
+/// Assemble a directive that takes a list of operands of any length, returning whether the line was one
+fn assemble_list_directive(line: &str, target: &mut Vec<u8>) -> AssemblerResult<bool>
+{
+    if let Some(operands) = line.strip_prefix(".locals ")
+    {
+        assemble_local_types(&mut operands.split_whitespace(), target)?;
+        return Ok(true);
+    }
+
+    if let Some(operands) = line.strip_prefix(".exception_table ")
+    {
+        assemble_exception_table(&mut operands.split_whitespace(), target)?;
+        return Ok(true);
+    }
+
+    Ok(false)
+}
+
+/// Declare the type of each local, in order. Each one is given as `i`, `f4`, `f8` or `ptr`.
+fn assemble_local_types<'a>(operands: &mut impl Iterator<Item = &'a str>, target: &mut Vec<u8>) -> AssemblerResult<()>
+{
+    const LOCAL_TYPES: [u8; 2] = [254, 5];
+
+    let tags = operands
+        .map(|x| match x
+        {
+            "i" => Ok(0),
+            "f4" => Ok(1),
+            "f8" => Ok(2),
+            "ptr" => Ok(3),
+            _ => Err(AssemblerError::UnknownLocalType),
+        })
+        .collect::<AssemblerResult<Vec<u8>>>()?;
+    let count = u16::try_from(tags.len()).map_err(|_| AssemblerError::IncorrectOperandCount)?;
+
+    target.extend_from_slice(&LOCAL_TYPES);
+    target.extend_from_slice(&count.to_le_bytes());
+    target.extend_from_slice(&tags);
+
+    Ok(())
+}
+
+/// Pad the code out with no-ops until `offset` is a multiple of the given alignment, using as few of them
+/// as possible.
+///
+/// The code isn't kept at any particular alignment once loaded, so the offset is relative to the start of
+/// the function's code.
+fn assemble_alignment(operand: &str, offset: usize, target: &mut Vec<u8>) -> AssemblerResult<()>
+{
+    // Each no-op along with its length, from longest to shortest
+    const PADDING: [(&str, usize); 4] = [("nop.d", 4), ("nop.t", 3), ("nop.w", 2), ("nop", 1)];
+
+    let alignment = numeric_from_str::<usize>(OperandType::Unsigned8, operand)?;
+    if !alignment.is_power_of_two()
+    {
+        return Err(AssemblerError::BadFormat);
+    }
+
+    let mut padding = offset.next_multiple_of(alignment) - offset;
+    while padding > 0
+    {
+        let &(mnemonic, length) = PADDING
+            .iter()
+            .find(|&&(_, length)| length <= padding)
+            .ok_or(AssemblerError::UnknownOpcode)?;
+
+        target.push(OPCODES[mnemonic].0);
+        target.resize(target.len() + length - 1, 0); // The operands are never read
+        padding -= length;
+    }
+
+    Ok(())
+}
+
 fn get_opcode_data<'a>(
     operation: &mut impl Iterator<Item = &'a str>,
     bytes: &mut [u8],
@@ -322,6 +797,8 @@ fn parse_operand(operand: &str, operand_type: OperandType, bytes: &mut [u8]) ->
             let number: u64 = numeric_from_str(operand_type, operand)?;
             bytes[0..size].copy_from_slice(&number.to_le_bytes());
         }
+        OperandType::Label =>
+        { /* Left as a placeholder until the label is resolved */ }
     }
 
     Ok(size)
@@ -0,0 +1,178 @@
+use std::{fs, path::Path, process::ExitCode, thread};
+
+use assert_cmd::cargo::cargo_bin_cmd;
+use azimuth_runtime::{
+    config::{Config, ConfigError},
+    engine::validator::ValidationError,
+};
+
+mod assembler;
+
+use assembler::AssemblerOptions;
+
+const PROGRAM_PATH: &str = "./tests/programs";
+const COMPILED_PATH: &str = concat!(env!("CARGO_TARGET_TMPDIR"), "/config");
+
+/// Assemble one of the test programs into a file for the binary to run, giving back its path
+fn compiled(name: &str) -> String
+{
+    let mut bytes: Vec<u8> = vec![];
+    assembler::assemble_file(
+        Path::new(&format!("{PROGRAM_PATH}/{name}.test")),
+        &mut bytes,
+        AssemblerOptions::default(),
+    )
+    .expect("Failed to assemble program");
+
+    // Tests run in parallel, so each one gets its own copy rather than writing over a file another is reading
+    let test = thread::current().name().unwrap_or("main").replace("::", "/");
+    let path = format!("{COMPILED_PATH}/{test}/{name}.azc");
+    fs::create_dir_all(Path::new(&path).parent().unwrap()).unwrap();
+    fs::write(&path, bytes).unwrap();
+    path
+}
+
+#[test]
+fn dry_run_valid()
+{
+    cargo_bin_cmd!()
+        .args(["--dry-run", &compiled("control_flow/fibonacci")])
+        .assert()
+        .success()
+        .stdout("functions:  1\nconstants:  1\nvalidation: OK\n");
+}
+
+#[test]
+fn dry_run_invalid()
+{
+    // Never returns, so is rejected by the validator
+    let assert = cargo_bin_cmd!()
+        .args(["--dry-run", &compiled("should_fail/missing_return")])
+        .assert()
+        .failure();
+
+    assert!(assert.get_output().stdout.ends_with(b"validation: FAIL\n"));
+}
+
+#[test]
+fn dry_run_does_not_execute()
+{
+    // Would divide by zero if it were actually run
+    cargo_bin_cmd!()
+        .args(["--dry-run", &compiled("should_fail/divide_by_zero")])
+        .assert()
+        .success();
+}
+
+#[test]
+fn verbose_heap_stats()
+{
+    let assert = cargo_bin_cmd!()
+        .args(["--verbose", &compiled("control_flow/fibonacci")])
+        .assert()
+        .success();
+
+    let stdout = String::from_utf8_lossy(&assert.get_output().stdout).into_owned();
+    assert!(stdout.starts_with("startup:"));
+    assert!(stdout.contains("functions:  1\nconstants:  1\nHeapStats {"));
+}
+
+#[test]
+fn halt_exit_code()
+{
+    cargo_bin_cmd!().arg(compiled("should_fail/halt")).assert().code(3);
+}
+
+#[test]
+fn verbose_return_value()
+{
+    let assert = cargo_bin_cmd!()
+        .args(["--verbose", &compiled("control_flow/count_loop")])
+        .assert()
+        .success();
+
+    assert!(assert.get_output().stdout.ends_with(b"Returned 100\n"));
+}
+
+#[test]
+fn quiet_by_default()
+{
+    cargo_bin_cmd!()
+        .arg(compiled("control_flow/count_loop"))
+        .assert()
+        .success()
+        .stdout("");
+}
+
+#[test]
+fn print_output()
+{
+    cargo_bin_cmd!()
+        .arg(compiled("basics/print"))
+        .assert()
+        .success()
+        .stdout("42\n2\n1.5\n0xffff\n");
+}
+
+#[test]
+fn quiet_print()
+{
+    cargo_bin_cmd!()
+        .args(["--quiet", &compiled("basics/print")])
+        .assert()
+        .success()
+        .stdout("");
+}
+
+#[test]
+fn max_constants()
+{
+    // Fibonacci has a single constant
+    for (limit, succeeds) in [("1", true), ("0", false)]
+    {
+        let assert = cargo_bin_cmd!()
+            .args(["--max-constants", limit, &compiled("control_flow/fibonacci")])
+            .assert();
+        assert_eq!(assert.get_output().status.success(), succeeds);
+    }
+}
+
+fn config(args: &[&str]) -> Result<Config, ConfigError>
+{
+    Config::from_args(&args.iter().map(|&x| x.to_owned()).collect::<Vec<_>>())
+}
+
+#[test]
+fn from_args()
+{
+    let halt = compiled("should_fail/halt");
+    assert_eq!(
+        config(&["--quiet", &halt]).unwrap().execute().unwrap(),
+        ExitCode::from(3)
+    );
+
+    assert!(matches!(config(&[]), Err(ConfigError::NoFileProvided)));
+    assert!(matches!(config(&["--maxstack"]), Err(ConfigError::MissingOperand(flag)) if flag == "--maxstack"));
+    assert!(matches!(
+        config(&["--maxstack", "lots", &halt]),
+        Err(ConfigError::InvalidOperand(operand)) if operand == "lots"
+    ));
+    assert!(matches!(
+        config(&["first.azc", "second.azc"]),
+        Err(ConfigError::UnknownFlag(arg)) if arg == "first.azc"
+    ));
+
+    let missing = config(&["does/not/exist.azc"]).unwrap();
+    assert!(matches!(missing.execute(), Err(ConfigError::LoaderInitError)));
+}
+
+#[test]
+fn invalid_jump()
+{
+    // Jumps past the end of the function, which the validator catches before anything is run
+    let bad_jump = compiled("should_fail/bad_jump");
+    assert!(matches!(
+        config(&[&bad_jump]).unwrap().execute(),
+        Err(ConfigError::ValidationFailed(errors)) if errors == [ValidationError::InvalidJumpTarget { at: 0, offset: 4 }]
+    ));
+}
@@ -0,0 +1,1391 @@
+use std::{cell::RefCell, fs, path::Path, rc::Rc};
+
+use azimuth_runtime::{
+    engine::{
+        ExecutionResult, Runner, RunnerBuilder, RunnerError, opcode_handler::ExecutionError, opcodes::Opcode,
+        optimizer::remove_nops, snapshot::RunnerSnapshot, stack::Stack, validator::ValidationError,
+    },
+    loader::{
+        LoadOptions, Loader,
+        linker::{Linker, LinkerError},
+        parser::{Directive, FileLayout, LocalType},
+        runnable::RunnableError,
+    },
+    memory::{heap::Heap, metaspace::Metaspace},
+};
+
+mod assembler;
+
+use assembler::{AssemblerError, AssemblerOptions};
+
+const PROGRAM_PATH: &str = "./tests/programs";
+
+/// Assemble one of the test programs
+fn program(name: &str) -> Vec<u8>
+{
+    let path = format!("{PROGRAM_PATH}/{name}.test");
+    let mut bytes: Vec<u8> = vec![];
+    assembler::assemble_file(Path::new(&path), &mut bytes, AssemblerOptions::default())
+        .expect("Failed to assemble program");
+    bytes
+}
+
+fn assemble(source: &str) -> Vec<u8>
+{
+    let mut bytes: Vec<u8> = vec![];
+    assembler::assemble(source, &mut bytes).expect("Failed to assemble program");
+    bytes
+}
+
+/// Assemble and load one of the test programs
+fn load(name: &str) -> Loader
+{
+    Loader::from_bytes(&program(name)).expect("Failed to load program")
+}
+
+fn load_source(source: &str) -> Loader
+{
+    Loader::from_bytes(&assemble(source)).expect("Failed to load program")
+}
+
+/// Assemble one of the test programs into a file, giving back its path
+fn program_file(name: &str, options: AssemblerOptions) -> String
+{
+    let suffix = match (options.compress, options.debug_info)
+    {
+        (true, _) => "lz4.azc",
+        (false, true) => "debug.azc",
+        (false, false) => "azc",
+    };
+    let filename = format!("{}/programs/{name}.{suffix}", env!("CARGO_TARGET_TMPDIR"));
+
+    let mut bytes: Vec<u8> = vec![];
+    assembler::assemble_file(Path::new(&format!("{PROGRAM_PATH}/{name}.test")), &mut bytes, options)
+        .expect("Failed to assemble program");
+    fs::create_dir_all(Path::new(&filename).parent().unwrap()).unwrap();
+    fs::write(&filename, bytes).unwrap();
+
+    filename
+}
+
+#[test]
+fn fibonacci()
+{
+    let loader = load("control_flow/fibonacci");
+    assert!(loader.validate_bytecode().is_ok());
+
+    let mut stack = Stack::new(1024);
+    let mut runner = Runner::new(&mut stack, &loader);
+
+    assert_eq!(runner.run_function("fib", &[10]).unwrap(), Some(55));
+    assert_eq!(runner.run_function("fib", &[0]).unwrap(), Some(0));
+    assert_eq!(runner.run_function("fib", &[1]).unwrap(), Some(1));
+    assert_eq!(runner.run_function("fib", &[20]).unwrap(), Some(6765));
+}
+
+#[test]
+fn function_names()
+{
+    let loader = load("modules/exports");
+
+    assert_eq!(loader.get_entry_point().unwrap().name(), "main");
+    assert_eq!(loader.get_function_by_name("helper").unwrap().name(), "helper");
+}
+
+#[test]
+fn count_loop()
+{
+    let loader = load("control_flow/count_loop");
+    assert!(loader.validate_bytecode().is_ok());
+
+    let mut stack = Stack::new(1024);
+    let mut runner = Runner::new(&mut stack, &loader);
+
+    assert_eq!(runner.run_function("main", &[]).unwrap(), Some(100));
+}
+
+#[test]
+fn count_loop_gas()
+{
+    // 7 instructions per iteration once the increment has been fused, and then 2 more to return
+    const STEPS: u64 = 7 * 100 + 2;
+
+    let loader = load("control_flow/count_loop");
+    let mut stack = Stack::new(1024);
+
+    let mut runner = Runner::new(&mut stack, &loader).with_gas_limit(STEPS);
+    assert_eq!(runner.run_function("main", &[]).unwrap(), Some(100));
+    assert_eq!(runner.remaining_gas(), Some(0));
+
+    let mut runner = Runner::new(&mut stack, &loader).with_gas_limit(STEPS - 1);
+    assert!(matches!(runner.run_function("main", &[]), Err(RunnerError::OutOfGas)));
+}
+
+#[test]
+fn count_loop_gas_unoptimized()
+{
+    // Without the optimiser every instruction is run as written, so there are 8 per iteration
+    const STEPS: u64 = 8 * 100 + 2;
+
+    let loader = Loader::from_bytes_unoptimized(&program("control_flow/count_loop")).unwrap();
+    let mut stack = Stack::new(1024);
+
+    let mut runner = Runner::new(&mut stack, &loader).with_gas_limit(STEPS);
+    assert_eq!(runner.run_function("main", &[]).unwrap(), Some(100));
+    assert_eq!(runner.remaining_gas(), Some(0));
+
+    let mut runner = Runner::new(&mut stack, &loader).with_gas_limit(STEPS - 1);
+    assert!(matches!(runner.run_function("main", &[]), Err(RunnerError::OutOfGas)));
+}
+
+#[test]
+fn execution_results()
+{
+    let run = |body: &str, length: u16| {
+        let loader = load_source(&format!(
+            "#0 string main
+
+.symbol 0 {length}
+.start
+.maxstack 1
+.maxlocal 0
+{body}"
+        ));
+        let mut stack = Stack::new(1024);
+        Runner::new(&mut stack, &loader).run()
+    };
+
+    assert_eq!(run("ret\n", 1), Ok(ExecutionResult::ReturnVoid));
+    assert_eq!(run("i.const 5\nret.val\n", 3), Ok(ExecutionResult::ReturnValue(5)));
+
+    // Halting stops the program straight away, whatever is left on the stack
+    assert_eq!(run("i.const 5\nhalt 3\n", 4), Ok(ExecutionResult::Halted(3)));
+}
+
+#[test]
+fn lazy_loading()
+{
+    let options = LoadOptions {
+        lazy: true,
+        ..LoadOptions::default()
+    };
+    let loader = Loader::from_bytes_with_options(&program("modules/lazy"), options).unwrap();
+
+    let mut stack = Stack::new(1024);
+    let mut runner = Runner::new(&mut stack, &loader);
+    assert_eq!(runner.run(), Ok(ExecutionResult::ReturnVoid));
+    assert_eq!(runner.run_function("second", &[]), Ok(Some(2)));
+    assert_eq!(runner.run_function("missing", &[]), Err(RunnerError::FunctionNotFound));
+}
+
+#[test]
+fn inline_hints()
+{
+    let loader = load_source(
+        "#0 string main
+#1 string square
+
+.symbol 1 4
+.inline
+.maxstack 2
+.maxlocal 1
+ld.arg.0
+dup
+i.mul
+ret.val
+.symbol 0 1
+.start
+.noinline
+.maxstack 0
+.maxlocal 0
+ret
+",
+    );
+
+    assert!(loader.get_function_by_name("square").unwrap().is_inline_hint());
+    assert!(!loader.get_entry_point().unwrap().is_inline_hint());
+}
+
+#[test]
+fn local_aliases()
+{
+    // Copies its argument into its second local, and returns it from there
+    let loader = load_source(
+        "#0 string main
+
+.symbol 0 7
+.start
+.maxstack 1
+.maxlocal 2
+ld.local 0
+st.local 1
+ld.local 1
+ret.val
+",
+    );
+    let main = loader.get_entry_point().unwrap();
+    assert_eq!(
+        main.code()[..6],
+        [Opcode::LdArg as u8, 0, Opcode::StArg as u8, 1, Opcode::LdArg as u8, 1]
+    );
+
+    let mut stack = Stack::new(1024);
+    assert_eq!(Runner::new(&mut stack, &loader).run_function("main", &[9]), Ok(Some(9)));
+}
+
+#[test]
+fn aligned_code()
+{
+    // The shortest padding is used to get from each offset up to the alignment
+    for (offset, padding) in [
+        (1, vec![Opcode::NopT as u8, 0, 0]),
+        (2, vec![Opcode::NopW as u8, 0]),
+        (3, vec![Opcode::Nop as u8]),
+        (4, vec![]),
+    ]
+    {
+        let loader = load_source(&format!(
+            "#0 string main
+
+.symbol 0 7
+.start
+.maxstack 1
+.maxlocal 0
+{}
+.align 4
+i.const 7
+ret.val
+",
+            "nop\n".repeat(offset)
+        ));
+        let main = loader.get_entry_point().unwrap();
+        assert_eq!(main.code()[offset..4], padding);
+
+        let mut stack = Stack::new(1024);
+        assert_eq!(
+            Runner::new(&mut stack, &loader).run(),
+            Ok(ExecutionResult::ReturnValue(7))
+        );
+    }
+
+    let loader = load_source(
+        "#0 string main
+
+.symbol 0 11
+.start
+.maxstack 1
+.maxlocal 0
+nop
+.align 8
+i.const 7
+ret.val
+",
+    );
+    let main = loader.get_entry_point().unwrap();
+    assert_eq!(
+        main.code()[1..8],
+        [Opcode::NopD as u8, 0, 0, 0, Opcode::NopT as u8, 0, 0]
+    );
+}
+
+#[test]
+fn nops_removed()
+{
+    // Sums the numbers from its argument down to 1, with no-ops scattered about, including where jumps land
+    let bytes = assemble(
+        "#0 string main
+
+.symbol 0 22
+.start
+.maxstack 2
+.maxlocal 2
+jmp @loop
+halt 1
+@loop:
+nop
+ld.arg.1
+ld.arg.0
+i.add
+st.arg.1
+nop
+ld.arg.0
+i.const.1
+i.sub
+dup
+st.arg.0
+jmp.if.true @loop
+nop
+ld.arg.1
+ret.val
+",
+    );
+
+    let metaspace = Metaspace::new();
+    let mut layout = FileLayout::from_bytes(&bytes, &metaspace).unwrap();
+    for function in layout.functions_mut()
+    {
+        function.replace_code(metaspace.intern_bytes(&remove_nops(function.code())).unwrap());
+    }
+    assert_eq!(layout.functions()[0].code().len(), 19);
+
+    let (original, stripped) = (
+        Loader::from_bytes(&bytes).unwrap(),
+        Loader::from_bytes(&layout.to_bytes().unwrap()).unwrap(),
+    );
+    let (mut original_stack, mut stripped_stack) = (Stack::new(1024), Stack::new(1024));
+    let mut original_runner = Runner::new(&mut original_stack, &original);
+    let mut stripped_runner = Runner::new(&mut stripped_stack, &stripped);
+
+    for n in 1..=10
+    {
+        let expected = Ok(Some(n * (n + 1) / 2));
+        assert_eq!(original_runner.run_function("main", &[n]), expected);
+        assert_eq!(stripped_runner.run_function("main", &[n]), expected);
+    }
+}
+
+#[test]
+fn assembler_labels()
+{
+    // Returns 7 if its argument is true, and nothing otherwise
+    let loader = load_source(
+        "#0 string main
+
+.symbol 0 8
+.start
+.maxstack 1
+.maxlocal 1
+ld.arg.0
+jmp.if.false @done
+i.const 7
+ret.val
+@done: ret
+",
+    );
+    let main = loader.get_entry_point().unwrap();
+    let code = main.code();
+
+    // The label is only defined after the jump, 6 bytes on from the start of it
+    assert_eq!(code[1], Opcode::JmpIfFalse as u8);
+    assert_eq!(code[2..4], 6_i16.to_le_bytes());
+
+    let mut stack = Stack::new(1024);
+    let mut runner = Runner::new(&mut stack, &loader);
+    assert_eq!(runner.run_function("main", &[1]), Ok(Some(7)));
+    assert_eq!(runner.run_function("main", &[0]), Ok(None));
+
+    // Counts its argument down to zero
+    let loader = load_source(
+        "#0 string main
+
+.symbol 0 9
+.start
+.maxstack 2
+.maxlocal 1
+@loop:
+ld.arg.0
+i.const.1
+i.sub
+dup
+st.arg.0
+jmp.if.true @loop
+ret
+",
+    );
+    let main = loader.get_entry_point().unwrap();
+    let code = main.code();
+
+    // Jumping backwards gives a negative offset
+    assert_eq!(code[5], Opcode::JmpIfTrue as u8);
+    assert_eq!(code[6..8], (-5_i16).to_le_bytes());
+
+    let mut runner = Runner::new(&mut stack, &loader).with_gas_limit(6 * 10 + 1);
+    assert_eq!(runner.run_function("main", &[10]), Ok(None));
+    assert_eq!(runner.remaining_gas(), Some(0));
+}
+
+#[test]
+fn wide_nops()
+{
+    // The padding is all illegal opcodes, so landing anywhere inside it fails
+    let loader = load_source(
+        "#0 string main
+
+.symbol 0 9
+.start
+.maxstack 1
+.maxlocal 0
+nop.w 255
+nop.d 255 65535
+i.const 7
+ret.val
+",
+    );
+    let mut stack = Stack::new(1024);
+    let mut runner = Runner::new(&mut stack, &loader).with_gas_limit(4);
+
+    // Each no-op is skipped over in a single instruction
+    assert_eq!(runner.run(), Ok(ExecutionResult::ReturnValue(7)));
+    assert_eq!(runner.remaining_gas(), Some(0));
+}
+
+#[test]
+fn yield_and_resume()
+{
+    // Counts up to the limit, yielding every 10 instructions with the comparison's result still on the stack
+    let program = |limit: u8| {
+        load_source(&format!(
+            "#0 string main
+
+.symbol 0 15
+.start
+.maxstack 2
+.maxlocal 1
+@loop:
+ld.arg.0
+i.const.1
+i.add
+dup
+st.arg.0
+i.const {limit}
+i.cmp.lt
+nop
+yield
+jmp.if.true @loop
+ld.arg.0
+ret.val
+"
+        ))
+    };
+    let (first, second) = (program(5), program(8));
+    let (mut first_stack, mut second_stack) = (Stack::new(1024), Stack::new(1024));
+
+    let mut runners = [
+        Runner::new(&mut first_stack, &first),
+        Runner::new(&mut second_stack, &second),
+    ];
+    let mut results = [None, None];
+    let mut yields = [0, 0];
+
+    // Take turns running each program until it yields, until both have finished
+    while results.iter().any(Option::is_none)
+    {
+        for ((runner, result), count) in runners.iter_mut().zip(&mut results).zip(&mut yields)
+        {
+            if result.is_some()
+            {
+                continue;
+            }
+
+            match runner.run()
+            {
+                Ok(ExecutionResult::Yielded) =>
+                {
+                    assert!(runner.is_suspended());
+                    *count += 1;
+                }
+                other => *result = Some(other),
+            }
+        }
+    }
+
+    assert_eq!(
+        results,
+        [
+            Some(Ok(ExecutionResult::ReturnValue(5))),
+            Some(Ok(ExecutionResult::ReturnValue(8)))
+        ]
+    );
+    assert_eq!(yields, [5, 8]);
+    assert!(runners.iter().all(|x| !x.is_suspended()));
+}
+
+#[test]
+fn snapshot_and_restore()
+{
+    // Counts up to 15, yielding once each time around with the comparison's result still on the stack
+    let loader = load_source(
+        "#0 string main
+
+.symbol 0 15
+.start
+.maxstack 2
+.maxlocal 1
+@loop:
+ld.arg.0
+i.const.1
+i.add
+dup
+st.arg.0
+i.const 15
+i.cmp.lt
+nop
+yield
+jmp.if.true @loop
+ld.arg.0
+ret.val
+",
+    );
+    let mut stack = Stack::new(1024);
+    let mut runner = Runner::new(&mut stack, &loader);
+    let run = |runner: &mut Runner, times: usize| {
+        for _ in 0..times
+        {
+            assert_eq!(runner.run(), Ok(ExecutionResult::Yielded));
+        }
+    };
+
+    assert_eq!(runner.snapshot(), None);
+    run(&mut runner, 5);
+    let snapshot = runner.snapshot().unwrap();
+    assert_eq!(
+        (snapshot.function(), snapshot.stack(), snapshot.locals()),
+        ("main", &[1][..], &[5][..])
+    );
+
+    // Going back to the snapshot undoes the runs since, so it takes another 10 to get to 15
+    run(&mut runner, 5);
+    let restored = RunnerSnapshot::from_bytes(&snapshot.to_bytes()).unwrap();
+    runner.restore(&restored).unwrap();
+    run(&mut runner, 9);
+    assert_eq!(runner.snapshot().unwrap().locals(), [14]);
+    run(&mut runner, 1);
+    assert_eq!(runner.run(), Ok(ExecutionResult::ReturnValue(15)));
+
+    // A snapshot can only be restored into a function with the same number of locals
+    let mut bytes = snapshot.to_bytes();
+    bytes.truncate(bytes.len() - 12);
+    bytes.extend_from_slice(&2_u32.to_le_bytes());
+    bytes.extend_from_slice(&[0; 16]);
+    let mismatched = RunnerSnapshot::from_bytes(&bytes).unwrap();
+    assert_eq!(mismatched.locals(), [0, 0]);
+    assert_eq!(runner.restore(&mismatched), Err(RunnerError::IncompatibleSnapshot));
+
+    // Nor can it carry on from anywhere other than just after a yield, including part way through an instruction
+    for pc in [0_u64, 7, snapshot.pc() as u64 + 1]
+    {
+        let mut bytes = snapshot.to_bytes();
+        bytes[8..16].copy_from_slice(&pc.to_le_bytes());
+        let moved = RunnerSnapshot::from_bytes(&bytes).unwrap();
+        assert_eq!(runner.restore(&moved), Err(RunnerError::IncompatibleSnapshot));
+    }
+}
+
+#[test]
+fn builder_gas()
+{
+    // 999 instructions that do nothing, and then a return
+    let source = format!(
+        "#0 string main
+
+.symbol 0 1000
+.start
+.maxstack 0
+.maxlocal 0
+{}ret
+",
+        "nop\n".repeat(999)
+    );
+    let loader = load_source(&source);
+    let mut stack = Stack::new(1024);
+
+    let mut runner = RunnerBuilder::new()
+        .stack(&mut stack)
+        .loader(&loader)
+        .gas(100)
+        .build()
+        .unwrap();
+    assert_eq!(runner.run(), Err(RunnerError::OutOfGas));
+    assert_eq!(runner.remaining_gas(), Some(0));
+
+    let mut runner = RunnerBuilder::new()
+        .stack(&mut stack)
+        .loader(&loader)
+        .gas(1000)
+        .build()
+        .unwrap();
+    assert_eq!(runner.run(), Ok(ExecutionResult::ReturnVoid));
+    assert_eq!(runner.remaining_gas(), Some(0));
+}
+
+#[test]
+fn infinite_loop_runs_out_of_gas()
+{
+    // Can't be one of the regular test programs, as those are run without any gas limit
+    let loader = load_source(
+        "#0 string main
+
+.symbol 0 4
+.start
+.maxstack 0
+.maxlocal 0
+@loop:
+nop
+jmp @loop
+",
+    );
+    assert!(loader.validate_bytecode().is_ok());
+
+    let mut stack = Stack::new(1024);
+    let mut runner = Runner::new(&mut stack, &loader).with_gas_limit(1000);
+
+    assert!(matches!(runner.run(), Err(RunnerError::OutOfGas)));
+    assert_eq!(runner.remaining_gas(), Some(0));
+}
+
+#[cfg(feature = "threaded-dispatch")]
+#[test]
+fn threaded_dispatch_matches()
+{
+    const PROGRAMS: [&str; 15] = [
+        "arithmetic/simple/add",
+        "arithmetic/simple/div",
+        "arithmetic/simple/mul",
+        "arithmetic/simple/sub",
+        "basics/assert",
+        "basics/halt",
+        "basics/i4const0",
+        "basics/nop",
+        "control_flow/count_loop",
+        "control_flow/fibonacci",
+        "modules/exports",
+        "should_fail/debug/assert",
+        "should_fail/divide_by_zero",
+        "should_fail/halt",
+        "should_fail/missing_return",
+    ];
+
+    for name in PROGRAMS
+    {
+        let loader = load(name);
+        let mut stack = Stack::new(1024);
+
+        // Both should also run exactly the same number of instructions
+        let mut runner = Runner::new(&mut stack, &loader).with_gas_limit(10_000);
+        let expected = runner.run();
+        let expected_gas = runner.remaining_gas();
+
+        let mut runner = Runner::new(&mut stack, &loader).with_gas_limit(10_000);
+        assert_eq!(runner.run_threaded(), expected, "Different result for {name}");
+        assert_eq!(runner.remaining_gas(), expected_gas, "Different gas used for {name}");
+    }
+}
+
+#[cfg(feature = "profiling")]
+#[test]
+fn profiler_finds_hot_opcode()
+{
+    use azimuth_runtime::engine::opcodes::Opcode;
+
+    // 900 instructions that do nothing, far outweighing the two needed to return
+    let source = format!(
+        "#0 string main
+
+.symbol 0 902
+.start
+.maxstack 1
+.maxlocal 0
+{}i.const.1
+ret.val
+",
+        "nop\n".repeat(900)
+    );
+    let loader = load_source(&source);
+    let mut stack = Stack::new(1024);
+    let mut runner = Runner::new(&mut stack, &loader);
+
+    assert_eq!(runner.run_function("main", &[]).unwrap(), Some(1));
+
+    let report = runner.profiler().report();
+    let counts: Vec<_> = report.iter().map(|&(opcode, _, count)| (opcode, count)).collect();
+
+    assert_eq!(report.len(), 3);
+    assert_eq!(report[0].0, Opcode::Nop as u8);
+    assert!(counts.contains(&(Opcode::Nop as u8, 900)));
+    assert!(counts.contains(&(Opcode::IConst1 as u8, 1)));
+    assert!(counts.contains(&(Opcode::RetVal as u8, 1)));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn mmap_matches_read()
+{
+    let compressed = AssemblerOptions {
+        compress: true,
+        ..AssemblerOptions::default()
+    };
+    let debug = AssemblerOptions {
+        debug_info: true,
+        ..AssemblerOptions::default()
+    };
+
+    for (name, options) in [
+        ("control_flow/fibonacci", AssemblerOptions::default()),
+        ("control_flow/fibonacci", compressed),
+        ("modules/exports", debug),
+    ]
+    {
+        let filename = program_file(name, options);
+
+        let mapped = Loader::from_file_mmap(&filename).unwrap();
+        let read = Loader::from_file_read(&filename).unwrap();
+
+        assert_eq!(
+            mapped.list_functions(),
+            read.list_functions(),
+            "Functions differ for {filename}"
+        );
+        assert_eq!(mapped.exports(), read.exports(), "Exports differ for {filename}");
+        assert_eq!(
+            mapped.source_path(),
+            read.source_path(),
+            "Source paths differ for {filename}"
+        );
+        assert_eq!(
+            mapped.get_constant_table_ref(),
+            read.get_constant_table_ref(),
+            "Constants differ for {filename}"
+        );
+        for index in 0..mapped.get_function_count()
+        {
+            assert_eq!(
+                mapped.get_function_by_index(index).unwrap().code(),
+                read.get_function_by_index(index).unwrap().code(),
+                "Code differs for {filename}"
+            );
+        }
+    }
+}
+
+#[test]
+fn optimized_without_debug_info()
+{
+    let debug = AssemblerOptions {
+        debug_info: true,
+        ..AssemblerOptions::default()
+    };
+
+    let plain = Loader::from_file(&program_file("control_flow/count_loop", AssemblerOptions::default())).unwrap();
+    let debug = Loader::from_file(&program_file("control_flow/count_loop", debug)).unwrap();
+    let fused = |loader: &Loader| {
+        loader
+            .get_function_by_index(0)
+            .unwrap()
+            .code()
+            .contains(&(Opcode::IConstAdd as u8))
+    };
+
+    assert!(fused(&plain));
+    assert!(!fused(&debug));
+}
+
+#[test]
+fn function_count()
+{
+    let loader = Loader::from_file(&program_file("modules/exports", AssemblerOptions::default())).unwrap();
+    assert_eq!(loader.get_function_count(), 2);
+    assert!(!loader.is_empty());
+    assert!(loader.has_entry_point());
+}
+
+#[test]
+fn hot_reload()
+{
+    use azimuth_runtime::loader::LoaderError;
+
+    // A file with a single function that returns the given value, and optionally a second one
+    let program = |value: u8, helper: bool| {
+        let mut source = format!(
+            "#0 string main
+#1 string helper
+
+.symbol 0 3
+.start
+.maxstack 1
+.maxlocal 0
+i.const {value}
+ret.val
+"
+        );
+        if helper
+        {
+            source.push_str("\n.symbol 1 1\n.maxstack 0\n.maxlocal 0\nret\n");
+        }
+        assemble(&source)
+    };
+
+    let filename = format!("{}/hot_reload.azc", env!("CARGO_TARGET_TMPDIR"));
+    fs::write(&filename, program(1, true)).unwrap();
+    let mut loader = Loader::from_file(&filename).unwrap();
+
+    let mut stack = Stack::new(1024);
+    assert_eq!(Runner::new(&mut stack, &loader).run_function("main", &[]), Ok(Some(1)));
+
+    fs::write(&filename, program(2, true)).unwrap();
+    loader.hot_reload(&filename).unwrap();
+    assert_eq!(Runner::new(&mut stack, &loader).run_function("main", &[]), Ok(Some(2)));
+
+    // Dropping a function isn't allowed, and leaves the previous version loaded
+    fs::write(&filename, program(3, false)).unwrap();
+    assert!(matches!(
+        loader.hot_reload(&filename),
+        Err(LoaderError::FunctionsRemoved)
+    ));
+    assert_eq!(Runner::new(&mut stack, &loader).run_function("main", &[]), Ok(Some(2)));
+}
+
+#[test]
+fn heap_round_trip()
+{
+    // Stores its second argument at the address given by its first, then loads it back
+    let loader = load_source(
+        "#0 string main
+
+.symbol 0 6
+.start
+.maxstack 2
+.maxlocal 2
+ld.arg.0
+ld.arg.1
+st.heap
+ld.arg.0
+ld.heap
+ret.val
+",
+    );
+    let mut stack = Stack::new(1024);
+    let mut heap = Heap::with_capacity(1 << 24).unwrap();
+    let ptr = heap.alloc(0_u64).unwrap().cast::<u8>();
+    let address = ptr.addr().get() as u64;
+
+    let mut runner = Runner::new(&mut stack, &loader).with_heap(&mut heap);
+    assert_eq!(
+        runner.run_function("main", &[address, 0xdead_beef]),
+        Ok(Some(0xdead_beef))
+    );
+    assert!(matches!(
+        runner.run_function("main", &[0x10, 1]),
+        Err(RunnerError::ExecutionError {
+            error: ExecutionError::InvalidAddress,
+            ..
+        })
+    ));
+    drop(runner);
+    assert_eq!(heap.read_u64(ptr), Some(0xdead_beef));
+
+    // Without a heap, there is nowhere to store anything
+    assert!(matches!(
+        Runner::new(&mut stack, &loader).run_function("main", &[address, 1]),
+        Err(RunnerError::ExecutionError {
+            error: ExecutionError::MissingHeap,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn heap_new()
+{
+    // Allocates 16 bytes, stores 5 in the second half, and loads it back
+    let loader = load_source(
+        "#0 string main
+
+.symbol 0 12
+.start
+.maxstack 3
+.maxlocal 0
+new 16
+i.const 8
+i.add
+dup
+i.const 5
+st.heap
+ld.heap
+ret.val
+",
+    );
+    let mut stack = Stack::new(1024);
+    let mut heap = Heap::with_capacity(1 << 24).unwrap();
+
+    assert_eq!(
+        Runner::new(&mut stack, &loader).with_heap(&mut heap).run(),
+        Ok(ExecutionResult::ReturnValue(5))
+    );
+    assert_eq!(heap.dump_stats().infant_used, 16);
+}
+
+#[test]
+fn heap_new_out_of_memory()
+{
+    // Keeps allocating until there is no room left
+    let loader = load_source(
+        "#0 string main
+
+.symbol 0 7
+.start
+.maxstack 1
+.maxlocal 0
+@loop:
+new 65535
+pop
+jmp @loop
+",
+    );
+    let mut stack = Stack::new(1024);
+    let mut heap = Heap::with_capacity(1 << 24).unwrap();
+
+    assert!(matches!(
+        Runner::new(&mut stack, &loader).with_heap(&mut heap).run(),
+        Err(RunnerError::ExecutionError {
+            error: ExecutionError::OutOfMemory,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn array_sum()
+{
+    // Fills an array with 0 to 99, then sums it
+    let loader = load("heap/array_sum");
+    assert!(loader.validate_bytecode().is_ok());
+
+    let mut stack = Stack::new(1024);
+    let mut heap = Heap::with_capacity(1 << 24).unwrap();
+
+    assert_eq!(
+        Runner::new(&mut stack, &loader).with_heap(&mut heap).run(),
+        Ok(ExecutionResult::ReturnValue(4950))
+    );
+}
+
+#[test]
+fn array_bounds()
+{
+    // Loads the element at the index given by its argument, from an array of 4 single bytes
+    let loader = load_source(
+        "#0 string main
+
+.symbol 0 8
+.start
+.maxstack 2
+.maxlocal 1
+i.const 4
+new.array 1
+ld.arg.0
+ld.array 1
+ret.val
+",
+    );
+    let mut stack = Stack::new(1024);
+    let mut heap = Heap::with_capacity(1 << 24).unwrap();
+    let mut runner = Runner::new(&mut stack, &loader).with_heap(&mut heap);
+
+    assert_eq!(runner.run_function("main", &[3]), Ok(Some(0)));
+    assert!(matches!(
+        runner.run_function("main", &[4]),
+        Err(RunnerError::ExecutionError {
+            error: ExecutionError::IndexOutOfBounds,
+            ..
+        })
+    ));
+    assert!(matches!(
+        runner.run_function("main", &[u64::MAX]),
+        Err(RunnerError::ExecutionError {
+            error: ExecutionError::IndexOutOfBounds,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn exception_handler()
+{
+    // Divides by zero, which is handled by returning 99 instead, if the division is covered
+    let program = |try_end: u8| {
+        load_source(&format!(
+            "#0 string main
+
+.symbol 0 7
+.start
+.maxstack 2
+.maxlocal 0
+.exception_table 0 {try_end} 4 0
+i.const.1
+i.const.0
+i.div
+ret.val
+i.const 99
+ret.val
+"
+        ))
+    };
+    let mut stack = Stack::new(1024);
+
+    let loader = program(4);
+    assert!(loader.validate_bytecode().is_ok());
+    assert_eq!(Runner::new(&mut stack, &loader).run_function("main", &[]), Ok(Some(99)));
+
+    // The covered range has to end on an instruction boundary
+    assert_eq!(
+        program(5).validate_bytecode(),
+        Err(vec![ValidationError::InvalidTryRange { start: 0, end: 5 }])
+    );
+
+    let loader = program(2);
+    assert!(matches!(
+        Runner::new(&mut stack, &loader).run_function("main", &[]),
+        Err(RunnerError::ExecutionError {
+            error: ExecutionError::DivideByZero,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn breakpoints()
+{
+    let loader = load_source(
+        "#0 string main
+
+.symbol 0 6
+.start
+.maxstack 2
+.maxlocal 0
+i.const.1
+breakpoint
+i.const.2
+breakpoint
+i.add
+ret.val
+",
+    );
+    let mut stack = Stack::new(1024);
+
+    // Without a handler, breakpoints do nothing
+    assert_eq!(Runner::new(&mut stack, &loader).run_function("main", &[]), Ok(Some(3)));
+
+    let hits = Rc::new(RefCell::new(vec![]));
+    let mut runner = Runner::new(&mut stack, &loader);
+    let recorded = Rc::clone(&hits);
+    runner.set_breakpoint_handler(Box::new(move |pc, frame| {
+        recorded.borrow_mut().push((pc, frame.depth()))
+    }));
+
+    assert_eq!(runner.run_function("main", &[]), Ok(Some(3)));
+    assert_eq!(*hits.borrow(), [(1, 1), (3, 2)]);
+}
+
+#[test]
+fn wide_immediates()
+{
+    let program = |instruction: &str, length: usize| {
+        load_source(&format!(
+            "#0 string main
+
+.symbol 0 {length}
+.start
+.maxstack 1
+.maxlocal 0
+{instruction}
+ret.val
+"
+        ))
+    };
+    let mut stack = Stack::new(1024);
+
+    let loader = program("i.const.l 18446744073709551615", 10);
+    assert_eq!(
+        Runner::new(&mut stack, &loader).run_function("main", &[]),
+        Ok(Some(u64::MAX))
+    );
+
+    let loader = program("i.const.l 81985529216486895", 10);
+    assert_eq!(
+        Runner::new(&mut stack, &loader).run_function("main", &[]),
+        Ok(Some(0x0123_4567_89ab_cdef))
+    );
+
+    // Only the given 4 bytes are pushed, with the rest of the value left as zero
+    let loader = program("i.const.d 4294967295", 6);
+    assert_eq!(
+        Runner::new(&mut stack, &loader).run_function("main", &[]),
+        Ok(Some(0xffff_ffff))
+    );
+}
+
+#[test]
+fn include()
+{
+    let loader = load("modules/include");
+    assert!(loader.validate_bytecode().is_ok());
+    assert_eq!(loader.list_functions(), ["double", "main"]);
+
+    let mut stack = Stack::new(1024);
+    let mut runner = Runner::new(&mut stack, &loader);
+    assert_eq!(runner.run(), Ok(ExecutionResult::ReturnVoid));
+    assert_eq!(runner.run_function("double", &[21]), Ok(Some(42)));
+
+    let mut bytes: Vec<u8> = vec![];
+    let result = assembler::assemble(&format!(".include \"{PROGRAM_PATH}/modules/circular.inc\""), &mut bytes);
+    assert!(matches!(result, Err(AssemblerError::CircularInclude(name)) if name == "circular.inc"));
+
+    let result = assembler::assemble(".include \"missing.inc\"", &mut bytes);
+    assert!(matches!(result, Err(AssemblerError::IncludeError(_))));
+}
+
+#[test]
+fn local_types()
+{
+    let loader = load_source(
+        "#0 string main
+
+.symbol 0 1
+.start
+.maxstack 0
+.maxlocal 4
+.locals i f4 f8 ptr
+ret
+",
+    );
+    let function = loader.get_function_by_name("main").unwrap();
+    assert_eq!(
+        (0..5).map(|x| function.local_type(x)).collect::<Vec<_>>(),
+        [
+            Some(LocalType::Int),
+            Some(LocalType::Float32),
+            Some(LocalType::Float64),
+            Some(LocalType::Ptr),
+            None
+        ]
+    );
+
+    let mut bytes: Vec<u8> = vec![];
+    let result = assembler::assemble(".locals i bool", &mut bytes);
+    assert!(matches!(result, Err(AssemblerError::UnknownLocalType)));
+}
+
+#[test]
+fn error_context()
+{
+    let source = "#0 string main
+#1 string divide
+
+.symbol 1 4
+.maxstack 2
+.maxlocal 2
+ld.arg.0
+ld.arg.1
+i.div
+ret.val
+.symbol 0 1
+.start
+.maxstack 0
+.maxlocal 0
+ret
+";
+    let loader = load_source(source);
+    let mut stack = Stack::new(1024);
+    let error = Runner::new(&mut stack, &loader)
+        .run_function("divide", &[1, 0])
+        .unwrap_err();
+
+    assert_eq!(
+        error,
+        RunnerError::ExecutionError {
+            error: ExecutionError::DivideByZero,
+            pc: Some(2),
+            function: Some("divide".to_owned()),
+            location: None,
+            source_path: None,
+        }
+    );
+    assert_eq!(
+        error.to_string(),
+        "execution error at pc=2 in function 'divide': divide by zero"
+    );
+
+    // With debug info, the line the instruction came from is known too
+    let mut bytes: Vec<u8> = vec![];
+    let options = AssemblerOptions {
+        debug_info: true,
+        ..AssemblerOptions::default()
+    };
+    assembler::assemble_with_options(source, &mut bytes, options).unwrap();
+    let loader = Loader::from_bytes(&bytes).unwrap();
+
+    let error = Runner::new(&mut stack, &loader)
+        .run_function("divide", &[1, 0])
+        .unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "execution error at pc=2 in function 'divide' (line 9): divide by zero"
+    );
+
+    // Files assembled from a file on disk know where they came from
+    let loader = load("should_fail/divide_by_zero");
+    let path = format!("{PROGRAM_PATH}/should_fail/divide_by_zero.test");
+    assert_eq!(loader.source_path(), Some(path.as_str()));
+
+    let error = Runner::new(&mut stack, &loader).run().unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        format!("execution error at pc=2 in function 'main' ({path}): divide by zero")
+    );
+}
+
+const MATHS_MODULE: &str = "#0 string square
+#1 string plus_hundred
+#2 int 100
+
+.symbol 0 4
+.export 0
+.maxstack 2
+.maxlocal 1
+ld.arg.0
+dup
+i.mul
+ret.val
+.symbol 1 8
+.export 1
+.maxstack 2
+.maxlocal 1
+ld.arg.0
+const 2
+i.add
+ret.val
+";
+
+const MAIN_MODULE: &str = "#0 string main
+#1 int 7
+#2 string maths
+#3 string square
+#4 int 100
+
+.import 2 3
+.symbol 0 6
+.start
+.maxstack 1
+.maxlocal 0
+const 4
+ret.val
+";
+
+#[test]
+fn linked_modules()
+{
+    let metaspace = Metaspace::new();
+    let (maths, main) = (assemble(MATHS_MODULE), assemble(MAIN_MODULE));
+
+    let mut linker = Linker::new(&metaspace);
+    linker
+        .add_module("maths", FileLayout::from_bytes(&maths, &metaspace).unwrap())
+        .add_module("main", FileLayout::from_bytes(&main, &metaspace).unwrap());
+    let linked = linker.link().unwrap();
+
+    // The names and the shared constant are only kept once
+    assert_eq!(linked.constants().entries().len(), 6);
+    assert_eq!(linked.function_count(), 3);
+    assert!(linked.imports().is_empty());
+
+    let loader = Loader::from_bytes(&linked.to_bytes().unwrap()).unwrap();
+    assert!(loader.validate_bytecode().is_ok());
+
+    let mut stack = Stack::new(1024);
+    let mut runner = Runner::new(&mut stack, &loader);
+    assert_eq!(runner.run(), Ok(ExecutionResult::ReturnValue(100)));
+    assert_eq!(runner.run_function("square", &[7]), Ok(Some(49)));
+    assert_eq!(runner.run_function("plus_hundred", &[5]), Ok(Some(105)));
+}
+
+#[test]
+fn link_errors()
+{
+    let metaspace = Metaspace::new();
+    let (maths, main) = (assemble(MATHS_MODULE), assemble(MAIN_MODULE));
+    let link = |modules: &[(&str, &[u8])]| {
+        let mut linker = Linker::new(&metaspace);
+        for &(name, module) in modules
+        {
+            linker.add_module(name, FileLayout::from_bytes(module, &metaspace).unwrap());
+        }
+        linker.link().map(|_| ())
+    };
+
+    let unresolved = Err(LinkerError::UnresolvedImport("maths".to_owned(), "square".to_owned()));
+    assert_eq!(link(&[("main", &main)]), unresolved);
+    assert_eq!(link(&[("maths", &maths)]), Err(LinkerError::NoEntryPoint));
+    assert_eq!(
+        link(&[("maths", &maths), ("main", &main), ("other", &main)]),
+        Err(LinkerError::DuplicateSymbol("main".to_owned()))
+    );
+
+    // The function has to be exported by the module the import names, not just any module
+    assert_eq!(link(&[("geometry", &maths), ("main", &main)]), unresolved);
+
+    // A module can't export a constant it doesn't have
+    let mut layout = FileLayout::from_bytes(&maths, &metaspace).unwrap();
+    for directive in layout.functions_mut()[0].directives_mut()
+    {
+        if let Directive::Export(ref mut index) = *directive
+        {
+            *index = 10;
+        }
+    }
+    let mut linker = Linker::new(&metaspace);
+    linker.add_module("maths", layout);
+    assert_eq!(
+        linker.link().map(|_| ()),
+        Err(LinkerError::InvalidConstant("maths".to_owned(), 10))
+    );
+}
+
+#[test]
+fn setup_info_checked()
+{
+    // Pushes a constant with no room on the stack for it
+    let loader = load_source(
+        "#0 string main
+
+.symbol 0 2
+.start
+.maxstack 0
+.maxlocal 0
+i.const.1
+ret.val
+",
+    );
+
+    let mut stack = Stack::new(1024);
+    let mut runner = Runner::new(&mut stack, &loader);
+    assert_eq!(
+        runner.run(),
+        Err(RunnerError::InvalidSetup(RunnableError::InsufficientStack {
+            maxstack: 0,
+            at: 0
+        }))
+    );
+}
+
+#[test]
+fn null_constant()
+{
+    // Pushes null, and gives back the type it was pushed as
+    let loader = load_source(
+        "#0 string main
+#1 null
+
+.symbol 0 7
+.start
+.maxstack 1
+.maxlocal 0
+const 1
+type.of
+ret.val
+",
+    );
+
+    let mut stack = Stack::new(1024);
+    let mut runner = Runner::new(&mut stack, &loader);
+    assert_eq!(runner.run_function("main", &[]), Ok(Some(3)));
+}
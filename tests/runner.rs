@@ -1,44 +1,90 @@
-use std::{fs::File, io::Write, path::Path};
+use std::{
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+};
 
 use assert_cmd::cargo::cargo_bin_cmd;
 use assert_cmd::prelude::*;
 
 mod assembler;
 
+use assembler::AssemblerOptions;
+
 const FILE_PATTERN: &str = r"^.*\.test$";
 
 const TEST_BASE: &str = "./tests";
 const PROGRAM_PATH: &str = constcat::concat!(TEST_BASE, "/programs");
-const COMPILED_PATH: &str = constcat::concat!(TEST_BASE, "/compiled");
+const SHOULD_FAIL_PATH: &str = constcat::concat!(PROGRAM_PATH, "/should_fail");
+const DEBUG_ONLY_PATH: &str = constcat::concat!(SHOULD_FAIL_PATH, "/debug");
+const COMPILED_PATH: &str = constcat::concat!(env!("CARGO_TARGET_TMPDIR"), "/compiled");
 
 const COMPILED_FILE_EXTENSION: &str = "azc";
+const COMPRESSED_FILE_EXTENSION: &str = "lz4.azc";
+const DEBUG_FILE_EXTENSION: &str = "debug.azc";
 
 fn test(path: &Path) -> datatest_stable::Result<()>
+{
+    // Some programs only fail through assertions, which are only checked in debug builds
+    let should_fail = path.starts_with(SHOULD_FAIL_PATH);
+    if path.starts_with(DEBUG_ONLY_PATH) && !cfg!(debug_assertions)
+    {
+        return Ok(());
+    }
+
+    // Every program is run as a plain file, a compressed one, and one with debug info
+    for (extension, options) in [
+        (COMPILED_FILE_EXTENSION, AssemblerOptions::default()),
+        (
+            COMPRESSED_FILE_EXTENSION,
+            AssemblerOptions {
+                compress: true,
+                ..AssemblerOptions::default()
+            },
+        ),
+        (
+            DEBUG_FILE_EXTENSION,
+            AssemblerOptions {
+                debug_info: true,
+                ..AssemblerOptions::default()
+            },
+        ),
+    ]
+    {
+        let bytecode_path = compile(path, extension, options)?;
+
+        let assert = cargo_bin_cmd!().arg(bytecode_path.to_str().unwrap()).assert();
+        if should_fail
+        {
+            assert.failure();
+        }
+        else
+        {
+            assert.success();
+        }
+    }
+
+    Ok(())
+}
+
+fn compile(path: &Path, extension: &str, options: AssemblerOptions) -> datatest_stable::Result<PathBuf>
 {
     let suffix = path.strip_prefix(Path::new(PROGRAM_PATH))?;
     let mut bytecode_path = Path::new(COMPILED_PATH).join(suffix);
-    bytecode_path.set_extension(COMPILED_FILE_EXTENSION);
+    bytecode_path.set_extension(extension);
 
     // Check whether to (re)compile
     if !bytecode_path.exists() || bytecode_path.metadata()?.modified()? < path.metadata()?.modified()?
     {
-        let string = std::fs::read_to_string(path)?;
-
         let mut bytes: Vec<u8> = vec![];
-        assembler::assemble(string.as_str(), &mut bytes)?;
+        assembler::assemble_file(path, &mut bytes, options)?;
 
         _ = std::fs::create_dir_all(bytecode_path.parent().unwrap());
         let mut file = File::create(&bytecode_path)?;
         file.write_all(&bytes)?;
     }
 
-    cargo_bin_cmd!()
-        .arg(bytecode_path.to_str().unwrap())
-        .unwrap()
-        .assert()
-        .success();
-
-    Ok(())
+    Ok(bytecode_path)
 }
 
 datatest_stable::harness! {